@@ -0,0 +1,62 @@
+// Measures the throughput of module parsing, pattern unpacking, and sample decoding, on test.xm and on large
+// generated modules. Run with `cargo bench --features testing`.
+//
+// Each benchmark is repeated until it has run for at least a second, and the best time per iteration is reported,
+// along with the throughput and the target for release builds.
+
+extern crate xmkit;
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use xmkit::testing::{self, GeneratorOptions, Rng};
+use xmkit::{XModule, XMPattern};
+
+// Runs f repeatedly, and reports the best time per iteration and the throughput for the given number of bytes.
+fn bench<F: FnMut()>(name: &str, bytes: usize, target_mb_per_s: f64, mut f: F) {
+    let mut best = Duration::MAX;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        let iteration = Instant::now();
+        f();
+        best = best.min(iteration.elapsed());
+    }
+
+    let mb_per_s = bytes as f64 / best.as_secs_f64() / 1_000_000.0;
+    let status = if mb_per_s >= target_mb_per_s { "ok" } else { "below target" };
+    println!("{:<32} {:>10.1} us {:>10.1} MB/s  (target {} MB/s, {})", name, best.as_secs_f64() * 1e6, mb_per_s,
+        target_mb_per_s, status);
+}
+
+fn main() {
+    let test_xm = fs::read("test.xm").unwrap();
+    bench("parse test.xm", test_xm.len(), 50.0, || {
+        black_box(XModule::parse(black_box(test_xm.clone())).unwrap());
+    });
+
+    // a pattern heavy module, as found in long songs with many channels
+    let options = GeneratorOptions { max_channels: 32, max_patterns: 128, max_rows: 256, max_instruments: 8,
+        max_samples: 4, max_sample_len: 0x10000 };
+    let large = testing::module_bytes(&mut Rng::new(1), &options);
+    bench("parse generated module", large.len(), 100.0, || {
+        black_box(XModule::parse(black_box(large.clone())).unwrap());
+    });
+
+    let xm = XModule::parse(large.clone()).unwrap();
+    let patterns: Vec<Vec<u8>> = xm.patterns.iter().map(|ptn| ptn.to_bytes(xmkit::PatternPacking::Canonical)).collect();
+    let pattern_bytes = patterns.iter().map(|ptn| ptn.len()).sum();
+    let channels = xm.channel_count();
+    bench("unpack patterns", pattern_bytes, 100.0, || {
+        for ptn in &patterns {
+            black_box(XMPattern::parse(black_box(ptn.clone()), channels).unwrap());
+        }
+    });
+
+    let samples: Vec<_> = xm.instruments.iter().flat_map(|instr| instr.samples.iter()).collect();
+    let sample_bytes = samples.iter().map(|smp| smp.len()).sum();
+    bench("decode samples", sample_bytes, 200.0, || {
+        for smp in &samples {
+            black_box(smp.decoder().fold(0i32, |sum, value| sum.wrapping_add(value as i32)));
+        }
+    });
+}
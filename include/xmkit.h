@@ -0,0 +1,78 @@
+/*
+ * C API of xmkit, a library for reading eXtended Module (XM) files.
+ *
+ * Build the shared library with the ffi feature
+ * (cargo rustc --lib --release --features ffi --crate-type cdylib), and link against the resulting libxmkit.
+ * See src/xmkit/ffi.rs for the implementation. This header is maintained by hand alongside it.
+ *
+ * Modules are parsed into opaque XMKitModule handles, which must be released with xmkit_free(). Patterns,
+ * instruments, and samples are addressed by their 0-based index. Names are returned as raw bytes, as stored in the
+ * module (usually DOS codepage 437), and are copied into caller-provided buffers. Internal errors (Rust panics) are
+ * caught and reported like other errors, through a null or error return value and xmkit_last_error().
+ */
+
+#ifndef XMKIT_H
+#define XMKIT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The success code of functions that return an int. */
+#define XMKIT_OK 0
+/* The error code for null pointers and indices that are out of range. */
+#define XMKIT_ERROR_RANGE (-1)
+
+/* An opaque handle to a parsed module. */
+typedef struct XMKitModule XMKitModule;
+
+/* The fields of a pattern cell. Empty fields are 0. */
+typedef struct XMKitCell {
+    uint8_t note;
+    uint8_t instrument;
+    uint8_t volume;
+    uint8_t fx_command;
+    uint8_t fx_param;
+} XMKitCell;
+
+/* Parses len bytes of module data. Returns null if the data is not a valid module, see xmkit_last_error(). */
+XMKitModule *xmkit_parse(const uint8_t *data, size_t len);
+/* Releases a module. Null handles are ignored. */
+void xmkit_free(XMKitModule *xm);
+/* Returns the message of the last error on the calling thread, or null. Valid until the next xmkit call. */
+const char *xmkit_last_error(void);
+
+/* Copies the zero-terminated module name into buf, and returns the length of the full name. */
+size_t xmkit_module_name(const XMKitModule *xm, char *buf, size_t buf_len);
+uint16_t xmkit_channel_count(const XMKitModule *xm);
+uint16_t xmkit_pattern_count(const XMKitModule *xm);
+uint16_t xmkit_instrument_count(const XMKitModule *xm);
+uint16_t xmkit_song_length(const XMKitModule *xm);
+/* Returns the pattern number at the given sequence position, or XMKIT_ERROR_RANGE. */
+int xmkit_order(const XMKitModule *xm, uint16_t position);
+uint16_t xmkit_restart_pos(const XMKitModule *xm);
+uint8_t xmkit_tempo(const XMKitModule *xm);
+uint8_t xmkit_bpm(const XMKitModule *xm);
+/* Returns 1 for the Amiga frequency table, 0 for the linear frequency table. */
+int xmkit_amiga_ft(const XMKitModule *xm);
+
+uint16_t xmkit_pattern_rows(const XMKitModule *xm, uint16_t pattern);
+/* Reads a pattern cell. Returns XMKIT_OK, or XMKIT_ERROR_RANGE if the cell does not exist. */
+int xmkit_pattern_cell(const XMKitModule *xm, uint16_t pattern, uint16_t row, uint16_t channel, XMKitCell *cell);
+
+/* Copies the zero-terminated instrument name into buf, and returns the length of the full name. */
+size_t xmkit_instrument_name(const XMKitModule *xm, uint16_t instrument, char *buf, size_t buf_len);
+uint16_t xmkit_sample_count(const XMKitModule *xm, uint16_t instrument);
+/* Copies the zero-terminated sample name into buf, and returns the length of the full name. */
+size_t xmkit_sample_name(const XMKitModule *xm, uint16_t instrument, uint16_t sample, char *buf, size_t buf_len);
+/* Copies up to len frames of 16-bit PCM into buf, and returns the total number of frames of the sample. */
+size_t xmkit_sample_pcm(const XMKitModule *xm, uint16_t instrument, uint16_t sample, int16_t *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* XMKIT_H */
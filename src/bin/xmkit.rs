@@ -0,0 +1,160 @@
+extern crate xmkit;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use xmkit::*;
+
+const USAGE: &str = "Usage: xmkit <command> <file.xm> [arguments]
+
+Commands:
+    info                        Show module information
+    dump-pattern <pattern>      Print the contents of a pattern
+    export-samples <dir> [fmt]  Write all samples to <dir> as wav (default) or raw signed PCM files, along with
+                                a manifest in JSON and CSV format
+    export-events               Print every cell played in the song as CSV, with its time and position
+    convert-it <out.it>         Convert the module to Impulse Tracker format, listing anything not converted exactly
+    validate                    Check the module for problems
+    optimize <out.xm>           Repair problems, repack patterns, and write the result to <out.xm>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let xm = match XModule::parse_file(Path::new(&args[1])) {
+        Err(e) => {
+            eprintln!("{}: {}", args[1], e);
+            process::exit(1);
+        },
+        Ok(xm) => xm,
+    };
+
+    let result = match (args[0].as_str(), args.get(2)) {
+        ("info", _) => info(&xm),
+        ("dump-pattern", Some(ptn)) => dump_pattern(&xm, ptn),
+        ("export-samples", Some(dir)) => export_samples(&xm, Path::new(dir), args.get(3)),
+        ("export-events", _) => xm.export_events_csv(std::io::stdout().lock()).map_err(|e| e.to_string()),
+        ("convert-it", Some(out)) => convert_it(&xm, Path::new(out)),
+        ("validate", _) => validate(&xm),
+        ("optimize", Some(out)) => optimize(xm, Path::new(out)),
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn info(xm: &XModule) -> Result<(), String> {
+    let rows = xm.play_rows();
+    let duration = rows.last().map_or(0.0, |row| (row.start + row.duration()).as_secs_f64());
+
+    println!("Name:          {}", xm.name());
+    println!("Tracker:       {} (probably {:?})", xm.tracker_name(), xm.probable_tracker());
+    println!("Channels:      {}", xm.channel_count());
+    println!("Patterns:      {}", xm.pattern_count());
+    println!("Instruments:   {}", xm.instrument_count());
+    println!("Frequencies:   {}", if xm.amiga_ft() { "Amiga" } else { "linear" });
+    println!("Tempo/BPM:     {}/{}", xm.tempo(), xm.bpm());
+    println!("Duration:      {}:{:05.2}", (duration / 60.0) as u32, duration % 60.0);
+    println!("Sequence:      {:?}, restart at {}", xm.sequence(), xm.restart_pos());
+
+    for (nr, instr) in xm.instruments.iter().enumerate() {
+        println!("{:02X} {}", nr + 1, instr.name());
+        for smp in &instr.samples {
+            println!("   {:<22} {:>7} bytes, {}-bit", smp.name(), smp.len(), if smp.is_16bit() { 16 } else { 8 });
+        }
+    }
+
+    Ok(())
+}
+
+fn dump_pattern(xm: &XModule, ptn: &str) -> Result<(), String> {
+    let nr = ptn.parse::<usize>().map_err(|_| format!("Invalid pattern number: {}", ptn))?;
+    let ptn = xm.patterns.get(nr).ok_or_else(|| format!("Pattern {} does not exist.", nr))?;
+
+    for row in 0..ptn.len() {
+        let mut line = format!("{:02X}", row);
+        for trk in &ptn.tracks {
+            line += &format!(" | {}", trk.cell(row).map_err(|e| e.to_string())?);
+        }
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn export_samples(xm: &XModule, dir: &Path, format: Option<&String>) -> Result<(), String> {
+    let format = match format.map(|f| f.as_str()) {
+        None | Some("wav") => ExportFormat::Wav,
+        Some("raw") => ExportFormat::Raw,
+        Some(other) => return Err(format!("Invalid export format: {}", other)),
+    };
+
+    for path in xm.export_samples(dir, format).map_err(|e| format!("{}: {}", dir.display(), e))? {
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+fn convert_it(xm: &XModule, out: &Path) -> Result<(), String> {
+    let (data, warnings) = xm.to_it();
+    for warning in &warnings {
+        println!("lossy: {}", warning);
+    }
+    fs::write(out, &data).map_err(|e| format!("{}: {}", out.display(), e))?;
+    println!("{} bytes written.", data.len());
+
+    Ok(())
+}
+
+fn validate(xm: &XModule) -> Result<(), String> {
+    let report = xm.validate();
+    print!("{}", report);
+
+    match report.max_severity() {
+        Some(Severity::Error) => Err(format!("{} error(s) found.", report.with_severity(Severity::Error).len())),
+        _ => Ok(()),
+    }
+}
+
+fn optimize(mut xm: XModule, out: &Path) -> Result<(), String> {
+    // the module is unmodified, so it is written back as it was read
+    let original_size = xm.to_bytes().len();
+
+    for instr in &mut xm.instruments {
+        for warning in instr.normalize_envelopes() {
+            println!("fixed: {}: {}", instr.name(), warning);
+        }
+        for smp in &mut instr.samples {
+            for warning in smp.repair() {
+                println!("fixed: {}: {}", smp.name(), warning);
+            }
+            let trimmed = smp.trim_after_loop();
+            if trimmed > 0 {
+                println!("trimmed: {}: {} bytes after loop end", smp.name(), trimmed);
+            }
+        }
+    }
+
+    let removed = xm.canonicalize_effects();
+    if removed > 0 {
+        println!("removed: {} redundant effects", removed);
+    }
+    let moved = xm.move_volume_fx_to_volume_column();
+    if moved > 0 {
+        println!("moved: {} volume effects to the volume column", moved);
+    }
+
+    let data = xm.to_bytes();
+    fs::write(out, &data).map_err(|e| format!("{}: {}", out.display(), e))?;
+    println!("{} bytes written ({} bytes before).", data.len(), original_size);
+
+    Ok(())
+}
@@ -1,3 +1,6 @@
+#[cfg(feature = "rodio")]
+extern crate rodio;
+
 pub use xmkit::*;
 
 /// A module for extracting information from eXtended Module (XM) files.
@@ -8,9 +11,138 @@ pub mod xmkit {
     use std::io::prelude::*;
     use std::path::Path;
     use std::str;
+    use std::sync::{Arc, OnceLock};
+
+    mod activity;
+    pub mod archive;
+    mod bake;
+    mod bank;
+    mod cells;
+    mod changes;
+    mod channels;
+    mod compile;
+    pub mod codec;
+    mod debug;
+    mod dirty;
+    mod effects;
+    mod events;
+    mod extensions;
+    mod export;
+    #[cfg(feature = "ffi")]
+    pub mod ffi;
+    mod flow;
+    mod freq_table;
+    mod groove;
+    mod humanize;
+    #[cfg(feature = "fuzz")]
+    pub mod fuzz;
+    #[cfg(feature = "image")]
+    mod image;
+    mod instrument_edit;
+    mod interpolate;
+    mod it;
+    mod keymap;
+    mod legacy;
+    mod levels;
+    mod lfo;
+    mod loops;
+    mod metadata;
+    mod meter;
+    mod midi;
+    mod names;
+    #[cfg(feature = "async")]
+    mod nonblocking;
+    mod note_usage;
+    mod openmpt;
+    mod optimize;
+    mod options;
+    mod orders;
+    mod pitch;
+    mod provenance;
+    mod psg;
+    mod query;
+    mod quirks;
+    mod raw;
+    mod recover;
+    mod render;
+    mod replace;
+    mod resize;
+    mod rng;
+    mod sample_edit;
+    mod sample_features;
+    mod selection;
+    mod similarity;
+    mod size_report;
+    mod snapshot;
+    mod source;
+    mod state;
+    mod structure;
+    #[cfg(feature = "testing")]
+    pub mod testing;
+    mod ticks;
+    mod timing;
+    mod validate;
+    mod visitor;
+    mod volume_slides;
+    mod wav;
+    mod waveform;
+    mod writer;
+    mod xi;
+    mod xp;
+
+    pub use self::activity::ChannelUsage;
+    pub use self::bank::{BankMapping, SampleBank};
+    pub use self::cells::CellMask;
+    pub use self::changes::RowChange;
+    pub use self::channels::{ReductionStrategy, XMChannel};
+    pub use self::codec::SampleDecoder;
+    pub use self::compile::{Bytecode, CompileBackend, EngineEvent, EngineTick};
+    pub use self::effects::{ChannelEffectMemory, Effect};
+    pub use self::events::NoteEvent;
+    pub use self::export::ExportFormat;
+    pub use self::extensions::ExtensionChunk;
+    pub use self::flow::{FlowGraph, FlowNode, FlowTarget};
+    pub use self::freq_table::{FrequencyConversion, FrequencyTable};
+    pub use self::groove::Groove;
+    #[cfg(feature = "image")]
+    pub use self::image::{PatternImageOptions, PatternImageStyle};
+    pub use self::it::ConversionWarning;
+    pub use self::levels::{LevelEstimate, Levels};
+    pub use self::lfo::{AutoVibrato, Lfo, LfoWaveform};
+    pub use self::loops::LoopPoint;
+    pub use self::meter::Meter;
+    pub use self::midi::MidiSettings;
+    pub use self::names::{NameEncoding, NamePolicy, NameTruncation};
+    #[cfg(feature = "async")]
+    pub use self::nonblocking::ParseFuture;
+    pub use self::openmpt::{ExtensionProperty, OpenMptExtensions, PluginSlot};
+    pub use self::options::{ParseOptions, ParseStage};
+    pub use self::pitch::{frequency_for_period, note_name, period_for_note, tuning_for_rate};
+    pub use self::provenance::Tracker;
+    pub use self::psg::{quantize_volume, tone_periods, ToneChannelMap, UnsupportedEffect, VolumeCurve};
+    pub use self::query::XMCell;
+    pub use self::quirks::QuirksMode;
+    pub use self::recover::ParseWarning;
+    pub use self::sample_features::SampleFeatures;
+    pub use self::selection::{CellPos, Selection};
+    pub use self::size_report::SizeReport;
+    pub use self::snapshot::Snapshot;
+    pub use self::source::XmSource;
+    pub use self::state::ChannelState;
+    pub use self::structure::SongSection;
+    pub use self::ticks::{TickEvent, TickEventKind};
+    pub use self::timing::{row_duration, tick_duration, ticks_duration, PlayRow};
+    pub use self::validate::{Envelope, EnvelopeWarning, SampleWarning, Severity, ValidationIssue, ValidationLocation,
+        ValidationReport};
+    pub use self::visitor::ModuleVisitor;
+    pub use self::volume_slides::VolumeSlideUsage;
+    pub use self::wav::{SampleDepth, SampleImportOptions};
+    pub use self::writer::PatternPacking;
+    use self::cells::PackedCell;
+    use self::dirty::Original;
 
     const XM_MODULE_NAME: usize = 0x11;
-    const XM_TRACKER_NAME: usize = 0x25;
+    const XM_TRACKER_NAME: usize = 0x26;
     const XM_VERSION_MINOR: usize = 0x3a;
     const XM_VERSION_MAJOR: usize = 0x3b;
     const XM_HEADER_SIZE: usize = 0x3c;
@@ -23,9 +155,13 @@ pub mod xmkit {
     const XM_DEFAULT_TEMPO: usize = 0x4c;
     const XM_DEFAULT_BPM: usize = 0x4e;    
     const XM_SEQUENCE_BEGIN: usize = 0x50;
+    const XM_SEQUENCE_MAX_LEN: usize = 256;
+    const XM_PATTERN_HEADER_SIZE: usize = 9;
+    const XM_INSTRUMENT_HEADER_SIZE: usize = 263;
+    const XM_SAMPLE_HEADER_SIZE: usize = 40;
     const XM_EFFECTS: [u8; 38] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0xa, 0xb, 0xc, 0xd, 0xf, 0x10, 0x11, 
         0x14, 0x15, 0x19, 0x1b, 0x1d, 0x22, 0x23, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xeb, 0xec, 0xed, 0xee];
-    const XM_EFFECTS_WITH_MEMORY: [u8; 19] = [1, 2, 3, 4, 5, 6, 7, 9, 0xa, 0x11, 0x19, 0x1b, 0x1d, 0x22, 0x23, 0xe1, 0xe2, 0xea, 0xeb];
+    pub const XM_EFFECTS_WITH_MEMORY: [u8; 19] = [1, 2, 3, 4, 5, 6, 7, 9, 0xa, 0x11, 0x19, 0x1b, 0x1d, 0x22, 0x23, 0xe1, 0xe2, 0xea, 0xeb];
     pub const XM_ENVELOPE_ON: u8 = 0x1;
     pub const XM_ENVELOPE_SUSTAIN: u8 = 0x2;
     pub const XM_ENVELOPE_LOOP: u8 = 0x4;
@@ -74,27 +210,30 @@ pub mod xmkit {
 
 
 
-    #[derive(Default)]
+    #[derive(Clone, Default)]
     pub struct XModule {
         header: Vec<u8>,
         pub patterns: Vec<XMPattern>,
         pub instruments: Vec<XMInstrument>,
+        // data following the last instrument, such as OpenMPT extensions
+        trailing: Vec<u8>,
+        original: Option<Original>,
     }
 
     impl XModule {
        
         /// Opens and parses an eXtended Module (XM) file, and constructs an XModule instance from it if the XM file is valid.
         pub fn parse_file(filepath: &Path) -> Result<XModule, XMParseError> {
-            let mut xmfile = match fs::File::open(&filepath) {
+            let mut xmfile = match fs::File::open(filepath) {
                 // TODO should propagate the actual io::Error instead of converting it
-                Err(e) => return Err(XMParseError::new(&format!("Couldn't open {}: {}", filepath.display(), e.description()))),
+                Err(e) => return Err(XMParseError::new(&format!("Couldn't open {}: {}", filepath.display(), e))),
                 Ok(xmfile) => xmfile,
             };
 
-            let metadata = match fs::metadata(&filepath) {
+            let metadata = match fs::metadata(filepath) {
                 // TODO should propagate the actual io::Error instead of converting it
                 Err(e) => return Err(XMParseError::new(&format!("{}: Couldn't read metadata: {}", 
-                    filepath.display(), e.description()))),
+                    filepath.display(), e))),
                 Ok(metadata) => metadata,   
             };
 
@@ -105,9 +244,21 @@ pub mod xmkit {
         }
 
         /// Parses eXtended Module data, and constructs an XModule instance from it if the data is valid. 
+        /// Module headers without room for a sequence of 256 entries are padded with zeros.
         pub fn parse(data: Vec<u8>) -> Result<XModule, XMParseError> {
+            XModule::parse_with(data, &ParseOptions::default(), None)
+        }
+
+        // Parses eXtended Module data. If a warnings list is given, broken patterns and instruments are replaced
+        // with empty ones and reported in the list, instead of aborting the parse.
+        fn parse_with(data: Vec<u8>, options: &ParseOptions, mut warnings: Option<&mut Vec<ParseWarning>>)
+            -> Result<XModule, XMParseError> {
 
             XModule::verify_filetype(&data)?;
+            if data.len() > options.max_total_size {
+                return Err(XMParseError::new(&format!("Module is {} bytes, the maximum is {}.", data.len(),
+                    options.max_total_size)).in_context(ValidationLocation::Module, 0));
+            }
 
             let mut xm: XModule = Default::default();
 
@@ -115,51 +266,153 @@ pub mod xmkit {
             // does not include bytes up to XM_HEADER_SIZE offset (0x3c)
             let mut file_offset: usize = XM_HEADER_SIZE + XModule::read_usize(&data, XM_HEADER_SIZE);
             xm.header = data[..file_offset].to_vec();
+            // short headers are padded, so that all header fields and a full sequence can be accessed
+            if xm.header.len() < XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN {
+                xm.header.resize(XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN, 0);
+                XModule::write_usize(&mut xm.header, XM_HEADER_SIZE, XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN - XM_HEADER_SIZE);
+            }
+            options.report(ParseStage::Header, 1, 1);
             let channel_count = xm.channel_count();
+            let stored_channels = XModule::read_u16(&xm.header, XM_CHANNEL_COUNT);
+            if stored_channels > options.max_channels as u16 {
+                return Err(XMParseError::new(&format!("Module has {} channels, the maximum is {}.", stored_channels,
+                    options.max_channels)).at(XM_CHANNEL_COUNT).in_context(ValidationLocation::Module, 0));
+            }
 
             // parse pattern data
-            for _ in 0..xm.pattern_count() {
-                let ptn_size = XModule::read_usize(&data, file_offset) + (XModule::read_u16(&data, file_offset + 7) as usize);
+            let mut unpacked_size = data.len();
+            for ptn_nr in 0..xm.pattern_count() as usize {
+                let location = ValidationLocation::Pattern(ptn_nr);
+                let remaining = data.len() - file_offset;
+                let ptn_size = if remaining < 9 { None }
+                    else { Some(XModule::read_usize(&data, file_offset) + (XModule::read_u16(&data, file_offset + 7) as usize)) };
+
+                // patterns are unpacked while they are parsed, which takes up to 5 bytes per cell
+                if let Some(size) = ptn_size.filter(|size| *size <= remaining) {
+                    let rows = (XModule::read_u16(&data, file_offset + 5) as usize).min(256);
+                    unpacked_size = unpacked_size - size + XModule::read_usize(&data, file_offset).min(size)
+                        + rows * channel_count as usize * 5;
+                    if unpacked_size > options.max_total_size {
+                        return Err(XMParseError::new(&format!("Module takes up more than {} bytes when unpacked.",
+                            options.max_total_size)).in_context(location, file_offset));
+                    }
+                }
+
+                let result = match ptn_size {
+                    None => Err(XMParseError::new("XM Pattern header incomplete.").with_sizes(9, remaining)),
+                    Some(size) if size > remaining => Err(XMParseError::new("XM Pattern data corrupt or incomplete.")
+                        .with_sizes(size, remaining)),
+                    Some(size) => XMPattern::parse(data[file_offset..(file_offset + size)].to_vec(), channel_count),
+                };
 
-                xm.patterns.push(XMPattern::parse(data[file_offset..(file_offset + ptn_size)].to_vec(), channel_count)?);
-                file_offset += ptn_size;
+                match (result, warnings.as_mut()) {
+                    (Ok(ptn), _) => {
+                        xm.patterns.push(ptn);
+                        file_offset += ptn_size.unwrap_or(0);
+                    },
+                    (Err(e), None) => return Err(e.in_context(location, file_offset)),
+                    (Err(e), Some(warnings)) => {
+                        warnings.push(ParseWarning{location, error: e.in_context(location, file_offset)});
+                        let rows = if ptn_size.is_some() { XModule::read_u16(&data, file_offset + 5) } else { 64 };
+                        xm.patterns.push(XMPattern::empty(if rows == 0 || rows > 256 { 64 } else { rows }, channel_count));
+
+                        // skip the broken pattern if its size is known, otherwise the remaining data is unusable
+                        file_offset = match ptn_size {
+                            Some(size) if size <= remaining => file_offset + size,
+                            _ => data.len(),
+                        };
+                    },
+                }
+                options.report(ParseStage::Patterns, ptn_nr + 1, xm.pattern_count() as usize);
+            }
+            if xm.pattern_count() == 0 {
+                options.report(ParseStage::Patterns, 0, 0);
             }
 
             // parse instruments
-            for _ in 0..xm.instrument_count() {
+            let instruments_offset = file_offset;
+            for instr_nr in 0..xm.instrument_count() as usize {
+                let location = ValidationLocation::Instrument(instr_nr);
                 let instr_offset = file_offset;
-                let sample_count = data[file_offset + 27];
-                file_offset += XModule::read_usize(&data, file_offset);
-
-                if sample_count == 0 {
-                    file_offset += 29;
-                }
-                else {
-                    let mut data_length: usize = 0;
-                    for _ in 0..sample_count {
-                        data_length += XModule::read_usize(&data, file_offset);
-                        file_offset += 40;
-                    }
-                    file_offset += data_length;
+                let max_sample_bytes = options.max_sample_bytes;
+                let result = XModule::instrument_size(&data, instr_nr, instr_offset, max_sample_bytes).and_then(|size| {
+                    file_offset += size;
+                    XMInstrument::parse(data[instr_offset..file_offset.min(data.len())].to_vec())
+                });
+
+                // the header size of instruments without samples is not checked against the data, so it may point
+                // past the end
+                file_offset = file_offset.min(data.len());
+                match (result, warnings.as_mut()) {
+                    (Ok(instr), _) => xm.instruments.push(instr),
+                    (Err(e), None) => return Err(e.in_context(location, instr_offset)),
+                    (Err(e), Some(warnings)) => {
+                        warnings.push(ParseWarning{location, error: e.in_context(location, instr_offset)});
+                        xm.instruments.push(XMInstrument::empty());
+
+                        // skip the broken instrument if its size is known, otherwise the remaining data is unusable
+                        if file_offset == instr_offset || file_offset > data.len() {
+                            file_offset = data.len();
+                        }
+                    },
                 }
+                options.report(ParseStage::Instruments, instr_nr + 1, xm.instrument_count() as usize);
+                options.report(ParseStage::Samples, file_offset.min(data.len()) - instruments_offset,
+                    data.len() - instruments_offset);
+            }
+            if xm.instrument_count() == 0 {
+                options.report(ParseStage::Instruments, 0, 0);
+            }
+            // data following the last instrument is not sample data
+            if xm.instrument_count() == 0 || file_offset < data.len() {
+                options.report(ParseStage::Samples, 1, 1);
+            }
 
-                match XMInstrument::parse(data[instr_offset..file_offset].to_vec()) {
-                    Err(e) => return Err(e),
-                    Ok(instr) => xm.instruments.push(instr),
-                }
+            if file_offset < data.len() {
+                xm.trailing = data[file_offset..].to_vec();
             }
+            xm.original = Some(Original::new(xm.header.clone(), xm.trailing.clone(), &[]));
 
             Ok(xm)
         }
 
-        /// Returns true if the Amiga frequency table is used, or false if the linear frequency table is used.
-        pub fn amiga_ft(&self) -> bool {
-            if self.header[XM_FREQ_TABLE_TYPE] == 0 {
-                return true;
+        // Determines the size of the instrument at the given offset, including sample headers and data.
+        // Errors are relative to the instrument offset. Samples longer than max_sample_bytes are rejected.
+        fn instrument_size(data: &[u8], instr_nr: usize, instr_offset: usize, max_sample_bytes: usize)
+            -> Result<usize, XMParseError> {
+            if data.len() < instr_offset + 29 {
+                return Err(XMParseError::new("XM Instrument header incomplete.")
+                    .with_sizes(29, data.len().saturating_sub(instr_offset)));
             }
-            else {
-                return false;
+
+            let sample_count = data[instr_offset + 27];
+            let mut size = XModule::read_usize(data, instr_offset);
+
+            let mut data_length: usize = 0;
+            for smp_nr in 0..sample_count as usize {
+                if data.len() < instr_offset + size + 40 {
+                    let mut e = XMParseError::new("XM Sample header incomplete.")
+                        .with_sizes(40, data.len().saturating_sub(instr_offset + size)).at(size);
+                    e.location = Some(ValidationLocation::Sample(instr_nr, smp_nr));
+                    return Err(e);
+                }
+                let sample_length = XModule::read_usize(data, instr_offset + size);
+                if sample_length > max_sample_bytes {
+                    let mut e = XMParseError::new(&format!("Sample is {} bytes, the maximum is {}.", sample_length,
+                        max_sample_bytes)).at(size);
+                    e.location = Some(ValidationLocation::Sample(instr_nr, smp_nr));
+                    return Err(e);
+                }
+                data_length += sample_length;
+                size += 40;
             }
+
+            Ok(size + data_length)
+        }
+
+        /// Returns true if the Amiga frequency table is used, or false if the linear frequency table is used.
+        pub fn amiga_ft(&self) -> bool {
+            self.header[XM_FREQ_TABLE_TYPE] == 0
         }
 
         /// Returns the default BPM value.
@@ -183,8 +436,14 @@ pub mod xmkit {
             XModule::read_u16(&self.header, XM_SEQUENCE_LEN)
         }
 
+        /// Returns true if the sequence (song) contains no entries.
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
         // or should we perhaps return a &str?
         /// Returns the module name.
+        /// Use name_with_encoding() to decode names containing DOS (CP437) characters.
         pub fn name(&self) -> String {
             XModule::read_string(&self.header, XM_MODULE_NAME, 20)
         }
@@ -201,7 +460,8 @@ pub mod xmkit {
 
         /// Returns the sequence (pattern order list)
         pub fn sequence(&self) -> Vec<u8> {
-            self.header[XM_SEQUENCE_BEGIN..(XM_SEQUENCE_BEGIN + self.len() as usize)].to_vec()
+            let len = (self.len() as usize).min(self.header.len().saturating_sub(XM_SEQUENCE_BEGIN));
+            self.header[XM_SEQUENCE_BEGIN..(XM_SEQUENCE_BEGIN + len)].to_vec()
         }
 
         /// Returns default tempo value.
@@ -223,32 +483,54 @@ pub mod xmkit {
             false
         }
 
-        fn read_u16(data: &Vec<u8>, offset: usize) -> u16 {
+        fn read_u16(data: &[u8], offset: usize) -> u16 {
             data[offset] as u16 + ((data[offset + 1] as u16) << 8)
         }
 
-        fn read_usize(data: &Vec<u8>, offset: usize) -> usize {
+        fn read_usize(data: &[u8], offset: usize) -> usize {
             data[offset] as usize + ((data[offset + 1] as usize) << 8)
                 + ((data[offset + 2] as usize) << 0x10) + ((data[offset + 3] as usize) << 0x18)
         }
 
+        fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+            data[offset] = value as u8;
+            data[offset + 1] = (value >> 8) as u8;
+        }
+
+        fn write_usize(data: &mut [u8], offset: usize, value: usize) {
+            for i in 0..4 {
+                data[offset + i] = (value >> (i * 8)) as u8;
+            }
+        }
+
+        // Writes a string into a fixed-size field, replacing non-ASCII characters with '?', truncating it if it is too
+        // long, and padding it with spaces.
+        fn write_string(data: &mut [u8], offset: usize, len: usize, value: &str) {
+            let mut bytes = value.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' });
+            for byte in data[offset..offset + len].iter_mut() {
+                *byte = bytes.next().unwrap_or(b' ');
+            }
+        }
+
         // TODO should check if there's enough data in buffer, and throw an XMParseError if not
-        fn read_string(data: &Vec<u8>, offset: usize, len: usize) -> String {
+        fn read_string(data: &[u8], offset: usize, len: usize) -> String {
             let mut buf: Vec<u8> = Vec::with_capacity(len);
             let mut pos = offset;
 
-            while pos <= offset + len && data[pos] != 0 {
+            while pos < offset + len && data[pos] != 0 {
                 buf.push(data[pos]);
                 pos += 1;
             }
 
-            String::from_utf8_lossy(&buf).into_owned().trim_right().to_string()
+            String::from_utf8_lossy(&buf).into_owned().trim_end().to_string()
         }
 
-        fn verify_filetype(data: &Vec<u8>) -> Result<(), XMParseError> {
+        fn verify_filetype(data: &[u8]) -> Result<(), XMParseError> {
 
-            if data.len() < 60 || data.len() < 60 + XModule::read_usize(&data, XM_HEADER_SIZE) {
-                return Err(XMParseError::new("Corrupted or invalid XM data."));
+            if data.len() < 64 || data.len() < XM_HEADER_SIZE + XModule::read_usize(data, XM_HEADER_SIZE) {
+                let expected = if data.len() < 64 { 64 } else { XM_HEADER_SIZE + XModule::read_usize(data, XM_HEADER_SIZE) };
+                return Err(XMParseError::new("Corrupted or invalid XM data.").with_sizes(expected, data.len())
+                    .in_context(ValidationLocation::Module, 0));
             }
 
             if data[..17].to_vec() != "Extended Module: ".as_bytes() {
@@ -265,85 +547,94 @@ pub mod xmkit {
 
 
     #[allow(dead_code, unused_variables)]
-    #[derive(Default)]
+    #[derive(Clone, Default)]
     pub struct XMPattern {
         header: Vec<u8>,
         pub tracks: Vec<XMTrack>,
+        original: Option<Original>,
     }
 
     impl XMPattern {
 
         /// Parses eXtended Module pattern data, and constructs an XMPattern instance from it if the data is valid.
+        /// Patterns can have up to 256 rows. Patterns without packed data are valid, and result in an empty
+        /// pattern with the number of rows given in the pattern header (usually 64). Like in FT2, patterns with 0
+        /// rows are loaded as empty 64-row patterns.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the pattern data is incomplete, or the pattern has more than 256 rows.
         pub fn parse(data: Vec<u8>, channel_count: u8) -> Result<XMPattern, XMParseError> {
 
-            if data.len() < 9 || data.len() != XModule::read_usize(&data, 0) + (XModule::read_u16(&data, 7) as usize) {
-                return Err(XMParseError::new("XM Pattern data corrupt or incomplete."))
+            if data.len() < XM_PATTERN_HEADER_SIZE {
+                return Err(XMParseError::new("XM Pattern header incomplete.").with_sizes(XM_PATTERN_HEADER_SIZE, data.len())
+                    .at(0))
+            }
+            if XModule::read_usize(&data, 0) < XM_PATTERN_HEADER_SIZE {
+                return Err(XMParseError::new(&format!("Invalid XM pattern header size of {} bytes.",
+                    XModule::read_usize(&data, 0))).at(0))
+            }
+            let ptn_size = XModule::read_usize(&data, 0) + (XModule::read_u16(&data, 7) as usize);
+            if data.len() != ptn_size {
+                return Err(XMParseError::new("XM Pattern data corrupt or incomplete.").with_sizes(ptn_size, data.len()).at(0))
             }
 
             let mut ptn: XMPattern = Default::default();
-            let mut file_offset = XModule::read_usize(&data, 0);
-            let ptn_len = data[5];
+            let file_offset = XModule::read_usize(&data, 0);
+            let ptn_len = XModule::read_u16(&data, 5);
             let channel_count = channel_count as usize;
 
+            if ptn_len > 256 {
+                return Err(XMParseError::new(&format!("Invalid XM pattern length of {} rows.", ptn_len)))
+            }
+
             ptn.header = data[0..file_offset].to_vec();
-            ptn.tracks = Vec::with_capacity(channel_count);
+            if ptn_len == 0 {
+                XModule::write_u16(&mut ptn.header, 5, 64);
+            }
+            let rows = ptn.len() as usize;
 
-            for _ in 0..channel_count {
-                ptn.tracks.push(Default::default())
+            // no packed data means the pattern is empty, and so do 0 rows
+            if file_offset == data.len() || ptn_len == 0 {
+                ptn.tracks = vec![XMTrack { cells: vec![PackedCell::default(); rows] }; channel_count];
+                ptn.original = Some(Original::new(ptn.header.clone(), data, &[]));
+                return Ok(ptn);
             }
 
-            for _ in 0..ptn_len {
-                for chan in 0..channel_count {
-                    let ctrl = data[file_offset];
-                    
+            ptn.tracks = (0..channel_count).map(|_| XMTrack { cells: Vec::with_capacity(rows) }).collect();
+
+            // the unread part of the packed data
+            let mut cells = &data[file_offset..];
+            for _ in 0..rows {
+                for trk in ptn.tracks.iter_mut() {
+                    let file_offset = data.len() - cells.len();
+                    let ctrl = match cells.first() {
+                        Some(ctrl) => *ctrl,
+                        None => return Err(XMParseError::new("XM Pattern data corrupt or incomplete.").at(file_offset)),
+                    };
+                    let cell_len = if ctrl & 0x80 != 0 { 1 + (ctrl & 0x1f).count_ones() as usize } else { 5 };
+                    let cell = match cells.get(..cell_len) {
+                        Some(cell) => cell,
+                        None => return Err(XMParseError::new("XM Pattern data corrupt or incomplete.")
+                            .with_sizes(cell_len, cells.len()).at(file_offset)),
+                    };
+
+                    let mut packed = PackedCell::default();
                     if ctrl & 0x80 != 0 {
-                        file_offset += 1;
-                        if ctrl & 1 != 0 {
-                            ptn.tracks[chan].notes.push(Some(data[file_offset]));
-                            file_offset += 1;
-                        }
-                        else {
-                            ptn.tracks[chan].notes.push(None);
-                        }
-                        if ctrl & 2 != 0 {
-                            ptn.tracks[chan].instruments.push(Some(data[file_offset]));
-                            file_offset += 1;
-                        }
-                        else {
-                            ptn.tracks[chan].instruments.push(None);
-                        }
-                        if ctrl & 4 != 0 {
-                            ptn.tracks[chan].volumes.push(Some(data[file_offset]));
-                            file_offset += 1;
-                        }
-                        else {
-                            ptn.tracks[chan].volumes.push(None);
-                        }
-                        if ctrl & 8 != 0 {
-                            ptn.tracks[chan].fx_commands.push(Some(data[file_offset]));
-                            file_offset += 1;
-                        }
-                        else {
-                            ptn.tracks[chan].fx_commands.push(None);
-                        }
-                        if ctrl & 0x10 != 0 {
-                            ptn.tracks[chan].fx_params.push(Some(data[file_offset]));
-                            file_offset += 1;
-                        }
-                        else {
-                            ptn.tracks[chan].fx_params.push(None);
+                        let mut fields = cell[1..].iter();
+                        for field in (0..5).filter(|field| ctrl & (1 << field) != 0) {
+                            packed.set(field, fields.next().copied());
                         }
                     }
                     else {
-                        ptn.tracks[chan].notes.push(Some(data[file_offset]));
-                        ptn.tracks[chan].instruments.push(Some(data[file_offset + 1]));
-                        ptn.tracks[chan].volumes.push(Some(data[file_offset + 2]));
-                        ptn.tracks[chan].fx_commands.push(Some(data[file_offset + 3]));
-                        ptn.tracks[chan].fx_params.push(Some(data[file_offset + 4]));
-                        file_offset += 5;
+                        for (field, value) in cell.iter().enumerate() {
+                            packed.set(field, Some(*value));
+                        }
                     }
-                } 
+                    trk.cells.push(packed);
+                    cells = &cells[cell_len..];
+                }
             }
+            ptn.original = Some(Original::new(ptn.header.clone(), data, &[]));
 
             Ok(ptn)
         }
@@ -354,25 +645,20 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row does not exist in the pattern.
-        pub fn bpm(&self, xm: &XModule, row: u8) -> Result<u8, XMParseError> {
+        pub fn bpm(&self, xm: &XModule, row: u16) -> Result<u8, XMParseError> {
 
             let mut bpm = xm.bpm();
             let mut row_val_detect = 0;
             for trk in &self.tracks {
-                for row_nr in row_val_detect..row + 1 {
-                    match trk.fx_command_raw(row_nr)? {
-                        Some(cmd) => {
-                            if cmd == 0xf {
-                                match trk.fx_param_raw(row_nr)? {
-                                    Some(param) => if param >= 0x20 {
-                                        bpm = param;
-                                        row_val_detect = row_nr;
-                                    },
-                                    None => (),
-                                };
+                let first_row = row_val_detect;
+                for row_nr in first_row..row + 1 {
+                    if let Some(0xf) = trk.fx_command_raw(row_nr)? {
+                        if let Some(param) = trk.fx_param_raw(row_nr)? {
+                            if param >= 0x20 {
+                                bpm = param;
+                                row_val_detect = row_nr;
                             }
-                        },
-                        None => (),
+                        }
                     }
                 }
             }
@@ -385,36 +671,50 @@ pub mod xmkit {
             self.tracks.len() as u8
         }
 
-        /// Returns the number of rows in the pattern. This value can be at most 256.
+        /// Returns the number of rows in the pattern. This value is between 1 and 256.
         pub fn len(&self) -> u16 {
             XModule::read_u16(&self.header, 5)
         }
 
+        /// Returns true if none of the pattern's cells contain any data.
+        /// Note that an empty pattern still has a length, usually 64 rows.
+        pub fn is_empty(&self) -> bool {
+            self.tracks.iter().all(|trk| {
+                trk.cells.iter().all(|cell| (0..5).all(|field| cell.get(field).unwrap_or(0) == 0))
+            })
+        }
+
         /// Returns the effective tempo setting on the given row.
         /// This function requires a reference to an XModule object, since it is not always possible to determine
         /// the correct value without this context.
         ///
+        /// F00 is interpreted according to QuirksMode::Generic, use tempo_with_quirks() to select a different behavior.
+        ///
         /// # Errors
         /// Returns an XMParseError if the given row does not exist in the pattern.
-        pub fn tempo(&self, xm: &XModule, row: u8) -> Result<u8, XMParseError> {
+        pub fn tempo(&self, xm: &XModule, row: u16) -> Result<u8, XMParseError> {
+            self.tempo_with_quirks(xm, row, QuirksMode::Generic)
+        }
+
+        /// Returns the effective tempo setting on the given row, interpreting F00 according to the given QuirksMode.
+        /// See tempo() for details.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the given row does not exist in the pattern.
+        pub fn tempo_with_quirks(&self, xm: &XModule, row: u16, quirks: QuirksMode) -> Result<u8, XMParseError> {
 
             let mut tempo = xm.tempo();
             let mut row_val_detect = 0;
             for trk in &self.tracks {
-                for row_nr in row_val_detect..row + 1 {
-                    match trk.fx_command_raw(row_nr)? {
-                        Some(cmd) => {
-                            if cmd == 0xf {
-                                match trk.fx_param_raw(row_nr)? {
-                                    Some(param) => if param < 0x20 {
-                                        tempo = param;
-                                        row_val_detect = row_nr;
-                                    },
-                                    None => (),
-                                };
+                let first_row = row_val_detect;
+                for row_nr in first_row..row + 1 {
+                    if let Some(0xf) = trk.fx_command_raw(row_nr)? {
+                        if let Some(param) = trk.fx_param_raw(row_nr)? {
+                            if param < 0x20 && (param > 0 || !quirks.speed_zero_stops()) {
+                                tempo = param;
+                                row_val_detect = row_nr;
                             }
-                        },
-                        None => (),
+                        }
                     }
                 }
             }
@@ -423,13 +723,9 @@ pub mod xmkit {
     }
 
 
-    #[derive(Default)]
+    #[derive(Clone, Default)]
     pub struct XMTrack {
-        notes: Vec<Option<u8>>,
-        instruments: Vec<Option<u8>>,
-        volumes: Vec<Option<u8>>,
-        fx_commands: Vec<Option<u8>>,
-        fx_params: Vec<Option<u8>>,
+        cells: Vec<PackedCell>,
     }
 
     impl XMTrack {
@@ -439,90 +735,33 @@ pub mod xmkit {
         /// To retrieve the raw effect command and parameter bytes, call fx_command_raw() and fx_param_raw() instead.
         /// To retrieve only volume effect commands, call volume_fx().
         ///
+        /// Effect memory is interpreted according to QuirksMode::Generic, use fx_with_quirks() to select a different behavior.
+        ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern, or if the given fx_command parameter is invalid.
-        pub fn fx(&self, fx_command: u8, row: u8) -> Result<u8, XMParseError> {
-            self.validate_row(&row)?;
-            let row = row as usize;
-            
-            let mut valid_fx: bool = false;
-            for fx in XM_EFFECTS.iter() {
-                if *fx == fx_command {
-                    valid_fx = true;
-                    break;
-                }
-            }
-            if !valid_fx {
-                return Err(XMParseError::new(&format!("Invalid fx command {} requested.", fx_command)));
-            }
+        pub fn fx(&self, fx_command: u8, row: u16) -> Result<u8, XMParseError> {
+            self.fx_with_quirks(fx_command, row, QuirksMode::Generic)
+        }
 
-            let mut fx_mem: bool = false;
-            for fx in XM_EFFECTS_WITH_MEMORY.iter() {
-                if *fx == fx_command {
-                    fx_mem = true;
-                    break;
-                }
-            }
+        /// Returns the currently effective parameter for the given effect command, interpreting effect memory
+        /// according to the given QuirksMode. See fx() for details.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the given row is greater than the length of the pattern, or if the given fx_command parameter is invalid.
+        pub fn fx_with_quirks(&self, fx_command: u8, row: u16, quirks: QuirksMode) -> Result<u8, XMParseError> {
+            self.validate_row(&row)?;
 
-            let mut param_default: u8 = 0;
-            if fx_command == XM_FX_E5X { param_default = 8; }
-            let mut param: u8 = 0;
+            let fx = match Effect::new(fx_command) {
+                Some(fx) => fx,
+                None => return Err(XMParseError::new(&format!("Invalid fx command {} requested.", fx_command))),
+            };
 
-            if fx_command <= XM_FX_TXX {
-                for r in 0..row + 1 {
-                    match self.notes[r] {
-                        Some(_) => param = param_default,
-                        None => (), 
-                    };
-                    match self.fx_commands[r] {
-                        Some(cmd) => {
-                            if cmd == fx_command {
-                                match self.fx_params[r] {
-                                    Some(p) => if p > 0 || !fx_mem { param = p; },
-                                    None => (),
-                                }
-                            }
-                            else if !fx_mem {
-                                param = param_default;
-                            }
-                        },
-                        None => if !fx_mem { param = param_default; },
-                    }
-                }
-            }
-            // have extended fx
-            else {
-                let mut cmd_hi = 0xe;
-                let mut cmd_lo = fx_command & 0xf;
-                if fx_command <= XM_FX_X2X {
-                    cmd_hi = 0x21;
-                    cmd_lo = (fx_command - 0x21) << 4;
-                }
-                for r in 0..row + 1 {
-                    match self.notes[r] {
-                        Some(_) => param = param_default,
-                        None => (),
-                    };
-                    match self.fx_commands[r] {
-                        Some(cmd) => {
-                            if cmd == cmd_hi {
-                                match self.fx_params[r] {
-                                    Some(p) => {
-                                        if p & 0xf0 == cmd_lo {
-                                            if p > 0 || !fx_mem { param = p & 0xf; }
-                                            else { param = param_default; }
-                                        }
-                                    },
-                                    None => (),
-                                }
-                            }
-                        },
-                        None => if !fx_mem { param = param_default; },
-                    }
-                }
+            let mut memory = ChannelEffectMemory::new(quirks);
+            for r in 0..=row {
+                memory.step_row(self, r)?;
             }
 
-            Ok(param)
+            Ok(memory.param(fx))
         }
 
         /// Returns the raw effect command data byte of the given row.
@@ -530,9 +769,9 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern.
-        pub fn fx_command_raw(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        pub fn fx_command_raw(&self, row: u16) -> Result<Option<u8>, XMParseError> {
             self.validate_row(&row)?;
-            Ok(self.fx_commands[row as usize])
+            Ok(self.cells[row as usize].fx_command())
         }
 
         /// Returns the raw effect parameter data byte of the given row.
@@ -541,38 +780,71 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern.
-        pub fn fx_param_raw(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        pub fn fx_param_raw(&self, row: u16) -> Result<Option<u8>, XMParseError> {
             self.validate_row(&row)?;
-            Ok(self.fx_params[row as usize])
+            Ok(self.cells[row as usize].fx_param())
         }
 
         /// Returns the instrument active on the given row. To retrieve the actual instrument data, use instrument_raw().
         /// If there is no note trigger on the given row, it will return the last used instrument.
         /// If no note was triggered in the pattern up to and including the given row, it will return 0.
+        /// Instrument numbers are interpreted according to QuirksMode::Generic, use instrument_with_quirks() to
+        /// select a different behavior.
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern.
-        pub fn instrument(&self, row: u8) -> Result<u8, XMParseError> {
+        pub fn instrument(&self, row: u16) -> Result<u8, XMParseError> {
             self.validate_row(&row)?;
 
             for current_row in (0..row + 1).rev() {
-                match self.instruments[current_row as usize] {
-                    Some(instr) => return Ok(instr),
-                    None => (),
-                };
+                if let Some(instr) = self.cells[current_row as usize].instrument() {
+                    return Ok(instr);
+                }
             }
 
             Ok(0)
         }
 
+        /// Returns the instrument that is playing on the given row, according to the given QuirksMode, or 0 if no
+        /// instrument is playing. The instrument count of the module is needed to tell invalid instrument numbers.
+        /// Unlike instrument(), this follows FT2 if the QuirksMode asks for it: an instrument number without a note,
+        /// or with a key-off or tone portamento, only resets the volume and envelopes of the instrument that is
+        /// playing, and a note with an instrument number beyond the instrument count is cut.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the given row is greater than the length of the pattern.
+        pub fn instrument_with_quirks(&self, row: u16, instrument_count: u16, quirks: QuirksMode)
+            -> Result<u8, XMParseError> {
+
+            if !quirks.instrument_needs_note() {
+                return self.instrument(row);
+            }
+            self.validate_row(&row)?;
+
+            let triggers = |cell: &PackedCell| {
+                let tone_porta = matches!(cell.fx_command(), Some(XM_FX_3XX) | Some(XM_FX_5XX))
+                    || cell.volume().unwrap_or(0) >= 0xf0;
+                cell.note().is_some_and(|note| (1..97).contains(&note)) && !tone_porta
+            };
+            let trigger_row = match self.cells[..=row as usize].iter().rposition(triggers) {
+                Some(trigger_row) => trigger_row,
+                None => return Ok(0),
+            };
+
+            // a note without an instrument number plays the last instrument set in the channel
+            let instr = self.cells[..=trigger_row].iter().rev()
+                .find_map(|cell| cell.instrument().filter(|instr| *instr > 0)).unwrap_or(0);
+            if instr as u16 > instrument_count && quirks.invalid_instrument_cuts() { Ok(0) } else { Ok(instr) }
+        }
+
         /// Returns the raw instrument data byte of the given row.
         /// To retrieve the instrument active on a given row instead, call instrument().
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern.
-        pub fn instrument_raw(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        pub fn instrument_raw(&self, row: u16) -> Result<Option<u8>, XMParseError> {
             self.validate_row(&row)?;
-            Ok(self.instruments[row as usize])
+            Ok(self.cells[row as usize].instrument())
         }
 
         /// Returns the note active on the given row. To retrieve the actual note data, use note_raw().
@@ -582,14 +854,13 @@ pub mod xmkit {
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the pattern.
         // TODO need to check for fx command K (key_off)
-        pub fn note(&self, row: u8) -> Result<u8, XMParseError> {
+        pub fn note(&self, row: u16) -> Result<u8, XMParseError> {
             self.validate_row(&row)?;
 
             for current_row in (0..row + 1).rev() {
-                match self.notes[current_row as usize] {
-                    Some(note) => return Ok(note),
-                    None => (),
-                };
+                if let Some(note) = self.cells[current_row as usize].note() {
+                    return Ok(note);
+                }
             }
 
             Ok(0)
@@ -600,16 +871,16 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the track.
-        pub fn note_raw(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        pub fn note_raw(&self, row: u16) -> Result<Option<u8>, XMParseError> {
             self.validate_row(&row)?;
-            Ok(self.notes[row as usize])
+            Ok(self.cells[row as usize].note())
         }
 
         /// Returns true if the given row contains a note trigger.
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the track.
-        pub fn note_trigger(&self, row: u8) -> Result<bool, XMParseError> {
+        pub fn note_trigger(&self, row: u16) -> Result<bool, XMParseError> {
             match self.note_raw(row)? {
                 Some(_) => Ok(true),
                 None => Ok(false),
@@ -620,10 +891,10 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the track.
-        pub fn trigger(&self, row: u8) -> Result<bool, XMParseError> {
+        pub fn trigger(&self, row: u16) -> Result<bool, XMParseError> {
             self.validate_row(&row)?;
 
-            match self.notes[row as usize] {
+            match self.cells[row as usize].note() {
                 Some(_) => Ok(true),
                 None => Ok(false),
             }
@@ -633,23 +904,36 @@ pub mod xmkit {
         /// It will only return the actual volume setting, adjusted to a range of 0..0x40.
         /// Volume column effects can be retrieved by calling volume_fx() or fx().
         /// The actual volume column byte can be retrieved by calling volume_raw().
+        /// Only the volume column is taken into account, use volume_with_quirks() to select a different behavior.
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the track.
-        pub fn volume(&self, row: u8) -> Result<u8, XMParseError> {
+        pub fn volume(&self, row: u16) -> Result<u8, XMParseError> {
+            self.volume_with_quirks(row, QuirksMode::Generic)
+        }
+
+        /// Returns the active volume setting on the current row, according to the given QuirksMode.
+        /// See volume() for details.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the given row is greater than the length of the track.
+        pub fn volume_with_quirks(&self, row: u16, quirks: QuirksMode) -> Result<u8, XMParseError> {
             self.validate_row(&row)?;
 
             for current_row in (0..row + 1).rev() {
-                
-                match self.volumes[current_row as usize] {
-                    Some(vol) => if vol >= 0x10 && vol <= 0x50 { return Ok(vol - 0x10); },
-                    None => (),
-                };
 
-                match self.notes[current_row as usize] {
-                    Some(_) => break,
-                    None => (),
-                };
+                // effects are processed after the volume column, so Cxx takes precedence
+                if quirks.set_volume_fx() && self.cells[current_row as usize].fx_command() == Some(XM_FX_CXX) {
+                    return Ok(self.cells[current_row as usize].fx_param().unwrap_or(0).min(0x40));
+                }
+
+                if let Some(vol) = self.cells[current_row as usize].volume() {
+                    if (0x10..=0x50).contains(&vol) { return Ok(vol - 0x10); }
+                }
+
+                if self.cells[current_row as usize].note().is_some() {
+                    break;
+                }
             }
 
             Ok(0x40)
@@ -661,16 +945,16 @@ pub mod xmkit {
         ///
         /// # Errors
         /// Returns an XMParseError if the given row is greater than the length of the track.
-        pub fn volume_raw(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        pub fn volume_raw(&self, row: u16) -> Result<Option<u8>, XMParseError> {
             self.validate_row(&row)?;
-            Ok(self.volumes[row as usize])
+            Ok(self.cells[row as usize].volume())
         }
 
-        fn validate_row(&self, _row: &u8) -> Result<bool, XMParseError> {
+        fn validate_row(&self, _row: &u16) -> Result<bool, XMParseError> {
             let row = *_row as usize;
 
-            if row >= self.notes.len() { 
-                return Err(XMParseError::new(&format!("Row {} does not exist in pattern, pattern length = {} rows.", row, self.notes.len())));
+            if row >= self.cells.len() { 
+                return Err(XMParseError::new(&format!("Row {} does not exist in pattern, pattern length = {} rows.", row, self.cells.len())));
             }
 
             Ok(true)
@@ -678,79 +962,132 @@ pub mod xmkit {
     }
 
 
-    #[derive(Default)]
+    #[derive(Clone)]
     pub struct XMInstrument {
         header: Vec<u8>,
         pub samples: Vec<XMSample>,
+        original: Option<Original>,
+    }
+
+    impl Default for XMInstrument {
+        fn default() -> XMInstrument {
+            XMInstrument::empty()
+        }
     }
 
     impl XMInstrument {
 
         /// Parses eXtended Module instrument data, and constructs an XMInstrument instance from it if the data is valid.
+        /// Instrument headers of instruments with samples that are shorter than the 263 bytes written by FT2 are
+        /// padded with zeros.
+        ///
+        /// # Errors
+        /// Returns an XMParseError if the instrument header, sample headers, or sample data are incomplete.
         pub fn parse(data: Vec<u8>) -> Result<XMInstrument, XMParseError> {
             let mut instr: XMInstrument = Default::default();
+            if data.len() < 29 {
+                return Err(XMParseError::new("XM Instrument header incomplete.").with_sizes(29, data.len()).at(0));
+            }
             let sample_count = data[27] as usize;
 
             if sample_count > 0 {
-                instr.header = data[..XModule::read_usize(&data, 0)].to_vec();
+                let header_size = XModule::read_usize(&data, 0);
+                if header_size < 29 || header_size + sample_count * XM_SAMPLE_HEADER_SIZE > data.len() {
+                    return Err(XMParseError::new("XM Instrument header incomplete.")
+                        .with_sizes(header_size.max(29) + sample_count * XM_SAMPLE_HEADER_SIZE, data.len()).at(0));
+                }
+
+                instr.header = data[..header_size].to_vec();
+                // short headers are padded, so that the envelope and vibrato settings can be accessed
+                if header_size < XM_INSTRUMENT_HEADER_SIZE {
+                    instr.header.resize(XM_INSTRUMENT_HEADER_SIZE, 0);
+                    XModule::write_usize(&mut instr.header, 0, XM_INSTRUMENT_HEADER_SIZE);
+                }
                 let mut instr_samples = Vec::with_capacity(sample_count);
-                let mut header_offset: usize = instr.header.len();
-                let mut data_offset: usize = header_offset + sample_count * 40;
+                let mut header_offset: usize = header_size;
+                let mut data_offset: usize = header_offset + sample_count * XM_SAMPLE_HEADER_SIZE;
                 
-                for _ in 0..sample_count {
+                for smp_nr in 0..sample_count {
+                    let sample_len = XModule::read_usize(&data, header_offset);
+                    if data_offset + sample_len > data.len() {
+                        let mut e = XMParseError::new("XM Sample data incomplete.")
+                            .with_sizes(sample_len, data.len().saturating_sub(data_offset)).at(data_offset);
+                        e.location = Some(ValidationLocation::Sample(0, smp_nr));
+                        return Err(e);
+                    }
                     instr_samples.push(XMSample{
-                        header: data[header_offset..(header_offset+40)].to_vec(),
-                        data: data[data_offset..data_offset + XModule::read_usize(&data, header_offset)].to_vec(),
+                        header: data[header_offset..(header_offset + XM_SAMPLE_HEADER_SIZE)].to_vec(),
+                        data: Arc::new(data[data_offset..data_offset + sample_len].to_vec()),
+                        pcm: OnceLock::new(),
                     });
 
-                    header_offset += 40;
-                    data_offset += XModule::read_usize(&data, header_offset);
+                    header_offset += XM_SAMPLE_HEADER_SIZE;
+                    data_offset += sample_len;
                 }
                 instr.samples = instr_samples;
             }
             else {
-                instr.header = data[..29].to_vec();
+                // the header size is not checked against the data, so it may point past the end; the size field is
+                // corrected, so that the instrument does not swallow the following data when it is written back
+                let header_size = XModule::read_usize(&data, 0).clamp(29, data.len());
+                instr.header = data[..header_size].to_vec();
+                XModule::write_usize(&mut instr.header, 0, header_size);
             }
+            // keep the headers as stored, sample data is tracked by reference
+            let stored = match instr.samples.len() {
+                0 => instr.header.clone(),
+                sample_count => data[..XModule::read_usize(&data, 0) + sample_count * XM_SAMPLE_HEADER_SIZE].to_vec(),
+            };
+            instr.original = Some(Original::new(instr.header.clone(), stored, &instr.samples));
 
             Ok(instr)
         }
 
         /// Returns the name of the instrument, or an empty string if the instrument is unnamed.
+        /// Use name_with_encoding() to decode names containing DOS (CP437) characters.
         pub fn name(&self) -> String {
             XModule::read_string(&self.header, 4, 22)
         }
 
-        /// Returns the points of the instrument's panning envelope, or None of the instrument has no samples,
-        /// or if there are no points in the envelope.
+        /// Returns the raw points of the instrument's panning envelope, or None of the instrument has no samples,
+        /// or if there are no points in the envelope. Each point takes up 4 bytes, containing the frame number and
+        /// the value as 16-bit little endian words. To retrieve the decoded points, call panning_envelope_points().
         pub fn panning_envelope(&self) -> Option<Vec<u8>> {
             if self.sample_count() == 0 || self.header[226] == 0 { None }
             else {
-                Some(self.header[177..(177 + (self.header[226] as usize))].to_vec())
+                Some(self.header[177..(177 + XMInstrument::envelope_len(self.header[226]))].to_vec())
             }
         }
 
-        /// Returns the volume loop start point; or None if the instrument has no samples, 
-        /// the volume envelope has no points, or volume envelope looping is inactive.
+        /// Returns the points of the instrument's panning envelope as (frame, value) pairs, or None if the
+        /// instrument has no samples, or if there are no points in the envelope.
+        /// At most 12 points are returned.
+        pub fn panning_envelope_points(&self) -> Option<Vec<(u16, u16)>> {
+            self.panning_envelope().map(|data| XMInstrument::envelope_points(&data))
+        }
+
+        /// Returns the panning loop start point; or None if the instrument has no samples, 
+        /// the panning envelope has no points, or panning envelope looping is inactive.
         pub fn panning_loop_start(&self) -> Option<u8> {
-            if self.sample_count() == 0 || self.header[225] == 0 || self.header[233] & XM_ENVELOPE_LOOP == 0 { None }
+            if self.sample_count() == 0 || self.header[226] == 0 || self.header[234] & XM_ENVELOPE_LOOP == 0 { None }
             else {
                 Some(self.header[231])
             }
         }
 
-        /// Returns the volume loop end point; or None if the instrument has no samples, 
-        /// the volume envelope has no points, or volume envelope looping is inactive.
+        /// Returns the panning loop end point; or None if the instrument has no samples, 
+        /// the panning envelope has no points, or panning envelope looping is inactive.
         pub fn panning_loop_end(&self) -> Option<u8> {
-            if self.sample_count() == 0 || self.header[225] == 0 || self.header[233] & XM_ENVELOPE_LOOP == 0 { None }
+            if self.sample_count() == 0 || self.header[226] == 0 || self.header[234] & XM_ENVELOPE_LOOP == 0 { None }
             else {
                 Some(self.header[232])
             }
         }
 
-        /// Returns the volume loop sustain point; or None if the instrument has no samples, 
-        /// or the volume envelope has no points.
+        /// Returns the panning sustain point; or None if the instrument has no samples, 
+        /// or the panning envelope has no points.
         pub fn panning_sustain(&self) -> Option<u8> {
-            if self.sample_count() == 0 || self.header[225] == 0 { None }
+            if self.sample_count() == 0 || self.header[226] == 0 { None }
             else {
                 Some(self.header[230])
             }
@@ -813,14 +1150,22 @@ pub mod xmkit {
             }
         }
 
-        /// Returns the points of the instrument's volume envelope, or None of the instrument has no samples,
-        /// or if there are no points in the envelope.
+        /// Returns the raw points of the instrument's volume envelope, or None of the instrument has no samples,
+        /// or if there are no points in the envelope. Each point takes up 4 bytes, containing the frame number and
+        /// the value as 16-bit little endian words. To retrieve the decoded points, call volume_envelope_points().
         pub fn volume_envelope(&self) -> Option<Vec<u8>> {
             if self.sample_count() == 0 || self.header[225] == 0 { None }
             else {
-                Some(self.header[129..(129 + (self.header[225] as usize))].to_vec())
+                Some(self.header[129..(129 + XMInstrument::envelope_len(self.header[225]))].to_vec())
             }
         }
+
+        /// Returns the points of the instrument's volume envelope as (frame, value) pairs, or None if the
+        /// instrument has no samples, or if there are no points in the envelope.
+        /// At most 12 points are returned.
+        pub fn volume_envelope_points(&self) -> Option<Vec<(u16, u16)>> {
+            self.volume_envelope().map(|data| XMInstrument::envelope_points(&data))
+        }
         
         /// Returns the volume fadeout setting, or None of the instrument has no samples.
         pub fn volume_fadeout(&self) -> Option<u16> {
@@ -866,78 +1211,69 @@ pub mod xmkit {
                 Some(self.header[233])
             }
         }
+
+        // Number of bytes used by an envelope with the given number of points. The header has room for 12 points.
+        fn envelope_len(point_count: u8) -> usize {
+            (point_count as usize).min(12) * 4
+        }
+
+        fn envelope_points(data: &[u8]) -> Vec<(u16, u16)> {
+            data.chunks(4).map(|point| (XModule::read_u16(point, 0), XModule::read_u16(point, 2))).collect()
+        }
     }
 
 
-    #[derive(Default)]
+    #[derive(Clone)]
     pub struct XMSample {
         header: Vec<u8>,
-        data: Vec<u8>,
+        // sample data is shared between clones, and only copied when modified
+        data: Arc<Vec<u8>>,
+        // decoded signed 16-bit PCM, filled on first access
+        pcm: OnceLock<Arc<Vec<i16>>>,
+    }
+
+    impl Default for XMSample {
+        fn default() -> XMSample {
+            XMSample { header: vec![0; XM_SAMPLE_HEADER_SIZE], data: Default::default(), pcm: OnceLock::new() }
+        }
     }
 
     impl XMSample {
         /// Returns true if the sample data has 16-bit resolution, false if it has 8-bit resolution.
         pub fn is_16bit(&self) -> bool {
-            if self.header[14] & 0x10 == 0 { false }
-            else { true }
+            self.header[14] & 0x10 != 0
         }
 
         /// Returns the sample data as signed 8-bit PCM.
         pub fn data_8bit_signed(&self) -> Vec<i8> {
-            let data_i16 = self.data_16bit_signed();
-            let mut data_i8: Vec<i8> = Vec::with_capacity(data_i16.len());
-            
-            for smp in data_i16 {
-                data_i8.push((smp >> 8) as i8);
-            }
-            
-            data_i8
+            self.pcm_16bit().iter().map(|smp| (smp >> 8) as i8).collect()
         }
 
         /// Returns the sample data as unsigned 8-bit PCM.
         pub fn data_8bit_unsigned(&self) -> Vec<u8> {
-            let data_i16 = self.data_16bit_signed();
-            let mut data_u8: Vec<u8> = Vec::with_capacity(data_i16.len());
-            
-            for smp in data_i16 {
-                data_u8.push((((smp as u16 >> 8) + 0x80) & 0xff) as u8);
-            }
-            
-            data_u8
+            self.pcm_16bit().iter().map(|smp| ((*smp as u16 >> 8) as u8).wrapping_add(0x80)).collect()
         }
 
         /// Returns the sample data as signed 16-bit PCM.
         pub fn data_16bit_signed(&self) -> Vec<i16> {
-            let step = if self.is_16bit() { 2 } else { 1 };
-            let mut data_i16: Vec<i16> = Vec::with_capacity(self.len() / step);
-            let mut pos = 0;
-            let mut smpval: i16 = 0;
-
-            while pos + step <= self.len() {
-                if self.is_16bit() {
-                    smpval = smpval.wrapping_add(XModule::read_u16(&self.data, pos) as i16);
-                }
-                else {
-                    smpval = smpval.wrapping_add((XModule::read_u16(&self.data, pos) as i16) << 8);
-                }
-                data_i16.push(smpval);
-                pos += step;
-            }
-
-            data_i16
+            self.pcm_16bit().to_vec()
         }
 
         /// Returns the sample data as unsigned 16-bit PCM.
         pub fn data_16bit_unsigned(&self) -> Vec<u16> {
-            let data_i16 = self.data_16bit_signed();
-            let mut data_u16: Vec<u16> = Vec::with_capacity(data_i16.len());
-            
-            for smp in data_i16 {
-                    // work-around to prevent the compiler from flagging 0x8000 literal being out of range
-                    data_u16.push(smp.wrapping_add(0x7fffi16.wrapping_add(1)) as u16);
-            }
+            self.pcm_16bit().iter().map(|smp| (*smp as u16).wrapping_add(0x8000)).collect()
+        }
 
-            data_u16
+        /// Returns the sample data as signed 16-bit PCM, without copying. The data is decoded on first access only,
+        /// so this is the preferred way of accessing sample data repeatedly, eg. while rendering.
+        pub fn pcm_16bit(&self) -> &[i16] {
+            self.pcm.get_or_init(|| Arc::new(self.decoder().collect()))
+        }
+
+        // Returns the sample data for modification, copying it if it is shared with other clones.
+        fn data_mut(&mut self) -> &mut Vec<u8> {
+            self.pcm = OnceLock::new();
+            Arc::make_mut(&mut self.data)
         }
 
         /// Returns the sample data in XM's native delta format.
@@ -956,6 +1292,11 @@ pub mod xmkit {
             XModule::read_usize(&self.header, 0)
         }
 
+        /// Returns true if the sample contains no data.
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
         /// Returns the loop length setting.
         pub fn loop_len(&self) -> usize {
             XModule::read_usize(&self.header, 8)
@@ -969,12 +1310,15 @@ pub mod xmkit {
         /// Returns the loop type used by the sample.
         /// This will evaluate to one of XM_SAMPLE_LOOP_NONE, XM_SAMPLE_LOOP_FORWARD, or XM_SAMPLE_LOOP_PINGPONG.
         pub fn loop_type(&self) -> u8 {
-            if self.header[14] & 1 != 0 { XM_SAMPLE_LOOP_NONE }
-            else if self.header[14] & 2 != 0 { XM_SAMPLE_LOOP_FORWARD }
-            else { XM_SAMPLE_LOOP_PINGPONG }
+            match self.header[14] & 3 {
+                0 => XM_SAMPLE_LOOP_NONE,
+                1 => XM_SAMPLE_LOOP_FORWARD,
+                _ => XM_SAMPLE_LOOP_PINGPONG,
+            }
         }
 
         /// Returns the name of the sample.
+        /// Use name_with_encoding() to decode names containing DOS (CP437) characters.
         pub fn name(&self) -> String {
             XModule::read_string(&self.header, 18, 22)
         }
@@ -999,17 +1343,80 @@ pub mod xmkit {
     #[derive(Default, Debug)]
     pub struct XMParseError {
         why: String,
+        offset: Option<usize>,
+        location: Option<ValidationLocation>,
+        sizes: Option<(usize, usize)>,
     }
 
     impl XMParseError {
         fn new(reason: &str) -> XMParseError {
-            XMParseError{why: reason.to_string()}
+            XMParseError{why: reason.to_string(), ..Default::default()}
+        }
+
+        /// Returns the offset in the parsed data at which the error occured, if known.
+        /// For errors returned by XModule::parse(), this is the file offset.
+        pub fn offset(&self) -> Option<usize> {
+            self.offset
+        }
+
+        /// Returns the structure that was being parsed when the error occured, if known.
+        pub fn location(&self) -> Option<ValidationLocation> {
+            self.location
+        }
+
+        /// Returns the number of bytes that were required to parse the structure, if the error was caused by
+        /// missing data.
+        pub fn expected_size(&self) -> Option<usize> {
+            self.sizes.map(|(expected, _)| expected)
+        }
+
+        /// Returns the number of bytes that were available, if the error was caused by missing data.
+        pub fn actual_size(&self) -> Option<usize> {
+            self.sizes.map(|(_, actual)| actual)
+        }
+
+        // Sets the offset at which the error occured, relative to the beginning of the parsed data.
+        fn at(mut self, offset: usize) -> XMParseError {
+            self.offset = Some(offset);
+            self
+        }
+
+        fn with_sizes(mut self, expected: usize, actual: usize) -> XMParseError {
+            self.sizes = Some((expected, actual));
+            self
+        }
+
+        // Places an error returned while parsing a structure at the given base offset into the enclosing data.
+        // Sample locations reported by XMInstrument::parse() are assigned to the given instrument.
+        fn in_context(mut self, location: ValidationLocation, base_offset: usize) -> XMParseError {
+            self.offset = Some(base_offset + self.offset.unwrap_or(0));
+            self.location = match (self.location, location) {
+                (Some(ValidationLocation::Sample(_, smp)), ValidationLocation::Instrument(instr)) =>
+                    Some(ValidationLocation::Sample(instr, smp)),
+                (None, location) => Some(location),
+                (inner, _) => inner,
+            };
+            self
         }
     }
 
     impl fmt::Display for XMParseError {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(f, "{}", self.why)
+            write!(f, "{}", self.why)?;
+            if let Some(location) = self.location {
+                write!(f, " In {}", location)?;
+                if let Some(offset) = self.offset {
+                    write!(f, " at offset 0x{:x}", offset)?;
+                }
+                write!(f, ".")?;
+            }
+            else if let Some(offset) = self.offset {
+                write!(f, " At offset 0x{:x}.", offset)?;
+            }
+            if let Some((expected, actual)) = self.sizes {
+                write!(f, " Expected {} bytes, found {}.", expected, actual)?;
+            }
+            Ok(())
         }
     }
 
@@ -1029,11 +1436,10 @@ pub mod xmkit {
 #[test]
 fn test_all() {
     use std::path::Path;
-    use std::error::Error;
     use xmkit;
 
-    let xm = match xmkit::XModule::parse_file(&Path::new("test.xm")) {
-        Err(e) => panic!("{}", e.description()),
+    let xm = match xmkit::XModule::parse_file(Path::new("test.xm")) {
+        Err(e) => panic!("{}", e),
         Ok(xm) => xm,
     };
 
@@ -1049,11 +1455,9 @@ fn test_all() {
     println!("Tempo: {}", xm.tempo());
 
     println!("Sequence:");
-    let mut pos = 0;
-    for it in &xm.sequence() {
+    for (pos, it) in xm.sequence().iter().enumerate() {
         // should be able to use {:02#x} as format!, but it's broken
         println!("0x{:02x}:\t0x{:02x}", pos, it);
-        pos = pos + 1;
     }
 
     println!("Pattern 0 is used: {}", xm.pattern_used(0));
@@ -1076,7 +1480,135 @@ fn test_all() {
                 print!("{},", sn);
             }
         
-            println!("");
+            println!();
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_shared_module() {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Clone + Send + Sync>() {}
+    assert_send_sync::<xmkit::XModule>();
+
+    let xm = xmkit::XModule::parse_file(Path::new("test.xm")).unwrap();
+    let pcm = xm.instruments[1].samples[1].pcm_16bit().as_ptr();
+    let copy = xm.clone();
+    assert_eq!(copy.instruments[1].samples[1].pcm_16bit().as_ptr(), pcm);
+
+    let xm = Arc::new(xm);
+    let workers: Vec<_> = (0..4).map(|_| {
+        let xm = xm.clone();
+        thread::spawn(move || xm.instruments[1].samples[1].data_16bit_signed())
+    }).collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), copy.instruments[1].samples[1].data_16bit_signed());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_error_context() {
+    use std::fs;
+    use xmkit::{XModule, ValidationLocation};
+
+    let data = fs::read("test.xm").unwrap();
+    let xm = XModule::parse(data.clone()).unwrap();
+    let header_len = 60 + 276;
+    let ptn0_len = xm.patterns[0].to_bytes(xmkit::PatternPacking::Canonical).len();
+
+    let e = XModule::parse(data[..50].to_vec()).unwrap_err();
+    assert_eq!(e.location(), Some(ValidationLocation::Module));
+    assert_eq!((e.expected_size(), e.actual_size()), (Some(64), Some(50)));
+
+    let e = XModule::parse(data[..header_len + ptn0_len + 12].to_vec()).unwrap_err();
+    assert_eq!(e.location(), Some(ValidationLocation::Pattern(1)));
+    assert_eq!(e.offset(), Some(header_len + ptn0_len));
+    assert_eq!(e.actual_size(), Some(12));
+    assert!(e.to_string().contains(&format!("In pattern 1 at offset 0x{:x}.", header_len + ptn0_len)));
+
+    let e = XModule::parse(data[..data.len() - 10].to_vec()).unwrap_err();
+    assert_eq!(e.location(), Some(ValidationLocation::Sample(1, 1)));
+    assert_eq!((e.expected_size(), e.actual_size()), (Some(64), Some(54)));
+    assert_eq!(e.offset(), Some(data.len() - 64));
+}
+
+#[cfg(test)]
+#[test]
+fn test_malformed_input() {
+    use std::fs;
+    use xmkit::{XModule, XMPattern, XMInstrument, XMSample, PatternPacking};
+
+    let data = fs::read("test.xm").unwrap();
+
+    // header without room for the sequence, and a sequence longer than the header
+    let mut short = data[..0x50].to_vec();
+    short[0x3c..0x40].copy_from_slice(&20u32.to_le_bytes());
+    short[0x40] = 10;
+    short[0x46] = 0;
+    short[0x48] = 0;
+    let xm = XModule::parse(short).unwrap();
+    assert_eq!(xm.sequence(), vec![0; 10]);
+    assert_eq!((xm.tempo(), xm.bpm()), (data[0x4c], data[0x4e]));
+    assert_eq!(XModule::parse(xm.to_bytes()).unwrap().sequence(), vec![0; 10]);
+
+    let mut long = data.clone();
+    long[0x40..0x42].copy_from_slice(&300u16.to_le_bytes());
+    assert_eq!(XModule::parse(long).unwrap().sequence().len(), 256);
+
+    // pattern header shorter than its fixed fields
+    let mut ptn = vec![5, 0, 0, 0, 0, 64, 0, 0, 0];
+    assert!(XMPattern::parse(ptn.clone(), 4).is_err());
+    ptn[0] = 9;
+    assert_eq!(XMPattern::parse(ptn.clone(), 4).unwrap().len(), 64);
+
+    // patterns with 0 rows are empty 64-row patterns, and are written back as such
+    ptn[5] = 0;
+    let empty = XMPattern::parse(ptn.clone(), 4).unwrap();
+    assert_eq!(empty.len(), 64);
+    assert_eq!(empty.tracks[3].note_raw(63).unwrap(), None);
+    assert!(empty.is_empty() && !empty.is_modified());
+    assert_eq!(empty.to_bytes(PatternPacking::Canonical), [9, 0, 0, 0, 0, 64, 0, 0, 0]);
+    let with_data = [&ptn[..7], &[2, 0, 0x80, 0x80]].concat();
+    assert!(XMPattern::parse(with_data, 4).unwrap().is_empty());
+    ptn[5..7].copy_from_slice(&257u16.to_le_bytes());
+    assert!(XMPattern::parse(ptn, 4).is_err());
+
+    // instrument header that ends before the envelope settings
+    let instr = XModule::parse(data.clone()).unwrap().instruments[0].to_bytes();
+    let header_size = u32::from_le_bytes([instr[0], instr[1], instr[2], instr[3]]) as usize;
+    let mut short = instr[..33].to_vec();
+    short[..4].copy_from_slice(&33u32.to_le_bytes());
+    short.extend(&instr[header_size..]);
+    let parsed = XMInstrument::parse(short.clone()).unwrap();
+    assert_eq!(parsed.sample_count(), 1);
+    assert_eq!((parsed.volume_type(), parsed.vibrato_rate(), parsed.volume_envelope()), (Some(0), Some(0), None));
+    assert_eq!(parsed.samples[0].data_native(), XMInstrument::parse(instr).unwrap().samples[0].data_native());
+    assert!(XMInstrument::parse(parsed.to_bytes()).is_ok());
+    short[0] = 20;
+    assert!(XMInstrument::parse(short).is_err());
+
+    // instrument without samples whose header size points past the end of the data, followed by another one
+    let instruments_len: usize = XModule::parse(data.clone()).unwrap().instruments.iter()
+        .map(|instr| instr.to_bytes().len()).sum();
+    let mut past_end = data[..data.len() - instruments_len].to_vec();
+    past_end[0x48..0x4a].copy_from_slice(&2u16.to_le_bytes());
+    for _ in 0..2 {
+        let mut instr = vec![0u8; 29];
+        instr[..4].copy_from_slice(&0x10_0000u32.to_le_bytes());
+        past_end.extend(instr);
+    }
+    assert!(XModule::parse(past_end.clone()).is_err());
+    let (xm, warnings) = XModule::parse_lenient(past_end).unwrap();
+    assert_eq!((xm.instruments.len(), warnings.len()), (2, 1));
+
+    assert_eq!(XMInstrument::default().sample_count(), 0);
+    assert_eq!(XMInstrument::default().sample_numbers(), None);
+    let smp = XMSample::default();
+    assert_eq!((smp.volume(), smp.panning(), smp.finetune(), smp.len()), (0, 0, 0, 0));
+    assert!(smp.pcm_16bit().is_empty());
+}
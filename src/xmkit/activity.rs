@@ -0,0 +1,148 @@
+use super::{XModule, XMTrack, XM_FX_CXX, XM_FX_KXX};
+
+/// Summarizes how busy a channel is over the course of the song.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelUsage {
+    /// The number of rows played in the song.
+    pub total_rows: usize,
+    /// The number of played rows on which the channel is audible.
+    pub active_rows: usize,
+    /// The number of notes triggered on the channel.
+    pub notes: usize,
+}
+
+impl ChannelUsage {
+    /// Returns the fraction of played rows on which the channel is audible, between 0.0 and 1.0.
+    pub fn ratio(&self) -> f64 {
+        if self.total_rows == 0 {
+            0.0
+        }
+        else {
+            self.active_rows as f64 / self.total_rows as f64
+        }
+    }
+}
+
+
+impl XMTrack {
+
+    /// Returns for each row of the track whether the channel is audibly playing.
+    /// A channel starts playing when a note is triggered, and stops when the note is cut (ECx, or a volume of 0 set
+    /// through the volume column or Cxx) or released (key off, Kxx). Rows containing a cut or release on a later tick
+    /// count as playing. Volume slides and envelopes are not taken into account. Notes carried over from a previous
+    /// pattern are not detected, so the channel is considered silent until the first note trigger in the track.
+    pub fn activity(&self) -> Vec<bool> {
+        self.occupancy(true)
+    }
+
+    // Determines which rows of the track are occupied by a note. If audible_only is false, notes are considered to
+    // be held until they are cut or released, regardless of their volume.
+    pub(crate) fn occupancy(&self, audible_only: bool) -> Vec<bool> {
+        let mut result = Vec::with_capacity(self.cells.len());
+        let mut held = false;
+        let mut audible = true;
+
+        for row in 0..self.cells.len() {
+            let cmd = self.cells[row].fx_command().unwrap_or(0);
+            let param = self.cells[row].fx_param().unwrap_or(0);
+
+            match self.cells[row].note() {
+                Some(97) => held = false,
+                Some(note) if note > 0 && note < 97 => {
+                    held = true;
+                    audible = true;
+                },
+                _ => if self.cells[row].instrument().unwrap_or(0) > 0 { audible = true; },
+            }
+
+            match self.cells[row].volume() {
+                Some(vol) if (0x10..=0x50).contains(&vol) => audible = vol > 0x10,
+                _ => (),
+            }
+            if cmd == XM_FX_CXX {
+                audible = param > 0;
+            }
+
+            let mut playing = held && (audible || !audible_only);
+
+            match cmd {
+                XM_FX_KXX => {
+                    playing = playing && param > 0;
+                    held = false;
+                },
+                0xe if param >> 4 == 0xc => {
+                    playing = playing && param & 0xf > 0;
+                    held = false;
+                },
+                _ => (),
+            }
+
+            result.push(playing);
+        }
+
+        result
+    }
+}
+
+
+impl XModule {
+
+    /// Returns a summary of how busy each channel is, based on the rows played in the song's sequence
+    /// (see play_rows()) and the activity of the channel's tracks (see XMTrack::activity()).
+    pub fn channel_usage(&self) -> Vec<ChannelUsage> {
+        let mut usage = vec![ChannelUsage::default(); self.channel_count() as usize];
+        let activity: Vec<Vec<Vec<bool>>> = self.patterns.iter()
+            .map(|ptn| ptn.tracks.iter().map(|trk| trk.activity()).collect())
+            .collect();
+
+        for play_row in self.play_rows() {
+            let ptn = match self.patterns.get(play_row.pattern as usize) {
+                Some(ptn) => ptn,
+                None => {
+                    for chan in usage.iter_mut() {
+                        chan.total_rows += 1;
+                    }
+                    continue;
+                },
+            };
+
+            for (chan, trk) in ptn.tracks.iter().enumerate().take(usage.len()) {
+                let row = play_row.row as usize;
+                usage[chan].total_rows += 1;
+                if activity[play_row.pattern as usize][chan][row] {
+                    usage[chan].active_rows += 1;
+                }
+                if let Some(note) = trk.cells[row].note() {
+                    if note > 0 && note < 97 {
+                        usage[chan].notes += 1;
+                    }
+                }
+            }
+        }
+
+        usage
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_channel_activity() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let activity = xm.patterns[0].tracks[0].activity();
+    assert_eq!(activity.len(), 16);
+    assert!(activity[..12].iter().all(|playing| *playing));
+    assert!(activity[12..].iter().all(|playing| !*playing));
+    assert!(xm.patterns[0].tracks[3].activity().iter().all(|playing| !*playing));
+
+    // the note on channel 1 is cut on tick 2 of row 11
+    let activity = xm.patterns[0].tracks[1].activity();
+    assert!(activity[11] && !activity[12]);
+
+    let usage = xm.channel_usage();
+    assert_eq!(usage.len(), 4);
+    assert_eq!(usage[3], ChannelUsage { total_rows: usage[0].total_rows, active_rows: 0, notes: 0 });
+    assert!(usage[0].ratio() > usage[1].ratio());
+}
@@ -0,0 +1,180 @@
+//! Finding samples and instruments that are shared between modules.
+//!
+//! Large module collections often contain the same samples many times, because they come from the same sample pack,
+//! or because an artist reused their own instruments. Identical samples and instruments are found by hashing their
+//! data, so collections can be cross-referenced without comparing every pair of modules.
+
+use std::collections::{BTreeSet, HashMap};
+use super::{XModule, XMInstrument, XM_INSTRUMENT_HEADER_SIZE};
+use super::bank::data_hash;
+
+/// A sample of a module passed to cross_reference(). All numbers are counted from 0, and the module number is the
+/// index in the list of modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SampleRef {
+    /// The module number.
+    pub module: usize,
+    /// The instrument number within the module.
+    pub instrument: usize,
+    /// The sample number within the instrument.
+    pub sample: usize,
+}
+
+/// An instrument of a module passed to cross_reference(). All numbers are counted from 0, and the module number is
+/// the index in the list of modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrumentRef {
+    /// The module number.
+    pub module: usize,
+    /// The instrument number within the module.
+    pub instrument: usize,
+}
+
+/// Sample data that occurs in more than one module. Samples count as identical if their data is identical, regardless
+/// of their names, loops, and other settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedSample {
+    /// The hash of the sample data.
+    pub hash: u64,
+    /// The size of the sample data, in bytes.
+    pub len: usize,
+    /// Every occurrence of the sample, ordered by module, instrument, and sample number.
+    pub occurrences: Vec<SampleRef>,
+}
+
+/// An instrument that occurs in more than one module. Instruments count as identical if all their settings, the
+/// settings of their samples, and the sample data are identical. Instrument and sample names are not compared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedInstrument {
+    /// The hash of the instrument and its samples.
+    pub hash: u64,
+    /// Every occurrence of the instrument, ordered by module and instrument number.
+    pub occurrences: Vec<InstrumentRef>,
+}
+
+/// The samples and instruments shared between modules, as returned by cross_reference().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CrossReference {
+    /// The shared samples, ordered by their first occurrence.
+    pub samples: Vec<SharedSample>,
+    /// The shared instruments, ordered by their first occurrence.
+    pub instruments: Vec<SharedInstrument>,
+}
+
+impl CrossReference {
+
+    /// Returns the numbers of the modules that share at least one sample with the given module, in ascending order.
+    pub fn modules_sharing_with(&self, module: usize) -> Vec<usize> {
+        let mut modules = BTreeSet::new();
+        for shared in self.samples.iter().filter(|shared| shared.occurrences.iter().any(|smp| smp.module == module)) {
+            modules.extend(shared.occurrences.iter().map(|smp| smp.module).filter(|other| *other != module));
+        }
+        modules.into_iter().collect()
+    }
+}
+
+
+/// Finds the samples and instruments that occur in more than one of the given modules. Occurrences within the same
+/// module are listed as well, as long as the sample or instrument also occurs in another module. Empty samples and
+/// instruments without samples are ignored. Hashes are computed with the standard library's default hasher, so they
+/// are only meant to be compared within one version of xmkit.
+pub fn cross_reference(modules: &[XModule]) -> CrossReference {
+    let mut samples = Groups::new();
+    let mut instruments = Groups::new();
+
+    for (module, xm) in modules.iter().enumerate() {
+        for (instrument, instr) in xm.instruments.iter().enumerate() {
+            for (sample, smp) in instr.samples.iter().enumerate().filter(|(_, smp)| !smp.is_empty()) {
+                samples.add(smp.data.to_vec(), SampleRef { module, instrument, sample });
+            }
+            if !instr.samples.is_empty() {
+                instruments.add(fingerprint(instr), InstrumentRef { module, instrument });
+            }
+        }
+    }
+
+    CrossReference {
+        samples: samples.shared(|smp| smp.module).map(|(hash, data, occurrences)| SharedSample {
+            hash, len: data.len(), occurrences
+        }).collect(),
+        instruments: instruments.shared(|instr| instr.module).map(|(hash, _, occurrences)| SharedInstrument {
+            hash, occurrences
+        }).collect(),
+    }
+}
+
+// Returns the bytes that identify an instrument: the instrument and sample headers without names and sizes, and
+// the hashes of the sample data.
+fn fingerprint(instr: &XMInstrument) -> Vec<u8> {
+    let mut bytes = instr.header[26..instr.header.len().min(XM_INSTRUMENT_HEADER_SIZE)].to_vec();
+    for smp in &instr.samples {
+        bytes.extend(&smp.header[4..17]);
+        bytes.extend(data_hash(&smp.data).to_le_bytes());
+    }
+    bytes
+}
+
+// Identical items, grouped by the hash of their data. Items with the same hash are compared, so hash collisions
+// do not merge different items.
+struct Groups<R> {
+    groups: Vec<(u64, Vec<u8>, Vec<R>)>,
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl<R: Copy> Groups<R> {
+    fn new() -> Groups<R> {
+        Groups { groups: Vec::new(), index: HashMap::new() }
+    }
+
+    fn add(&mut self, data: Vec<u8>, item: R) {
+        let hash = data_hash(&data);
+        let groups = &mut self.groups;
+        let candidates = self.index.entry(hash).or_default();
+        match candidates.iter().find(|&&group| groups[group].1 == data) {
+            Some(&group) => groups[group].2.push(item),
+            None => {
+                candidates.push(groups.len());
+                groups.push((hash, data, vec![item]));
+            },
+        }
+    }
+
+    // Returns the groups whose items come from more than one module.
+    fn shared<F: Fn(&R) -> usize>(self, module: F) -> impl Iterator<Item = (u64, Vec<u8>, Vec<R>)> {
+        self.groups.into_iter()
+            .filter(move |(_, _, items)| items.iter().any(|item| module(item) != module(&items[0])))
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_cross_reference() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut renamed = xm.clone();
+    renamed.instruments[1].set_name("renamed");
+    renamed.instruments[0].samples[0].normalize(0.5);
+    let mut unrelated = xm.clone();
+    for instr in &mut unrelated.instruments {
+        for smp in &mut instr.samples {
+            smp.normalize(0.25);
+        }
+    }
+
+    let refs = cross_reference(&[xm.clone(), unrelated, renamed]);
+    let samples: Vec<Vec<SampleRef>> = refs.samples.iter().map(|shared| shared.occurrences.clone()).collect();
+    let smp = |module: usize, instrument: usize, sample: usize| SampleRef { module, instrument, sample };
+    assert_eq!(samples, vec![vec![smp(0, 1, 0), smp(2, 1, 0)], vec![smp(0, 1, 1), smp(2, 1, 1)]]);
+    assert_eq!(refs.samples[1].len, xm.instruments[1].samples[1].len());
+    assert_eq!(refs.instruments.len(), 1);
+    assert_eq!(refs.instruments[0].occurrences, vec![InstrumentRef { module: 0, instrument: 1 },
+        InstrumentRef { module: 2, instrument: 1 }]);
+    assert_eq!(refs.modules_sharing_with(2), vec![0]);
+    assert!(refs.modules_sharing_with(1).is_empty());
+
+    // duplicates within a single module are not shared
+    assert_eq!(cross_reference(std::slice::from_ref(&xm)), CrossReference::default());
+    assert_eq!(cross_reference(&[xm.clone(), xm]).instruments.len(), 2);
+}
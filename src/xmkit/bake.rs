@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use super::{AutoVibrato, Envelope, TickEventKind, XModule, XMInstrument, XM_ENVELOPE_ON, XM_FX_4XX, XM_FX_CXX,
+    XM_FX_KXX};
+use super::render::{envelope_value, next_envelope_tick};
+use super::state::ChannelSim;
+
+// The volume envelope of the note playing on a channel.
+#[derive(Clone, Copy)]
+struct NoteEnvelope<'a> {
+    instrument: &'a XMInstrument,
+    tick: u16,
+    fadeout: u32,
+}
+
+impl<'a> NoteEnvelope<'a> {
+
+    // Returns the envelope of a note triggered with the given instrument, or None if it has no volume envelope.
+    fn trigger(instrument: Option<&'a XMInstrument>) -> Option<NoteEnvelope<'a>> {
+        instrument.filter(|instr| instr.volume_type().unwrap_or(0) & XM_ENVELOPE_ON != 0)
+            .map(|instrument| NoteEnvelope { instrument, tick: 0, fadeout: 0x10000 })
+    }
+
+    // Advances the envelope and the fadeout by one tick, and returns the resulting volume factor (0.0..1.0).
+    fn tick(&mut self, key_off: bool) -> f64 {
+        let instr = self.instrument;
+        self.tick = next_envelope_tick(self.tick, instr.volume_envelope_points(), instr.volume_type().unwrap_or(0),
+            instr.volume_sustain(), instr.volume_loop_start().zip(instr.volume_loop_end()), key_off);
+        if key_off {
+            self.fadeout = self.fadeout.saturating_sub(instr.volume_fadeout().unwrap_or(0) as u32 * 2);
+        }
+        envelope_value(instr.volume_envelope_points(), instr.volume_type(), self.tick).unwrap_or(64.0) / 64.0
+            * self.fadeout as f64 / 65536.0
+    }
+}
+
+
+impl XModule {
+
+    /// Replaces the autovibrato of all instruments with vibrato effects (4xy) in the patterns, for replayers that do
+    /// not support instrument vibrato. The song is played through in sequence order (see play_rows()), running the
+    /// autovibrato of the instrument playing on each channel tick by tick, including its sweep and the effect of
+    /// note delays and key offs (see XMTrack::tick_events()). Each row on which the autovibrato is active gets a 4xy
+    /// whose speed follows the autovibrato rate and whose depth follows the peak offset reached on the row, so sweeps
+    /// become a series of growing depths. Rows are only changed where the effect column is empty and the volume
+    /// column holds no vibrato (Sx, Vx). Rows that are played more than once take the values of their first pass.
+    /// The vibrato uses the waveform of the channel, usually a sine, whatever the autovibrato type. Afterwards, the
+    /// autovibrato depth of all instruments is set to 0. Returns the number of cells changed.
+    pub fn bake_autovibrato(&mut self) -> usize {
+        let channels = self.patterns.iter().map(|ptn| ptn.tracks.len()).max().unwrap_or(0);
+        // the autovibrato running on each channel, the speed of its 4xy, and whether the note is released
+        let mut running: Vec<Option<(AutoVibrato, u8, bool)>> = vec![None; channels];
+        let mut current: Vec<u8> = vec![0; channels];
+        let mut changed = 0;
+
+        let play_rows = self.play_rows();
+        let instruments = &self.instruments;
+        for play_row in play_rows {
+            let ptn = match self.patterns.get_mut(play_row.pattern as usize) {
+                Some(ptn) => ptn,
+                None => continue,
+            };
+            for (chn, trk) in ptn.tracks.iter_mut().enumerate() {
+                let events = trk.tick_events(play_row.row, play_row.tempo).unwrap_or_default();
+                let mut peak: u16 = 0;
+                for tick in 0..play_row.ticks {
+                    for event in events.iter().filter(|event| event.tick as u16 == tick) {
+                        match event.kind {
+                            TickEventKind::NoteOn { instrument, .. } => {
+                                current[chn] = instrument.unwrap_or(current[chn]);
+                                running[chn] = current[chn].checked_sub(1)
+                                    .and_then(|instr| instruments.get(instr as usize))
+                                    .filter(|instr| instr.vibrato_depth().unwrap_or(0) > 0)
+                                    .map(|instr| (AutoVibrato::for_instrument(instr),
+                                        ((instr.vibrato_rate().unwrap_or(0) + 2) / 4).clamp(1, 15), false));
+                            },
+                            TickEventKind::KeyOff => if let Some(auto) = running[chn].as_mut() {
+                                auto.2 = true;
+                            },
+                            TickEventKind::NoteCut => running[chn] = None,
+                            TickEventKind::Retrigger { .. } => (),
+                        }
+                    }
+                    if let Some((auto, _, key_off)) = running[chn].as_mut() {
+                        peak = peak.max(auto.tick(*key_off).unsigned_abs());
+                    }
+                }
+
+                // 4xy reaches 255 * y / 32 period units at its peak
+                let depth = ((peak as u32 * 32 + 127) / 255).min(15) as u8;
+                let cell = &mut trk.cells[play_row.row as usize];
+                let free = cell.fx_command().unwrap_or(0) == 0 && cell.fx_param().unwrap_or(0) == 0
+                    && !cell.volume().is_some_and(|vol| (0xa0..0xc0).contains(&vol));
+                if let (Some((_, speed, _)), true, true) = (running[chn], depth > 0, free) {
+                    cell.set_fx_command(Some(XM_FX_4XX));
+                    cell.set_fx_param(Some(speed << 4 | depth));
+                    changed += 1;
+                }
+            }
+        }
+
+        for instr in self.instruments.iter_mut().filter(|instr| instr.vibrato_depth().unwrap_or(0) > 0) {
+            instr.header[237] = 0;
+        }
+        changed
+    }
+
+    /// Replaces the volume envelopes of all instruments with volume column values in the patterns, for replayers that
+    /// do not support envelopes. The song is played through in sequence order (see play_rows()), simulating each
+    /// channel tick by tick, and each row on which a note with a volume envelope plays gets the channel volume scaled
+    /// by the envelope and fadeout, averaged over the ticks of the row. To keep the patterns sparse, a value is only
+    /// written if it differs from the last one written on the channel by at least `resolution` (1..64), or if the row
+    /// triggers a note, sets a volume itself, or silences the note. Set volume effects (Cxx) take the baked value
+    /// instead of the volume column. Since FT2 cuts notes on key off when there is no volume envelope, key offs on rows
+    /// that are baked are removed, and the release is carried by the volume column as well. Rows are only changed where
+    /// the volume column is empty or sets a volume, and rows that are played more than once take the values of their
+    /// first pass. Volume slides stay in the patterns and act on the baked values. Afterwards, the volume envelopes of
+    /// all instruments are switched off. Returns the number of cells changed.
+    pub fn bake_volume_envelopes(&mut self, resolution: u8) -> usize {
+        let resolution = resolution.max(1);
+        let channels = self.patterns.iter().map(|ptn| ptn.tracks.len()).max().unwrap_or(0);
+        let mut sims = vec![ChannelSim::new(); channels];
+        let mut envelopes: Vec<Option<NoteEnvelope>> = vec![None; channels];
+        // the last value written on each channel
+        let mut written: Vec<Option<u8>> = vec![None; channels];
+        let mut visited: HashSet<(u8, u16, usize)> = HashSet::new();
+        // the cells to change: pattern, row, channel, baked volume, and whether the row releases the note
+        let mut changes: Vec<(usize, usize, usize, u8, bool)> = Vec::new();
+
+        for play_row in self.play_rows() {
+            let ptn = match self.patterns.get(play_row.pattern as usize) {
+                Some(ptn) => ptn,
+                None => continue,
+            };
+            let row = play_row.row as usize;
+
+            for (chn, trk) in ptn.tracks.iter().enumerate() {
+                let sim = &mut sims[chn];
+                let events = trk.tick_events(play_row.row, play_row.tempo).unwrap_or_default();
+                let (mut level, mut triggered, mut released) = (0.0, false, false);
+
+                for t in 0..play_row.ticks {
+                    // pattern delays repeat the row without processing it again
+                    let tick = (t % play_row.tempo as u16) as u8;
+                    if t == 0 || tick > 0 {
+                        if tick == 0 { sim.start_row(self, trk, row); } else { sim.tick(self, tick); }
+                        for event in events.iter().filter(|event| event.tick == tick) {
+                            match event.kind {
+                                TickEventKind::NoteOn { .. } | TickEventKind::Retrigger { .. } => {
+                                    triggered = true;
+                                    envelopes[chn] = NoteEnvelope::trigger(
+                                        self.instruments.get((sim.state.instrument as usize).wrapping_sub(1)));
+                                },
+                                TickEventKind::KeyOff => released = true,
+                                TickEventKind::NoteCut => (),
+                            }
+                        }
+                    }
+                    if let Some(envelope) = envelopes[chn].as_mut() {
+                        level += sim.state.volume as f64 * envelope.tick(sim.state.key_off);
+                    }
+                }
+
+                if envelopes[chn].is_none() {
+                    continue;
+                }
+                if !visited.insert((play_row.pattern, play_row.row, chn)) {
+                    written[chn] = None;
+                    continue;
+                }
+                let volume = (level / play_row.ticks.max(1) as f64).round().min(64.0) as u8;
+                let cell = &trk.cells[row];
+                let set_volume = cell.fx_command() == Some(XM_FX_CXX)
+                    || cell.volume().is_some_and(|vol| (0x10..=0x50).contains(&vol));
+                if !set_volume && cell.volume().unwrap_or(0) != 0 {
+                    continue;
+                }
+                let significant = written[chn]
+                    .is_none_or(|last| last.abs_diff(volume) >= resolution || (volume == 0 && last > 0));
+                if triggered || released || set_volume || significant {
+                    changes.push((play_row.pattern as usize, row, chn, volume, released));
+                    written[chn] = Some(volume);
+                }
+            }
+        }
+
+        for &(ptn, row, chn, volume, released) in &changes {
+            let cell = &mut self.patterns[ptn].tracks[chn].cells[row];
+            if cell.fx_command() == Some(XM_FX_CXX) {
+                cell.set_fx_param(Some(volume));
+            }
+            else {
+                cell.set_volume(Some(0x10 + volume));
+            }
+            if released && cell.note() == Some(97) {
+                cell.set_note(None);
+            }
+            if released && cell.fx_command() == Some(XM_FX_KXX) {
+                cell.set_fx_command(None);
+                cell.set_fx_param(None);
+            }
+        }
+
+        let offset = Envelope::Volume.type_offset();
+        for instr in &mut self.instruments {
+            if instr.volume_type().unwrap_or(0) & XM_ENVELOPE_ON != 0 {
+                instr.header[offset] &= !XM_ENVELOPE_ON;
+            }
+        }
+        changes.len()
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_bake_autovibrato() {
+    use std::path::Path;
+    use super::{XMPattern, XM_FX_AXX, XM_SEQUENCE_BEGIN, XM_SEQUENCE_LEN};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    xm.patterns.clear();
+    xm.header[XM_SEQUENCE_BEGIN] = 0;
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, 1);
+
+    // instrument 1 sweeps to the full depth of 15 over 4 rows at rate 32
+    let sweep = xm.tempo() * 4;
+    xm.instruments[0].header[235..239].copy_from_slice(&[0, sweep, 15, 32]);
+    xm.instruments[1].header[237] = 0;
+    let mut ptn = XMPattern::empty(8, 2);
+    ptn.tracks[0].cells[0].set_note(Some(49));
+    ptn.tracks[0].cells[0].set_instrument(Some(1));
+    ptn.tracks[0].cells[5].set_fx_command(Some(XM_FX_AXX));
+    ptn.tracks[0].cells[5].set_fx_param(Some(0x01));
+    ptn.tracks[0].cells[6].set_note(Some(97));
+    ptn.tracks[0].cells[7].set_note(Some(50));
+    ptn.tracks[0].cells[7].set_instrument(Some(2));
+    xm.patterns.push(ptn);
+
+    let changed = xm.bake_autovibrato();
+    let fx: Vec<(Option<u8>, Option<u8>)> = xm.patterns[0].tracks[0].cells.iter()
+        .map(|cell| (cell.fx_command(), cell.fx_param())).collect();
+    // the first row rounds to no vibrato at all
+    assert_eq!(fx[..5], [(None, None), (Some(XM_FX_4XX), Some(0x81)), (Some(XM_FX_4XX), Some(0x82)),
+        (Some(XM_FX_4XX), Some(0x82)), (Some(XM_FX_4XX), Some(0x82))]);
+
+    // effects are kept, released notes keep their vibrato, and instrument 2 has no autovibrato
+    assert_eq!(fx[5], (Some(XM_FX_AXX), Some(0x01)));
+    assert_eq!(fx[6], (Some(XM_FX_4XX), Some(0x82)));
+    assert_eq!(fx[7], (None, None));
+    assert_eq!(changed, 5);
+    assert!(xm.patterns[0].tracks[1].cells.iter().all(|cell| cell.fx_command().is_none()));
+    assert_eq!(xm.instruments[0].vibrato_depth(), Some(0));
+    assert_eq!(xm.bake_autovibrato(), 0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_bake_volume_envelopes() {
+    use std::path::Path;
+    use super::{XMPattern, XM_ENVELOPE_SUSTAIN, XM_SEQUENCE_BEGIN, XM_SEQUENCE_LEN};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    xm.patterns.clear();
+    xm.header[XM_SEQUENCE_BEGIN] = 0;
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, 1);
+
+    let tempo = xm.tempo() as u16;
+    let instr = &mut xm.instruments[0];
+    instr.set_envelope_points(Envelope::Volume, &[(0, 64), (tempo * 2, 32), (tempo * 4, 0)]).unwrap();
+    instr.set_envelope_sustain(Envelope::Volume, 1).unwrap();
+    instr.set_envelope_type(Envelope::Volume, XM_ENVELOPE_ON | XM_ENVELOPE_SUSTAIN).unwrap();
+    let mut ptn = XMPattern::empty(10, 1);
+    ptn.tracks[0].cells[0].set_note(Some(49));
+    ptn.tracks[0].cells[0].set_instrument(Some(1));
+    ptn.tracks[0].cells[0].set_volume(Some(0x50));
+    ptn.tracks[0].cells[5].set_note(Some(97));
+    xm.patterns.push(ptn);
+
+    let mut coarse = xm.clone();
+    let columns = |xm: &XModule| xm.patterns[0].tracks[0].cells.iter().map(|cell| (cell.note(), cell.volume()))
+        .collect::<Vec<_>>();
+
+    // the note sustains at half volume, and fades out over two rows after the key off, which is removed
+    assert_eq!(xm.bake_volume_envelopes(1), 6);
+    assert_eq!(columns(&xm), vec![(Some(49), Some(0x10 + 55)), (None, Some(0x10 + 39)), (None, Some(0x10 + 32)),
+        (None, None), (None, None), (None, Some(0x10 + 22)), (None, Some(0x10 + 6)), (None, Some(0x10)), (None, None),
+        (None, None)]);
+    assert_eq!(xm.instruments[0].volume_type(), Some(XM_ENVELOPE_SUSTAIN));
+    assert_eq!(xm.bake_volume_envelopes(1), 0);
+
+    // smaller changes are skipped at a coarser resolution, except for key offs and silence
+    assert_eq!(coarse.bake_volume_envelopes(16), 5);
+    assert_eq!(columns(&coarse)[1..8], [(None, Some(0x10 + 39)), (None, None), (None, None), (None, None),
+        (None, Some(0x10 + 22)), (None, Some(0x10 + 6)), (None, Some(0x10))]);
+}
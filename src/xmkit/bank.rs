@@ -0,0 +1,174 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+use super::{XModule, XMParseError};
+
+/// A set of unique sample data shared by several modules. Modules can be split into a stripped module without sample
+/// data (see XModule::to_bytes_without_samples()) and a BankMapping that refers to the bank, so that samples used
+/// by several modules are only stored once. Samples are considered identical if their data is identical, regardless
+/// of their name, loop, volume, or pitch settings, which stay in the stripped modules.
+#[derive(Clone, Debug, Default)]
+pub struct SampleBank {
+    samples: Vec<Arc<Vec<u8>>>,
+    // indices of the samples by hash of their data
+    index: HashMap<u64, Vec<usize>>,
+}
+
+/// The bank indices of the samples of a module, as returned by SampleBank::add_module().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BankMapping {
+    /// The bank index of each sample, by instrument.
+    pub samples: Vec<Vec<usize>>,
+}
+
+impl BankMapping {
+
+    /// Encodes the mapping as a list of 16-bit little endian bank indices, in the order of the instruments and their
+    /// samples. The number of samples per instrument is stored in the stripped module.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if a bank index does not fit into 16 bits, which happens with banks of more than
+    /// 65536 samples.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, XMParseError> {
+        self.samples.iter().flatten().map(|&index| u16::try_from(index).map(u16::to_le_bytes)
+            .map_err(|_| XMParseError::new(&format!("Bank index {} does not fit into 16 bits.", index))))
+            .collect::<Result<Vec<[u8; 2]>, XMParseError>>().map(|indices| indices.concat())
+    }
+}
+
+impl SampleBank {
+
+    /// Creates an empty sample bank.
+    pub fn new() -> SampleBank {
+        SampleBank::default()
+    }
+
+    /// Creates a sample bank containing the samples of all given modules.
+    pub fn from_modules(modules: &[XModule]) -> SampleBank {
+        let mut bank = SampleBank::new();
+        for xm in modules {
+            bank.add_module(xm);
+        }
+        bank
+    }
+
+    /// Adds the samples of the module that are not in the bank yet, and returns the bank indices of all samples of
+    /// the module.
+    pub fn add_module(&mut self, xm: &XModule) -> BankMapping {
+        BankMapping {
+            samples: xm.instruments.iter().map(|instr| instr.samples.iter().map(|smp| self.add(&smp.data)).collect())
+                .collect(),
+        }
+    }
+
+    /// Adds the samples of the module to the bank, and returns the module without sample data as encoded by
+    /// XModule::to_bytes_without_samples(), together with the mapping needed to restore it with restore().
+    pub fn strip(&mut self, xm: &XModule) -> (Vec<u8>, BankMapping) {
+        (xm.to_bytes_without_samples(), self.add_module(xm))
+    }
+
+    /// Restores the sample data of a stripped module from the bank, using the mapping returned when the module was
+    /// added. The sample data is shared with the bank until it is modified.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the mapping does not match the instruments and samples of the module, or if it
+    /// refers to samples that are not in the bank.
+    pub fn restore(&self, xm: &mut XModule, mapping: &BankMapping) -> Result<(), XMParseError> {
+        if mapping.samples.len() != xm.instruments.len()
+            || xm.instruments.iter().zip(&mapping.samples).any(|(instr, smps)| instr.samples.len() != smps.len()) {
+            return Err(XMParseError::new("Bank mapping does not match the samples of the module."));
+        }
+        if let Some(index) = mapping.samples.iter().flatten().find(|&&index| index >= self.samples.len()) {
+            return Err(XMParseError::new(&format!("Sample {} is not in the bank.", index)));
+        }
+        for (instr, indices) in xm.instruments.iter_mut().zip(&mapping.samples) {
+            for (smp, &index) in instr.samples.iter_mut().zip(indices) {
+                let data = &self.samples[index];
+                smp.data = data.clone();
+                smp.pcm = OnceLock::new();
+                XModule::write_usize(&mut smp.header, 0, data.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the data of the sample with the given bank index in XM's native delta format, or None if there is no
+    /// such sample.
+    pub fn sample_data(&self, index: usize) -> Option<&[u8]> {
+        self.samples.get(index).map(|data| &data[..])
+    }
+
+    /// Returns the number of unique samples in the bank.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if the bank contains no samples.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Returns the total size of the sample data in the bank, in bytes.
+    pub fn data_len(&self) -> usize {
+        self.samples.iter().map(|data| data.len()).sum()
+    }
+
+    // Returns the index of the given sample data, adding it if it is not in the bank yet.
+    fn add(&mut self, data: &Arc<Vec<u8>>) -> usize {
+        let samples = &mut self.samples;
+        let candidates = self.index.entry(data_hash(data)).or_default();
+        if let Some(&index) = candidates.iter().find(|&&index| samples[index] == *data) {
+            return index;
+        }
+        candidates.push(samples.len());
+        samples.push(data.clone());
+        samples.len() - 1
+    }
+}
+
+// Returns the hash of the given data, as used to find identical sample data.
+pub(crate) fn data_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_sample_bank() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut other = xm.clone();
+    other.instruments[0].samples[0].normalize(0.5);
+    let sample_count: usize = xm.instruments.iter().map(|instr| instr.samples.len()).sum();
+
+    let mut bank = SampleBank::new();
+    let (stripped, mapping) = bank.strip(&xm);
+    assert_eq!(bank.len(), sample_count);
+    assert_eq!(mapping.to_bytes().unwrap().len(), sample_count * 2);
+    assert!(BankMapping { samples: vec![vec![1, 0x10000]] }.to_bytes().is_err());
+
+    // only the modified sample is added
+    let (other_stripped, other_mapping) = bank.strip(&other);
+    assert_eq!(bank.len(), sample_count + 1);
+    assert_eq!(other_mapping.samples[0][0], sample_count);
+    assert_eq!(other_mapping.samples[1], mapping.samples[1]);
+    assert_eq!(bank.sample_data(sample_count), Some(&other.instruments[0].samples[0].data[..]));
+
+    let mut restored = XModule::parse(stripped).unwrap();
+    bank.restore(&mut restored, &mapping).unwrap();
+    assert!(restored.to_bytes() == xm.to_bytes());
+    let mut restored = XModule::parse(other_stripped).unwrap();
+    bank.restore(&mut restored, &other_mapping).unwrap();
+    assert!(restored.to_bytes() == other.to_bytes());
+
+    assert!(bank.restore(&mut restored, &BankMapping::default()).is_err());
+    let mut invalid = mapping.clone();
+    invalid.samples[0][0] = bank.len();
+    assert!(bank.restore(&mut restored, &invalid).is_err());
+    assert_eq!(SampleBank::from_modules(&[xm, other]).len(), bank.len());
+}
@@ -0,0 +1,201 @@
+use super::{XMCell, XMParseError, XMTrack};
+
+// Indices of the fields of a cell, in the order in which they are stored in XM pattern data.
+pub(crate) const NOTE: usize = 0;
+pub(crate) const INSTRUMENT: usize = 1;
+pub(crate) const VOLUME: usize = 2;
+pub(crate) const FX_COMMAND: usize = 3;
+pub(crate) const FX_PARAM: usize = 4;
+
+/// The storage of a pattern cell. Bit n of the mask is set if field n is present, like in the control byte of packed
+/// XM pattern data, so a cell takes 6 bytes instead of the 10 bytes of five Option<u8> fields.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PackedCell {
+    mask: u8,
+    values: [u8; 5],
+}
+
+impl PackedCell {
+    /// Returns the given field, counted from NOTE to FX_PARAM.
+    pub(crate) fn get(&self, field: usize) -> Option<u8> {
+        if self.mask & (1 << field) != 0 { Some(self.values[field]) } else { None }
+    }
+
+    /// Sets or clears the given field, counted from NOTE to FX_PARAM.
+    pub(crate) fn set(&mut self, field: usize, value: Option<u8>) {
+        match value {
+            Some(value) => {
+                self.mask |= 1 << field;
+                self.values[field] = value;
+            },
+            None => {
+                self.mask &= !(1 << field);
+                self.values[field] = 0;
+            },
+        }
+    }
+
+    /// Returns true if none of the fields are present.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mask == 0
+    }
+
+    /// Returns the fields that are present.
+    pub(crate) fn mask(&self) -> CellMask {
+        CellMask(self.mask)
+    }
+
+    pub(crate) fn note(&self) -> Option<u8> { self.get(NOTE) }
+    pub(crate) fn instrument(&self) -> Option<u8> { self.get(INSTRUMENT) }
+    pub(crate) fn volume(&self) -> Option<u8> { self.get(VOLUME) }
+    pub(crate) fn fx_command(&self) -> Option<u8> { self.get(FX_COMMAND) }
+    pub(crate) fn fx_param(&self) -> Option<u8> { self.get(FX_PARAM) }
+
+    pub(crate) fn set_note(&mut self, value: Option<u8>) { self.set(NOTE, value) }
+    pub(crate) fn set_instrument(&mut self, value: Option<u8>) { self.set(INSTRUMENT, value) }
+    pub(crate) fn set_volume(&mut self, value: Option<u8>) { self.set(VOLUME, value) }
+    pub(crate) fn set_fx_command(&mut self, value: Option<u8>) { self.set(FX_COMMAND, value) }
+    pub(crate) fn set_fx_param(&mut self, value: Option<u8>) { self.set(FX_PARAM, value) }
+}
+
+
+/// The fields of a pattern cell that are present in the pattern data, as returned by XMTrack::cell_mask(). Unlike the
+/// values of an XMCell, this tells fields that were stored with a value of 0 apart from fields that were left out
+/// of packed data, and always includes all fields of unpacked cells.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CellMask(u8);
+
+impl CellMask {
+    /// Creates a mask from the lower 5 bits of the given value, which are used like in the control byte of packed
+    /// XM pattern data: bit 0 for the note, up to bit 4 for the effect parameter.
+    pub fn from_bits(bits: u8) -> CellMask {
+        CellMask(bits & 0x1f)
+    }
+
+    /// Returns the mask as in the control byte of packed XM pattern data, without the packing flag (0x80).
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if the note is present.
+    pub fn note(self) -> bool {
+        self.0 & (1 << NOTE) != 0
+    }
+
+    /// Returns true if the instrument number is present.
+    pub fn instrument(self) -> bool {
+        self.0 & (1 << INSTRUMENT) != 0
+    }
+
+    /// Returns true if the volume column is present.
+    pub fn volume(self) -> bool {
+        self.0 & (1 << VOLUME) != 0
+    }
+
+    /// Returns true if the effect command is present.
+    pub fn fx_command(self) -> bool {
+        self.0 & (1 << FX_COMMAND) != 0
+    }
+
+    /// Returns true if the effect parameter is present.
+    pub fn fx_param(self) -> bool {
+        self.0 & (1 << FX_PARAM) != 0
+    }
+
+    /// Returns true if no fields are present.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the number of fields present.
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+}
+
+impl From<PackedCell> for XMCell {
+    fn from(cell: PackedCell) -> XMCell {
+        XMCell {
+            note: cell.note(),
+            instrument: cell.instrument(),
+            volume: cell.volume(),
+            fx_command: cell.fx_command(),
+            fx_param: cell.fx_param(),
+        }
+    }
+}
+
+impl From<XMCell> for PackedCell {
+    fn from(cell: XMCell) -> PackedCell {
+        let mut packed = PackedCell::default();
+        packed.set_note(cell.note);
+        packed.set_instrument(cell.instrument);
+        packed.set_volume(cell.volume);
+        packed.set_fx_command(cell.fx_command);
+        packed.set_fx_param(cell.fx_param);
+        packed
+    }
+}
+
+impl XMTrack {
+
+    /// Returns the fields of the given row that are present in the pattern data, including fields stored with a
+    /// value of 0.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn cell_mask(&self, row: u16) -> Result<CellMask, XMParseError> {
+        self.validate_row(&row)?;
+        Ok(self.cells[row as usize].mask())
+    }
+
+    // Builds a track from its columns, which must have the same length. Used to set up tests.
+    #[cfg(test)]
+    pub(crate) fn from_columns(notes: &[Option<u8>], instruments: &[Option<u8>], volumes: &[Option<u8>],
+        fx_commands: &[Option<u8>], fx_params: &[Option<u8>]) -> XMTrack {
+
+        let cells = (0..notes.len()).map(|row| XMCell { note: notes[row], instrument: instruments[row],
+            volume: volumes[row], fx_command: fx_commands[row], fx_param: fx_params[row] }.into()).collect();
+        XMTrack { cells }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_packed_cell() {
+    use std::mem::size_of;
+
+    assert_eq!(size_of::<PackedCell>(), 6);
+    assert!(size_of::<PackedCell>() < size_of::<XMCell>());
+
+    let cell = XMCell { note: Some(58), instrument: Some(1), volume: None, fx_command: Some(0xc), fx_param: Some(0) };
+    let mut packed = PackedCell::from(cell);
+    assert_eq!(XMCell::from(packed), cell);
+    assert_eq!((packed.get(VOLUME), packed.fx_param()), (None, Some(0)));
+
+    // cleared fields compare equal to fields that were never set
+    packed.set_note(None);
+    packed.set_instrument(None);
+    packed.set_fx_command(None);
+    packed.set_fx_param(None);
+    assert!(packed.is_empty());
+    assert!(packed == PackedCell::default());
+}
+
+#[cfg(test)]
+#[test]
+fn test_cell_mask() {
+    use super::XMPattern;
+
+    // a packed cell with a note and an effect parameter of 0, an empty packed cell, and an unpacked cell of zeroes
+    let ptn = XMPattern::parse(vec![9, 0, 0, 0, 0, 3, 0, 9, 0, 0x91, 49, 0, 0x80, 0, 0, 0, 0, 0], 1).unwrap();
+    let trk = &ptn.tracks[0];
+    let mask = trk.cell_mask(0).unwrap();
+    assert!(mask.note() && mask.fx_param() && !mask.instrument() && !mask.volume() && !mask.fx_command());
+    assert_eq!((mask.bits(), mask.len()), (0x11, 2));
+    assert_eq!(trk.fx_param_raw(0).unwrap(), Some(0));
+    assert!(trk.cell_mask(1).unwrap().is_empty());
+    assert_eq!(trk.cell_mask(2).unwrap(), CellMask::from_bits(0xff));
+    assert!(trk.cell_mask(3).is_err());
+}
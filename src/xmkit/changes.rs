@@ -0,0 +1,94 @@
+use super::{XMPattern, XMTrack, XMParseError};
+
+/// The changes on one channel of a pattern row, relative to the previous row. See XMPattern::row_changes().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RowChange {
+    /// The channel, counted from 0.
+    pub channel: u8,
+    /// True if the row contains a note, including key-off.
+    pub note: bool,
+    /// True if the active instrument (see XMTrack::instrument()) differs from the previous row.
+    pub instrument: bool,
+    /// True if the active volume (see XMTrack::volume()) differs from the previous row.
+    pub volume: bool,
+    /// True if the effect command or parameter differs from the previous row.
+    pub fx: bool,
+}
+
+impl RowChange {
+    /// Returns true if anything changed on the channel.
+    pub fn any(&self) -> bool {
+        self.note || self.instrument || self.volume || self.fx
+    }
+}
+
+
+impl XMPattern {
+
+    /// Returns the channels whose state changes on the given row, relative to the previous row, in channel order.
+    /// Channels without changes are omitted. The first row is compared to an empty row, which has no instrument,
+    /// full volume, and no effect.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row does not exist in the pattern.
+    pub fn row_changes(&self, row: u16) -> Result<Vec<RowChange>, XMParseError> {
+        let mut changes = Vec::new();
+        for (channel, trk) in self.tracks.iter().enumerate() {
+            let change = row_change(trk, row)?;
+            if change.any() {
+                changes.push(RowChange { channel: channel as u8, ..change });
+            }
+        }
+        Ok(changes)
+    }
+}
+
+// Compares the given row of a track to the previous one.
+fn row_change(trk: &XMTrack, row: u16) -> Result<RowChange, XMParseError> {
+    let cell = trk.cell(row)?;
+    let (instrument, volume, fx) = match row.checked_sub(1) {
+        Some(previous) => {
+            let previous_cell = trk.cell(previous)?;
+            (trk.instrument(previous)?, trk.volume(previous)?, (previous_cell.fx_command, previous_cell.fx_param))
+        },
+        None => (0, 0x40, (None, None)),
+    };
+
+    let fx_value = |fx: (Option<u8>, Option<u8>)| (fx.0.unwrap_or(0), fx.1.unwrap_or(0));
+    Ok(RowChange {
+        channel: 0,
+        note: cell.note.is_some_and(|note| note > 0),
+        instrument: trk.instrument(row)? != instrument,
+        volume: trk.volume(row)? != volume,
+        fx: fx_value((cell.fx_command, cell.fx_param)) != fx_value(fx),
+    })
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_row_changes() {
+    let mut ptn = XMPattern::empty(4, 2);
+    assert!(ptn.row_changes(0).unwrap().is_empty());
+
+    // a note with instrument and volume, a repeated effect, and a volume change
+    ptn.tracks[1].cells[0].set_note(Some(49));
+    ptn.tracks[1].cells[0].set_instrument(Some(1));
+    ptn.tracks[1].cells[0].set_volume(Some(0x30));
+    for row in 0..2 {
+        ptn.tracks[0].cells[row].set_fx_command(Some(0xa));
+        ptn.tracks[0].cells[row].set_fx_param(Some(0x0f));
+    }
+    ptn.tracks[1].cells[2].set_volume(Some(0x40));
+
+    assert_eq!(ptn.row_changes(0).unwrap(), vec![
+        RowChange { channel: 0, fx: true, ..Default::default() },
+        RowChange { channel: 1, note: true, instrument: true, volume: true, fx: false },
+    ]);
+    assert!(ptn.row_changes(1).unwrap().is_empty());
+    assert_eq!(ptn.row_changes(2).unwrap(), vec![
+        RowChange { channel: 0, fx: true, ..Default::default() },
+        RowChange { channel: 1, volume: true, ..Default::default() },
+    ]);
+    assert!(ptn.row_changes(4).is_err());
+}
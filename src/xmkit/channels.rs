@@ -0,0 +1,259 @@
+use super::{XModule, XMTrack, XMParseError, XM_CHANNEL_COUNT, XM_FX_BXX, XM_FX_DXX, XM_FX_FXX, XM_FX_GXX,
+    XM_FX_HXX, XM_FX_KXX, XM_FX_ECX};
+
+/// Determines how XModule::reduce_channels() gets rid of channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReductionStrategy {
+    /// Only merges channels whose notes never overlap. Fails if the target channel count cannot be reached this way.
+    Merge,
+    /// Merges channels whose notes never overlap, then drops the quietest channels until the target channel count
+    /// is reached. Global effects (Bxx, Dxx, Fxx, Gxx, Hxx, E6x, EEx) on dropped channels are moved to another
+    /// channel on the same row, if one has a free effect slot.
+    Prioritize,
+}
+
+/// The settings of a channel of a module, as returned by XModule::channels(). XM files do not store channel panning
+/// or mute states, so these start out at their defaults, and tools can use the struct to keep track of their own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XMChannel {
+    /// The channel number, counted from 0.
+    pub index: u8,
+    /// The name of the channel as stored by OpenMPT, if it has one.
+    pub name: Option<String>,
+    /// The panning (0..0xff) of the channel at the beginning of the song, before any panning is set in the patterns.
+    pub default_panning: u8,
+    /// True if the channel is muted.
+    pub muted: bool,
+}
+
+
+impl XModule {
+
+    /// Returns the settings of all channels of the module. Channel names are taken from the OpenMPT extensions (see
+    /// channel_names()). All channels start out centered and unmuted, as in FT2.
+    pub fn channels(&self) -> Vec<XMChannel> {
+        self.channel_names().into_iter().enumerate().map(|(index, name)| XMChannel {
+            index: index as u8,
+            name: Some(name).filter(|name| !name.is_empty()),
+            default_panning: 0x80,
+            muted: false,
+        }).collect()
+    }
+
+    /// Returns the settings of the given channel (counted from 0), or None if the channel does not exist.
+    pub fn channel(&self, channel: u8) -> Option<XMChannel> {
+        self.channels().into_iter().nth(channel as usize)
+    }
+
+    /// Removes the given channel (counted from 0) from all patterns.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the channel does not exist, or if it is the only channel.
+    pub fn remove_channel(&mut self, channel: u8) -> Result<(), XMParseError> {
+        let count = self.channel_count();
+        if channel >= count || count == 1 {
+            return Err(XMParseError::new(&format!("Cannot remove channel {} from module with {} channels.",
+                channel, count)));
+        }
+
+        for ptn in &mut self.patterns {
+            if (channel as usize) < ptn.tracks.len() {
+                ptn.tracks.remove(channel as usize);
+            }
+        }
+        XModule::write_u16(&mut self.header, XM_CHANNEL_COUNT, (count - 1) as u16);
+
+        Ok(())
+    }
+
+    /// Reduces the number of channels to the given count, for replayers that support fewer channels than the module
+    /// uses. Two channels can be merged if, in all patterns, one of them is silent wherever the other one holds a
+    /// note or contains any data. Channels that are used least are merged first.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if target_count is 0, or if the strategy is Merge and the target channel count cannot
+    /// be reached. In this case, the module is left unchanged.
+    pub fn reduce_channels(&mut self, target_count: u8, strategy: ReductionStrategy) -> Result<(), XMParseError> {
+        if target_count == 0 {
+            return Err(XMParseError::new("Cannot reduce module to 0 channels."));
+        }
+
+        let mut reduced = self.clone();
+        while reduced.channel_count() > target_count {
+            if let Some((into, from)) = reduced.mergeable_channels() {
+                reduced.merge_channel(into, from);
+                reduced.remove_channel(from)?;
+                continue;
+            }
+
+            if strategy == ReductionStrategy::Merge {
+                return Err(XMParseError::new(&format!("Cannot merge module down to {} channels, {} channels needed.",
+                    target_count, reduced.channel_count())));
+            }
+
+            let quietest = reduced.quietest_channel();
+            reduced.move_global_effects(quietest);
+            reduced.remove_channel(quietest)?;
+        }
+
+        *self = reduced;
+        Ok(())
+    }
+
+    // Finds a pair of channels that can be merged, as (into, from). Prefers to merge the least used channel.
+    fn mergeable_channels(&self) -> Option<(u8, u8)> {
+        let busy: Vec<Vec<Vec<bool>>> = (0..self.channel_count()).map(|chan| self.busy_rows(chan)).collect();
+        let usage = self.channel_usage();
+        let mut candidates: Vec<u8> = (0..self.channel_count()).collect();
+        candidates.sort_by_key(|chan| (usage[*chan as usize].active_rows, usage[*chan as usize].notes));
+
+        for from in candidates {
+            let into = (0..self.channel_count()).find(|into| *into != from
+                && busy[*into as usize].iter().zip(&busy[from as usize])
+                    .all(|(a, b)| a.iter().zip(b).all(|(a, b)| !(*a && *b))));
+
+            if let Some(into) = into {
+                return Some((into, from));
+            }
+        }
+
+        None
+    }
+
+    // Returns, for each pattern, the rows on which the channel holds a note or contains data. Notes may carry over
+    // from the previous pattern, so if any track ends with a held note, the rows before the first note trigger or
+    // release in each pattern are considered busy as well.
+    fn busy_rows(&self, channel: u8) -> Vec<Vec<bool>> {
+        let tracks: Vec<&XMTrack> = self.patterns.iter().map(|ptn| &ptn.tracks[channel as usize]).collect();
+        let carries = tracks.iter().any(|trk| trk.occupancy(false).last() == Some(&true));
+
+        tracks.iter().map(|trk| {
+            let mut carried = carries;
+            trk.occupancy(false).iter().enumerate().map(|(row, held)| {
+                let cell = trk.cell_unchecked(row);
+                if cell.note.is_some() || cell.has_effect(XM_FX_KXX) || cell.has_effect(XM_FX_ECX) {
+                    carried = false;
+                }
+                *held || carried || cell != Default::default()
+            }).collect()
+        }).collect()
+    }
+
+    // Copies all cells of one channel into another. The target channel must be empty wherever the source has data.
+    fn merge_channel(&mut self, into: u8, from: u8) {
+        for ptn in &mut self.patterns {
+            let source = ptn.tracks[from as usize].clone();
+            let target = &mut ptn.tracks[into as usize];
+
+            for row in 0..source.cells.len() {
+                if source.cell_unchecked(row) != Default::default() {
+                    target.cells[row] = source.cells[row];
+                }
+            }
+        }
+    }
+
+    // Returns the channel with the lowest sum of volumes over the rows on which it is audible.
+    fn quietest_channel(&self) -> u8 {
+        let mut loudness = vec![0u64; self.channel_count() as usize];
+        let activity: Vec<Vec<Vec<bool>>> = self.patterns.iter()
+            .map(|ptn| ptn.tracks.iter().map(|trk| trk.activity()).collect())
+            .collect();
+
+        for play_row in self.play_rows() {
+            if let Some(ptn) = self.patterns.get(play_row.pattern as usize) {
+                for (chan, trk) in ptn.tracks.iter().enumerate() {
+                    if activity[play_row.pattern as usize][chan][play_row.row as usize] {
+                        loudness[chan] += trk.volume(play_row.row).unwrap_or(0) as u64 + 1;
+                    }
+                }
+            }
+        }
+
+        (0..self.channel_count()).min_by_key(|chan| loudness[*chan as usize]).unwrap_or(0)
+    }
+
+    // Moves the global effects of a channel to free effect slots of other channels on the same row.
+    fn move_global_effects(&mut self, channel: u8) {
+        for ptn in &mut self.patterns {
+            for row in 0..ptn.tracks[channel as usize].cells.len() {
+                let cmd = ptn.tracks[channel as usize].cells[row].fx_command().unwrap_or(0);
+                let param = ptn.tracks[channel as usize].cells[row].fx_param();
+                let global = match cmd {
+                    XM_FX_BXX | XM_FX_DXX | XM_FX_FXX | XM_FX_GXX | XM_FX_HXX => true,
+                    0xe => param.unwrap_or(0) >> 4 == 6 || param.unwrap_or(0) >> 4 == 0xe,
+                    _ => false,
+                };
+                if !global {
+                    continue;
+                }
+
+                let free = (0..ptn.tracks.len()).find(|chan| *chan != channel as usize
+                    && ptn.tracks[*chan].cells[row].fx_command().unwrap_or(0) == 0
+                    && ptn.tracks[*chan].cells[row].fx_param().unwrap_or(0) == 0);
+                if let Some(free) = free {
+                    ptn.tracks[free].cells[row].set_fx_command(Some(cmd));
+                    ptn.tracks[free].cells[row].set_fx_param(param);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_reduce_channels() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let timing: Vec<_> = xm.play_rows().iter().map(|row| row.start).collect();
+
+    // channel 3 only contains effects, which collide with notes on all other channels
+    assert!(xm.reduce_channels(3, ReductionStrategy::Merge).is_err());
+    assert_eq!(xm.channel_count(), 4);
+
+    let mut dropped = xm.clone();
+    dropped.reduce_channels(3, ReductionStrategy::Prioritize).unwrap();
+    assert_eq!(dropped.channel_count(), 3);
+    assert!(dropped.patterns.iter().all(|ptn| ptn.tracks.len() == 3));
+    assert_eq!(dropped.patterns[0].tracks[0].fx_command_raw(8).unwrap(), Some(XM_FX_FXX));
+    assert_eq!(dropped.play_rows().iter().map(|row| row.start).collect::<Vec<_>>(), timing);
+    assert_eq!(XModule::parse(dropped.to_bytes()).unwrap().channel_count(), 3);
+
+    // replace channel 3 with a short note that fits into a gap on channel 0
+    for ptn in &mut xm.patterns {
+        let len = ptn.len() as usize;
+        ptn.tracks[3] = XMTrack::from_columns(&vec![None; len], &vec![None; len], &vec![None; len], &vec![None; len],
+            &vec![None; len]);
+    }
+    xm.patterns[0].tracks[3].cells[14].set_note(Some(49));
+    xm.patterns[0].tracks[3].cells[14].set_instrument(Some(1));
+    xm.patterns[0].tracks[3].cells[15].set_note(Some(97));
+
+    xm.reduce_channels(3, ReductionStrategy::Merge).unwrap();
+    assert_eq!(xm.channel_count(), 3);
+    assert_eq!(xm.patterns[0].tracks[0].note_raw(14).unwrap(), Some(49));
+    assert_eq!(xm.patterns[0].tracks[0].note_raw(15).unwrap(), Some(97));
+}
+
+#[cfg(test)]
+#[test]
+fn test_channels() {
+    use super::ExtensionChunk;
+    use std::fs;
+
+    let mut data = fs::read("test.xm").unwrap();
+    data.extend(ExtensionChunk { id: *b"CNAM", data: [&b"lead"[..], &[0; 16], &[0; 20], b"perc", &[0; 16]].concat() }
+        .to_bytes());
+    let xm = XModule::parse(data).unwrap();
+
+    let channels = xm.channels();
+    assert_eq!(channels.len(), 4);
+    assert_eq!(channels.iter().map(|chn| chn.name.as_deref()).collect::<Vec<_>>(),
+        vec![Some("lead"), None, Some("perc"), None]);
+    assert!(channels.iter().enumerate().all(|(index, chn)| chn.index as usize == index && chn.default_panning == 0x80
+        && !chn.muted));
+    assert_eq!(xm.channel(2), Some(channels[2].clone()));
+    assert_eq!(xm.channel(4), None);
+}
@@ -0,0 +1,138 @@
+//! Conversion between plain PCM and the delta encoding XM uses for sample data.
+//!
+//! XM stores each sample value as the difference to the previous one, starting from 0. Differences wrap around,
+//! so encoding and decoding is lossless for any input.
+
+use super::{XModule, XMSample};
+
+/// An iterator that decodes the data of a sample to signed 16-bit PCM on the fly. 8-bit samples are scaled up
+/// to 16 bits. Created by `XMSample::decoder`.
+#[derive(Clone, Debug)]
+pub struct SampleDecoder<'a> {
+    data: &'a [u8],
+    is_16bit: bool,
+    pos: usize,
+    value: i16,
+}
+
+impl<'a> Iterator for SampleDecoder<'a> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.is_16bit {
+            if self.pos + 2 > self.data.len() {
+                return None;
+            }
+            self.value = self.value.wrapping_add(XModule::read_u16(self.data, self.pos) as i16);
+            self.pos += 2;
+        }
+        else {
+            self.value = self.value.wrapping_add(((*self.data.get(self.pos)? as u16) << 8) as i16);
+            self.pos += 1;
+        }
+
+        Some(self.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.data.len() - self.pos) / if self.is_16bit { 2 } else { 1 };
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for SampleDecoder<'a> {}
+
+impl XMSample {
+    /// Returns an iterator that decodes the sample data to signed 16-bit PCM without allocating.
+    pub fn decoder(&self) -> SampleDecoder<'_> {
+        SampleDecoder { data: &self.data, is_16bit: self.is_16bit(), pos: 0, value: 0 }
+    }
+
+    /// Decodes the sample data to signed 16-bit PCM into the given buffer, without allocating.
+    /// Decodes at most `buffer.len()` samples, and returns the number of samples written.
+    pub fn decode_into(&self, buffer: &mut [i16]) -> usize {
+        let mut count = 0;
+        for (dest, smp) in buffer.iter_mut().zip(self.decoder()) {
+            *dest = smp;
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Delta-encodes signed 8-bit PCM.
+pub fn delta_encode_8(data: &[i8]) -> Vec<i8> {
+    let mut previous: i8 = 0;
+    data.iter().map(|smp| {
+        let delta = smp.wrapping_sub(previous);
+        previous = *smp;
+        delta
+    }).collect()
+}
+
+/// Delta-encodes signed 16-bit PCM.
+pub fn delta_encode_16(data: &[i16]) -> Vec<i16> {
+    let mut previous: i16 = 0;
+    data.iter().map(|smp| {
+        let delta = smp.wrapping_sub(previous);
+        previous = *smp;
+        delta
+    }).collect()
+}
+
+/// Decodes delta-encoded 8-bit sample data to signed 8-bit PCM.
+pub fn delta_decode_8(data: &[i8]) -> Vec<i8> {
+    let mut value: i8 = 0;
+    data.iter().map(|delta| {
+        value = value.wrapping_add(*delta);
+        value
+    }).collect()
+}
+
+/// Decodes delta-encoded 16-bit sample data to signed 16-bit PCM.
+pub fn delta_decode_16(data: &[i16]) -> Vec<i16> {
+    let mut value: i16 = 0;
+    data.iter().map(|delta| {
+        value = value.wrapping_add(*delta);
+        value
+    }).collect()
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_delta_codec() {
+    let pcm8: Vec<i8> = vec![0, 10, -10, 127, -128, 5];
+    assert_eq!(delta_encode_8(&pcm8), vec![0, 10, -20, -119, 1, -123]);
+    assert_eq!(delta_decode_8(&delta_encode_8(&pcm8)), pcm8);
+
+    let pcm16: Vec<i16> = vec![1000, -1000, 32767, -32768, 0];
+    assert_eq!(delta_encode_16(&pcm16), vec![1000, -2000, -31769, 1, -32768]);
+    assert_eq!(delta_decode_16(&delta_encode_16(&pcm16)), pcm16);
+
+    assert!(delta_encode_16(&[]).is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sample_decoding() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    for smp in xm.instruments.iter().flat_map(|instr| instr.samples.iter()) {
+        let pcm: Vec<i16> = smp.decoder().collect();
+        assert_eq!(smp.decoder().len(), pcm.len());
+        assert_eq!(smp.pcm_16bit(), &pcm[..]);
+        assert_eq!(smp.data_16bit_signed(), pcm);
+
+        let mut buffer = [0i16; 40];
+        let count = smp.decode_into(&mut buffer);
+        assert_eq!(count, pcm.len().min(40));
+        assert_eq!(&buffer[..count], &pcm[..count]);
+    }
+
+    let smp = &xm.instruments[1].samples[0];
+    assert!(!smp.is_16bit());
+    let pcm8: Vec<i8> = smp.data_native().iter().map(|delta| *delta as i8).collect();
+    assert_eq!(smp.data_8bit_signed(), delta_decode_8(&pcm8));
+}
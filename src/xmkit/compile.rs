@@ -0,0 +1,325 @@
+use super::{XModule, TickEventKind};
+use super::state::{ChannelSim, GlobalVolumeSim};
+
+// Bytecode opcodes, see Bytecode.
+const OP_END: u8 = 0x00;
+const OP_NOTE_ON: u8 = 0x01;
+const OP_NOTE_OFF: u8 = 0x02;
+const OP_VOLUME: u8 = 0x03;
+const OP_PERIOD: u8 = 0x04;
+const OP_PANNING: u8 = 0x05;
+const OP_BPM: u8 = 0x06;
+const OP_WAIT: u8 = 0x80;
+
+/// A change on a channel that a sound engine has to carry out, as produced by XModule::engine_ticks().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineEvent {
+    /// A note is triggered (or retriggered) with the given instrument, at the given period.
+    NoteOn { note: u8, instrument: u8, period: u16 },
+    /// The note is released, by a key off note or Kxx.
+    NoteOff,
+    /// The volume changes (0..0x40). The value is the channel volume scaled by the global volume.
+    Volume(u8),
+    /// The period changes, eg. because of a portamento, vibrato, or arpeggio.
+    Period(u16),
+    /// The panning changes (0..0xff).
+    Panning(u8),
+}
+
+/// The events that happen on a single tick of the song.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EngineTick {
+    /// The sequence position being played.
+    pub order: u16,
+    /// The row being played.
+    pub row: u16,
+    /// The tick within the row, counted from 0. Ticks added by pattern delays (EEx) are counted as well.
+    pub tick: u16,
+    /// The BPM setting in effect, which determines the length of the tick (2.5 / BPM seconds).
+    pub bpm: u8,
+    /// The events of the tick, as pairs of channel number and event, ordered by channel.
+    pub events: Vec<(u8, EngineEvent)>,
+}
+
+/// A back-end for XModule::compile(), turning the engine-neutral event stream into the data format of a specific
+/// sound engine.
+pub trait CompileBackend {
+    /// The result of the compilation.
+    type Output;
+
+    /// Processes the next tick of the song. Called once for every tick, including ticks without events.
+    fn tick(&mut self, tick: &EngineTick);
+
+    /// Finishes the compilation after the last tick.
+    fn finish(self) -> Self::Output;
+}
+
+
+/// A back-end that produces a compact byte code, as a starting point for simple sound drivers.
+/// Instructions consist of an opcode byte followed by its operands, 16-bit values are stored little endian:
+///
+/// - `01 ch note instrument period_lo period_hi`: note on
+/// - `02 ch`: note off
+/// - `03 ch volume`: set volume (0..0x40)
+/// - `04 ch period_lo period_hi`: set period
+/// - `05 ch panning`: set panning
+/// - `06 bpm`: set BPM
+/// - `80+n`: wait n ticks (1..0x7f)
+/// - `00`: end of song
+#[derive(Clone, Debug, Default)]
+pub struct Bytecode {
+    data: Vec<u8>,
+    bpm: u8,
+    wait: u8,
+}
+
+impl Bytecode {
+
+    /// Creates a new byte code back-end.
+    pub fn new() -> Bytecode {
+        Bytecode::default()
+    }
+
+    fn flush_wait(&mut self) {
+        if self.wait > 0 {
+            self.data.push(OP_WAIT | self.wait);
+            self.wait = 0;
+        }
+    }
+}
+
+impl CompileBackend for Bytecode {
+    type Output = Vec<u8>;
+
+    fn tick(&mut self, tick: &EngineTick) {
+        if tick.bpm != self.bpm || !tick.events.is_empty() {
+            self.flush_wait();
+        }
+        if tick.bpm != self.bpm {
+            self.data.extend(&[OP_BPM, tick.bpm]);
+            self.bpm = tick.bpm;
+        }
+
+        for &(chan, event) in &tick.events {
+            match event {
+                EngineEvent::NoteOn { note, instrument, period } => {
+                    self.data.extend(&[OP_NOTE_ON, chan, note, instrument]);
+                    self.data.extend(&period.to_le_bytes());
+                },
+                EngineEvent::NoteOff => self.data.extend(&[OP_NOTE_OFF, chan]),
+                EngineEvent::Volume(volume) => self.data.extend(&[OP_VOLUME, chan, volume]),
+                EngineEvent::Period(period) => {
+                    self.data.extend(&[OP_PERIOD, chan]);
+                    self.data.extend(&period.to_le_bytes());
+                },
+                EngineEvent::Panning(panning) => self.data.extend(&[OP_PANNING, chan, panning]),
+            }
+        }
+
+        self.wait += 1;
+        if self.wait == 0x7f {
+            self.flush_wait();
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.flush_wait();
+        self.data.push(OP_END);
+        self.data
+    }
+}
+
+
+// The values last sent to the engine for a channel.
+#[derive(Clone, Copy, Default)]
+struct EngineChannel {
+    volume: u8,
+    period: u16,
+    panning: u8,
+}
+
+
+impl XModule {
+
+    /// Flattens the song into the ticks a sound engine has to play, following the sequence the way play_rows()
+    /// does. Effects are expanded into note, volume, period, and panning changes on the ticks where they happen,
+    /// so an engine only needs to apply the events of each tick. Note delays, retriggers, note cuts, key offs,
+    /// portamentos, volume and panning slides, arpeggios, vibrato, tremolo, and global volume are taken into account.
+    /// Envelopes, autovibrato (see AutoVibrato), and sample playback are left to the engine. Volume, period, and panning events are only
+    /// generated when the value changes.
+    pub fn engine_ticks(&self) -> Vec<EngineTick> {
+        let channel_count = self.channel_count() as usize;
+        let mut sims = vec![ChannelSim::new(); channel_count];
+        let mut sent = vec![EngineChannel { panning: 0x80, ..Default::default() }; channel_count];
+        let mut global = GlobalVolumeSim::new();
+        let mut ticks = Vec::new();
+
+        for play_row in self.play_rows() {
+            let ptn = self.patterns.get(play_row.pattern as usize);
+            let row = play_row.row as usize;
+            global.start_row(ptn.iter().flat_map(|ptn| &ptn.tracks), row);
+
+            for t in 0..play_row.ticks {
+                let mut events = Vec::new();
+                let tick = (t % play_row.tempo as u16) as u8;
+                let ptn = match ptn {
+                    Some(ptn) if t == 0 || tick > 0 => ptn,
+                    _ => {
+                        ticks.push(EngineTick { order: play_row.order, row: play_row.row, tick: t, bpm: play_row.bpm,
+                            events });
+                        continue;
+                    },
+                };
+
+                if tick > 0 {
+                    global.tick();
+                }
+
+                for (chan, trk) in ptn.tracks.iter().enumerate().take(channel_count) {
+                    let sim = &mut sims[chan];
+                    if tick == 0 {
+                        sim.start_row(self, trk, row);
+                    }
+                    else {
+                        sim.tick(self, tick);
+                    }
+
+                    let state = sim.state;
+                    let arpeggio = trk.arpeggio(play_row.row, play_row.tempo).ok().flatten()
+                        .map_or(0, |offsets| offsets[tick as usize]);
+                    let period = match state.period {
+                        0 => 0,
+                        period => (period as i32 + sim.vibrato_offset as i32).clamp(1, 32000) as u16,
+                    };
+                    let period = shift_period(period, arpeggio, self.amiga_ft());
+                    let volume = (state.volume as i16 + sim.tremolo_offset).clamp(0, 0x40) as u16;
+                    let volume = (volume * global.volume as u16 / 0x40) as u8;
+                    let sent = &mut sent[chan];
+                    let chan = chan as u8;
+
+                    for event in trk.tick_events(play_row.row, play_row.tempo).unwrap_or_default() {
+                        if event.tick != tick {
+                            continue;
+                        }
+                        match event.kind {
+                            TickEventKind::NoteOn { .. } | TickEventKind::Retrigger { .. } if state.note > 0 => {
+                                events.push((chan, EngineEvent::NoteOn { note: state.note,
+                                    instrument: state.instrument, period }));
+                                sent.period = period;
+                            },
+                            TickEventKind::KeyOff => events.push((chan, EngineEvent::NoteOff)),
+                            _ => (),
+                        }
+                    }
+
+                    if volume != sent.volume {
+                        events.push((chan, EngineEvent::Volume(volume)));
+                        sent.volume = volume;
+                    }
+                    if state.panning != sent.panning {
+                        events.push((chan, EngineEvent::Panning(state.panning)));
+                        sent.panning = state.panning;
+                    }
+                    if period != sent.period && period > 0 {
+                        events.push((chan, EngineEvent::Period(period)));
+                        sent.period = period;
+                    }
+                }
+
+                ticks.push(EngineTick { order: play_row.order, row: play_row.row, tick: t, bpm: play_row.bpm, events });
+            }
+        }
+
+        ticks
+    }
+
+    /// Compiles the song with the given back-end, passing it each tick returned by engine_ticks().
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::Path;
+    /// use xmkit::{Bytecode, XModule};
+    ///
+    /// let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    /// let bytecode = xm.compile(Bytecode::new());
+    /// assert_eq!(bytecode.last(), Some(&0));
+    /// ```
+    pub fn compile<B: CompileBackend>(&self, mut backend: B) -> B::Output {
+        for tick in self.engine_ticks() {
+            backend.tick(&tick);
+        }
+        backend.finish()
+    }
+}
+
+// Shifts a period up by the given number of semitones.
+fn shift_period(period: u16, semitones: u8, amiga_ft: bool) -> u16 {
+    if period == 0 || semitones == 0 {
+        period
+    }
+    else if amiga_ft {
+        (period as f64 / 2f64.powf(semitones as f64 / 12.0)).round() as u16
+    }
+    else {
+        period.saturating_sub(semitones as u16 * 64).max(1)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_compile() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ticks = xm.engine_ticks();
+    assert_eq!(ticks.len(), xm.play_rows().iter().map(|row| row.ticks as usize).sum::<usize>());
+
+    // C-4 on channel 0 and A-4 with C20 on channel 2
+    let first = &ticks[0];
+    assert_eq!((first.order, first.row, first.tick, first.bpm), (0, 0, 0, 125));
+    assert!(matches!(first.events[0], (0, EngineEvent::NoteOn { note: 49, instrument: 1, .. })));
+    assert!(first.events.contains(&(2, EngineEvent::Volume(0x20))));
+
+    let at = |row: u16, tick: u16| ticks.iter().find(|t| (t.order, t.row, t.tick) == (0, row, tick)).unwrap();
+    // ED3 delays the D-3 on channel 1 to tick 3
+    assert!(!at(2, 0).events.iter().any(|(chan, _)| *chan == 1));
+    assert!(matches!(at(2, 3).events[0], (1, EngineEvent::NoteOn { note: 39, .. })));
+    // 108 slides the period up on every tick but the first, 200 has no porta down memory to slide with
+    let period = |row: u16, tick: u16| at(row, tick).events.iter().find_map(|event| match event {
+        (2, EngineEvent::Period(period)) => Some(*period),
+        _ => None,
+    });
+    assert_eq!(period(1, 0), None);
+    assert_eq!(period(1, 2).unwrap() + 32, period(1, 1).unwrap());
+    assert!((0..6).all(|tick| period(3, tick).is_none()));
+    // 446 starts at the zero crossing of the sine, then raises the period on each tick
+    assert_eq!(period(7, 1), None);
+    assert!((2..5).all(|tick| period(7, tick).unwrap() < period(7, tick + 1).unwrap()));
+    // 037 arpeggio on channel 0, and its key off on row 12
+    assert!(at(4, 1).events.iter().any(|event| matches!(event, (0, EngineEvent::Period(_)))));
+    assert_eq!(at(12, 0).events.first(), Some(&(0, EngineEvent::NoteOff)));
+
+    let bytecode = xm.compile(Bytecode::new());
+    assert_eq!(&bytecode[..2], &[OP_BPM, 125]);
+    assert_eq!(&bytecode[2..4], &[OP_NOTE_ON, 0]);
+    assert_eq!(bytecode.last(), Some(&OP_END));
+
+    // walk the instructions, counting the ticks waited
+    let (mut pos, mut waited) = (0, 0);
+    while bytecode[pos] != OP_END {
+        let op = bytecode[pos];
+        pos += match op {
+            OP_NOTE_ON => 6,
+            OP_PERIOD => 4,
+            OP_VOLUME | OP_PANNING => 3,
+            OP_NOTE_OFF | OP_BPM => 2,
+            _ => {
+                assert!(op > OP_WAIT);
+                waited += (op & 0x7f) as usize;
+                1
+            },
+        };
+    }
+    assert_eq!((pos, waited), (bytecode.len() - 1, ticks.len()));
+}
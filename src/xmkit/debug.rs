@@ -0,0 +1,164 @@
+use std::fmt;
+use super::{note_name, XModule, XMPattern, XMTrack, XMInstrument, XMSample, XMCell};
+
+impl fmt::Debug for XModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XModule")
+            .field("name", &self.name())
+            .field("tracker_name", &self.tracker_name())
+            .field("channels", &self.channel_count())
+            .field("amiga_ft", &self.amiga_ft())
+            .field("tempo", &self.tempo())
+            .field("bpm", &self.bpm())
+            .field("sequence", &self.sequence())
+            .field("restart_pos", &self.restart_pos())
+            .field("patterns", &self.patterns)
+            .field("instruments", &self.instruments)
+            .finish()
+    }
+}
+
+impl fmt::Display for XModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" ({} channels, {} patterns, {} instruments, {})", self.name(), self.channel_count(),
+            self.patterns.len(), self.instruments.len(), self.tracker_name())
+    }
+}
+
+impl fmt::Debug for XMPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XMPattern")
+            .field("rows", &self.len())
+            .field("tracks", &self.tracks)
+            .finish()
+    }
+}
+
+impl fmt::Display for XMPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} rows, {} channels", self.len(), self.channel_count())
+    }
+}
+
+// Lists the non-empty cells of a track by row.
+struct Cells<'a>(&'a XMTrack);
+
+impl<'a> fmt::Debug for Cells<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries((0..self.0.cells.len())
+                .map(|row| (row, self.0.cell_unchecked(row)))
+                .filter(|(_, cell)| *cell != XMCell::default()))
+            .finish()
+    }
+}
+
+impl fmt::Debug for XMTrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XMTrack")
+            .field("rows", &self.cells.len())
+            .field("cells", &Cells(self))
+            .finish()
+    }
+}
+
+impl fmt::Display for XMTrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let used = (0..self.cells.len()).filter(|row| self.cell_unchecked(*row) != XMCell::default()).count();
+        write!(f, "{} rows, {} used", self.cells.len(), used)
+    }
+}
+
+/// Displays the cell the way trackers do, eg. "C-4 01 40 C20". Empty fields are displayed as dots.
+impl fmt::Display for XMCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let byte = |value: Option<u8>| match value {
+            Some(v) if v > 0 => format!("{:02X}", v),
+            _ => "..".to_string(),
+        };
+        let fx = effect_name(self).unwrap_or_else(|| "...".to_string());
+
+        write!(f, "{} {} {} {}", note_name(self.note.unwrap_or(0)), byte(self.instrument), byte(self.volume), fx)
+    }
+}
+
+// The effect of the cell the way trackers display it, eg. "C20", or None if the cell has no effect.
+pub(crate) fn effect_name(cell: &XMCell) -> Option<String> {
+    let cmd = cell.fx_command.unwrap_or(0);
+    let param = cell.fx_param.unwrap_or(0);
+    if cmd == 0 && param == 0 {
+        return None;
+    }
+    let cmd_char = std::char::from_digit(cmd as u32 % 36, 36).unwrap_or('?').to_ascii_uppercase();
+    Some(format!("{}{:02X}", cmd_char, param))
+}
+
+impl fmt::Debug for XMInstrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XMInstrument")
+            .field("name", &self.name())
+            .field("sample_numbers", &self.sample_numbers())
+            .field("volume_envelope", &self.volume_envelope_points())
+            .field("volume_type", &self.volume_type())
+            .field("volume_sustain", &self.volume_sustain())
+            .field("volume_loop", &self.volume_loop_start().zip(self.volume_loop_end()))
+            .field("panning_envelope", &self.panning_envelope_points())
+            .field("panning_type", &self.panning_type())
+            .field("panning_sustain", &self.panning_sustain())
+            .field("panning_loop", &self.panning_loop_start().zip(self.panning_loop_end()))
+            .field("vibrato", &(self.vibrato_type(), self.vibrato_sweep(), self.vibrato_depth(), self.vibrato_rate()))
+            .field("volume_fadeout", &self.volume_fadeout())
+            .field("samples", &self.samples)
+            .finish()
+    }
+}
+
+impl fmt::Display for XMInstrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" ({} samples)", self.name(), self.samples.len())
+    }
+}
+
+impl fmt::Debug for XMSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XMSample")
+            .field("name", &self.name())
+            .field("len", &self.len())
+            .field("is_16bit", &self.is_16bit())
+            .field("loop_type", &self.loop_type())
+            .field("loop_start", &self.loop_start())
+            .field("loop_len", &self.loop_len())
+            .field("volume", &self.volume())
+            .field("panning", &self.panning())
+            .field("finetune", &self.finetune())
+            .field("relative_note", &self.relative_note())
+            .finish()
+    }
+}
+
+impl fmt::Display for XMSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" ({} bytes, {}-bit)", self.name(), self.len(), if self.is_16bit() { 16 } else { 8 })
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_debug_display() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert!(xm.to_string().starts_with("\"xmkit test module\" (4 channels, 3 patterns, 2 instruments, "));
+    assert_eq!(xm.patterns[2].to_string(), "4 rows, 4 channels");
+    assert_eq!(xm.instruments[1].to_string(), "\"bass\" (2 samples)");
+    assert_eq!(xm.instruments[1].samples[1].to_string(), "\"high\" (64 bytes, 16-bit)");
+    assert_eq!(xm.patterns[0].tracks[2].cell(0).unwrap().to_string(), "A-4 01 .. C20");
+    assert_eq!(xm.patterns[0].tracks[0].cell(12).unwrap().to_string(), "=== .. .. ...");
+
+    assert_eq!(format!("{:?}", xm.patterns[2].tracks[1]), "XMTrack { rows: 4, cells: {1: XMCell { note: Some(51), \
+        instrument: Some(1), volume: Some(80), fx_command: None, fx_param: None }} }");
+    let debug = format!("{:?}", xm);
+    assert!(debug.starts_with("XModule { name: \"xmkit test module\""));
+    assert!(debug.contains("XMSample { name: \"square\", len: 32, is_16bit: false"));
+}
@@ -0,0 +1,212 @@
+use std::sync::{Arc, Weak};
+use super::{XModule, XMPattern, XMInstrument, XMSample, PatternPacking, XM_SAMPLE_HEADER_SIZE};
+use super::cells::PackedCell;
+
+// The state of a module, pattern, or instrument when it was parsed (or last marked as unmodified), used to tell
+// whether it has been modified since, and to write unmodified parts back exactly as they were stored.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Original {
+    // the header as held by the parsed part, which may differ from the stored one, eg. when it was padded
+    header: Vec<u8>,
+    // the stored bytes: the complete pattern, or the instrument header and sample headers without the sample data.
+    // For modules, this is the trailing data.
+    bytes: Arc<Vec<u8>>,
+    // the sample data of an instrument; sample data that is modified or replaced no longer points to the same data
+    samples: Vec<Weak<Vec<u8>>>,
+}
+
+impl Original {
+    pub(crate) fn new(header: Vec<u8>, bytes: Vec<u8>, samples: &[XMSample]) -> Original {
+        Original { header, bytes: Arc::new(bytes), samples: samples.iter().map(|smp| Arc::downgrade(&smp.data)).collect() }
+    }
+}
+
+
+impl XModule {
+
+    /// Returns true if the module has been modified since it was parsed, or since mark_unmodified() was called.
+    /// This includes changes to the header, to the number of patterns and instruments, to any pattern or
+    /// instrument (see XMPattern::is_modified() and XMInstrument::is_modified()), and to the trailing data.
+    /// Modules that were not parsed from XM data always count as modified.
+    pub fn is_modified(&self) -> bool {
+        let original = match &self.original {
+            Some(original) => original,
+            None => return true,
+        };
+        original.header != self.header || *original.bytes != self.trailing
+            || self.patterns.len() != self.pattern_count() as usize
+            || self.instruments.len() != self.instrument_count() as usize
+            || self.patterns.iter().any(|ptn| ptn.is_modified())
+            || self.instruments.iter().any(|instr| instr.is_modified())
+    }
+
+    /// Marks the module as unmodified, as an editor would after saving it. Modified patterns and instruments take
+    /// their current encoding as written by to_bytes() as their new original, so later calls to to_bytes() reuse
+    /// it until they are modified again.
+    pub fn mark_unmodified(&mut self) {
+        for ptn in self.patterns.iter_mut().filter(|ptn| ptn.is_modified()) {
+            ptn.original = Some(Original::new(ptn.header.clone(), ptn.to_bytes(PatternPacking::Canonical), &[]));
+        }
+        for instr in self.instruments.iter_mut().filter(|instr| instr.is_modified()) {
+            instr.original = Some(Original::new(instr.header.clone(), instr.encode_headers(), &instr.samples));
+        }
+        XModule::write_u16(&mut self.header, super::XM_PATTERN_COUNT, self.patterns.len() as u16);
+        XModule::write_u16(&mut self.header, super::XM_INSTRUMENT_COUNT, self.instruments.len() as u16);
+        self.original = Some(Original::new(self.header.clone(), self.trailing.clone(), &[]));
+    }
+}
+
+
+impl XMPattern {
+
+    /// Returns true if the pattern no longer matches the data it was parsed from, or if it was not parsed from XM
+    /// data. XModule::to_bytes() writes unmodified patterns exactly as they were stored, and packs modified ones.
+    pub fn is_modified(&self) -> bool {
+        self.original_bytes().is_none()
+    }
+
+    // Returns the bytes the pattern was parsed from, if they still decode to the pattern as it is.
+    pub(crate) fn original_bytes(&self) -> Option<&[u8]> {
+        let original = self.original.as_ref()?;
+        let bytes = &original.bytes[..];
+        let rows = self.len() as usize;
+        if original.header != self.header || bytes.len() < self.header.len()
+            || self.tracks.iter().any(|trk| trk.cells.len() != rows) {
+            return None;
+        }
+
+        // no packed data means the pattern is empty
+        let mut offset = self.header.len();
+        if offset == bytes.len() {
+            let empty = self.tracks.iter().all(|trk| trk.cells.iter().all(PackedCell::is_empty));
+            return if empty { Some(bytes) } else { None };
+        }
+
+        for row in 0..rows {
+            for trk in &self.tracks {
+                let (fields, len) = decode_cell(bytes.get(offset..)?)?;
+                if (0..5).any(|field| fields[field] != trk.cells[row].get(field)) {
+                    return None;
+                }
+                offset += len;
+            }
+        }
+
+        if offset == bytes.len() { Some(bytes) } else { None }
+    }
+}
+
+// Decodes a packed or unpacked cell the way XMPattern::parse() does. Returns the fields and the number of bytes the
+// cell takes up, or None if the data is incomplete.
+fn decode_cell(data: &[u8]) -> Option<([Option<u8>; 5], usize)> {
+    let ctrl = *data.first()?;
+    if ctrl & 0x80 == 0 {
+        let cell = data.get(..5)?;
+        return Some(([Some(cell[0]), Some(cell[1]), Some(cell[2]), Some(cell[3]), Some(cell[4])], 5));
+    }
+
+    let mut fields = [None; 5];
+    let mut len = 1;
+    for (bit, field) in fields.iter_mut().enumerate() {
+        if ctrl & (1 << bit) != 0 {
+            *field = Some(*data.get(len)?);
+            len += 1;
+        }
+    }
+    Some((fields, len))
+}
+
+
+impl XMInstrument {
+
+    /// Returns true if the instrument or its samples no longer match the data they were parsed from, or if the
+    /// instrument was not parsed from XM data. XModule::to_bytes() writes the headers of unmodified instruments
+    /// exactly as they were stored.
+    pub fn is_modified(&self) -> bool {
+        self.original_bytes().is_none()
+    }
+
+    // Returns the stored instrument header and sample headers the instrument was parsed from, if the instrument
+    // and its samples are unchanged.
+    pub(crate) fn original_bytes(&self) -> Option<&[u8]> {
+        let original = self.original.as_ref()?;
+        let bytes = &original.bytes[..];
+        let sample_headers = bytes.len().checked_sub(self.samples.len() * XM_SAMPLE_HEADER_SIZE)?;
+        let unchanged = original.header == self.header && original.samples.len() == self.samples.len()
+            && self.samples.iter().zip(&original.samples).enumerate().all(|(nr, (smp, data))| {
+                let offset = sample_headers + nr * XM_SAMPLE_HEADER_SIZE;
+                data.as_ptr() == Arc::as_ptr(&smp.data) && smp.header[..] == bytes[offset..offset + XM_SAMPLE_HEADER_SIZE]
+            });
+
+        if unchanged { Some(bytes) } else { None }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_dirty_tracking() {
+    use std::fs;
+
+    let data = fs::read("test.xm").unwrap();
+    let mut xm = XModule::parse(data.clone()).unwrap();
+    assert!(!xm.is_modified());
+    assert!(xm.patterns.iter().all(|ptn| !ptn.is_modified()));
+    assert!(xm.instruments.iter().all(|instr| !instr.is_modified()));
+    assert!(XModule::default().is_modified());
+
+    // a pattern with non-canonical packing is written back as it was stored, until it is modified
+    let unpacked = xm.to_bytes_with_packing(PatternPacking::Unpacked);
+    let mut xm_unpacked = XModule::parse(unpacked.clone()).unwrap();
+    assert_eq!(xm_unpacked.to_bytes(), unpacked);
+    xm_unpacked.patterns[2].tracks[0].cells[0].set_note(Some(50));
+    assert!(xm_unpacked.patterns[2].is_modified() && !xm_unpacked.patterns[1].is_modified());
+    assert!(xm_unpacked.is_modified());
+    let bytes = xm_unpacked.to_bytes();
+    assert!(bytes.len() < unpacked.len());
+    assert_eq!(XModule::parse(bytes).unwrap().patterns[2].tracks[0].note(0).unwrap(), 50);
+
+    // changing a value back makes the pattern unmodified again
+    let note = XModule::parse(unpacked.clone()).unwrap().patterns[2].tracks[0].cells[0].note();
+    xm_unpacked.patterns[2].tracks[0].cells[0].set_note(note);
+    assert!(!xm_unpacked.is_modified());
+
+    // replacing a track, or adding a pattern
+    xm.patterns[0].tracks.swap(0, 1);
+    assert!(xm.patterns[0].is_modified());
+    xm.patterns[0].tracks.swap(0, 1);
+    xm.patterns.push(XMPattern::empty(64, 4));
+    assert!(xm.is_modified());
+    xm.mark_unmodified();
+    assert!(!xm.is_modified());
+    assert_eq!(XModule::parse(xm.to_bytes()).unwrap().pattern_count(), 4);
+
+    // sample data and sample settings
+    xm.instruments[0].samples[0].normalize(0.5);
+    assert!(xm.instruments[0].is_modified() && !xm.instruments[1].is_modified());
+    xm.mark_unmodified();
+    assert!(!xm.instruments[0].is_modified());
+    xm.instruments[1].samples[0].set_tuning_from_rate(44100);
+    assert!(xm.instruments[1].is_modified());
+    xm.instruments[1].samples[0] = xm.instruments[0].samples[0].clone();
+    assert!(xm.instruments[1].is_modified());
+    let bytes = xm.to_bytes();
+    assert_eq!(XModule::parse(bytes.clone()).unwrap().to_bytes(), bytes);
+
+    // instrument headers are written as they were stored, even if they were padded when parsing
+    let mut short = data[..0x150].to_vec();
+    let mut xm = XModule::parse(data).unwrap();
+    xm.patterns.truncate(0);
+    xm.instruments.truncate(1);
+    xm.mark_unmodified();
+    let instr = xm.instruments[0].to_bytes();
+    let header_size = u32::from_le_bytes([instr[0], instr[1], instr[2], instr[3]]) as usize;
+    XModule::write_u16(&mut short, super::XM_PATTERN_COUNT, 0);
+    XModule::write_u16(&mut short, super::XM_INSTRUMENT_COUNT, 1);
+    short.extend(&33u32.to_le_bytes());
+    short.extend(&instr[4..33]);
+    short.extend(&instr[header_size..]);
+    let xm = XModule::parse(short.clone()).unwrap();
+    assert_eq!(xm.instruments[0].to_bytes().len(), instr.len());
+    assert_eq!(xm.to_bytes(), short);
+}
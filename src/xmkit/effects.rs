@@ -0,0 +1,161 @@
+use super::{XMTrack, XMParseError, QuirksMode, XMCell, XM_EFFECTS, XM_EFFECTS_WITH_MEMORY, XM_FX_5XX, XM_FX_6XX,
+    XM_FX_AXX, XM_FX_E5X, XM_FX_EEX, XM_FX_RXX, XM_FX_TXX, XM_FX_X1X, XM_FX_X2X};
+
+/// An effect command, as identified by the XM_FX_* constants. Extended effects (E1x..EEx, X1, X2) are considered
+/// seperate effects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Effect(u8);
+
+impl Effect {
+    /// Returns the effect for the given XM_FX_* constant, or None if the command is not a valid effect.
+    pub fn new(fx_command: u8) -> Option<Effect> {
+        if XM_EFFECTS.contains(&fx_command) { Some(Effect(fx_command)) } else { None }
+    }
+
+    /// Returns the effect and its parameter for the raw effect command and parameter bytes of a pattern cell, or None
+    /// if the cell does not contain a valid effect. The parameter of extended effects is the lower nibble.
+    pub fn from_raw(fx_command: u8, fx_param: u8) -> Option<(Effect, u8)> {
+        match fx_command {
+            0xe => Effect::new(0xe0 | fx_param >> 4).map(|fx| (fx, fx_param & 0xf)),
+            0x21 => Effect::new(0x21 + (fx_param >> 4)).filter(|fx| fx.0 == XM_FX_X1X || fx.0 == XM_FX_X2X)
+                .map(|fx| (fx, fx_param & 0xf)),
+            _ => Effect::new(fx_command).map(|fx| (fx, fx_param)),
+        }
+    }
+
+    /// Returns the XM_FX_* constant of the effect.
+    pub fn command(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if the effect is extended (E1x..EEx, X1, X2), ie. its parameter is a single nibble.
+    pub fn is_extended(self) -> bool {
+        self.0 > XM_FX_TXX
+    }
+
+    /// Returns true if the effect remembers its last non-zero parameter, so that a parameter of 0 continues with the
+    /// previous one. The effects with memory are listed in XM_EFFECTS_WITH_MEMORY.
+    pub fn has_memory(self) -> bool {
+        XM_EFFECTS_WITH_MEMORY.contains(&self.0)
+    }
+
+    /// Returns true if setting the parameter of the other effect also sets the parameter memory of this effect,
+    /// according to the given QuirksMode. Every effect with memory shares it with itself.
+    pub fn shares_memory_with(self, other: Effect, quirks: QuirksMode) -> bool {
+        let volume_slides = [XM_FX_AXX, XM_FX_5XX, XM_FX_6XX];
+
+        self == other || (quirks.shared_volume_slide_memory() && volume_slides.contains(&self.0)
+            && volume_slides.contains(&other.0))
+    }
+
+    /// Returns the parameter that the effect uses while it is not set: 8 (no finetune) for E5x, 0 otherwise.
+    pub fn default_param(self) -> u8 {
+        if self.0 == XM_FX_E5X { 8 } else { 0 }
+    }
+}
+
+
+/// The effect parameters currently in effect on a channel, including effect memory. Step it through the rows of a
+/// track with step() to follow how the parameters change, as fx() does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelEffectMemory {
+    quirks: QuirksMode,
+    params: Vec<u8>,
+}
+
+impl ChannelEffectMemory {
+
+    /// Creates the effect memory of a channel at the start of a song, interpreting effect memory according to the
+    /// given QuirksMode.
+    pub fn new(quirks: QuirksMode) -> ChannelEffectMemory {
+        let mut params = vec![0; XM_FX_EEX as usize + 1];
+        params[XM_FX_E5X as usize] = Effect(XM_FX_E5X).default_param();
+        ChannelEffectMemory { quirks, params }
+    }
+
+    /// Returns the parameter currently in effect for the given effect.
+    pub fn param(&self, fx: Effect) -> u8 {
+        self.params[fx.0 as usize]
+    }
+
+    /// Updates the memory with the contents of the next row.
+    pub fn step(&mut self, cell: &XMCell) {
+        let raw_cmd = cell.fx_command;
+        let row_fx = raw_cmd.and_then(|cmd| Effect::from_raw(cmd, cell.fx_param.unwrap_or(0)));
+        let row_param = cell.fx_param.and(row_fx.map(|(_, param)| param));
+
+        for fx in XM_EFFECTS.iter().map(|cmd| Effect(*cmd)) {
+            let mut param = self.param(fx);
+            if cell.note.is_some() && self.quirks.note_resets_fx_memory() {
+                param = fx.default_param();
+            }
+
+            match (row_fx, row_param) {
+                (Some((other, _)), Some(p)) if fx.shares_memory_with(other, self.quirks) => {
+                    if fx.0 == XM_FX_RXX && self.quirks.retrig_nibble_memory() {
+                        if p & 0xf0 != 0 { param = (param & 0xf) | (p & 0xf0); }
+                        if p & 0xf != 0 { param = (param & 0xf0) | (p & 0xf); }
+                    }
+                    else if p > 0 || !fx.has_memory() { param = p; }
+                },
+                (Some((other, _)), None) if fx.shares_memory_with(other, self.quirks) => (),
+                // extended effects only forget their parameter on rows without any effect
+                _ => if !fx.has_memory() && (!fx.is_extended() || raw_cmd.is_none()) {
+                    param = fx.default_param();
+                },
+            }
+
+            self.params[fx.0 as usize] = param;
+        }
+    }
+
+    /// Updates the memory with the contents of the given row of the track.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn step_row(&mut self, trk: &XMTrack, row: u16) -> Result<(), XMParseError> {
+        self.step(&trk.cell(row)?);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_effect_memory() {
+    use super::{XM_FX_1XX, XM_FX_8XX, XM_FX_E1X};
+
+    let porta = Effect::new(XM_FX_1XX).unwrap();
+    assert!(porta.has_memory());
+    assert!(!Effect::new(XM_FX_8XX).unwrap().has_memory());
+    assert_eq!(Effect::new(0x13), None);
+    assert_eq!(Effect::from_raw(0xe, 0x13), Some((Effect::new(XM_FX_E1X).unwrap(), 3)));
+    assert_eq!(Effect::from_raw(0x21, 0x32), None);
+
+    let vol_slide = Effect::new(XM_FX_AXX).unwrap();
+    let vibrato_vol = Effect::new(XM_FX_6XX).unwrap();
+    assert!(!vol_slide.shares_memory_with(vibrato_vol, QuirksMode::Generic));
+    assert!(vol_slide.shares_memory_with(vibrato_vol, QuirksMode::FT2));
+
+    let trk = XMTrack::from_columns(
+        &[Some(49), None, None, Some(50), None],
+        &[None; 5],
+        &[None; 5],
+        &[Some(XM_FX_1XX), Some(XM_FX_1XX), Some(0xe), Some(0xe), None],
+        &[Some(0x20), Some(0), Some(0x14), Some(0x10), None],
+    );
+    let fine_porta = Effect::new(XM_FX_E1X).unwrap();
+    let mut memory = ChannelEffectMemory::new(QuirksMode::FT2);
+    let mut generic = ChannelEffectMemory::new(QuirksMode::Generic);
+    let mut params = Vec::new();
+    for row in 0..5 {
+        memory.step_row(&trk, row).unwrap();
+        generic.step_row(&trk, row).unwrap();
+        params.push((memory.param(porta), memory.param(fine_porta), generic.param(fine_porta)));
+        assert_eq!(generic.param(porta), trk.fx(XM_FX_1XX, row).unwrap());
+        assert_eq!(generic.param(fine_porta), trk.fx(XM_FX_E1X, row).unwrap());
+    }
+    // E10 continues with the parameter of E14, unless the note on the same row resets the memory
+    assert_eq!(params, vec![(0x20, 0, 0), (0x20, 0, 0), (0x20, 4, 4), (0x20, 4, 0), (0x20, 4, 0)]);
+    assert!(memory.step_row(&trk, 5).is_err());
+}
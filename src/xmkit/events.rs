@@ -0,0 +1,121 @@
+use super::{XMPattern, XM_FX_3XX, XM_FX_5XX, XM_FX_CXX, XM_FX_KXX};
+
+/// A note played in a pattern, with its duration in rows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoteEvent {
+    /// The channel the note is played on, counted from 0.
+    pub channel: u8,
+    /// The row on which the note is triggered.
+    pub start_row: u16,
+    /// The number of rows the note is held for, at least 1.
+    pub duration_rows: u16,
+    /// The note (1..96).
+    pub note: u8,
+    /// The instrument used, counted from 1, or 0 if no instrument has been set in the pattern.
+    pub instrument: u8,
+    /// The volume set with the note through the volume column or Cxx (0..0x40). Notes without a volume setting
+    /// use 0x40.
+    pub velocity: u8,
+}
+
+
+impl XMPattern {
+
+    /// Returns the notes played in the pattern as events with a start row and a duration, ordered by start row and
+    /// then by channel. A note lasts until the next note is triggered on the channel, until it is released (key off,
+    /// Kxx) or cut (ECx, or a volume of 0 set through the volume column or Cxx), or until the end of the pattern.
+    /// Rows on which a note is released or cut on a later tick count towards the note. Notes played with tone
+    /// portamento (3xx, 5xx, volume column Mx) are not triggered, and extend the note they glide from instead.
+    /// Notes held over from the previous pattern are not included.
+    pub fn events(&self) -> Vec<NoteEvent> {
+        let mut events = Vec::new();
+
+        for (chan, trk) in self.tracks.iter().enumerate() {
+            let mut instrument = 0;
+            let mut current: Option<NoteEvent> = None;
+
+            for row in 0..trk.cells.len() {
+                let cell = trk.cell_unchecked(row);
+                let note = cell.note.unwrap_or(0);
+                let cmd = cell.fx_command.unwrap_or(0);
+                let param = cell.fx_param.unwrap_or(0);
+                let vol = cell.volume.unwrap_or(0);
+                if let Some(instr) = cell.instrument.filter(|instr| *instr > 0) {
+                    instrument = instr;
+                }
+
+                let tone_porta = cmd == XM_FX_3XX || cmd == XM_FX_5XX || vol >= 0xf0;
+                let velocity = match (vol, cmd) {
+                    (0x10..=0x50, _) => Some(vol - 0x10),
+                    (_, XM_FX_CXX) => Some(param.min(0x40)),
+                    _ => None,
+                };
+
+                // the row on which the note stops: None if it keeps playing, or Some(true) if this row still counts
+                let stop = match (note, cmd) {
+                    (97, _) => Some(false),
+                    (_, XM_FX_KXX) => Some(param > 0),
+                    (_, 0xe) if param >> 4 == 0xc => Some(param & 0xf > 0),
+                    _ if velocity == Some(0) => Some(false),
+                    _ => None,
+                };
+
+                if (1..97).contains(&note) && !(tone_porta && current.is_some()) {
+                    events.extend(current.take().map(|event| end_event(event, row)));
+                    current = Some(NoteEvent { channel: chan as u8, start_row: row as u16, duration_rows: 0, note,
+                        instrument, velocity: velocity.unwrap_or(0x40) });
+                }
+
+                if let Some(including) = stop {
+                    events.extend(current.take().map(|event| end_event(event, row + including as usize)));
+                }
+            }
+
+            events.extend(current.take().map(|event| end_event(event, trk.cells.len())));
+        }
+
+        events.sort_by_key(|event| (event.start_row, event.channel));
+        events
+    }
+}
+
+// Sets the duration of an event that ends before the given row. Events last at least one row.
+fn end_event(mut event: NoteEvent, end_row: usize) -> NoteEvent {
+    event.duration_rows = (end_row.saturating_sub(event.start_row as usize) as u16).max(1);
+    event
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_note_events() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let events = xm.patterns[0].events();
+
+    assert_eq!(events[0], NoteEvent { channel: 0, start_row: 0, duration_rows: 4, note: 49, instrument: 1,
+        velocity: 0x40 });
+    // G-4 on channel 0 inherits the instrument, and is held until the key off on row 12
+    assert!(events.contains(&NoteEvent { channel: 0, start_row: 8, duration_rows: 4, note: 56, instrument: 1,
+        velocity: 0x20 }));
+    // B-4 on channel 2 is played with tone portamento, extending the note set to volume 0x20 by C20
+    assert!(events.contains(&NoteEvent { channel: 2, start_row: 0, duration_rows: 16, note: 58, instrument: 1,
+        velocity: 0x20 }));
+    assert!(events.iter().all(|event| event.duration_rows > 0 && event.instrument > 0));
+    assert!(events.windows(2).all(|pair| (pair[0].start_row, pair[0].channel) < (pair[1].start_row, pair[1].channel)));
+    assert!(events.iter().all(|event| event.channel != 3));
+
+    // notes on a track never overlap
+    for chan in 0..4 {
+        let track: Vec<&NoteEvent> = events.iter().filter(|event| event.channel == chan).collect();
+        assert!(track.windows(2).all(|pair| pair[0].start_row + pair[0].duration_rows <= pair[1].start_row));
+    }
+
+    // the note on channel 1 is cut on tick 2 of row 11
+    let last = events.iter().rfind(|event| event.channel == 1).unwrap();
+    assert_eq!(last.start_row + last.duration_rows, 12);
+
+    assert_eq!(xm.patterns[2].events().iter().find(|event| event.channel == 1).unwrap().velocity, 0x40);
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use super::debug::effect_name;
+use super::{note_name, XModule, XMSample, XM_SAMPLE_LOOP_NONE, XM_SAMPLE_LOOP_PINGPONG};
+
+/// The file format used by XModule::export_samples().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// RIFF WAVE files, with loop points stored in a sampler chunk.
+    #[default]
+    Wav,
+    /// Headerless signed PCM data in the sample's native resolution, with 16-bit data in little endian byte order.
+    Raw,
+}
+
+// One line of the export manifest.
+struct ManifestEntry {
+    instrument: usize,
+    sample: usize,
+    instrument_name: String,
+    sample_name: String,
+    file: String,
+    frames: usize,
+    bits: u8,
+    loop_type: &'static str,
+    loop_start: usize,
+    loop_len: usize,
+    volume: u8,
+    panning: u8,
+    finetune: i8,
+    relative_note: i8,
+    rate: u32,
+}
+
+impl XModule {
+
+    /// Writes all samples of the module to the given directory, which is created if it does not exist. Files are
+    /// named after the instrument number (in hex, counted from 1), the sample index, and the sample name, for
+    /// example "01_00_square.wav". Empty samples are skipped.
+    /// Additionally, a manifest listing the instrument and sample number, the instrument and sample names, the file
+    /// name, length, resolution, loop points (in frames), volume, panning, and tuning of each sample is written as
+    /// "samples.json" and "samples.csv". Returns the paths of all files written.
+    ///
+    /// # Errors
+    /// Returns an io::Error if the directory cannot be created, or if writing any of the files fails.
+    pub fn export_samples(&self, dir: &Path, format: ExportFormat) -> io::Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        let mut manifest = Vec::new();
+
+        for (instr_nr, instr) in self.instruments.iter().enumerate() {
+            for (smp_nr, smp) in instr.samples.iter().enumerate().filter(|(_, smp)| !smp.is_empty()) {
+                let extension = match format {
+                    ExportFormat::Wav => "wav",
+                    ExportFormat::Raw => "raw",
+                };
+                let file = format!("{:02X}_{:02}_{}.{}", instr_nr + 1, smp_nr, file_name(&smp.name()), extension);
+                let data = match format {
+                    ExportFormat::Wav => smp.to_wav(),
+                    ExportFormat::Raw => raw_pcm(smp),
+                };
+                fs::write(dir.join(&file), data)?;
+                written.push(dir.join(&file));
+
+                let (loop_start, loop_len) = if smp.loop_type() == XM_SAMPLE_LOOP_NONE { (0, 0) }
+                    else { smp.loop_frames() };
+                manifest.push(ManifestEntry {
+                    instrument: instr_nr + 1,
+                    sample: smp_nr,
+                    instrument_name: instr.name(),
+                    sample_name: smp.name(),
+                    file,
+                    frames: smp.pcm_16bit().len(),
+                    bits: if smp.is_16bit() { 16 } else { 8 },
+                    loop_type: match smp.loop_type() {
+                        XM_SAMPLE_LOOP_NONE => "none",
+                        XM_SAMPLE_LOOP_PINGPONG => "pingpong",
+                        _ => "forward",
+                    },
+                    loop_start,
+                    loop_len,
+                    volume: smp.volume(),
+                    panning: smp.panning(),
+                    finetune: smp.finetune(),
+                    relative_note: smp.relative_note(),
+                    rate: smp.c4_rate().round() as u32,
+                });
+            }
+        }
+
+        fs::write(dir.join("samples.json"), manifest_json(&manifest))?;
+        written.push(dir.join("samples.json"));
+        fs::write(dir.join("samples.csv"), manifest_csv(&manifest))?;
+        written.push(dir.join("samples.csv"));
+
+        Ok(written)
+    }
+
+    /// Writes the cells played in the song as CSV, with one line per non-empty cell in the order in which they are
+    /// played (see play_rows()). The columns are the time in seconds at which the row starts, the sequence position,
+    /// pattern and row number, the channel (counted from 0), the note name (eg. "C-4", or "===" for key-off), the
+    /// instrument number, the raw volume column byte, and the effect (eg. "C20"). Empty fields are left blank.
+    /// The first line holds the column names.
+    ///
+    /// # Errors
+    /// Returns an io::Error if writing fails.
+    pub fn export_events_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "time,order,pattern,row,channel,note,instrument,volume,effect")?;
+        let field = |value: Option<u8>| value.map_or_else(String::new, |v| v.to_string());
+
+        for play_row in self.play_rows() {
+            let tracks = self.patterns.get(play_row.pattern as usize).map_or(&[][..], |ptn| &ptn.tracks[..]);
+            for (channel, trk) in tracks.iter().enumerate() {
+                let cell = trk.cell_unchecked(play_row.row as usize);
+                if cell == Default::default() {
+                    continue;
+                }
+                writeln!(writer, "{:.3},{},{},{},{},{},{},{},{}", play_row.start.as_secs_f64(), play_row.order,
+                    play_row.pattern, play_row.row, channel, cell.note.map_or_else(String::new, note_name),
+                    field(cell.instrument), field(cell.volume), effect_name(&cell).unwrap_or_default())?;
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+// Returns the sample data as signed PCM in the sample's native resolution.
+fn raw_pcm(smp: &XMSample) -> Vec<u8> {
+    if smp.is_16bit() {
+        smp.pcm_16bit().iter().flat_map(|v| vec![*v as u8, (*v >> 8) as u8]).collect()
+    }
+    else {
+        smp.data_8bit_signed().iter().map(|v| *v as u8).collect()
+    }
+}
+
+// Replaces characters that are not safe to use in file names.
+fn file_name(name: &str) -> String {
+    let name = name.trim();
+    if name.is_empty() {
+        return "unnamed".to_string();
+    }
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn manifest_json(manifest: &[ManifestEntry]) -> String {
+    let entries: Vec<String> = manifest.iter().map(|e| format!("  {{\"instrument\": {}, \"sample\": {}, \
+        \"instrument_name\": {}, \"sample_name\": {}, \"file\": {}, \"frames\": {}, \"bits\": {}, \
+        \"loop_type\": \"{}\", \"loop_start\": {}, \"loop_len\": {}, \"volume\": {}, \"panning\": {}, \
+        \"finetune\": {}, \"relative_note\": {}, \"rate\": {}}}",
+        e.instrument, e.sample, json_string(&e.instrument_name), json_string(&e.sample_name), json_string(&e.file),
+        e.frames, e.bits, e.loop_type, e.loop_start, e.loop_len, e.volume, e.panning, e.finetune, e.relative_note,
+        e.rate)).collect();
+
+    if entries.is_empty() {
+        "[]\n".to_string()
+    }
+    else {
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+fn manifest_csv(manifest: &[ManifestEntry]) -> String {
+    let mut csv = String::from("instrument,sample,instrument_name,sample_name,file,frames,bits,loop_type,loop_start,\
+        loop_len,volume,panning,finetune,relative_note,rate\n");
+    for e in manifest {
+        csv.push_str(&format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n", e.instrument, e.sample,
+            csv_string(&e.instrument_name), csv_string(&e.sample_name), csv_string(&e.file), e.frames, e.bits,
+            e.loop_type, e.loop_start, e.loop_len, e.volume, e.panning, e.finetune, e.relative_note, e.rate));
+    }
+    csv
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_export_samples() {
+    use super::SampleImportOptions;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let dir = std::env::temp_dir().join(format!("xmkit_export_{}", std::process::id()));
+
+    let written = xm.export_samples(&dir, ExportFormat::Wav).unwrap();
+    assert_eq!(written.len(), 5);
+    assert_eq!(written[2], dir.join("02_01_high.wav"));
+
+    let json = fs::read_to_string(dir.join("samples.json")).unwrap();
+    assert!(json.contains("\"file\": \"01_00_square.wav\", \"frames\": 32, \"bits\": 8, \"loop_type\": \"forward\""));
+    let csv = fs::read_to_string(dir.join("samples.csv")).unwrap();
+    assert_eq!(csv.lines().count(), 4);
+    assert!(csv.contains("2,1,\"bass\",\"high\",\"02_01_high.wav\",32,16,pingpong,4,16,"));
+
+    let smp = &xm.instruments[1].samples[1];
+    let imported = XMSample::from_wav(fs::File::open(&written[2]).unwrap(), SampleImportOptions::default()).unwrap();
+    assert!(imported.is_16bit());
+    assert_eq!(imported.pcm_16bit(), smp.pcm_16bit());
+    assert_eq!((imported.relative_note(), imported.finetune()), (smp.relative_note(), smp.finetune()));
+
+    let written = xm.export_samples(&dir, ExportFormat::Raw).unwrap();
+    assert_eq!(fs::read(&written[1]).unwrap(), xm.instruments[1].samples[0].data_8bit_signed().iter()
+        .map(|v| *v as u8).collect::<Vec<u8>>());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_export_events_csv() {
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut csv = Vec::new();
+    xm.export_events_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("time,order,pattern,row,channel,note,instrument,volume,effect"));
+
+    let events: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert!(events.iter().all(|fields| fields.len() == 9));
+    let cells: usize = xm.play_rows().iter().map(|play_row| xm.patterns[play_row.pattern as usize].tracks.iter()
+        .filter(|trk| !trk.cells[play_row.row as usize].is_empty()).count()).sum();
+    assert_eq!(events.len(), cells);
+
+    // events are in playback order, with times matching play_rows()
+    let times: Vec<f64> = events.iter().map(|fields| fields[0].parse().unwrap()).collect();
+    assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    let first = &events[0];
+    let (order, row, channel): (u16, u16, usize) = (first[1].parse().unwrap(), first[3].parse().unwrap(),
+        first[4].parse().unwrap());
+    let cell = xm.pattern_at_order(order).unwrap().tracks[channel].cell(row).unwrap();
+    assert_eq!(first[5], cell.note.map_or_else(String::new, note_name));
+}
@@ -0,0 +1,156 @@
+use super::XModule;
+
+// Chunks that are stored with a 32-bit length after their ID. FXnn plugin chunks are recognized separately.
+const LENGTH_PREFIXED: [&[u8; 4]; 5] = [b"text", b"MIDI", b"PNAM", b"CNAM", b"CHFX"];
+
+/// A block of extension data stored after the last instrument of a module, as written by OpenMPT and ModPlug
+/// Tracker. See XModule::extension_chunks().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtensionChunk {
+    /// The four character chunk ID, eg. "CNAM" for channel names.
+    pub id: [u8; 4],
+    /// The chunk data, without ID and length.
+    pub data: Vec<u8>,
+}
+
+impl ExtensionChunk {
+
+    /// Returns the chunk ID as text.
+    pub fn id_str(&self) -> String {
+        String::from_utf8_lossy(&self.id).into_owned()
+    }
+
+    /// Returns true if the chunk is stored with a length after its ID, rather than extending to a known end.
+    /// This is the case for all chunks except the instrument (XTPM) and song (STPM) property blocks.
+    pub fn is_length_prefixed(&self) -> bool {
+        is_length_prefixed(&self.id)
+    }
+
+    /// Encodes the chunk the way it is stored in a module.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.id.to_vec();
+        if self.is_length_prefixed() {
+            data.extend(&(self.data.len() as u32).to_le_bytes());
+        }
+        data.extend(&self.data);
+        data
+    }
+}
+
+fn is_length_prefixed(id: &[u8]) -> bool {
+    LENGTH_PREFIXED.iter().any(|known| &known[..] == id)
+        || (id.len() == 4 && &id[..2] == b"FX" && id[2..].iter().all(|digit| digit.is_ascii_digit()))
+}
+
+
+impl XModule {
+
+    /// Returns the data that follows the last instrument in the module. FT2 does not store any data there, but
+    /// OpenMPT and ModPlug Tracker store their extensions there, and some files carry arbitrary data such as
+    /// text. The data is kept when the module is written.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    /// Replaces the data that follows the last instrument in the module. Pass an empty vector to remove it.
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing = data;
+    }
+
+    /// Splits the trailing data into OpenMPT extension chunks. Chunks are recognized by their IDs: the song message
+    /// ("text"), MIDI configuration ("MIDI"), pattern names ("PNAM"), channel names ("CNAM"), channel plugins
+    /// ("CHFX"), plugin data ("FX00".."FX99"), and the extended instrument ("XTPM") and song ("STPM") properties.
+    /// Splitting stops at the first unrecognized or incomplete chunk; the remaining data is still available
+    /// through trailing_data().
+    pub fn extension_chunks(&self) -> Vec<ExtensionChunk> {
+        let data = &self.trailing;
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+
+        while offset + 4 <= data.len() {
+            let mut id = [0u8; 4];
+            id.copy_from_slice(&data[offset..offset + 4]);
+            let start = offset + 4;
+
+            let end = if is_length_prefixed(&id) {
+                if start + 4 > data.len() {
+                    break;
+                }
+                let end = start + 4 + XModule::read_usize(data, start);
+                if end > data.len() {
+                    break;
+                }
+                offset = start + 4;
+                end
+            }
+            else if &id == b"XTPM" {
+                offset = start;
+                self.instrument_properties_end(start)
+            }
+            else if &id == b"STPM" {
+                offset = start;
+                data.len()
+            }
+            else {
+                break;
+            };
+
+            chunks.push(ExtensionChunk { id, data: data[offset..end].to_vec() });
+            offset = end;
+        }
+
+        chunks
+    }
+
+    // Finds the end of the extended instrument properties starting at the given offset into the trailing data.
+    // Each property consists of a four character code and a 16-bit size, followed by the property data of each
+    // instrument. The properties end where the song properties start, or at the end of the data.
+    fn instrument_properties_end(&self, mut offset: usize) -> usize {
+        let data = &self.trailing;
+        while offset + 6 <= data.len() && &data[offset..offset + 4] != b"STPM" {
+            let size = XModule::read_u16(data, offset + 4) as usize * self.instruments.len();
+            if offset + 6 + size > data.len() {
+                return data.len();
+            }
+            offset += 6 + size;
+        }
+
+        offset.min(data.len())
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_extension_chunks() {
+    use std::fs;
+
+    let mut data = fs::read("test.xm").unwrap();
+    assert!(XModule::parse(data.clone()).unwrap().trailing_data().is_empty());
+
+    let text = ExtensionChunk { id: *b"text", data: b"hello".to_vec() };
+    let names = ExtensionChunk { id: *b"CNAM", data: [&b"lead"[..], &[0; 16], b"bass", &[0; 16]].concat() };
+    let plugin = ExtensionChunk { id: *b"FX00", data: vec![1, 2, 3] };
+    // one instrument property of 2 bytes, for each of the 2 instruments
+    let instruments = ExtensionChunk { id: *b"XTPM", data: [&b"VE.."[..], &[2, 0], &[1, 0, 2, 0]].concat() };
+    let song = ExtensionChunk { id: *b"STPM", data: [&b"DT.."[..], &[4, 0], &[125, 0, 0, 0]].concat() };
+    let chunks = vec![text, names, plugin, instruments, song];
+    for chunk in &chunks {
+        data.extend(chunk.to_bytes());
+    }
+
+    let xm = XModule::parse(data.clone()).unwrap();
+    assert_eq!(xm.extension_chunks(), chunks);
+    assert_eq!(xm.extension_chunks()[1].id_str(), "CNAM");
+    assert_eq!(xm.to_bytes(), data);
+
+    // unrecognized data ends the list of chunks, but is kept
+    let mut xm = xm;
+    let mut trailing = xm.trailing_data()[..17].to_vec();
+    trailing.extend(b"garbage");
+    xm.set_trailing_data(trailing.clone());
+    assert_eq!(xm.extension_chunks(), chunks[..1].to_vec());
+    assert!(xm.to_bytes().ends_with(&trailing));
+    xm.set_trailing_data(Vec::new());
+    assert!(xm.extension_chunks().is_empty());
+}
@@ -0,0 +1,372 @@
+//! A C API for loading modules with xmkit. Only available with the `ffi` feature. The declarations are in
+//! include/xmkit.h.
+//!
+//! Modules are parsed into opaque XMKitModule handles, which must be released with xmkit_free(). Patterns,
+//! instruments, and samples are addressed by their 0-based index. Functions that take an index return 0 or an
+//! error code if the index is out of range. Names are returned as raw bytes, as stored in the module, and are
+//! copied into caller-provided buffers. Panics are caught at the boundary, so they never unwind into C: the function
+//! returns null or an error value instead, and xmkit_last_error() describes the panic.
+//!
+//! include/xmkit.h is maintained by hand, to keep the build free of code generators. test_ffi() checks that every
+//! exported function is declared there, but changed signatures must be carried over manually.
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use super::{XModule, XMInstrument, XMSample};
+
+/// The success code of functions that return an int.
+pub const XMKIT_OK: c_int = 0;
+/// The error code for null pointers and indices that are out of range.
+pub const XMKIT_ERROR_RANGE: c_int = -1;
+
+/// The fields of a pattern cell. Empty fields are 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XMKitCell {
+    pub note: u8,
+    pub instrument: u8,
+    pub volume: u8,
+    pub fx_command: u8,
+    pub fx_param: u8,
+}
+
+/// An opaque handle to a parsed module.
+pub struct XMKitModule {
+    module: XModule,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Stores the error message returned by xmkit_last_error().
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Runs the body of an exported function, returning fallback and storing the panic message as the last error if it
+// panics, since unwinding into C is undefined behavior.
+fn catch_panic<T, F: FnOnce() -> T>(fallback: T, body: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned()).unwrap_or_default();
+        set_last_error(format!("internal error: {}", message));
+        fallback
+    })
+}
+
+// Copies bytes into a caller-provided buffer, truncating them if needed, and terminating them with a zero byte.
+// Returns the length of the bytes, so callers can detect truncation.
+unsafe fn copy_name(bytes: &[u8], buf: *mut c_char, buf_len: usize) -> usize {
+    if !buf.is_null() && buf_len > 0 {
+        let len = bytes.len().min(buf_len - 1);
+        ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buf, len);
+        *buf.add(len) = 0;
+    }
+    bytes.len()
+}
+
+unsafe fn module<'a>(xm: *const XMKitModule) -> Option<&'a XModule> {
+    xm.as_ref().map(|xm| &xm.module)
+}
+
+unsafe fn instrument<'a>(xm: *const XMKitModule, instrument: u16) -> Option<&'a XMInstrument> {
+    module(xm).and_then(|xm| xm.instruments.get(instrument as usize))
+}
+
+unsafe fn sample<'a>(xm: *const XMKitModule, instrument: u16, sample: u16) -> Option<&'a XMSample> {
+    self::instrument(xm, instrument).and_then(|instr| instr.samples.get(sample as usize))
+}
+
+
+/// Parses module data. Returns a handle to the module, or null if the data is not a valid module, in which case
+/// xmkit_last_error() describes the problem.
+///
+/// # Safety
+/// data must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_parse(data: *const u8, len: usize) -> *mut XMKitModule {
+    catch_panic(ptr::null_mut(), || {
+        if data.is_null() {
+            set_last_error("data is null".to_string());
+            return ptr::null_mut();
+        }
+
+        match XModule::parse(slice::from_raw_parts(data, len).to_vec()) {
+            Ok(module) => Box::into_raw(Box::new(XMKitModule { module })),
+            Err(e) => {
+                set_last_error(e.to_string());
+                ptr::null_mut()
+            },
+        }
+    })
+}
+
+/// Releases a module. Null handles are ignored.
+///
+/// # Safety
+/// xm must be null, or a handle returned by xmkit_parse() that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_free(xm: *mut XMKitModule) {
+    catch_panic((), || {
+        if !xm.is_null() {
+            drop(Box::from_raw(xm));
+        }
+    })
+}
+
+/// Returns the message of the last error that occurred on the calling thread, or null if there was none. The
+/// message remains valid until the next call of an xmkit function on the same thread.
+#[no_mangle]
+pub extern "C" fn xmkit_last_error() -> *const c_char {
+    catch_panic(ptr::null(), || {
+        LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+    })
+}
+
+/// Copies the module name into buf as a zero-terminated string, truncated to buf_len - 1 bytes. Returns the length
+/// of the full name, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle, and buf must be null or point to buf_len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_module_name(xm: *const XMKitModule, buf: *mut c_char, buf_len: usize) -> usize {
+    catch_panic(0, || module(xm).map_or(0, |xm| copy_name(&xm.name_bytes(), buf, buf_len)))
+}
+
+/// Returns the number of channels, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_channel_count(xm: *const XMKitModule) -> u16 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.channel_count() as u16))
+}
+
+/// Returns the number of patterns, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_pattern_count(xm: *const XMKitModule) -> u16 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.patterns.len() as u16))
+}
+
+/// Returns the number of instruments, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_instrument_count(xm: *const XMKitModule) -> u16 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.instruments.len() as u16))
+}
+
+/// Returns the length of the sequence, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_song_length(xm: *const XMKitModule) -> u16 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.len()))
+}
+
+/// Returns the pattern number at the given sequence position, or -1 if xm is null or the position is out of range.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_order(xm: *const XMKitModule, position: u16) -> c_int {
+    catch_panic(XMKIT_ERROR_RANGE, || {
+        module(xm).and_then(|xm| xm.sequence().get(position as usize).map(|ptn| *ptn as c_int))
+            .unwrap_or(XMKIT_ERROR_RANGE)
+    })
+}
+
+/// Returns the restart position, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_restart_pos(xm: *const XMKitModule) -> u16 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.restart_pos()))
+}
+
+/// Returns the default tempo (ticks per row), or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_tempo(xm: *const XMKitModule) -> u8 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.tempo()))
+}
+
+/// Returns the default BPM, or 0 if xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_bpm(xm: *const XMKitModule) -> u8 {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.bpm()))
+}
+
+/// Returns 1 if the module uses the Amiga frequency table, or 0 if it uses the linear frequency table or xm is null.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_amiga_ft(xm: *const XMKitModule) -> c_int {
+    catch_panic(0, || module(xm).map_or(0, |xm| xm.amiga_ft() as c_int))
+}
+
+/// Returns the number of rows of a pattern, or 0 if xm is null or the pattern does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_pattern_rows(xm: *const XMKitModule, pattern: u16) -> u16 {
+    catch_panic(0, || module(xm).and_then(|xm| xm.patterns.get(pattern as usize)).map_or(0, |ptn| ptn.len()))
+}
+
+/// Reads a pattern cell into cell. Returns XMKIT_OK, or XMKIT_ERROR_RANGE if a pointer is null, or the pattern,
+/// row, or channel does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle, and cell must be null or point to a writable XMKitCell.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_pattern_cell(xm: *const XMKitModule, pattern: u16, row: u16, channel: u16,
+    cell: *mut XMKitCell) -> c_int {
+
+    catch_panic(XMKIT_ERROR_RANGE, || {
+        let trk = module(xm).and_then(|xm| xm.patterns.get(pattern as usize))
+            .and_then(|ptn| ptn.tracks.get(channel as usize));
+        let (trk, cell) = match (trk, cell.as_mut()) {
+            (Some(trk), Some(cell)) if (row as usize) < trk.cells.len() => (trk, cell),
+            _ => return XMKIT_ERROR_RANGE,
+        };
+
+        let fields = trk.cells[row as usize];
+        *cell = XMKitCell { note: fields.note().unwrap_or(0), instrument: fields.instrument().unwrap_or(0),
+            volume: fields.volume().unwrap_or(0), fx_command: fields.fx_command().unwrap_or(0),
+            fx_param: fields.fx_param().unwrap_or(0) };
+        XMKIT_OK
+    })
+}
+
+/// Copies the name of an instrument into buf, like xmkit_module_name(). Returns the length of the full name, or 0
+/// if xm is null or the instrument does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle, and buf must be null or point to buf_len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_instrument_name(xm: *const XMKitModule, instrument: u16, buf: *mut c_char,
+    buf_len: usize) -> usize {
+
+    catch_panic(0, || self::instrument(xm, instrument).map_or(0, |instr| copy_name(&instr.name_bytes(), buf, buf_len)))
+}
+
+/// Returns the number of samples of an instrument, or 0 if xm is null or the instrument does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_sample_count(xm: *const XMKitModule, instrument: u16) -> u16 {
+    catch_panic(0, || self::instrument(xm, instrument).map_or(0, |instr| instr.samples.len() as u16))
+}
+
+/// Copies the name of a sample into buf, like xmkit_module_name(). Returns the length of the full name, or 0 if xm
+/// is null or the sample does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle, and buf must be null or point to buf_len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_sample_name(xm: *const XMKitModule, instrument: u16, sample: u16, buf: *mut c_char,
+    buf_len: usize) -> usize {
+
+    catch_panic(0, || self::sample(xm, instrument, sample).map_or(0, |smp| copy_name(&smp.name_bytes(), buf, buf_len)))
+}
+
+/// Copies up to len frames of a sample, decoded to signed 16-bit PCM, into buf. 8-bit samples are scaled up to 16
+/// bits. Returns the total number of frames of the sample, so passing a null buf returns the required length.
+/// Returns 0 if xm is null or the sample does not exist.
+///
+/// # Safety
+/// xm must be null or a valid handle, and buf must be null or point to len writable 16-bit values.
+#[no_mangle]
+pub unsafe extern "C" fn xmkit_sample_pcm(xm: *const XMKitModule, instrument: u16, sample: u16, buf: *mut i16,
+    len: usize) -> usize {
+
+    catch_panic(0, || {
+        let pcm = match self::sample(xm, instrument, sample) {
+            Some(smp) => smp.pcm_16bit(),
+            None => return 0,
+        };
+        if !buf.is_null() {
+            ptr::copy_nonoverlapping(pcm.as_ptr(), buf, pcm.len().min(len));
+        }
+        pcm.len()
+    })
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_ffi() {
+    use std::ffi::CStr;
+    use std::fs;
+
+    // every exported function is declared in the C header
+    let header = fs::read_to_string("include/xmkit.h").unwrap();
+    let source = fs::read_to_string("src/xmkit/ffi.rs").unwrap();
+    let exported: Vec<&str> = source.split("extern \"C\" fn ").skip(1)
+        .map(|decl| decl.split('(').next().unwrap()).collect();
+    assert!(exported.len() > 15);
+    for name in exported {
+        assert!(header.contains(&format!(" {}(", name)) || header.contains(&format!("*{}(", name)), "{}", name);
+    }
+
+    unsafe {
+        let data = fs::read("test.xm").unwrap();
+        assert!(xmkit_parse(data.as_ptr(), 10).is_null());
+        assert!(CStr::from_ptr(xmkit_last_error()).to_str().unwrap().contains("Corrupted"));
+        let xm = xmkit_parse(data.as_ptr(), data.len());
+        assert!(!xm.is_null());
+
+        let mut name = [0 as c_char; 8];
+        assert_eq!(xmkit_module_name(xm, name.as_mut_ptr(), name.len()), 17);
+        assert_eq!(CStr::from_ptr(name.as_ptr()).to_bytes(), b"xmkit t");
+        assert_eq!((xmkit_channel_count(xm), xmkit_pattern_count(xm), xmkit_song_length(xm)), (4, 3, 4));
+        assert_eq!((xmkit_order(xm, 1), xmkit_order(xm, 4)), (1, XMKIT_ERROR_RANGE));
+        assert_eq!((xmkit_tempo(xm), xmkit_bpm(xm), xmkit_amiga_ft(xm)), (6, 125, 0));
+        assert_eq!(xmkit_pattern_rows(xm, 1), 8);
+
+        // A-4 01 C20 on row 0, channel 2 of pattern 0
+        let mut cell = XMKitCell::default();
+        assert_eq!(xmkit_pattern_cell(xm, 0, 0, 2, &mut cell), XMKIT_OK);
+        assert_eq!(cell, XMKitCell { note: 58, instrument: 1, volume: 0, fx_command: 0xc, fx_param: 0x20 });
+        assert_eq!(xmkit_pattern_cell(xm, 1, 8, 0, &mut cell), XMKIT_ERROR_RANGE);
+        assert_eq!(xmkit_pattern_cell(xm, 0, 0, 0, ptr::null_mut()), XMKIT_ERROR_RANGE);
+
+        let module = &(*xm).module;
+        assert_eq!(xmkit_instrument_count(xm), module.instruments.len() as u16);
+        assert_eq!(xmkit_sample_count(xm, 0), module.instruments[0].samples.len() as u16);
+        assert_eq!(xmkit_instrument_name(xm, 0, ptr::null_mut(), 0), module.instruments[0].name_bytes().len());
+        assert_eq!(xmkit_sample_name(xm, 0, 99, name.as_mut_ptr(), name.len()), 0);
+        let frames = xmkit_sample_pcm(xm, 0, 0, ptr::null_mut(), 0);
+        let mut pcm = vec![0i16; frames];
+        assert_eq!(xmkit_sample_pcm(xm, 0, 0, pcm.as_mut_ptr(), pcm.len()), frames);
+        assert_eq!(pcm, module.instruments[0].samples[0].pcm_16bit());
+
+        xmkit_free(xm);
+        xmkit_free(ptr::null_mut());
+        assert_eq!(xmkit_channel_count(ptr::null()), 0);
+
+        // panics don't unwind into the caller
+        assert_eq!(catch_panic(XMKIT_ERROR_RANGE, || panic!("out of {}", "bounds")), XMKIT_ERROR_RANGE);
+        assert_eq!(CStr::from_ptr(xmkit_last_error()).to_str().unwrap(), "internal error: out of bounds");
+    }
+}
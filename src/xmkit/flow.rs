@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use super::{XModule, XM_FX_BXX, XM_FX_DXX, XM_FX_FXX};
+
+/// Where playback continues after leaving a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowTarget {
+    /// Playback continues at the given row of the given sequence position.
+    Position { order: u16, row: u16 },
+    /// The end of the sequence is reached, or a position jump (Bxx) leads beyond it, so playback continues at the
+    /// restart position.
+    Restart,
+    /// The tempo is set to 0 (F00), which stops the song.
+    Stop,
+}
+
+/// A pass through a sequence position: the pattern is entered at a row, and left at a row with a position jump
+/// (Bxx) or pattern break (Dxx), or at its last row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlowNode {
+    /// The sequence position.
+    pub order: u16,
+    /// The row at which the pattern is entered.
+    pub entry_row: u16,
+    /// The row at which the pattern is left.
+    pub exit_row: u16,
+    /// Where playback continues.
+    pub target: FlowTarget,
+    /// The index of the node that playback continues with, or None if the song stops here.
+    pub next: Option<usize>,
+}
+
+/// The control flow through the sequence, as determined by position jumps (Bxx) and pattern breaks (Dxx).
+/// See XModule::flow_graph().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlowGraph {
+    /// The passes through sequence positions that playback reaches, from the start of the song and after
+    /// restarting, in the order in which they are first played.
+    pub nodes: Vec<FlowNode>,
+    /// The sequence positions that are never played.
+    pub unreachable_orders: Vec<u16>,
+    /// The indices into nodes of a loop that playback enters and never leaves, so that the end of the sequence is
+    /// never reached. Empty if there is no such loop.
+    pub infinite_loop: Vec<usize>,
+}
+
+
+impl XModule {
+
+    /// Follows the control flow through the sequence, starting at the first sequence position and continuing at
+    /// the restart position once the end of the sequence is reached, until playback stops or repeats. Only
+    /// position jumps (Bxx), pattern breaks (Dxx), and song stops (F00) are taken into account; pattern loops (E6x)
+    /// do not change the sequence position, so they are ignored.
+    ///
+    /// The result tells which sequence positions are never played, and whether the song gets stuck in a loop of
+    /// jumps that never reaches the end of the sequence, in which case it has no defined duration.
+    pub fn flow_graph(&self) -> FlowGraph {
+        let sequence = self.sequence();
+        let mut graph = FlowGraph::default();
+        let mut indices: HashMap<(u16, u16), usize> = HashMap::new();
+        // the nodes in order of playback
+        let mut walk: Vec<usize> = Vec::new();
+        let mut position = if sequence.is_empty() { None } else { Some((0, 0)) };
+        let restart = if (self.restart_pos() as usize) < sequence.len() { self.restart_pos() } else { 0 };
+
+        while let Some((order, row)) = position {
+            if let Some(index) = indices.get(&(order, row)) {
+                // playback repeats; it is stuck unless the end of the sequence was reached along the way
+                let repeated = &walk[walk.iter().position(|node| node == index).unwrap_or(0)..];
+                if !repeated.iter().any(|node| graph.nodes[*node].target == FlowTarget::Restart) {
+                    graph.infinite_loop = repeated.to_vec();
+                }
+                break;
+            }
+
+            let node = self.flow_node(&sequence, order, row);
+            let index = graph.nodes.len();
+            if let Some(previous) = walk.last() {
+                graph.nodes[*previous].next = Some(index);
+            }
+            indices.insert((order, row), index);
+            walk.push(index);
+            graph.nodes.push(node);
+
+            position = match node.target {
+                FlowTarget::Position { order, row } => Some((order, row)),
+                FlowTarget::Restart => Some((restart, 0)),
+                FlowTarget::Stop => None,
+            };
+            if let Some(repeated) = position.and_then(|position| indices.get(&position)) {
+                graph.nodes[index].next = Some(*repeated);
+            }
+        }
+
+        graph.unreachable_orders = (0..sequence.len() as u16)
+            .filter(|order| !graph.nodes.iter().any(|node| node.order == *order))
+            .collect();
+        graph
+    }
+
+    // Plays the pattern at the given sequence position from the given row, until it is left.
+    fn flow_node(&self, sequence: &[u8], order: u16, entry_row: u16) -> FlowNode {
+        let ptn_nr = sequence[order as usize];
+        let rows = self.pattern_rows(ptn_nr);
+        let mut node = FlowNode { order, entry_row, exit_row: rows.saturating_sub(1) as u16, target: FlowTarget::Stop,
+            next: None };
+
+        for row in entry_row as usize..rows {
+            let mut jump: Option<usize> = None;
+            let mut pattern_break: Option<usize> = None;
+            let mut stop = false;
+            for trk in self.patterns.get(ptn_nr as usize).iter().flat_map(|ptn| ptn.tracks.iter()) {
+                let param = trk.cells[row].fx_param().unwrap_or(0);
+                match trk.cells[row].fx_command() {
+                    Some(XM_FX_FXX) if param == 0 => stop = true,
+                    Some(XM_FX_BXX) => jump = Some(param as usize),
+                    Some(XM_FX_DXX) => pattern_break = Some(((param >> 4) * 10 + (param & 0xf)) as usize),
+                    _ => (),
+                }
+            }
+
+            if stop || jump.is_some() || pattern_break.is_some() {
+                node.exit_row = row as u16;
+                if !stop {
+                    node.target = self.flow_target(sequence, jump.unwrap_or(order as usize + 1),
+                        pattern_break.unwrap_or(0));
+                }
+                return node;
+            }
+        }
+
+        node.target = self.flow_target(sequence, order as usize + 1, 0);
+        node
+    }
+
+    // The target of a jump to the given row of the given sequence position. Rows beyond the end of the pattern
+    // lead to the first row.
+    fn flow_target(&self, sequence: &[u8], order: usize, row: usize) -> FlowTarget {
+        match sequence.get(order) {
+            Some(ptn_nr) => FlowTarget::Position {
+                order: order as u16,
+                row: if row < self.pattern_rows(*ptn_nr) { row as u16 } else { 0 },
+            },
+            None => FlowTarget::Restart,
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_flow_graph() {
+    use std::path::Path;
+    use super::XM_SEQUENCE_BEGIN;
+
+    // the test module plays straight through its sequence of 0, 1, 2, 1
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let graph = xm.flow_graph();
+    let orders: Vec<u16> = graph.nodes.iter().map(|node| node.order).collect();
+    assert_eq!(orders, vec![0, 1, 2, 3]);
+    assert_eq!(graph.nodes[3].target, FlowTarget::Restart);
+    assert_eq!(graph.nodes[3].next, Some(xm.restart_pos() as usize));
+    assert!(graph.unreachable_orders.is_empty() && graph.infinite_loop.is_empty());
+
+    // jump from the first pattern to row 2 of position 2, skipping position 1, which the restart doesn't reach either
+    let mut jumping = xm.clone();
+    jumping.header[XM_SEQUENCE_BEGIN..XM_SEQUENCE_BEGIN + 4].copy_from_slice(&[0, 2, 1, 2]);
+    jumping.set_restart_pos(0).unwrap();
+    jumping.patterns[0].tracks[0].cells[3].set_fx_command(Some(XM_FX_BXX));
+    jumping.patterns[0].tracks[0].cells[3].set_fx_param(Some(2));
+    jumping.patterns[0].tracks[1].cells[3].set_fx_command(Some(XM_FX_DXX));
+    jumping.patterns[0].tracks[1].cells[3].set_fx_param(Some(2));
+    let graph = jumping.flow_graph();
+    assert_eq!(graph.nodes[0], FlowNode { order: 0, entry_row: 0, exit_row: 3,
+        target: FlowTarget::Position { order: 2, row: 2 }, next: Some(1) });
+    assert_eq!((graph.nodes[1].order, graph.nodes[1].entry_row), (2, 2));
+    assert_eq!(graph.unreachable_orders, vec![1]);
+    assert!(graph.infinite_loop.is_empty());
+
+    // position 3 jumps back to position 2, which never reaches the end of the sequence
+    let mut stuck = xm.clone();
+    stuck.header[XM_SEQUENCE_BEGIN + 3] = 2;
+    stuck.patterns[2].tracks[0].cells[0].set_fx_command(Some(XM_FX_BXX));
+    stuck.patterns[2].tracks[0].cells[0].set_fx_param(Some(3));
+    let graph = stuck.flow_graph();
+    let looped: Vec<u16> = graph.infinite_loop.iter().map(|node| graph.nodes[*node].order).collect();
+    assert_eq!(looped, vec![3]);
+    assert_eq!(graph.nodes[graph.infinite_loop[0]].next, Some(graph.infinite_loop[0]));
+
+    // F00 stops the song
+    let mut stopped = xm.clone();
+    stopped.patterns[1].tracks[0].cells[1].set_fx_command(Some(XM_FX_FXX));
+    stopped.patterns[1].tracks[0].cells[1].set_fx_param(Some(0));
+    let graph = stopped.flow_graph();
+    assert_eq!(graph.nodes.last().unwrap().target, FlowTarget::Stop);
+    assert_eq!(graph.unreachable_orders, vec![2, 3]);
+}
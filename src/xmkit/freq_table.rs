@@ -0,0 +1,190 @@
+use std::f64::consts::LN_2;
+use super::{XModule, XMTrack, XM_FX_1XX, XM_FX_2XX, XM_FX_3XX};
+use super::{frequency_for_period, period_for_note};
+use super::state::sample_for;
+
+/// The frequency tables supported by the XM format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrequencyTable {
+    /// Linear frequency table, on which all slides change the pitch at the same rate regardless of the note.
+    Linear,
+    /// Amiga frequency table, on which slides change the pitch faster on higher notes.
+    Amiga,
+}
+
+/// Describes the changes made by XModule::convert_frequency_table(), and the pitch error introduced by them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrequencyConversion {
+    /// The number of portamento effects whose parameters were recalculated.
+    pub adjusted: usize,
+    /// The number of portamento effects that were left unchanged, because they continue a previous slide through
+    /// effect memory, or because no note precedes them in the pattern.
+    pub unadjusted: usize,
+    /// The largest deviation of a recalculated slide speed from the original one, as a fraction of the original
+    /// (0.1 = 10%). This is caused by rounding and by clamping to the valid parameter range.
+    pub max_slide_error: f64,
+    /// The largest difference in pitch between the two tables of any note on any sample, in cents.
+    pub max_note_error: f64,
+}
+
+
+impl XModule {
+
+    /// Returns the frequency table used by the module.
+    pub fn frequency_table(&self) -> FrequencyTable {
+        if self.amiga_ft() { FrequencyTable::Amiga } else { FrequencyTable::Linear }
+    }
+
+    /// Switches the module to the given frequency table. Notes play at nearly the same pitch on both tables, but
+    /// slides do not: on the linear table a slide parameter corresponds to a fixed number of semitones, while on the
+    /// Amiga table its effect depends on the note. The parameters of portamento effects (1xx, 2xx, 3xx, E1x, E2x,
+    /// X1x, X2x, and volume column tone portamento) are therefore recalculated so that they slide at the original
+    /// speed on the note that is playing in the track when the slide starts. Slides that cover a wide range of
+    /// notes still sound different, and vibrato depths are not adjusted.
+    /// Returns a summary of the changes and the pitch error introduced. If the module already uses the target table,
+    /// nothing is changed.
+    pub fn convert_frequency_table(&mut self, target: FrequencyTable) -> FrequencyConversion {
+        let mut result = FrequencyConversion::default();
+        if target == self.frequency_table() {
+            return result;
+        }
+
+        for smp in self.instruments.iter().flat_map(|instr| instr.samples.iter()) {
+            for note in 0..96 {
+                let real_note = note + smp.relative_note() as i16;
+                let linear = frequency_for_period(period_for_note(real_note, smp.finetune(), false), false);
+                let amiga = frequency_for_period(period_for_note(real_note, smp.finetune(), true), true);
+                let cents = (1200.0 * (amiga / linear).log2()).abs();
+                if cents > result.max_note_error {
+                    result.max_note_error = cents;
+                }
+            }
+        }
+
+        // pitch change per period unit on the Amiga table, relative to the linear table, for a given Amiga period
+        let to_amiga = target == FrequencyTable::Amiga;
+        let scale = |amiga_period: f64| if to_amiga { amiga_period * LN_2 / 768.0 } else { 768.0 / (amiga_period * LN_2) };
+
+        let mut patterns = std::mem::take(&mut self.patterns);
+        for trk in patterns.iter_mut().flat_map(|ptn| ptn.tracks.iter_mut()) {
+            self.convert_track_slides(trk, &scale, &mut result);
+        }
+        self.patterns = patterns;
+
+        self.set_amiga_ft(to_amiga);
+        result
+    }
+
+    // Recalculates the portamento parameters in a track, based on the Amiga period of the note playing when each
+    // slide starts.
+    fn convert_track_slides<F: Fn(f64) -> f64>(&self, trk: &mut XMTrack, scale: &F, result: &mut FrequencyConversion) {
+        let mut instrument = 0;
+        let mut period: Option<f64> = None;
+
+        for row in 0..trk.cells.len() {
+            let note = trk.cells[row].note().unwrap_or(0);
+            instrument = trk.cells[row].instrument().filter(|instr| *instr > 0).unwrap_or(instrument);
+            let note_period = if note > 0 && note < 97 {
+                sample_for(self, instrument, note)
+                    .map(|smp| (note as i16 - 1 + smp.relative_note() as i16, smp.finetune()))
+                    .or(Some((note as i16 - 1, 0)))
+                    .map(|(real_note, finetune)| period_for_note(real_note, finetune, true) as f64)
+            }
+            else {
+                None
+            };
+
+            let cmd = trk.cells[row].fx_command().unwrap_or(0);
+            let param = trk.cells[row].fx_param().unwrap_or(0);
+            let vol = trk.cells[row].volume().unwrap_or(0);
+            let tone_porta = cmd == XM_FX_3XX || vol >= 0xf0;
+
+            // tone portamento slides towards the new note, so the speed is matched halfway between the two notes
+            let slide_period = match (period, note_period) {
+                (Some(from), Some(to)) if tone_porta => Some((from + to) / 2.0),
+                (_, Some(to)) if !tone_porta => Some(to),
+                (from, _) => from,
+            };
+            if note_period.is_some() {
+                period = note_period;
+            }
+
+            let (value, max) = match cmd {
+                XM_FX_1XX | XM_FX_2XX | XM_FX_3XX => (param, 0xff),
+                0xe | 0x21 if param >> 4 == 1 || param >> 4 == 2 => (param & 0xf, 0xf),
+                _ => (0, 0),
+            };
+            if max > 0 {
+                if let Some(new) = convert_slide(value, max, slide_period, scale, result) {
+                    trk.cells[row].set_fx_param(Some((param & !max) | new));
+                }
+            }
+            if vol >= 0xf0 {
+                if let Some(new) = convert_slide(vol & 0xf, 0xf, slide_period, scale, result) {
+                    trk.cells[row].set_volume(Some(0xf0 | new));
+                }
+            }
+        }
+    }
+}
+
+// Scales a slide parameter to the target table. Returns None if the parameter cannot be adjusted.
+fn convert_slide<F: Fn(f64) -> f64>(value: u8, max: u8, period: Option<f64>, scale: &F,
+    result: &mut FrequencyConversion) -> Option<u8> {
+    let period = match period {
+        Some(period) if value > 0 && period > 0.0 => period,
+        _ => {
+            result.unadjusted += 1;
+            return None;
+        },
+    };
+
+    let exact = value as f64 * scale(period);
+    let new = exact.round().clamp(1.0, max as f64);
+    let error = (new / exact - 1.0).abs();
+    if error > result.max_slide_error {
+        result.max_slide_error = error;
+    }
+    result.adjusted += 1;
+
+    Some(new as u8)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_convert_frequency_table() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert_eq!(xm.frequency_table(), FrequencyTable::Linear);
+    assert_eq!(xm.convert_frequency_table(FrequencyTable::Linear), FrequencyConversion::default());
+    let unmodified = xm.clone().convert_frequency_table(FrequencyTable::Amiga);
+
+    // C-5 on instrument 1, sliding up by 1/4 semitone per tick on the linear table
+    let trk = &mut xm.patterns[1].tracks[0];
+    trk.cells[1].set_fx_command(Some(XM_FX_1XX));
+    trk.cells[1].set_fx_param(Some(0x10));
+    trk.cells[2].set_fx_command(Some(0xe));
+    trk.cells[2].set_fx_param(Some(0x24));
+    trk.cells[3].set_fx_command(Some(XM_FX_1XX));
+    trk.cells[3].set_fx_param(Some(0));
+
+    let original = xm.clone();
+    let result = xm.convert_frequency_table(FrequencyTable::Amiga);
+    assert!(xm.amiga_ft());
+    assert_eq!((result.adjusted - unmodified.adjusted, result.unadjusted - unmodified.unadjusted), (2, 1));
+    // Amiga periods get coarse on very high notes
+    assert!(result.max_note_error > 10.0 && result.max_note_error < 100.0);
+    assert!(result.max_slide_error < 0.1);
+
+    // the Amiga period of C-5 is 856, so the slide needs 856 * ln(2) / 768 times as many period units
+    let trk = &xm.patterns[1].tracks[0];
+    assert_eq!(trk.cells[1].fx_param(), Some(12));
+    assert_eq!(trk.cells[2].fx_param(), Some(0x23));
+    assert_eq!(trk.cells[3].fx_param(), Some(0));
+
+    xm.convert_frequency_table(FrequencyTable::Linear);
+    assert!(!xm.amiga_ft());
+    assert_eq!(xm.patterns[1].tracks[0].cells[1].fx_param(), original.patterns[1].tracks[0].cells[1].fx_param());
+}
@@ -0,0 +1,233 @@
+//! Entry points for fuzzing the parsers, and a helper for minimizing failing inputs. Only available with the
+//! `fuzz` feature.
+//!
+//! A cargo-fuzz target only needs to pass its input to parse_any():
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| xmkit::fuzz::parse_any(data));
+//! ```
+
+use std::ops::Range;
+use super::{XModule, XMPattern, XMInstrument, XMSample, SampleImportOptions, XM_HEADER_SIZE, XM_PATTERN_COUNT,
+    XM_INSTRUMENT_COUNT};
+
+/// Passes the data to every parser of the crate: modules (strict and lenient), patterns, instruments, XI instruments,
+/// XP patterns, OpenMPT clipboard text, and WAV samples. Parsed modules are inspected further, by validating them,
+/// reading their extensions, decoding their sample data, and writing them back. There are no side effects, such as
+/// file access.
+///
+/// # Panics
+/// Panics only if a bug is found: either a parser panics, or a parsed module cannot be parsed again after writing it.
+pub fn parse_any(data: &[u8]) {
+    // most of the parsers take ownership of the data; short inputs are the interesting ones, so the copies are cheap
+    let channel_count = data.first().map_or(4, |chan| chan & 0x7f);
+
+    if let Ok(xm) = XModule::parse(data.to_vec()) {
+        inspect(&xm);
+    }
+    if let Ok((xm, _)) = XModule::parse_lenient(data.to_vec()) {
+        inspect(&xm);
+    }
+
+    if let Ok(ptn) = XMPattern::parse(data.to_vec(), channel_count) {
+        let _ = ptn.to_openmpt_clipboard();
+    }
+    if let Ok(ptn) = XMPattern::from_xp(data.to_vec(), channel_count) {
+        let _ = ptn.to_xp();
+    }
+    let _ = XMPattern::from_openmpt_clipboard(&String::from_utf8_lossy(data));
+
+    if let Ok(instr) = XMInstrument::parse(data.to_vec()) {
+        inspect_instrument(&instr);
+    }
+    if let Ok(instr) = XMInstrument::from_xi(data.to_vec()) {
+        inspect_instrument(&instr);
+    }
+    if let Ok(smp) = XMSample::from_wav(data, SampleImportOptions::default()) {
+        let _ = smp.validate();
+    }
+}
+
+// Exercises the accessors of a parsed module that interpret its data.
+fn inspect(xm: &XModule) {
+    let _ = xm.validate();
+    let _ = xm.openmpt_extensions();
+    let _ = xm.extension_chunks();
+    let _ = xm.channel_names();
+    for instr in &xm.instruments {
+        inspect_instrument(instr);
+    }
+
+    let data = xm.to_bytes();
+    if let Err(e) = XModule::parse(data) {
+        panic!("written module cannot be parsed: {}", e);
+    }
+}
+
+fn inspect_instrument(instr: &XMInstrument) {
+    let _ = instr.validate();
+    let _ = instr.volume_envelope_points();
+    let _ = instr.panning_envelope_points();
+    let _ = instr.sample_numbers();
+    for smp in &instr.samples {
+        let _ = smp.validate();
+        let _ = smp.decoder().count();
+    }
+}
+
+
+/// Reduces an input that triggers a bug to a smaller one that still triggers it. The reproduces function is called
+/// with candidate inputs, and returns true if the candidate still triggers the bug (eg. by running parse_any() under
+/// std::panic::catch_unwind). Returns the input unchanged if it does not reproduce the bug itself.
+///
+/// Inputs that look like modules are reduced structurally first: patterns and instruments are removed one by one,
+/// adjusting the counts in the module header, and the data is cut at pattern and instrument boundaries. After that,
+/// and for all other inputs, chunks of decreasing size are cut from the end of the data.
+pub fn minimize<F>(data: &[u8], mut reproduces: F) -> Vec<u8>
+    where F: FnMut(&[u8]) -> bool {
+
+    let mut data = data.to_vec();
+    if !reproduces(&data) {
+        return data;
+    }
+
+    loop {
+        let len = data.len();
+        minimize_structure(&mut data, &mut reproduces);
+        minimize_tail(&mut data, &mut reproduces);
+        if data.len() == len {
+            return data;
+        }
+    }
+}
+
+// Removes patterns and instruments, and cuts the data at structure boundaries, as long as the bug is reproduced.
+fn minimize_structure<F>(data: &mut Vec<u8>, reproduces: &mut F)
+    where F: FnMut(&[u8]) -> bool {
+
+    let Layout { patterns, instruments } = match layout(data) {
+        Some(layout) => layout,
+        None => return,
+    };
+
+    // later parts first, so the ranges of earlier ones stay valid
+    for (ranges, count_offset) in [(instruments, XM_INSTRUMENT_COUNT), (patterns, XM_PATTERN_COUNT)].iter() {
+        for range in ranges.iter().rev() {
+            let mut candidate = data.clone();
+            candidate.drain(range.clone());
+            let count = XModule::read_u16(&candidate, *count_offset);
+            XModule::write_u16(&mut candidate, *count_offset, count.saturating_sub(1));
+            if reproduces(&candidate) {
+                *data = candidate;
+            }
+        }
+    }
+
+    if let Some(layout) = layout(data) {
+        let boundaries = layout.patterns.iter().chain(layout.instruments.iter()).map(|range| range.start);
+        if let Some(end) = boundaries.filter(|end| reproduces(&data[..*end])).min() {
+            data.truncate(end);
+        }
+    }
+}
+
+// Cuts chunks of halving size from the end of the data, as long as the bug is reproduced.
+fn minimize_tail<F>(data: &mut Vec<u8>, reproduces: &mut F)
+    where F: FnMut(&[u8]) -> bool {
+
+    let mut chunk = data.len() / 2;
+    while chunk > 0 {
+        if chunk <= data.len() && reproduces(&data[..data.len() - chunk]) {
+            let len = data.len() - chunk;
+            data.truncate(len);
+        }
+        else {
+            chunk /= 2;
+        }
+    }
+}
+
+// The byte ranges of the complete patterns and instruments in module data.
+struct Layout {
+    patterns: Vec<Range<usize>>,
+    instruments: Vec<Range<usize>>,
+}
+
+// Determines the layout of module data, the way the module parser reads it. Incomplete parts at the end are not
+// included. Returns None if the data has no complete module header.
+fn layout(data: &[u8]) -> Option<Layout> {
+    if data.len() < XM_INSTRUMENT_COUNT + 2 || data.len() < XM_HEADER_SIZE + XModule::read_usize(data, XM_HEADER_SIZE) {
+        return None;
+    }
+
+    let mut offset = XM_HEADER_SIZE + XModule::read_usize(data, XM_HEADER_SIZE);
+    let mut patterns = Vec::new();
+    for _ in 0..XModule::read_u16(data, XM_PATTERN_COUNT) {
+        if data.len() < offset + 9 {
+            return Some(Layout { patterns, instruments: Vec::new() });
+        }
+        let size = XModule::read_usize(data, offset) + XModule::read_u16(data, offset + 7) as usize;
+        if size == 0 || data.len() < offset + size {
+            return Some(Layout { patterns, instruments: Vec::new() });
+        }
+        patterns.push(offset..offset + size);
+        offset += size;
+    }
+
+    let mut instruments = Vec::new();
+    for instr_nr in 0..XModule::read_u16(data, XM_INSTRUMENT_COUNT) as usize {
+        match XModule::instrument_size(data, instr_nr, offset, usize::MAX) {
+            Ok(size) if size > 0 && offset + size <= data.len() => {
+                instruments.push(offset..offset + size);
+                offset += size;
+            },
+            _ => break,
+        }
+    }
+
+    Some(Layout { patterns, instruments })
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_fuzz_helpers() {
+    use std::fs;
+
+    let data = fs::read("test.xm").unwrap();
+    parse_any(&data);
+    parse_any(&[]);
+    for len in (0..data.len()).step_by(97) {
+        parse_any(&data[..len]);
+    }
+    let mut corrupt = data.clone();
+    for pos in (0..corrupt.len()).step_by(13) {
+        corrupt[pos] ^= 0xa5;
+    }
+    parse_any(&corrupt);
+
+    // an instrument without samples whose header size points past the end, followed by one that is only recovered
+    // by the lenient parser
+    let mut xm = XModule::parse(data.clone()).unwrap();
+    xm.instruments.extend([XMInstrument::empty(), XMInstrument::empty()]);
+    let mut lenient_only = xm.to_bytes();
+    let len = lenient_only.len();
+    lenient_only[len - 57] = 0xae;
+    assert!(XModule::parse(lenient_only.clone()).is_err());
+    parse_any(&lenient_only);
+
+    // keep the 8 row pattern only
+    let has_short_pattern = |data: &[u8]| XModule::parse(data.to_vec())
+        .is_ok_and(|xm| xm.patterns.iter().any(|ptn| ptn.len() == 8));
+    let minimized = minimize(&data, has_short_pattern);
+    assert!(has_short_pattern(&minimized));
+    let xm = XModule::parse(minimized.clone()).unwrap();
+    assert_eq!((xm.patterns.len(), xm.instruments.len()), (1, 0));
+    assert!(minimized.len() < data.len() / 3);
+
+    assert_eq!(minimize(&data, |data| data.len() >= 100).len(), 100);
+    assert_eq!(minimize(b"abc", |_| false), b"abc");
+}
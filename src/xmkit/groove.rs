@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use super::{XModule, XMPattern, XM_FX_EDX};
+use super::cells::PackedCell;
+
+// The cycle lengths in rows that are tried when detecting a groove, shortest first.
+const GROOVE_PERIODS: [usize; 7] = [2, 3, 4, 6, 8, 12, 16];
+// The fraction of notes that must follow a groove for it to be detected.
+const GROOVE_CONSISTENCY: f64 = 0.9;
+
+/// A groove template: the note delay in ticks for each row of a repeating cycle of rows, starting at the first row
+/// of every pattern. For example, [0, 2] delays the notes on every second row by 2 ticks, which is a typical swing.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Groove {
+    /// The note delay for each row of the cycle, in ticks (0..=15).
+    pub delays: Vec<u8>,
+}
+
+impl Groove {
+
+    /// Creates a groove with the given note delays per row. Delays above 15 ticks are capped, since they can't be
+    /// expressed with EDx.
+    pub fn new(delays: &[u8]) -> Groove {
+        Groove { delays: delays.iter().map(|delay| (*delay).min(0xf)).collect() }
+    }
+
+    /// Creates a groove without delays, which moves all notes back onto their rows.
+    pub fn straight() -> Groove {
+        Groove::default()
+    }
+
+    /// Creates a swing groove that delays the second half of every two steps of the given number of rows. With
+    /// 4 rows per beat, swing(1, delay) swings 16th notes, and swing(2, delay) swings 8th notes.
+    pub fn swing(step: usize, delay: u8) -> Groove {
+        let mut delays = vec![0; step.max(1) * 2];
+        delays[step.max(1)] = delay;
+        Groove::new(&delays)
+    }
+
+    /// Returns the note delay in ticks for the given pattern row.
+    pub fn delay_at(&self, row: u16) -> u8 {
+        if self.delays.is_empty() { 0 } else { self.delays[row as usize % self.delays.len()] }
+    }
+
+    /// Returns true if the groove delays no rows.
+    pub fn is_straight(&self) -> bool {
+        self.delays.iter().all(|delay| *delay == 0)
+    }
+}
+
+
+impl XMPattern {
+
+    /// Sets the note delays (EDx) of all notes and key-offs to the delays of the given groove, for the row they are
+    /// on. Delays are removed where the groove has none, so Groove::straight() turns swung patterns into straight
+    /// ones. Notes whose effect column holds another effect are left unchanged. Delays should stay below the tempo
+    /// (ticks per row), or the notes are not played at all. Returns the number of cells changed.
+    pub fn apply_groove(&mut self, groove: &Groove) -> usize {
+        let mut changed = 0;
+        for trk in &mut self.tracks {
+            for (row, cell) in trk.cells.iter_mut().enumerate() {
+                if !cell.note().is_some_and(|note| (1..=97).contains(&note)) {
+                    continue;
+                }
+                let delay = groove.delay_at(row as u16);
+                let free = cell.fx_command().unwrap_or(0) == 0 && cell.fx_param().unwrap_or(0) == 0;
+                if note_delay(cell) == Some(delay) || (!free && note_delay(cell).is_none()) || (free && delay == 0) {
+                    continue;
+                }
+                cell.set_fx_command(if delay > 0 { Some(0xe) } else { None });
+                cell.set_fx_param(if delay > 0 { Some((XM_FX_EDX & 0xf) << 4 | delay) } else { None });
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+
+impl XModule {
+
+    /// Detects a groove made with note delays (EDx) in the patterns of the module, such as a swing that delays the
+    /// notes on every second row. The shortest cycle of rows (2, 3, 4, 6, 8, 12, or 16) is chosen for which at least
+    /// 90% of the notes are delayed like the other notes on the same row of the cycle, with the cycle starting at the
+    /// first row of every pattern. Cycles are only considered if every row of the cycle that has notes has at least
+    /// two of them. Returns None if no notes are delayed, or if the delays follow no such cycle.
+    pub fn detect_groove(&self) -> Option<Groove> {
+        let notes: Vec<(usize, u8)> = self.patterns.iter().flat_map(|ptn| ptn.tracks.iter())
+            .flat_map(|trk| trk.cells.iter().enumerate())
+            .filter(|(_, cell)| cell.note().is_some_and(|note| (1..97).contains(&note)))
+            .map(|(row, cell)| (row, note_delay(cell).unwrap_or(0)))
+            .collect();
+        if notes.iter().all(|(_, delay)| *delay == 0) {
+            return None;
+        }
+
+        GROOVE_PERIODS.iter().find_map(|&period| {
+            let mut counts: Vec<HashMap<u8, usize>> = vec![HashMap::new(); period];
+            for &(row, delay) in &notes {
+                *counts[row % period].entry(delay).or_default() += 1;
+            }
+            let delays: Vec<(u8, usize)> = counts.iter().map(|counts| counts.iter()
+                .max_by_key(|&(delay, count)| (*count, std::cmp::Reverse(*delay)))
+                .map_or((0, 0), |(delay, count)| (*delay, *count))).collect();
+            let matching: usize = delays.iter().map(|(_, count)| count).sum();
+            let repeated = counts.iter().all(|counts| counts.values().sum::<usize>() != 1);
+            let groove = Groove::new(&delays.iter().map(|(delay, _)| *delay).collect::<Vec<u8>>());
+            Some(groove).filter(|groove| repeated && !groove.is_straight()
+                && matching as f64 >= notes.len() as f64 * GROOVE_CONSISTENCY)
+        })
+    }
+
+    /// Applies the given groove to all patterns, see XMPattern::apply_groove(). Returns the number of cells changed.
+    pub fn apply_groove(&mut self, groove: &Groove) -> usize {
+        self.patterns.iter_mut().map(|ptn| ptn.apply_groove(groove)).sum()
+    }
+}
+
+// Returns the note delay (EDx) of the cell, or None if it has no note delay effect.
+fn note_delay(cell: &PackedCell) -> Option<u8> {
+    let param = cell.fx_param().unwrap_or(0);
+    Some(param & 0xf).filter(|_| cell.fx_command() == Some(0xe) && param >> 4 == XM_FX_EDX & 0xf)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_groove() {
+    use super::XM_FX_CXX;
+
+    let mut xm = XModule::default();
+    let mut ptn = XMPattern::empty(16, 2);
+    for row in 0..16 {
+        ptn.tracks[0].cells[row].set_note(Some(49));
+    }
+    ptn.tracks[1].cells[4].set_note(Some(97));
+    ptn.tracks[0].cells[6].set_fx_command(Some(XM_FX_CXX));
+    ptn.tracks[0].cells[6].set_fx_param(Some(0x20));
+    xm.patterns.push(ptn);
+    assert_eq!(xm.detect_groove(), None);
+
+    // swing the 8th notes, which leaves the note with the volume effect straight
+    assert_eq!(Groove::swing(2, 3), Groove::new(&[0, 0, 3, 0]));
+    assert_eq!(xm.apply_groove(&Groove::swing(2, 3)), 3);
+    let params: Vec<Option<u8>> = xm.patterns[0].tracks[0].cells[..8].iter().map(|cell| cell.fx_param()).collect();
+    assert_eq!(params, vec![None, None, Some(0xd3), None, None, None, Some(0x20), None]);
+    assert_eq!(xm.apply_groove(&Groove::swing(2, 3)), 0);
+
+    // a single note out of line still counts as a swing, but not two
+    assert_eq!(xm.detect_groove(), Some(Groove::new(&[0, 0, 3, 0])));
+    xm.patterns[0].tracks[0].cells[1].set_fx_command(Some(0xe));
+    xm.patterns[0].tracks[0].cells[1].set_fx_param(Some(0xd1));
+    assert_eq!(xm.detect_groove(), None);
+
+    // straightening removes all note delays, including on key-offs
+    xm.patterns[0].tracks[1].cells[4].set_fx_command(Some(0xe));
+    xm.patterns[0].tracks[1].cells[4].set_fx_param(Some(0xd2));
+    assert_eq!(xm.apply_groove(&Groove::straight()), 5);
+    assert!(xm.patterns[0].tracks.iter().flat_map(|trk| trk.cells.iter()).all(|cell| note_delay(cell).is_none()));
+    assert_eq!(xm.patterns[0].tracks[0].cells[6].fx_param(), Some(0x20));
+    assert_eq!(Groove::new(&[0, 20]).delay_at(3), 15);
+}
@@ -0,0 +1,81 @@
+use super::{XMPattern, XM_FX_EDX};
+use super::rng::Rng;
+
+impl XMPattern {
+
+    /// Makes the pattern sound less mechanical by randomly varying its notes. Volumes set in the volume column
+    /// (0x10..0x50) are moved up or down by up to volume_jitter. Notes with an empty effect column are delayed by up
+    /// to delay_jitter ticks (at most 15) with a note delay (EDx), which should stay below the tempo (ticks per row),
+    /// or the notes are not played at all. The same seed always produces the same result.
+    pub fn humanize(&mut self, volume_jitter: u8, delay_jitter: u8, seed: u64) {
+        let mut rng = Rng::new(seed);
+        let delay_jitter = delay_jitter.min(0xf);
+
+        for cell in self.tracks.iter_mut().flat_map(|trk| trk.cells.iter_mut()) {
+            if !cell.note().is_some_and(|note| (1..97).contains(&note)) {
+                continue;
+            }
+
+            if let Some(vol) = cell.volume().filter(|vol| (0x10..=0x50).contains(vol)) {
+                let jitter = rng.range(0, volume_jitter as u32 * 2) as i32 - volume_jitter as i32;
+                cell.set_volume(Some((vol as i32 + jitter).clamp(0x10, 0x50) as u8));
+            }
+
+            let delay = rng.range(0, delay_jitter as u32) as u8;
+            if delay > 0 && cell.fx_command().is_none() && cell.fx_param().is_none() {
+                cell.set_fx_command(Some(0xe));
+                cell.set_fx_param(Some((XM_FX_EDX & 0xf) << 4 | delay));
+            }
+        }
+    }
+
+    /// Moves all notes back onto the row by removing note delays (EDx), and rounds the volumes set in the volume
+    /// column to the nearest multiple of volume_step. A volume_step of 0 or 1 leaves volumes unchanged.
+    pub fn quantize(&mut self, volume_step: u8) {
+        for cell in self.tracks.iter_mut().flat_map(|trk| trk.cells.iter_mut()) {
+            if cell.fx_command() == Some(0xe) && cell.fx_param().unwrap_or(0) >> 4 == XM_FX_EDX & 0xf {
+                cell.set_fx_command(None);
+                cell.set_fx_param(None);
+            }
+
+            if let Some(vol) = cell.volume().filter(|vol| (0x10..=0x50).contains(vol) && volume_step > 1) {
+                let step = volume_step as u32;
+                let rounded = ((vol as u32 - 0x10 + step / 2) / step * step).min(0x40);
+                cell.set_volume(Some(rounded as u8 + 0x10));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_humanize() {
+    let mut ptn = XMPattern::empty(64, 2);
+    for row in 0..64 {
+        ptn.tracks[0].cells[row].set_note(Some(49));
+        ptn.tracks[0].cells[row].set_volume(Some(0x30));
+    }
+    ptn.tracks[0].cells[0].set_fx_command(Some(0xc));
+    ptn.tracks[0].cells[0].set_fx_param(Some(0x20));
+
+    let mut humanized = ptn.clone();
+    humanized.humanize(4, 2, 1);
+    let cells = &humanized.tracks[0].cells;
+    assert!(cells.iter().all(|cell| (0x2c..=0x34).contains(&cell.volume().unwrap())));
+    assert!(cells.iter().any(|cell| cell.volume() != Some(0x30)));
+    assert!(cells[1..].iter().any(|cell| cell.fx_param() == Some(0xd2)));
+    assert!(cells[1..].iter().all(|cell| cell.fx_param().unwrap_or(0xd0) & 0xf <= 2));
+    assert_eq!((cells[0].fx_command(), cells[0].fx_param()), (Some(0xc), Some(0x20)));
+    assert!(humanized.tracks[1].cells.iter().all(|cell| cell.is_empty()));
+
+    let mut again = ptn.clone();
+    again.humanize(4, 2, 1);
+    assert!(again.tracks[0].cells == humanized.tracks[0].cells);
+
+    // quantizing removes the delays and the volume variations
+    humanized.quantize(0x10);
+    let cells = &humanized.tracks[0].cells;
+    assert!(cells[1..].iter().all(|cell| cell.fx_command().is_none()));
+    assert!(cells.iter().all(|cell| cell.volume() == Some(0x30)));
+}
@@ -0,0 +1,298 @@
+use super::XMPattern;
+use super::cells::PackedCell;
+
+// The width of a channel in the tracker view, in characters: note (3), instrument (2), volume (2), effect (3).
+const TRACKER_FIELDS: [(usize, usize); 4] = [(0, 3), (3, 2), (5, 2), (7, 3)];
+const TRACKER_CHANNEL_WIDTH: usize = 10;
+
+const BACKGROUND: [u8; 3] = [0x14, 0x14, 0x1c];
+const HIGHLIGHT: [u8; 3] = [0x24, 0x24, 0x30];
+const SEPARATOR: [u8; 3] = [0x3c, 0x3c, 0x4c];
+const BLACK_KEY: [u8; 3] = [0x0c, 0x0c, 0x12];
+const KEY_OFF: [u8; 3] = [0x80, 0x80, 0x80];
+
+/// The layout of a pattern image, see XMPattern::render_png().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PatternImageStyle {
+    /// Channels side by side as in a tracker, with a colored block for every field that is set: notes colored by
+    /// pitch class, instruments by number, volumes by level, and effects by command.
+    #[default]
+    Tracker,
+    /// A piano roll with one column per key, covering the octaves used by the pattern. Notes are drawn from the row
+    /// they are triggered on until the next note or key-off on their channel, colored by channel.
+    PianoRoll,
+}
+
+/// Options for rendering patterns to images with `XMPattern::render_png`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatternImageOptions {
+    /// The layout of the image. Defaults to PatternImageStyle::Tracker.
+    pub style: PatternImageStyle,
+    /// The height of a row in pixels. Defaults to 2.
+    pub row_height: u32,
+    /// The width of a character (tracker view) or key (piano roll) in pixels. Defaults to 2.
+    pub column_width: u32,
+    /// Every this many rows, a row is highlighted, like the beat highlights of trackers. 0 disables highlights.
+    /// Defaults to 4.
+    pub highlight_rows: u16,
+}
+
+impl Default for PatternImageOptions {
+    fn default() -> PatternImageOptions {
+        PatternImageOptions { style: PatternImageStyle::Tracker, row_height: 2, column_width: 2, highlight_rows: 4 }
+    }
+}
+
+// An RGB image, in units of rows and columns until it is scaled.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas { width, height, pixels: vec![BACKGROUND; width * height] }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for row in y..(y + height).min(self.height) {
+            let start = row * self.width;
+            for pixel in &mut self.pixels[start + x.min(self.width)..start + (x + width).min(self.width)] {
+                *pixel = color;
+            }
+        }
+    }
+
+    // Returns the image scaled by the given factors as RGB bytes.
+    fn scaled(&self, x_scale: usize, y_scale: usize) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.pixels.len() * x_scale * y_scale * 3);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            let line: Vec<u8> = row.iter().flat_map(|pixel| pixel.repeat(x_scale)).collect();
+            for _ in 0..y_scale {
+                rgb.extend(&line);
+            }
+        }
+        rgb
+    }
+}
+
+
+impl XMPattern {
+
+    /// Renders the pattern as a PNG image, such as a thumbnail for a module archive. The image is a raster of colored
+    /// blocks without text, in the style set by the options. Patterns without rows or channels produce an image of
+    /// at least 1x1 pixels.
+    pub fn render_png(&self, options: PatternImageOptions) -> Vec<u8> {
+        let canvas = match options.style {
+            PatternImageStyle::Tracker => self.tracker_canvas(options.highlight_rows),
+            PatternImageStyle::PianoRoll => self.piano_roll_canvas(options.highlight_rows),
+        };
+        let (x_scale, y_scale) = (options.column_width.max(1) as usize, options.row_height.max(1) as usize);
+        encode_png(canvas.width * x_scale, canvas.height * y_scale, &canvas.scaled(x_scale, y_scale))
+    }
+
+    fn tracker_canvas(&self, highlight_rows: u16) -> Canvas {
+        let width = (self.tracks.len() * (TRACKER_CHANNEL_WIDTH + 1)).max(2) - 1;
+        let mut canvas = background(width, self.len() as usize, highlight_rows);
+        for (chn, trk) in self.tracks.iter().enumerate() {
+            let x = chn * (TRACKER_CHANNEL_WIDTH + 1);
+            canvas.fill(x + TRACKER_CHANNEL_WIDTH, 0, 1, canvas.height, SEPARATOR);
+            for (row, cell) in trk.cells.iter().enumerate() {
+                for (field, &(offset, len)) in TRACKER_FIELDS.iter().enumerate() {
+                    if let Some(color) = field_color(cell, field) {
+                        canvas.fill(x + offset, row, len, 1, color);
+                    }
+                }
+            }
+        }
+        canvas
+    }
+
+    fn piano_roll_canvas(&self, highlight_rows: u16) -> Canvas {
+        let notes = || self.tracks.iter().flat_map(|trk| trk.cells.iter()).filter_map(|cell| cell.note())
+            .filter(|note| (1..97).contains(note));
+        let lowest = notes().min().map_or(0, |note| (note as usize - 1) / 12 * 12);
+        let highest = notes().max().map_or(12, |note| ((note as usize - 1) / 12 + 1) * 12);
+        let mut canvas = background(highest - lowest, self.len() as usize, highlight_rows);
+        for key in (lowest..highest).filter(|key| [1, 3, 6, 8, 10].contains(&(key % 12))) {
+            canvas.fill(key - lowest, 0, 1, canvas.height, BLACK_KEY);
+        }
+
+        for (chn, trk) in self.tracks.iter().enumerate() {
+            let color = hue(chn as f64 * 0.618);
+            let mut playing: Option<(usize, usize)> = None;
+            for (row, cell) in trk.cells.iter().enumerate().map(|(row, cell)| (row, cell.note()))
+                .chain(Some((trk.cells.len(), Some(97)))) {
+                let note = match cell {
+                    Some(note @ 1..=97) => note as usize,
+                    _ => continue,
+                };
+                if let Some((key, start)) = playing.take() {
+                    canvas.fill(key - lowest, start, 1, row - start, color);
+                }
+                if note < 97 {
+                    playing = Some((note - 1, row));
+                }
+            }
+        }
+        canvas
+    }
+}
+
+// Returns a canvas with the given number of rows, with every highlight_rows-th row highlighted.
+fn background(width: usize, rows: usize, highlight_rows: u16) -> Canvas {
+    let mut canvas = Canvas::new(width.max(1), rows.max(1));
+    if highlight_rows > 0 {
+        for row in (0..rows).step_by(highlight_rows as usize) {
+            canvas.fill(0, row, canvas.width, 1, HIGHLIGHT);
+        }
+    }
+    canvas
+}
+
+// Returns the color of the given field (note, instrument, volume, effect) of the cell, or None if it is not set.
+fn field_color(cell: &PackedCell, field: usize) -> Option<[u8; 3]> {
+    match field {
+        0 => match cell.note()? {
+            0 => None,
+            97 => Some(KEY_OFF),
+            note => Some(hue((note - 1) as f64 % 12.0 / 12.0)),
+        },
+        1 => cell.instrument().filter(|instr| *instr > 0).map(|instr| hue(instr as f64 * 0.618)),
+        2 => match cell.volume()? {
+            vol @ 0x10..=0x50 => Some([0x40 + (vol - 0x10) * 2; 3]),
+            vol @ 0x60..=0xff => Some(hue(((vol >> 4) - 6) as f64 / 10.0 + 0.5)),
+            _ => None,
+        },
+        _ => match (cell.fx_command().unwrap_or(0), cell.fx_param().unwrap_or(0)) {
+            (0, 0) => None,
+            (cmd, _) => Some(hue(cmd as f64 / 36.0)),
+        },
+    }
+}
+
+// Returns a bright, saturated color with the given hue (0.0..1.0, wrapping around).
+fn hue(hue: f64) -> [u8; 3] {
+    let channel = |offset: f64| {
+        let pos = ((hue + offset).rem_euclid(1.0) * 6.0 - 3.0).abs() - 1.0;
+        (80.0 + pos.clamp(0.0, 1.0) * 160.0) as u8
+    };
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+// Encodes RGB pixel data as an 8-bit truecolor PNG. The image data is stored uncompressed, since thumbnails are small
+// and a deflate encoder would outweigh the savings.
+fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(rgb.len() + height);
+    for line in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend(line);
+    }
+
+    // zlib stream with stored deflate blocks of up to 65535 bytes
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if raw.is_empty() { vec![&[]] } else { raw.chunks(0xffff).collect() };
+    for (index, block) in blocks.iter().enumerate() {
+        zlib.push((index == blocks.len() - 1) as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(*block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (id, data) in [(b"IHDR", &header), (b"IDAT", &zlib), (b"IEND", &Vec::new())] {
+        png.extend((data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend(id);
+        png.extend(data);
+        let crc = crc32(&png[start..]);
+        png.extend(crc.to_be_bytes());
+    }
+    png
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_render_png() {
+    use std::path::Path;
+    use super::XModule;
+
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+    // decodes the pixels of a PNG written by encode_png()
+    let be32 = |data: &[u8], pos: usize| u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+    let pixels = |png: &[u8]| -> (u32, u32, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let (width, height) = (be32(png, 16), be32(png, 20));
+        let idat_len = be32(png, 33) as usize;
+        let zlib = &png[41..41 + idat_len];
+        let mut raw = Vec::new();
+        let mut pos = 2;
+        while pos < zlib.len() - 4 {
+            let len = zlib[pos + 1] as usize | (zlib[pos + 2] as usize) << 8;
+            raw.extend(&zlib[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+        }
+        assert_eq!(&zlib[zlib.len() - 4..], adler32(&raw).to_be_bytes());
+        let rgb = raw.chunks(width as usize * 3 + 1).flat_map(|line| line[1..].to_vec()).collect();
+        (width, height, rgb)
+    };
+    let pixel = |rgb: &[u8], width: u32, x: u32, y: u32| -> [u8; 3] {
+        let pos = ((y * width + x) * 3) as usize;
+        [rgb[pos], rgb[pos + 1], rgb[pos + 2]]
+    };
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ptn = &xm.patterns[0];
+    let png = ptn.render_png(PatternImageOptions::default());
+    let (width, height, rgb) = pixels(&png);
+    assert_eq!((width, height), (2 * (4 * 11 - 1), 2 * ptn.len() as u32));
+    assert_eq!(rgb.len() as u32, width * height * 3);
+    assert_eq!(pixel(&rgb, width, 2 * 10, 0), SEPARATOR);
+    let note = ptn.tracks[2].note_raw(0).unwrap().unwrap();
+    assert_eq!(pixel(&rgb, width, 2 * 22 + 1, 1), hue((note - 1) as f64 % 12.0 / 12.0));
+
+    let mut roll = XMPattern::empty(8, 2);
+    roll.tracks[0].cells[1].set_note(Some(13));
+    roll.tracks[0].cells[3].set_note(Some(97));
+    roll.tracks[1].cells[6].set_note(Some(26));
+    let options = PatternImageOptions { style: PatternImageStyle::PianoRoll, row_height: 1, column_width: 1,
+        highlight_rows: 0 };
+    let (width, height, rgb) = pixels(&roll.render_png(options));
+    assert_eq!((width, height), (24, 8));
+    assert_eq!([pixel(&rgb, width, 0, 0), pixel(&rgb, width, 0, 1), pixel(&rgb, width, 0, 2)],
+        [BACKGROUND, hue(0.0), hue(0.0)]);
+    assert_eq!(pixel(&rgb, width, 0, 3), BACKGROUND);
+    assert_eq!(pixel(&rgb, width, 1, 0), BLACK_KEY);
+    assert_eq!([pixel(&rgb, width, 13, 5), pixel(&rgb, width, 13, 6), pixel(&rgb, width, 13, 7)],
+        [BLACK_KEY, hue(0.618), hue(0.618)]);
+
+    // large images are split into several deflate blocks
+    let (width, height, rgb) = pixels(&XMPattern::empty(256, 32).render_png(PatternImageOptions::default()));
+    assert_eq!(rgb.len() as u32, width * height * 3);
+    assert_eq!(pixel(&rgb, width, 0, 2 * 4), HIGHLIGHT);
+    assert_eq!(pixel(&rgb, width, 0, 2 * 5), BACKGROUND);
+}
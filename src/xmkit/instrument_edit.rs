@@ -0,0 +1,189 @@
+use super::{Envelope, XModule, XMInstrument, XMParseError, XM_ENVELOPE_LOOP, XM_ENVELOPE_ON, XM_ENVELOPE_SUSTAIN};
+
+impl XMInstrument {
+
+    /// Reduces the instrument to the single sample that is mapped to the given note (1..96), and maps all notes to
+    /// it, for replayers that do not support multisampled instruments. Since each sample carries its own tuning,
+    /// notes that were mapped to the kept sample sound as before, and all other notes play the kept sample at their
+    /// original pitch. Instruments with a single sample are left unchanged, apart from the note mapping.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples, if the note is out of range, or if the note is
+    /// mapped to a sample that does not exist.
+    pub fn flatten_to_single_sample(&mut self, note: u8) -> Result<(), XMParseError> {
+        if note == 0 || note > 96 {
+            return Err(XMParseError::new(&format!("Invalid note {}.", note)));
+        }
+        let sample = match self.sample_numbers() {
+            Some(map) => map[note as usize - 1] as usize,
+            None => return Err(XMParseError::new("Cannot flatten instrument without samples.")),
+        };
+        if sample >= self.samples.len() {
+            return Err(XMParseError::new(&format!("Note {} is mapped to nonexistent sample {}.", note, sample)));
+        }
+
+        let kept = self.samples.swap_remove(sample);
+        self.samples = vec![kept];
+        self.header[27] = 1;
+        self.header[28] = 0;
+        for smp in self.header[33..129].iter_mut() {
+            *smp = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the points of the given envelope with the given (frame, value) pairs. Sustain and loop points that
+    /// refer to points beyond the new last point are moved to the last point. The envelope type flags are left
+    /// unchanged, see set_envelope_type().
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples, if there are more than 12 points, if the frame
+    /// numbers are not in ascending order, or if a value is greater than 64.
+    pub fn set_envelope_points(&mut self, envelope: Envelope, points: &[(u16, u16)]) -> Result<(), XMParseError> {
+        self.check_envelope_header()?;
+        if points.len() > 12 {
+            return Err(XMParseError::new(&format!("{} has {} points, at most 12 are allowed.", envelope,
+                points.len())));
+        }
+        if let Some(point) = (1..points.len()).find(|&point| points[point].0 <= points[point - 1].0) {
+            return Err(XMParseError::new(&format!("{} point {} is not positioned after the preceding point.",
+                envelope, point)));
+        }
+        if let Some(point) = points.iter().position(|&(_, value)| value > 64) {
+            return Err(XMParseError::new(&format!("{} point {} has value {}, at most 64 is allowed.", envelope,
+                point, points[point].1)));
+        }
+
+        let (points_offset, count_offset, sustain_offset, loop_start_offset, loop_end_offset) = envelope.offsets();
+        for point in 0..12 {
+            let (frame, value) = points.get(point).copied().unwrap_or((0, 0));
+            XModule::write_u16(&mut self.header, points_offset + point * 4, frame);
+            XModule::write_u16(&mut self.header, points_offset + point * 4 + 2, value);
+        }
+        self.header[count_offset] = points.len() as u8;
+        let last = points.len().saturating_sub(1) as u8;
+        for offset in &[sustain_offset, loop_start_offset, loop_end_offset] {
+            self.header[*offset] = self.header[*offset].min(last);
+        }
+        Ok(())
+    }
+
+    /// Sets the sustain point of the given envelope, counted from 0. The sustain flag is not changed.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples, or if the point does not exist.
+    pub fn set_envelope_sustain(&mut self, envelope: Envelope, point: u8) -> Result<(), XMParseError> {
+        self.check_envelope_point(envelope, point)?;
+        self.header[envelope.offsets().2] = point;
+        Ok(())
+    }
+
+    /// Sets the loop start and loop end points of the given envelope, counted from 0. The loop flag is not changed.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples, if either point does not exist, or if the loop
+    /// start comes after the loop end.
+    pub fn set_envelope_loop(&mut self, envelope: Envelope, start: u8, end: u8) -> Result<(), XMParseError> {
+        self.check_envelope_point(envelope, start)?;
+        self.check_envelope_point(envelope, end)?;
+        if start > end {
+            return Err(XMParseError::new(&format!("{} loop start point {} comes after loop end point {}.", envelope,
+                start, end)));
+        }
+        let (_, _, _, loop_start_offset, loop_end_offset) = envelope.offsets();
+        self.header[loop_start_offset] = start;
+        self.header[loop_end_offset] = end;
+        Ok(())
+    }
+
+    /// Sets the type flags of the given envelope, a combination of XM_ENVELOPE_ON, XM_ENVELOPE_SUSTAIN, and
+    /// XM_ENVELOPE_LOOP. Other bits are ignored.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples.
+    pub fn set_envelope_type(&mut self, envelope: Envelope, flags: u8) -> Result<(), XMParseError> {
+        self.check_envelope_header()?;
+        self.header[envelope.type_offset()] = flags & (XM_ENVELOPE_ON | XM_ENVELOPE_SUSTAIN | XM_ENVELOPE_LOOP);
+        Ok(())
+    }
+
+    // Instruments without samples do not store envelopes.
+    fn check_envelope_header(&self) -> Result<(), XMParseError> {
+        if self.sample_count() == 0 || self.header.len() <= Envelope::Panning.type_offset() {
+            return Err(XMParseError::new("Cannot edit envelopes of instrument without samples."));
+        }
+        Ok(())
+    }
+
+    fn check_envelope_point(&self, envelope: Envelope, point: u8) -> Result<(), XMParseError> {
+        self.check_envelope_header()?;
+        if point >= self.header[envelope.offsets().1].min(12) {
+            return Err(XMParseError::new(&format!("{} point {} does not exist.", envelope, point)));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_flatten_instrument() {
+    use std::path::Path;
+    use super::{XModule, PatternPacking};
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let bass = &xm.instruments[1];
+    let map = bass.sample_numbers().unwrap();
+    let note = map.iter().position(|smp| *smp == 1).unwrap() as u8 + 1;
+
+    let mut flat = bass.clone();
+    flat.flatten_to_single_sample(note).unwrap();
+    assert_eq!(flat.sample_count(), 1);
+    assert_eq!(flat.samples[0].name(), "high");
+    assert_eq!(flat.sample_numbers(), Some(vec![0; 96]));
+    assert_eq!(flat.name(), bass.name());
+    assert_eq!(XMInstrument::parse(flat.to_bytes()).unwrap().samples[0].name(), "high");
+
+    let mut xm = xm.clone();
+    xm.instruments[1] = flat;
+    assert_eq!(XModule::parse(xm.to_bytes_with_packing(PatternPacking::Canonical)).unwrap().instruments[1]
+        .sample_count(), 1);
+
+    assert!(bass.clone().flatten_to_single_sample(0).is_err());
+    assert!(XMInstrument::empty().flatten_to_single_sample(49).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_envelope_setters() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut instr = xm.instruments[1].clone();
+    let points = [(0, 64), (10, 32), (20, 48), (40, 0)];
+    instr.set_envelope_points(Envelope::Volume, &points).unwrap();
+    instr.set_envelope_sustain(Envelope::Volume, 1).unwrap();
+    instr.set_envelope_loop(Envelope::Volume, 1, 2).unwrap();
+    instr.set_envelope_type(Envelope::Volume, XM_ENVELOPE_ON | XM_ENVELOPE_LOOP | 0x80).unwrap();
+    assert_eq!(instr.volume_envelope_points(), Some(points.to_vec()));
+    assert_eq!((instr.volume_sustain(), instr.volume_loop_start(), instr.volume_loop_end()),
+        (Some(1), Some(1), Some(2)));
+    assert_eq!(instr.volume_type(), Some(XM_ENVELOPE_ON | XM_ENVELOPE_LOOP));
+    assert!(instr.validate().is_empty());
+
+    // the edits survive a round trip, and shorter envelopes move the sustain and loop points
+    let mut instr = XMInstrument::parse(instr.to_bytes()).unwrap();
+    assert_eq!(instr.volume_envelope_points(), Some(points.to_vec()));
+    instr.set_envelope_points(Envelope::Volume, &points[..2]).unwrap();
+    assert_eq!((instr.volume_sustain(), instr.volume_loop_start(), instr.volume_loop_end()),
+        (Some(1), Some(1), Some(1)));
+    assert!(instr.validate().is_empty());
+
+    assert!(instr.set_envelope_points(Envelope::Panning, &[(0, 32); 13]).is_err());
+    assert!(instr.set_envelope_points(Envelope::Panning, &[(0, 32), (0, 16)]).is_err());
+    assert!(instr.set_envelope_points(Envelope::Panning, &[(0, 65)]).is_err());
+    assert!(instr.set_envelope_sustain(Envelope::Volume, 2).is_err());
+    assert!(instr.set_envelope_loop(Envelope::Volume, 1, 0).is_err());
+    assert!(XMInstrument::empty().set_envelope_type(Envelope::Volume, XM_ENVELOPE_ON).is_err());
+}
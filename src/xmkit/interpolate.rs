@@ -0,0 +1,98 @@
+use super::{XMTrack, XMParseError};
+
+impl XMTrack {
+
+    /// Fills the volume column between the given rows with volumes that slide linearly from the volume set on
+    /// start_row to the volume set on end_row, like the "interpolate selection" command of trackers. Volume column
+    /// effects on the rows in between are overwritten.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if either row does not exist, if end_row does not come after start_row, or if the
+    /// volume column of either row does not set a volume (0x10..0x50).
+    pub fn interpolate_volume(&mut self, start_row: u16, end_row: u16) -> Result<(), XMParseError> {
+        self.validate_range(start_row, end_row)?;
+        let is_volume = |vol: Option<u8>| vol.filter(|vol| (0x10..=0x50).contains(vol));
+        let (start, end) = match (is_volume(self.cells[start_row as usize].volume()),
+            is_volume(self.cells[end_row as usize].volume())) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(XMParseError::new(&format!("Rows {} and {} must both set a volume to interpolate.",
+                start_row, end_row))),
+        };
+
+        for (row, vol) in interpolate(start_row, end_row, start, end) {
+            self.cells[row].set_volume(Some(vol));
+        }
+        Ok(())
+    }
+
+    /// Fills the effect column between the given rows with the effect set on start_row and end_row, with parameters
+    /// that slide linearly from the one on start_row to the one on end_row. Effects on the rows in between are
+    /// overwritten. The parameter is interpolated as a whole, so for extended effects (Exy, X1y, X2y) both rows must
+    /// use the same subcommand.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if either row does not exist, if end_row does not come after start_row, or if the
+    /// rows do not have the same effect.
+    pub fn interpolate_fx_param(&mut self, start_row: u16, end_row: u16) -> Result<(), XMParseError> {
+        self.validate_range(start_row, end_row)?;
+        let (start, end) = (self.cells[start_row as usize], self.cells[end_row as usize]);
+        let (start_param, end_param) = (start.fx_param().unwrap_or(0), end.fx_param().unwrap_or(0));
+        let cmd = start.fx_command().unwrap_or(0);
+        let extended = cmd == 0xe || cmd == 0x21;
+        if (start.fx_command().is_none() && start.fx_param().is_none()) || end.fx_command().unwrap_or(0) != cmd
+            || (extended && start_param >> 4 != end_param >> 4) {
+            return Err(XMParseError::new(&format!("Rows {} and {} must have the same effect to interpolate.",
+                start_row, end_row)));
+        }
+
+        for (row, param) in interpolate(start_row, end_row, start_param, end_param) {
+            self.cells[row].set_fx_command(Some(cmd));
+            self.cells[row].set_fx_param(Some(param));
+        }
+        Ok(())
+    }
+
+    fn validate_range(&self, start_row: u16, end_row: u16) -> Result<(), XMParseError> {
+        self.validate_row(&start_row)?;
+        self.validate_row(&end_row)?;
+        if end_row <= start_row {
+            return Err(XMParseError::new(&format!("Cannot interpolate from row {} to row {}.", start_row, end_row)));
+        }
+        Ok(())
+    }
+}
+
+// The rounded values of a linear slide from start to end, for the rows between start_row and end_row.
+fn interpolate(start_row: u16, end_row: u16, start: u8, end: u8) -> impl Iterator<Item = (usize, u8)> {
+    let span = (end_row - start_row) as f32;
+    (start_row + 1..end_row).map(move |row| {
+        let pos = (row - start_row) as f32 / span;
+        (row as usize, (start as f32 + (end as f32 - start as f32) * pos).round() as u8)
+    })
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_interpolate() {
+    let mut trk = XMTrack::from_columns(&[None; 5], &[None; 5], &[Some(0x10), Some(0x65), None, None, Some(0x50)],
+        &[Some(0xe), None, None, Some(0xa), Some(0xe)], &[Some(0xa1), None, None, Some(0x0f), Some(0xa9)]);
+
+    trk.interpolate_volume(0, 4).unwrap();
+    let volumes: Vec<Option<u8>> = trk.cells.iter().map(|cell| cell.volume()).collect();
+    assert_eq!(volumes, vec![Some(0x10), Some(0x20), Some(0x30), Some(0x40), Some(0x50)]);
+
+    trk.interpolate_fx_param(0, 4).unwrap();
+    let params: Vec<(Option<u8>, Option<u8>)> = trk.cells.iter().map(|cell| (cell.fx_command(), cell.fx_param()))
+        .collect();
+    assert_eq!(params, vec![(Some(0xe), Some(0xa1)), (Some(0xe), Some(0xa3)), (Some(0xe), Some(0xa5)),
+        (Some(0xe), Some(0xa7)), (Some(0xe), Some(0xa9))]);
+
+    // the ends must match, and lie in the track
+    trk.cells[4].set_fx_param(Some(0xb9));
+    assert!(trk.interpolate_fx_param(0, 4).is_err());
+    trk.cells[4].set_volume(Some(0x65));
+    assert!(trk.interpolate_volume(0, 4).is_err());
+    assert!(trk.interpolate_volume(2, 1).is_err());
+    assert!(trk.interpolate_volume(0, 5).is_err());
+}
@@ -0,0 +1,621 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use super::{XModule, XMCell, XMInstrument, XMSample, Effect, ValidationLocation, XM_ENVELOPE_ON, XM_ENVELOPE_LOOP,
+    XM_ENVELOPE_SUSTAIN, XM_SAMPLE_LOOP_FORWARD, XM_SAMPLE_LOOP_NONE, XM_FX_0XX, XM_FX_1XX, XM_FX_2XX, XM_FX_3XX,
+    XM_FX_4XX, XM_FX_5XX, XM_FX_6XX, XM_FX_7XX, XM_FX_8XX, XM_FX_9XX, XM_FX_AXX, XM_FX_BXX, XM_FX_CXX, XM_FX_DXX,
+    XM_FX_E1X, XM_FX_E2X, XM_FX_E3X, XM_FX_E4X, XM_FX_E5X, XM_FX_E6X, XM_FX_E7X, XM_FX_E8X, XM_FX_E9X, XM_FX_EAX,
+    XM_FX_EBX, XM_FX_ECX, XM_FX_EDX, XM_FX_EEX, XM_FX_FXX, XM_FX_GXX, XM_FX_HXX, XM_FX_KXX, XM_FX_LXX, XM_FX_PXX,
+    XM_FX_RXX, XM_FX_TXX, XM_FX_X1X, XM_FX_X2X};
+
+const IT_HEADER_SIZE: usize = 0xc0;
+const IT_INSTRUMENT_SIZE: usize = 554;
+const IT_SAMPLE_HEADER_SIZE: usize = 80;
+const IT_PATTERN_HEADER_SIZE: usize = 8;
+// Compatible with / created with Impulse Tracker 2.14, the last version of the format.
+const IT_VERSION: u16 = 0x0214;
+const IT_MAX_CHANNELS: usize = 64;
+// Impulse Tracker itself handles at most 99 instruments and samples, and 200 patterns of 32..200 rows.
+const IT_MAX_INSTRUMENTS: usize = 99;
+const IT_MAX_PATTERNS: usize = 200;
+const IT_NOTE_OFF: u8 = 255;
+// IT notes start at C-0, XM notes (1 = C-0) at 12 = B-0 in IT, so that C-4 in XM and C-5 in IT play at the
+// sample rate.
+const IT_NOTE_OFFSET: u8 = 11;
+// Portamento speeds of the volume column (Gx), in the order of the x values.
+const IT_VOLUME_PORTAMENTO: [u8; 10] = [0, 1, 4, 8, 16, 32, 64, 96, 128, 255];
+
+// IT effect letters.
+const IT_FX_A: u8 = 1;
+const IT_FX_B: u8 = 2;
+const IT_FX_C: u8 = 3;
+const IT_FX_D: u8 = 4;
+const IT_FX_E: u8 = 5;
+const IT_FX_F: u8 = 6;
+const IT_FX_G: u8 = 7;
+const IT_FX_H: u8 = 8;
+const IT_FX_I: u8 = 9;
+const IT_FX_J: u8 = 10;
+const IT_FX_K: u8 = 11;
+const IT_FX_L: u8 = 12;
+const IT_FX_O: u8 = 15;
+const IT_FX_P: u8 = 16;
+const IT_FX_Q: u8 = 17;
+const IT_FX_R: u8 = 18;
+const IT_FX_S: u8 = 19;
+const IT_FX_T: u8 = 20;
+const IT_FX_V: u8 = 22;
+const IT_FX_W: u8 = 23;
+const IT_FX_X: u8 = 24;
+
+
+/// A part of a module that could not be converted exactly by XModule::to_it().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionWarning {
+    pub location: ValidationLocation,
+    pub message: String,
+}
+
+impl fmt::Display for ConversionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+
+// A pattern cell in IT terms.
+#[derive(Default)]
+struct ItCell {
+    note: Option<u8>,
+    instrument: Option<u8>,
+    volpan: Option<u8>,
+    fx: Option<(u8, u8)>,
+}
+
+impl XModule {
+
+    /// Converts the module to an Impulse Tracker module (.it file), in instrument mode. Instruments keep their
+    /// sample map, volume and panning envelopes, fadeout, and auto-vibrato (which IT stores per sample). All new note
+    /// actions are set to "note cut", so that notes stop like they do in FastTracker II. Effects and volume column
+    /// commands are translated to their IT equivalents.
+    ///
+    /// Returns the file data, and a list of everything that could not be converted exactly, like effects without IT
+    /// equivalent (E5x, Lxx, F00, ...) or limits of Impulse Tracker itself. Dropped effects are reported once per
+    /// pattern, with the number of cells affected.
+    pub fn to_it(&self) -> (Vec<u8>, Vec<ConversionWarning>) {
+        let mut warnings = Vec::new();
+        let mut warn = |location: ValidationLocation, message: String| {
+            warnings.push(ConversionWarning { location, message });
+        };
+
+        let channels = self.patterns.iter().map(|ptn| ptn.tracks.len()).max().unwrap_or(0)
+            .max(self.channel_count() as usize);
+        if channels > IT_MAX_CHANNELS {
+            warn(ValidationLocation::Module, format!("only the first {} of {} channels are converted",
+                IT_MAX_CHANNELS, channels));
+        }
+        if self.restart_pos() != 0 {
+            warn(ValidationLocation::Module, format!("restart position {} is not supported, the song restarts \
+                from the beginning", self.restart_pos()));
+        }
+        if self.instruments.len() > IT_MAX_INSTRUMENTS {
+            warn(ValidationLocation::Module, format!("{} instruments exceed the limit of Impulse Tracker ({})",
+                self.instruments.len(), IT_MAX_INSTRUMENTS));
+        }
+        let sample_count: usize = self.instruments.iter().map(|instr| instr.samples.len()).sum();
+        if sample_count > IT_MAX_INSTRUMENTS {
+            warn(ValidationLocation::Module, format!("{} samples exceed the limit of Impulse Tracker ({})",
+                sample_count, IT_MAX_INSTRUMENTS));
+        }
+        if self.patterns.len() > IT_MAX_PATTERNS {
+            warn(ValidationLocation::Module, format!("{} patterns exceed the limit of Impulse Tracker ({})",
+                self.patterns.len(), IT_MAX_PATTERNS));
+        }
+
+        // the global sample number of the first sample of each instrument, counted from 1
+        let mut first_samples = Vec::with_capacity(self.instruments.len());
+        let mut next_sample = 1;
+        for instr in &self.instruments {
+            first_samples.push(next_sample);
+            next_sample += instr.samples.len();
+        }
+
+        let instruments: Vec<Vec<u8>> = self.instruments.iter().zip(&first_samples)
+            .map(|(instr, first)| it_instrument(instr, *first)).collect();
+        let mut samples = Vec::with_capacity(sample_count);
+        for (nr, instr) in self.instruments.iter().enumerate() {
+            for (smp_nr, smp) in instr.samples.iter().enumerate() {
+                samples.push((it_sample_header(instr, smp, ValidationLocation::Sample(nr, smp_nr), &mut warn),
+                    it_sample_data(smp)));
+            }
+        }
+        let patterns: Vec<Vec<u8>> = self.patterns.iter().enumerate()
+            .map(|(nr, ptn)| {
+                let rows = ptn.len();
+                if !(32..=200).contains(&rows) {
+                    warn(ValidationLocation::Pattern(nr), format!("{} rows are outside the range supported by \
+                        Impulse Tracker (32..200)", rows));
+                }
+                let mut lossy = BTreeMap::new();
+                let (data, kept) = it_pattern(self, nr, channels.min(IT_MAX_CHANNELS), &mut lossy);
+                if kept < rows {
+                    warn(ValidationLocation::Pattern(nr), format!("only the first {} of {} rows fit into the 64 KB \
+                        limit of Impulse Tracker patterns", kept, rows));
+                }
+                for (message, count) in lossy {
+                    warn(ValidationLocation::Pattern(nr), format!("{} ({} cells)", message, count));
+                }
+                data
+            }).collect();
+
+        let mut orders = self.sequence();
+        orders.push(255);
+        let mut data = vec![0u8; IT_HEADER_SIZE];
+        data[0..4].copy_from_slice(b"IMPM");
+        let name = self.name();
+        let name = name.as_bytes();
+        data[4..4 + name.len().min(25)].copy_from_slice(&name[..name.len().min(25)]);
+        data[0x1e] = 4;
+        data[0x1f] = 16;
+        data[0x20..0x22].copy_from_slice(&(orders.len() as u16).to_le_bytes());
+        data[0x22..0x24].copy_from_slice(&(instruments.len() as u16).to_le_bytes());
+        data[0x24..0x26].copy_from_slice(&(samples.len() as u16).to_le_bytes());
+        data[0x26..0x28].copy_from_slice(&(patterns.len() as u16).to_le_bytes());
+        data[0x28..0x2a].copy_from_slice(&IT_VERSION.to_le_bytes());
+        data[0x2a..0x2c].copy_from_slice(&IT_VERSION.to_le_bytes());
+        // stereo, instrument mode, linear slides
+        data[0x2c] = 0x1 | 0x4 | if self.amiga_ft() { 0 } else { 0x8 };
+        data[0x30] = 128;
+        data[0x31] = 48;
+        data[0x32] = self.tempo();
+        data[0x33] = self.bpm();
+        data[0x34] = 128;
+        for chn in 0..IT_MAX_CHANNELS {
+            // channels beyond the module's channel count are disabled
+            data[0x40 + chn] = if chn < channels { 32 } else { 32 | 0x80 };
+            data[0x80 + chn] = 64;
+        }
+        data.extend(&orders);
+
+        // offsets of instruments, sample headers, and patterns, followed by the actual data in the same order and
+        // the sample data
+        let mut offset = data.len() + (instruments.len() + samples.len() + patterns.len()) * 4;
+        let mut sample_offset = offset + instruments.len() * IT_INSTRUMENT_SIZE
+            + samples.len() * IT_SAMPLE_HEADER_SIZE + patterns.iter().map(|ptn| ptn.len()).sum::<usize>();
+        let sizes = instruments.iter().map(|instr| instr.len())
+            .chain(samples.iter().map(|smp| smp.0.len()))
+            .chain(patterns.iter().map(|ptn| ptn.len()));
+        for size in sizes {
+            data.extend(&(offset as u32).to_le_bytes());
+            offset += size;
+        }
+        for instr in &instruments {
+            data.extend(instr);
+        }
+        for (header, smp_data) in &samples {
+            let start = data.len();
+            data.extend(header);
+            data[start + 0x48..start + 0x4c].copy_from_slice(&(sample_offset as u32).to_le_bytes());
+            sample_offset += smp_data.len();
+        }
+        for ptn in &patterns {
+            data.extend(ptn);
+        }
+        for (_, smp_data) in &samples {
+            data.extend(smp_data);
+        }
+
+        (data, warnings)
+    }
+}
+
+// Encodes an instrument header. Samples are referenced by global sample number, starting with first_sample.
+fn it_instrument(instr: &XMInstrument, first_sample: usize) -> Vec<u8> {
+    let mut data = vec![0u8; IT_INSTRUMENT_SIZE];
+    data[0..4].copy_from_slice(b"IMPI");
+    // NNA note cut, duplicate check off
+    data[0x11] = 0;
+    data[0x14..0x16].copy_from_slice(&instr.volume_fadeout().map_or(0, |fadeout| (fadeout as u32 + 16) / 32)
+        .min(256).to_le_bytes()[..2]);
+    data[0x18] = 128;
+    // default panning disabled, samples keep their own
+    data[0x19] = 32 | 0x80;
+    data[0x1c..0x1e].copy_from_slice(&IT_VERSION.to_le_bytes());
+    data[0x1e] = instr.sample_count();
+    let name = instr.name();
+    let name = name.as_bytes();
+    data[0x20..0x20 + name.len().min(25)].copy_from_slice(&name[..name.len().min(25)]);
+    data[0x3d] = 0xff;
+    data[0x3e..0x40].copy_from_slice(&0xffffu16.to_le_bytes());
+
+    // notes below and above the XM range use the sample of the nearest XM note
+    let keymap = instr.sample_numbers().unwrap_or_default();
+    for note in 0..120 {
+        // the keymap starts at XM note 1
+        let xm_note = (note as i32 - IT_NOTE_OFFSET as i32 - 1).clamp(0, 95) as usize;
+        let sample = match keymap.get(xm_note) {
+            Some(&smp) if (smp as usize) < instr.samples.len() => first_sample + smp as usize,
+            _ => 0,
+        };
+        data[0x40 + note * 2] = note as u8;
+        data[0x41 + note * 2] = sample.min(255) as u8;
+    }
+
+    let volume = it_envelope(instr.volume_type(), instr.volume_envelope_points(), instr.volume_loop_start(),
+        instr.volume_loop_end(), instr.volume_sustain(), 0);
+    data[0x130..0x130 + volume.len()].copy_from_slice(&volume);
+    let panning = it_envelope(instr.panning_type(), instr.panning_envelope_points(), instr.panning_loop_start(),
+        instr.panning_loop_end(), instr.panning_sustain(), 32);
+    data[0x182..0x182 + panning.len()].copy_from_slice(&panning);
+    data
+}
+
+// Encodes an envelope, with values shifted down by center.
+fn it_envelope(flags: Option<u8>, points: Option<Vec<(u16, u16)>>, loop_start: Option<u8>, loop_end: Option<u8>,
+    sustain: Option<u8>, center: i16) -> Vec<u8> {
+    let mut data = vec![0u8; 82];
+    let (flags, points) = match (flags, points) {
+        (Some(flags), Some(points)) => (flags, points),
+        _ => return data,
+    };
+    if flags & XM_ENVELOPE_ON != 0 {
+        data[0] |= 1;
+    }
+    if let (true, Some(start), Some(end)) = (flags & XM_ENVELOPE_LOOP != 0, loop_start, loop_end) {
+        data[0] |= 2;
+        data[2] = start;
+        data[3] = end;
+    }
+    if let (true, Some(sustain)) = (flags & XM_ENVELOPE_SUSTAIN != 0, sustain) {
+        data[0] |= 4;
+        data[4] = sustain;
+        data[5] = sustain;
+    }
+    data[1] = points.len().min(25) as u8;
+    for (i, (tick, value)) in points.iter().take(25).enumerate() {
+        data[6 + i * 3] = (*value as i16 - center).clamp(-32, 64) as i8 as u8;
+        data[7 + i * 3..9 + i * 3].copy_from_slice(&tick.to_le_bytes());
+    }
+    data
+}
+
+// Encodes a sample header without the data offset, which is filled in by to_it().
+fn it_sample_header<W: FnMut(ValidationLocation, String)>(instr: &XMInstrument, smp: &XMSample,
+    location: ValidationLocation, warn: &mut W) -> Vec<u8> {
+    let mut data = vec![0u8; IT_SAMPLE_HEADER_SIZE];
+    data[0..4].copy_from_slice(b"IMPS");
+    data[0x11] = 64;
+    let bytes = if smp.is_16bit() { 2 } else { 1 };
+    let frames = smp.len() / bytes;
+    if frames > 0 {
+        data[0x12] |= 0x1;
+    }
+    if smp.is_16bit() {
+        data[0x12] |= 0x2;
+    }
+    let (loop_start, loop_len) = smp.loop_frames();
+    if smp.loop_type() != XM_SAMPLE_LOOP_NONE && loop_len > 0 {
+        data[0x12] |= if smp.loop_type() == XM_SAMPLE_LOOP_FORWARD { 0x10 } else { 0x10 | 0x40 };
+    }
+    data[0x13] = smp.volume().min(64);
+    let name = smp.name();
+    let name = name.as_bytes();
+    data[0x14..0x14 + name.len().min(25)].copy_from_slice(&name[..name.len().min(25)]);
+    // signed samples
+    data[0x2e] = 1;
+    data[0x2f] = (smp.panning() as u16 * 64 / 255) as u8 | 0x80;
+    data[0x30..0x34].copy_from_slice(&(frames as u32).to_le_bytes());
+    data[0x34..0x38].copy_from_slice(&(loop_start as u32).to_le_bytes());
+    data[0x38..0x3c].copy_from_slice(&((loop_start + loop_len) as u32).to_le_bytes());
+    data[0x3c..0x40].copy_from_slice(&(smp.c4_rate().round() as u32).to_le_bytes());
+
+    // auto-vibrato, which IT keeps per sample
+    let (depth, rate, sweep) = (instr.vibrato_depth().unwrap_or(0), instr.vibrato_rate().unwrap_or(0),
+        instr.vibrato_sweep().unwrap_or(0));
+    data[0x4c] = rate;
+    data[0x4d] = depth;
+    data[0x4e] = if sweep == 0 { 255 } else { (255 / sweep as u16) as u8 };
+    data[0x4f] = match instr.vibrato_type().unwrap_or(0) {
+        1 => 2,
+        2 => {
+            if depth > 0 {
+                warn(location, "ramp up auto-vibrato is not supported, using ramp down".to_string());
+            }
+            1
+        },
+        3 => 1,
+        _ => 0,
+    };
+    data
+}
+
+// The sample data as signed little endian PCM.
+fn it_sample_data(smp: &XMSample) -> Vec<u8> {
+    if smp.is_16bit() {
+        smp.pcm_16bit().iter().flat_map(|frame| frame.to_le_bytes()).collect()
+    } else {
+        smp.data_8bit_signed().iter().map(|&frame| frame as u8).collect()
+    }
+}
+
+// Encodes a pattern including its header, and returns it along with the number of rows encoded. Rows that don't fit
+// into the 64 KB of packed data that IT allows are dropped. Lossy conversions are counted in `lossy`.
+fn it_pattern(xm: &XModule, nr: usize, channels: usize, lossy: &mut BTreeMap<&'static str, usize>)
+    -> (Vec<u8>, u16) {
+    let ptn = &xm.patterns[nr];
+    let mut rows = ptn.len();
+    let mut packed = Vec::new();
+    for row in 0..rows as usize {
+        let row_start = packed.len();
+        for (chn, trk) in ptn.tracks.iter().enumerate().take(channels) {
+            let cell = it_cell(&trk.cell_unchecked(row), lossy);
+            let mask = cell.note.map_or(0, |_| 1) | cell.instrument.map_or(0, |_| 2) | cell.volpan.map_or(0, |_| 4)
+                | cell.fx.map_or(0, |_| 8);
+            if mask == 0 {
+                continue;
+            }
+            packed.push((chn as u8 + 1) | 0x80);
+            packed.push(mask);
+            packed.extend(cell.note);
+            packed.extend(cell.instrument);
+            packed.extend(cell.volpan);
+            if let Some((cmd, param)) = cell.fx {
+                packed.extend(&[cmd, param]);
+            }
+        }
+        packed.push(0);
+        if packed.len() > u16::MAX as usize {
+            packed.truncate(row_start);
+            rows = row as u16;
+            break;
+        }
+    }
+
+    let mut data = Vec::with_capacity(IT_PATTERN_HEADER_SIZE + packed.len());
+    data.extend(&(packed.len() as u16).to_le_bytes());
+    data.extend(&rows.to_le_bytes());
+    data.extend(&[0; 4]);
+    data.extend(packed);
+    (data, rows)
+}
+
+// Converts a single cell.
+fn it_cell(cell: &XMCell, lossy: &mut BTreeMap<&'static str, usize>) -> ItCell {
+    let mut lose = |message: &'static str| *lossy.entry(message).or_insert(0) += 1;
+    let mut it = ItCell {
+        note: match cell.note {
+            Some(note @ 1..=96) => Some(note + IT_NOTE_OFFSET),
+            Some(97) => Some(IT_NOTE_OFF),
+            _ => None,
+        },
+        instrument: cell.instrument.filter(|&instr| instr > 0),
+        ..Default::default()
+    };
+    if it.instrument.is_some_and(|instr| instr as usize > IT_MAX_INSTRUMENTS) {
+        lose("instrument numbers above 99 are not supported by Impulse Tracker");
+    }
+
+    it.volpan = match cell.volume.unwrap_or(0) {
+        vol @ 0x10..=0x50 => Some(vol - 0x10),
+        vol @ 0x60..=0x9f => {
+            if vol & 0xf > 9 {
+                lose("volume column slides are limited to 9");
+            }
+            // slide down, slide up, fine slide down, fine slide up
+            let base = [95, 85, 75, 65][(vol as usize >> 4) - 6];
+            Some(base + (vol & 0xf).min(9))
+        },
+        vol @ 0xb0..=0xbf => {
+            if vol & 0xf > 9 {
+                lose("volume column vibrato depth is limited to 9");
+            }
+            Some(203 + (vol & 0xf).min(9))
+        },
+        vol @ 0xc0..=0xcf => Some(128 + ((vol & 0xf) as u16 * 64 / 15) as u8),
+        vol @ 0xf0..=0xff => {
+            let speed = (vol & 0xf) as i32 * 16;
+            let index = (0..IT_VOLUME_PORTAMENTO.len())
+                .min_by_key(|&i| (IT_VOLUME_PORTAMENTO[i] as i32 - speed).abs()).unwrap_or(0);
+            if IT_VOLUME_PORTAMENTO[index] as i32 != speed {
+                lose("volume column portamento speed rounded");
+            }
+            Some(193 + index as u8)
+        },
+        0xa0..=0xaf => {
+            lose("volume column vibrato speed (Sx) dropped");
+            None
+        },
+        0xd0..=0xef => {
+            lose("volume column panning slide dropped");
+            None
+        },
+        _ => None,
+    };
+
+    let (fx, param) = match Effect::from_raw(cell.fx_command.unwrap_or(0), cell.fx_param.unwrap_or(0)) {
+        Some((fx, param)) => (fx.command(), param),
+        None => return it,
+    };
+    // volume slides use a single nibble in IT, with slides up taking precedence like in FT2
+    let slide = |param: u8| if param >> 4 != 0 { param & 0xf0 } else { param & 0xf };
+    it.fx = match fx {
+        XM_FX_0XX if param == 0 => None,
+        XM_FX_0XX => Some((IT_FX_J, param)),
+        XM_FX_1XX => Some((IT_FX_F, param.min(0xdf))),
+        XM_FX_2XX => Some((IT_FX_E, param.min(0xdf))),
+        XM_FX_3XX => Some((IT_FX_G, param)),
+        XM_FX_4XX => Some((IT_FX_H, param)),
+        XM_FX_5XX => Some((IT_FX_L, slide(param))),
+        XM_FX_6XX => Some((IT_FX_K, slide(param))),
+        XM_FX_7XX => Some((IT_FX_R, param)),
+        XM_FX_8XX => Some((IT_FX_X, param)),
+        XM_FX_9XX => Some((IT_FX_O, param)),
+        XM_FX_AXX => Some((IT_FX_D, slide(param))),
+        XM_FX_BXX => Some((IT_FX_B, param)),
+        XM_FX_CXX => {
+            if it.volpan.is_none_or(|vol| vol <= 64) {
+                it.volpan = Some(param.min(64));
+            } else {
+                lose("set volume (Cxx) next to a volume column effect dropped");
+            }
+            None
+        },
+        XM_FX_DXX => Some((IT_FX_C, (param >> 4) * 10 + (param & 0xf))),
+        XM_FX_E1X => Some((IT_FX_F, 0xf0 | param)),
+        XM_FX_E2X => Some((IT_FX_E, 0xf0 | param)),
+        XM_FX_E3X => Some((IT_FX_S, 0x10 | param)),
+        XM_FX_E4X => Some((IT_FX_S, 0x30 | waveform(param))),
+        XM_FX_E5X => {
+            lose("set finetune (E5x) dropped");
+            None
+        },
+        XM_FX_E6X => Some((IT_FX_S, 0xb0 | param)),
+        XM_FX_E7X => Some((IT_FX_S, 0x40 | waveform(param))),
+        XM_FX_E8X => Some((IT_FX_S, 0x80 | param)),
+        XM_FX_E9X => Some((IT_FX_Q, param)),
+        // IT has no separate memory for fine volume slides, so D00 is the closest match for EA0 and EB0
+        XM_FX_EAX => Some((IT_FX_D, if param > 0 { param << 4 | 0xf } else { 0 })),
+        XM_FX_EBX => Some((IT_FX_D, if param > 0 { 0xf0 | param } else { 0 })),
+        XM_FX_ECX => Some((IT_FX_S, 0xc0 | param)),
+        XM_FX_EDX => Some((IT_FX_S, 0xd0 | param)),
+        XM_FX_EEX => Some((IT_FX_S, 0xe0 | param)),
+        XM_FX_FXX if param == 0 => {
+            lose("song stop (F00) dropped");
+            None
+        },
+        XM_FX_FXX if param < 0x20 => Some((IT_FX_A, param)),
+        XM_FX_FXX => Some((IT_FX_T, param)),
+        XM_FX_GXX => Some((IT_FX_V, (param as u16 * 2).min(128) as u8)),
+        XM_FX_HXX => Some((IT_FX_W, slide(param))),
+        XM_FX_KXX if it.note.is_none() => {
+            it.note = Some(IT_NOTE_OFF);
+            if param > 0 { Some((IT_FX_S, 0xd0 | param.min(0xf))) } else { None }
+        },
+        XM_FX_KXX => {
+            lose("key off (Kxx) next to a note dropped");
+            None
+        },
+        XM_FX_LXX => {
+            lose("set envelope position (Lxx) dropped");
+            None
+        },
+        // IT pans left with Px0, FT2 with P0x
+        XM_FX_PXX => Some((IT_FX_P, if param >> 4 != 0 { param >> 4 } else { (param & 0xf) << 4 })),
+        XM_FX_RXX => Some((IT_FX_Q, param)),
+        // IT counts the on and off times of tremor from 0, FT2 from 1
+        XM_FX_TXX => Some((IT_FX_I, ((param >> 4) + 1).min(0xf) << 4 | ((param & 0xf) + 1).min(0xf))),
+        XM_FX_X1X => Some((IT_FX_F, 0xe0 | param)),
+        XM_FX_X2X => Some((IT_FX_E, 0xe0 | param)),
+        _ => None,
+    };
+    it
+}
+
+// Maps an FT2 vibrato/tremolo waveform to IT. IT has no continuous waveforms, and FT2 plays waveform 3 as square.
+fn waveform(param: u8) -> u8 {
+    match param & 3 {
+        3 => 2,
+        waveform => waveform,
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_to_it() {
+    use std::path::Path;
+    use super::XMPattern;
+
+    let read_u32 = |data: &[u8], offset: usize| u32::from_le_bytes([data[offset], data[offset + 1],
+        data[offset + 2], data[offset + 3]]) as usize;
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let (it, warnings) = xm.to_it();
+    assert_eq!(&it[0..4], b"IMPM");
+    let (orders, instruments, samples, patterns) = (XModule::read_u16(&it, 0x20) as usize,
+        XModule::read_u16(&it, 0x22) as usize, XModule::read_u16(&it, 0x24) as usize,
+        XModule::read_u16(&it, 0x26) as usize);
+    assert_eq!((orders, instruments, patterns), (xm.len() as usize + 1, xm.instruments.len(), 3));
+    assert_eq!(samples, xm.instruments.iter().map(|instr| instr.samples.len()).sum::<usize>());
+    assert_eq!(&it[IT_HEADER_SIZE..IT_HEADER_SIZE + orders], &[0, 1, 2, 1, 255]);
+    assert_eq!((it[0x32], it[0x33]), (xm.tempo(), xm.bpm()));
+    assert!(warnings.iter().any(|warning| warning.location == ValidationLocation::Module));
+
+    // the offset tables point to instruments, sample headers, and patterns
+    let table = IT_HEADER_SIZE + orders;
+    for i in 0..instruments {
+        assert_eq!(&it[read_u32(&it, table + i * 4)..][..4], b"IMPI");
+    }
+    let mut smp_nr = 0;
+    for instr in &xm.instruments {
+        for smp in &instr.samples {
+            let header = read_u32(&it, table + (instruments + smp_nr) * 4);
+            assert_eq!(&it[header..header + 4], b"IMPS");
+            let (data, len) = (read_u32(&it, header + 0x48), read_u32(&it, header + 0x30));
+            assert_eq!(it[data..data + smp.len()].to_vec(), it_sample_data(smp));
+            assert_eq!(len, if smp.is_16bit() { smp.len() / 2 } else { smp.len() });
+            smp_nr += 1;
+        }
+    }
+    let ptn = read_u32(&it, table + (instruments + samples) * 4);
+    assert_eq!(XModule::read_u16(&it, ptn + 2), xm.patterns[0].len());
+
+    // the multisampled bass plays the same sample for each note as in the XM, ramp up vibrato becomes ramp down
+    let bass = &xm.instruments[1];
+    let keymap = bass.sample_numbers().unwrap();
+    let first_sample = xm.instruments[0].samples.len() + 1;
+    let it_bass = read_u32(&it, table + 4);
+    for note in 1..=96 {
+        let it_note = note + IT_NOTE_OFFSET as usize;
+        assert_eq!(it[it_bass + 0x41 + it_note * 2] as usize, first_sample + keymap[note - 1] as usize);
+    }
+    let mut instr = bass.clone();
+    instr.header[235] = 3;
+    assert_eq!(it_sample_header(&instr, &instr.samples[0], ValidationLocation::Module, &mut |_, _| ())[0x4f], 1);
+    let mut ramp_up = Vec::new();
+    instr.header[235] = 2;
+    instr.header[237] = 4;
+    assert_eq!(it_sample_header(&instr, &instr.samples[0], ValidationLocation::Module,
+        &mut |_, message| ramp_up.push(message))[0x4f], 1);
+    assert_eq!(ramp_up.len(), 1);
+
+    // lossy effects are reported per pattern
+    let mut ptn = XMPattern::empty(64, 2);
+    let cells = [(Some(49), None, Some((0xe, 0x51))), (None, Some(0x20), Some((XM_FX_CXX, 0x30))),
+        (Some(97), None, Some((0xe, 0x52))), (None, None, Some((XM_FX_FXX, 0x7d)))];
+    for (row, (note, vol, fx)) in cells.iter().enumerate() {
+        let cell = &mut ptn.tracks[0].cells[row];
+        cell.set_note(*note);
+        cell.set_volume(*vol);
+        cell.set_fx_command(fx.map(|fx| fx.0));
+        cell.set_fx_param(fx.map(|fx| fx.1));
+    }
+    xm.patterns = vec![ptn];
+    let (it, warnings) = xm.to_it();
+    let pattern_warnings: Vec<String> = warnings.iter()
+        .filter(|warning| warning.location == ValidationLocation::Pattern(0)).map(|warning| warning.to_string())
+        .collect();
+    assert_eq!(pattern_warnings, vec!["pattern 0: set finetune (E5x) dropped (2 cells)".to_string()]);
+
+    // C-4 at row 0, volume 0x30 from Cxx at row 1, note off at row 2, tempo 125 at row 3
+    let ptn = read_u32(&it, IT_HEADER_SIZE + orders + (instruments + samples) * 4);
+    let packed = &it[ptn + IT_PATTERN_HEADER_SIZE..];
+    assert_eq!(&packed[..18], &[0x81, 1, 60, 0, 0x81, 4, 0x30, 0, 0x81, 1, IT_NOTE_OFF, 0, 0x81, 8, IT_FX_T, 0x7d,
+        0, 0]);
+
+    // a full pattern is cut short at the 64 KB limit of IT patterns
+    let mut ptn = XMPattern::empty(256, 64);
+    for cell in ptn.tracks.iter_mut().flat_map(|trk| trk.cells.iter_mut()) {
+        cell.set_note(Some(49));
+        cell.set_instrument(Some(1));
+        cell.set_volume(Some(0x30));
+        cell.set_fx_command(Some(XM_FX_AXX));
+        cell.set_fx_param(Some(0x01));
+    }
+    xm.patterns = vec![ptn];
+    let (it, warnings) = xm.to_it();
+    let ptn = read_u32(&it, IT_HEADER_SIZE + orders + (instruments + samples) * 4);
+    // 64 cells of 7 bytes and the end of row marker
+    assert_eq!(XModule::read_u16(&it, ptn + 2), (u16::MAX as usize / (64 * 7 + 1)) as u16);
+    assert_eq!(XModule::read_u16(&it, ptn) as usize, XModule::read_u16(&it, ptn + 2) as usize * (64 * 7 + 1));
+    assert!(warnings.iter().any(|warning| warning.location == ValidationLocation::Pattern(0)
+        && warning.message.contains("rows fit")));
+}
@@ -0,0 +1,101 @@
+use std::ops::RangeInclusive;
+use super::{XMInstrument, XMParseError};
+
+impl XMInstrument {
+
+    /// Returns the sample number (counted from 0) for each of the 96 notes, starting at C-0. Unlike sample_numbers(),
+    /// this also works for instruments without samples, whose notes are all mapped to sample 0.
+    pub fn keymap(&self) -> [u8; 96] {
+        let mut keymap = [0; 96];
+        if let Some(map) = self.sample_numbers() {
+            keymap.copy_from_slice(&map);
+        }
+        keymap
+    }
+
+    /// Returns the keymap as spans of consecutive notes (counted from 1, so 1..=96 covers the whole keyboard) that
+    /// are mapped to the same sample, in ascending order. This is what keymap editors draw, and an instrument whose
+    /// keymap has more than one span is a multisample.
+    pub fn keymap_ranges(&self) -> Vec<(RangeInclusive<u8>, u8)> {
+        let mut ranges: Vec<(RangeInclusive<u8>, u8)> = Vec::new();
+        for (note, smp) in (1..=96).zip(self.keymap()) {
+            match ranges.last_mut() {
+                Some((range, last)) if *last == smp => *range = *range.start()..=note,
+                _ => ranges.push((note..=note, smp)),
+            }
+        }
+        ranges
+    }
+
+    /// Maps the notes of a multisampled instrument to its samples, given the root note (1..96) of each sample as
+    /// (sample number counted from 0, root note) pairs. Each note plays the sample with the nearest root note, so the
+    /// split points lie halfway between neighbouring roots. Notes exactly halfway between two roots play the higher
+    /// sample, since samples usually sound better pitched down than up. The tuning of the samples is not changed, so
+    /// their relative notes should already make them play at their root notes.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the instrument has no samples, if no roots are given, if a sample does not exist,
+    /// or if a root note is out of range.
+    pub fn auto_keymap(&mut self, roots: &[(u8, u8)]) -> Result<(), XMParseError> {
+        if self.sample_count() == 0 || self.header.len() < 129 {
+            return Err(XMParseError::new("Cannot map notes of instrument without samples."));
+        }
+        if roots.is_empty() {
+            return Err(XMParseError::new("No samples to map notes to."));
+        }
+        if let Some((smp, root)) = roots.iter().find(|(smp, root)| *smp as usize >= self.samples.len()
+            || !(1..=96).contains(root)) {
+            return Err(XMParseError::new(&format!("Cannot map sample {} with root note {}.", smp, root)));
+        }
+
+        for note in 1..=96u8 {
+            let nearest = roots.iter().min_by_key(|(_, root)| (root.abs_diff(note), *root < note)).unwrap();
+            self.header[32 + note as usize] = nearest.0;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_keymap() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let bass = &xm.instruments[1];
+    assert_eq!(bass.keymap().to_vec(), bass.sample_numbers().unwrap());
+
+    let ranges = bass.keymap_ranges();
+    assert!(ranges.len() > 1);
+    assert_eq!((*ranges[0].0.start(), *ranges.last().unwrap().0.end()), (1, 96));
+    for (span, next) in ranges.iter().zip(&ranges[1..]) {
+        assert_eq!(*span.0.end() + 1, *next.0.start());
+        assert_ne!(span.1, next.1);
+    }
+    for (range, smp) in &ranges {
+        assert!(range.clone().all(|note| bass.keymap()[note as usize - 1] == *smp));
+    }
+
+    assert_eq!(XMInstrument::empty().keymap_ranges(), vec![(1..=96, 0)]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_auto_keymap() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut bass = xm.instruments[1].clone();
+    bass.auto_keymap(&[(1, 61), (0, 37)]).unwrap();
+    assert_eq!(bass.keymap_ranges(), vec![(1..=48, 0), (49..=96, 1)]);
+    bass.auto_keymap(&[(1, 40)]).unwrap();
+    assert_eq!(bass.keymap_ranges(), vec![(1..=96, 1)]);
+
+    assert!(bass.auto_keymap(&[]).is_err());
+    assert!(bass.auto_keymap(&[(2, 49)]).is_err());
+    assert!(bass.auto_keymap(&[(0, 97)]).is_err());
+    assert!(XMInstrument::empty().auto_keymap(&[(0, 49)]).is_err());
+}
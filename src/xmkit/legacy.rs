@@ -0,0 +1,143 @@
+// The row accessors as they were before rows were addressed with u16, kept so that callers holding u8 rows can move
+// over gradually.
+
+use std::time::Duration;
+use super::{XModule, XMPattern, XMTrack, XMParseError, ChannelState, TickEvent};
+
+impl XMPattern {
+
+    /// Like bpm(), with the row given as u8.
+    #[deprecated(note = "use bpm(), which takes the row as u16")]
+    pub fn bpm_u8(&self, xm: &XModule, row: u8) -> Result<u8, XMParseError> {
+        self.bpm(xm, row.into())
+    }
+
+    /// Like tempo(), with the row given as u8.
+    #[deprecated(note = "use tempo(), which takes the row as u16")]
+    pub fn tempo_u8(&self, xm: &XModule, row: u8) -> Result<u8, XMParseError> {
+        self.tempo(xm, row.into())
+    }
+}
+
+
+impl XMTrack {
+
+    /// Like fx(), with the row given as u8.
+    #[deprecated(note = "use fx(), which takes the row as u16")]
+    pub fn fx_u8(&self, fx_command: u8, row: u8) -> Result<u8, XMParseError> {
+        self.fx(fx_command, row.into())
+    }
+
+    /// Like fx_command_raw(), with the row given as u8.
+    #[deprecated(note = "use fx_command_raw(), which takes the row as u16")]
+    pub fn fx_command_raw_u8(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        self.fx_command_raw(row.into())
+    }
+
+    /// Like fx_param_raw(), with the row given as u8.
+    #[deprecated(note = "use fx_param_raw(), which takes the row as u16")]
+    pub fn fx_param_raw_u8(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        self.fx_param_raw(row.into())
+    }
+
+    /// Like instrument(), with the row given as u8.
+    #[deprecated(note = "use instrument(), which takes the row as u16")]
+    pub fn instrument_u8(&self, row: u8) -> Result<u8, XMParseError> {
+        self.instrument(row.into())
+    }
+
+    /// Like instrument_raw(), with the row given as u8.
+    #[deprecated(note = "use instrument_raw(), which takes the row as u16")]
+    pub fn instrument_raw_u8(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        self.instrument_raw(row.into())
+    }
+
+    /// Like note(), with the row given as u8.
+    #[deprecated(note = "use note(), which takes the row as u16")]
+    pub fn note_u8(&self, row: u8) -> Result<u8, XMParseError> {
+        self.note(row.into())
+    }
+
+    /// Like note_raw(), with the row given as u8.
+    #[deprecated(note = "use note_raw(), which takes the row as u16")]
+    pub fn note_raw_u8(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        self.note_raw(row.into())
+    }
+
+    /// Like note_trigger(), with the row given as u8.
+    #[deprecated(note = "use note_trigger(), which takes the row as u16")]
+    pub fn note_trigger_u8(&self, row: u8) -> Result<bool, XMParseError> {
+        self.note_trigger(row.into())
+    }
+
+    /// Like trigger(), with the row given as u8.
+    #[deprecated(note = "use trigger(), which takes the row as u16")]
+    pub fn trigger_u8(&self, row: u8) -> Result<bool, XMParseError> {
+        self.trigger(row.into())
+    }
+
+    /// Like volume(), with the row given as u8.
+    #[deprecated(note = "use volume(), which takes the row as u16")]
+    pub fn volume_u8(&self, row: u8) -> Result<u8, XMParseError> {
+        self.volume(row.into())
+    }
+
+    /// Like volume_raw(), with the row given as u8.
+    #[deprecated(note = "use volume_raw(), which takes the row as u16")]
+    pub fn volume_raw_u8(&self, row: u8) -> Result<Option<u8>, XMParseError> {
+        self.volume_raw(row.into())
+    }
+
+    /// Like state_at(), with the row given as u8.
+    #[deprecated(note = "use state_at(), which takes the row as u16")]
+    pub fn state_at_u8(&self, row: u8, xm: &XModule) -> Result<ChannelState, XMParseError> {
+        self.state_at(row.into(), xm)
+    }
+
+    /// Like tick_events(), with the row given as u8.
+    #[deprecated(note = "use tick_events(), which takes the row as u16")]
+    pub fn tick_events_u8(&self, row: u8, tempo: u8) -> Result<Vec<TickEvent>, XMParseError> {
+        self.tick_events(row.into(), tempo)
+    }
+
+    /// Like arpeggio(), with the row given as u8.
+    #[deprecated(note = "use arpeggio(), which takes the row as u16")]
+    pub fn arpeggio_u8(&self, row: u8, tempo: u8) -> Result<Option<Vec<u8>>, XMParseError> {
+        self.arpeggio(row.into(), tempo)
+    }
+}
+
+
+impl XModule {
+
+    /// Like time_at(), with the row given as u8.
+    #[deprecated(note = "use time_at(), which takes the row as u16")]
+    pub fn time_at_u8(&self, order: u16, row: u8) -> Result<Duration, XMParseError> {
+        self.time_at(order, row.into())
+    }
+
+    /// Like position_at(), with the row returned as u8. Since patterns have at most 256 rows, every row fits.
+    #[deprecated(note = "use position_at(), which returns the row as u16")]
+    pub fn position_at_u8(&self, time: Duration) -> Result<(u16, u8), XMParseError> {
+        self.position_at(time).map(|(order, row)| (order, row as u8))
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+#[allow(deprecated)]
+fn test_legacy_rows() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let trk = &xm.patterns[0].tracks[2];
+    assert_eq!(trk.note_raw_u8(0).unwrap(), trk.note_raw(0).unwrap());
+    assert_eq!(trk.fx_param_raw_u8(0).unwrap(), Some(0x20));
+    assert_eq!(trk.state_at_u8(3, &xm).unwrap(), trk.state_at(3, &xm).unwrap());
+    assert!(trk.volume_u8(255).is_err());
+    assert_eq!(xm.patterns[0].tempo_u8(&xm, 0).unwrap(), xm.tempo());
+
+    let time = xm.time_at_u8(1, 4).unwrap();
+    assert_eq!(xm.position_at_u8(time).unwrap(), (1, 4));
+}
@@ -0,0 +1,147 @@
+use super::{XModule, XMSample, XM_ENVELOPE_ON, XM_FX_GXX};
+use super::state::{sample_for, ChannelSim};
+
+/// Estimated signal levels, relative to full scale. A peak above 1.0 means that the signal may clip.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LevelEstimate {
+    /// The estimated highest absolute sample value.
+    pub peak: f64,
+    /// The estimated root mean square level over the whole song.
+    pub rms: f64,
+}
+
+impl LevelEstimate {
+    /// Returns the peak level in dBFS.
+    pub fn peak_db(&self) -> f64 {
+        20.0 * self.peak.log10()
+    }
+
+    /// Returns the RMS level in dBFS.
+    pub fn rms_db(&self) -> f64 {
+        20.0 * self.rms.log10()
+    }
+
+    /// Returns true if the estimated peak exceeds full scale.
+    pub fn clips(&self) -> bool {
+        self.peak > 1.0
+    }
+}
+
+/// The result of XModule::estimate_levels().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Levels {
+    /// The estimated levels of each channel.
+    pub channels: Vec<LevelEstimate>,
+    /// The estimated levels of the mix of all channels.
+    pub total: LevelEstimate,
+}
+
+
+impl XModule {
+
+    /// Estimates the peak and RMS levels of each channel and of the complete mix, without rendering the song.
+    /// For each row played (see play_rows()), the level of a channel is derived from the peak and RMS values of the
+    /// playing sample, the channel volume at the end of the row, the maximum of the instrument's volume envelope,
+    /// panning, and global volume (Gxx). The peak of the mix assumes that the peaks of all channels coincide, while
+    /// its RMS level assumes uncorrelated channels, so the peak is a worst case estimate.
+    /// The levels are relative to full scale as used by render() and render_stems(), where a sample at full scale
+    /// played at full volume with center panning has a level of 0.71.
+    pub fn estimate_levels(&self) -> Levels {
+        let channel_count = self.channel_count() as usize;
+        let mut sims = vec![ChannelSim::new(); channel_count];
+        let mut levels = Levels { channels: vec![LevelEstimate::default(); channel_count], ..Default::default() };
+        let mut sums = vec![0.0; channel_count];
+        let mut global_volume = 0x40;
+        let rows = self.play_rows();
+
+        for play_row in &rows {
+            let ptn = match self.patterns.get(play_row.pattern as usize) {
+                Some(ptn) => ptn,
+                None => continue,
+            };
+            let row = play_row.row as usize;
+            if let Some(param) = ptn.tracks.iter().filter(|trk| trk.cells[row].fx_command() == Some(XM_FX_GXX))
+                .filter_map(|trk| trk.cells[row].fx_param()).next_back() {
+                global_volume = param.min(0x40);
+            }
+
+            let mut row_peak = 0.0;
+            for (chan, trk) in ptn.tracks.iter().enumerate().take(channel_count) {
+                let sim = &mut sims[chan];
+                sim.start_row(self, trk, row);
+                for tick in 1..play_row.tempo {
+                    sim.tick(self, tick);
+                }
+
+                let state = sim.state;
+                let smp = match sample_for(self, state.instrument, state.note) {
+                    Some(smp) if state.note > 0 => smp,
+                    _ => continue,
+                };
+                let envelope = self.instruments.get(state.instrument as usize - 1)
+                    .filter(|instr| instr.volume_type().unwrap_or(0) & XM_ENVELOPE_ON != 0)
+                    .and_then(|instr| instr.volume_envelope_points())
+                    .map_or(1.0, |points| points.iter().map(|point| point.1.min(64)).max().unwrap_or(64) as f64 / 64.0);
+                let pan = state.panning as f64;
+                let pan_gain = ((256.0 - pan) / 256.0).sqrt().max((pan / 256.0).sqrt());
+                let gain = state.volume.min(0x40) as f64 / 64.0 * envelope * pan_gain * global_volume as f64 / 64.0;
+
+                let (peak, rms) = sample_levels(smp);
+                let channel = &mut levels.channels[chan];
+                channel.peak = channel.peak.max(peak * gain);
+                sums[chan] += (rms * gain).powi(2);
+                row_peak += peak * gain;
+            }
+
+            levels.total.peak = levels.total.peak.max(row_peak);
+        }
+
+        let played = rows.len().max(1) as f64;
+        for (channel, sum) in levels.channels.iter_mut().zip(&sums) {
+            channel.rms = (sum / played).sqrt();
+        }
+        levels.total.rms = (sums.iter().sum::<f64>() / played).sqrt();
+        levels
+    }
+}
+
+// Returns the peak and RMS level of the sample data, relative to full scale.
+fn sample_levels(smp: &XMSample) -> (f64, f64) {
+    let pcm = smp.pcm_16bit();
+    if pcm.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let peak = pcm.iter().map(|value| (*value as f64).abs()).fold(0.0, f64::max) / 32768.0;
+    let rms = (pcm.iter().map(|value| (*value as f64 / 32768.0).powi(2)).sum::<f64>() / pcm.len() as f64).sqrt();
+    (peak, rms)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_estimate_levels() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let levels = xm.estimate_levels();
+    assert_eq!(levels.channels.len(), 4);
+    assert_eq!(levels.channels[3], LevelEstimate::default());
+    assert!(levels.channels[..3].iter().all(|channel| channel.peak > 0.0 && channel.rms > 0.0));
+    assert!(levels.channels.iter().all(|channel| channel.rms <= channel.peak));
+    assert!(levels.total.peak >= levels.channels.iter().map(|channel| channel.peak).fold(0.0, f64::max));
+
+    // the estimated peaks are an upper bound for the rendered peaks
+    let stems = xm.render_stems(22050);
+    for (stem, estimate) in stems.iter().zip(&levels.channels) {
+        let peak = stem.iter().map(|value| (*value as f64).abs()).fold(0.0, f64::max) / 32768.0;
+        assert!(peak <= estimate.peak + 0.01, "rendered {} estimated {}", peak, estimate.peak);
+    }
+
+    // raising all sample volumes and the global volume cannot lower the estimate
+    let mut loud = xm.clone();
+    for smp in loud.instruments.iter_mut().flat_map(|instr| instr.samples.iter_mut()) {
+        smp.header[12] = 0x40;
+    }
+    assert!(loud.estimate_levels().total.peak >= levels.total.peak);
+}
@@ -0,0 +1,243 @@
+use super::XMInstrument;
+use super::rng::Rng;
+
+// FT2's vibrato and tremolo table: the first half of a sine wave with an amplitude of 255, in 32 steps.
+const VIBRATO_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253,
+    255, 253, 250, 244, 235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24];
+
+/// The waveforms of vibrato, tremolo, and autovibrato.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LfoWaveform {
+    #[default]
+    Sine,
+    /// FT2's ramp down waveform, waveform 1 of E4x and E7x, and autovibrato type 3.
+    RampDown,
+    /// FT2's ramp up waveform, autovibrato type 2. For vibrato and tremolo, it is the ramp down waveform inverted.
+    RampUp,
+    Square,
+    /// Pseudo-random values, which are the same on every run. FT2 plays waveform 3 of E4x and E7x as a square wave
+    /// instead, so this waveform is only available to autovibrato and custom players.
+    Random,
+}
+
+impl LfoWaveform {
+
+    /// Returns the waveform selected by the lower 2 bits of an E4x or E7x parameter, as FT2 interprets it:
+    /// 0 is sine, 1 is ramp down, and 2 and 3 are square.
+    pub fn from_control(control: u8) -> LfoWaveform {
+        match control & 3 {
+            0 => LfoWaveform::Sine,
+            1 => LfoWaveform::RampDown,
+            _ => LfoWaveform::Square,
+        }
+    }
+
+    /// Returns the waveform selected by an instrument's vibrato type setting (see XMInstrument::vibrato_type()):
+    /// 0 is sine, 1 is square, 2 is ramp up, and 3 is ramp down.
+    pub fn from_autovibrato_type(vibrato_type: u8) -> LfoWaveform {
+        match vibrato_type & 3 {
+            0 => LfoWaveform::Sine,
+            1 => LfoWaveform::Square,
+            2 => LfoWaveform::RampUp,
+            _ => LfoWaveform::RampDown,
+        }
+    }
+
+    /// Returns the value of the waveform at the given position of a 256 step cycle, as used by FT2's vibrato and
+    /// tremolo (-255..255). The first half of the cycle is positive. The ramps are built from FT2's 32 step table,
+    /// so the values repeat 4 times each.
+    pub fn value(self, position: u8) -> i16 {
+        let step = (position >> 2) & 0x1f;
+        let negative = position >= 128;
+        let magnitude = match self {
+            LfoWaveform::Sine => VIBRATO_TABLE[step as usize],
+            LfoWaveform::RampDown => if negative { !(step << 3) } else { step << 3 },
+            LfoWaveform::RampUp => return -LfoWaveform::RampDown.value(position),
+            LfoWaveform::Square => 255,
+            LfoWaveform::Random => return random(position) as i16 * 2 - 255,
+        } as i16;
+        if negative { -magnitude } else { magnitude }
+    }
+
+    // Returns the value of the waveform at the given position of a 256 step cycle, as used by FT2's autovibrato
+    // (-64..64). Note that the autovibrato sine starts with its negative half.
+    fn autovibrato_value(self, position: u8) -> i16 {
+        let position = position as i16;
+        match self {
+            LfoWaveform::Sine => -(64.0 * (position as f64 * std::f64::consts::PI / 128.0).sin()).round() as i16,
+            LfoWaveform::RampDown => ((64 - (position >> 1)) & 127) - 64,
+            LfoWaveform::RampUp => (((position >> 1) + 64) & 127) - 64,
+            LfoWaveform::Square => if position > 127 { 64 } else { -64 },
+            LfoWaveform::Random => random(position as u8) as i16 / 2 - 64,
+        }
+    }
+}
+
+// A fixed pseudo-random byte for each position.
+fn random(position: u8) -> u8 {
+    Rng::new(position as u64).byte()
+}
+
+
+/// The oscillator of a vibrato (4xy, 6xy, volume column Vx) or tremolo (7xy) effect on a channel, following FT2.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Lfo {
+    /// The waveform.
+    pub waveform: LfoWaveform,
+    /// If false, the position is reset to the start of the cycle whenever a note is triggered.
+    pub continuous: bool,
+    /// The position within the cycle of 256 steps.
+    pub position: u8,
+}
+
+impl Lfo {
+
+    /// Sets the waveform and retrigger behavior from the parameter of an E4x (vibrato) or E7x (tremolo) effect.
+    pub fn set_control(&mut self, control: u8) {
+        self.waveform = LfoWaveform::from_control(control);
+        self.continuous = control & 4 != 0;
+    }
+
+    /// Resets the position unless the oscillator is continuous. Call this when a note is triggered.
+    pub fn retrigger(&mut self) {
+        if !self.continuous {
+            self.position = 0;
+        }
+    }
+
+    /// Returns the vibrato offset for the current tick, to be added to the period, and advances the position.
+    /// Speed and depth are the x and y parameters of the vibrato effect. FT2 applies vibrato on all ticks of a row
+    /// except the first one.
+    pub fn vibrato(&mut self, speed: u8, depth: u8) -> i16 {
+        let value = self.waveform.value(self.position);
+        let offset = (value.unsigned_abs() * depth as u16 / 32) as i16 * value.signum();
+        self.position = self.position.wrapping_add(speed << 2);
+        offset
+    }
+
+    /// Returns the tremolo offset for the current tick, to be added to the volume, and advances the position.
+    /// Speed and depth are the x and y parameters of the tremolo effect. FT2 uses the vibrato position of the
+    /// channel instead of the tremolo position to decide in which half of the cycle the ramp down waveform is,
+    /// so it has to be passed as well.
+    pub fn tremolo(&mut self, speed: u8, depth: u8, vibrato_position: u8) -> i16 {
+        let value = match self.waveform {
+            LfoWaveform::RampDown => {
+                let ramp = ((self.position >> 2) & 0x1f) << 3;
+                let magnitude = if vibrato_position >= 128 { !ramp } else { ramp } as i16;
+                if self.position >= 128 { -magnitude } else { magnitude }
+            },
+            waveform => waveform.value(self.position),
+        };
+        let offset = (value.unsigned_abs() * depth as u16 / 64) as i16 * value.signum();
+        self.position = self.position.wrapping_add(speed << 2);
+        offset
+    }
+}
+
+
+/// The autovibrato of an instrument, following FT2. Create a new one whenever a note is triggered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoVibrato {
+    waveform: LfoWaveform,
+    depth: u8,
+    rate: u8,
+    position: u8,
+    // the current amplitude and the amount it grows per tick, in 1/256 depth units
+    amplitude: u16,
+    sweep: u16,
+}
+
+impl AutoVibrato {
+
+    /// Creates an autovibrato with the given instrument settings, see XMInstrument::vibrato_type() and friends.
+    /// The vibrato starts at full depth if sweep is 0, otherwise it takes sweep ticks to reach full depth. Depths
+    /// above 15, which FT2 does not allow, are capped.
+    pub fn new(vibrato_type: u8, sweep: u8, depth: u8, rate: u8) -> AutoVibrato {
+        let depth = depth.min(0xf);
+        let (amplitude, sweep) = match sweep {
+            0 => ((depth as u16) << 8, 0),
+            _ => (0, ((depth as u16) << 8) / sweep as u16),
+        };
+        AutoVibrato { waveform: LfoWaveform::from_autovibrato_type(vibrato_type), depth, rate, position: 0,
+            amplitude, sweep }
+    }
+
+    /// Creates an autovibrato with the settings of the given instrument. Instruments without samples have no
+    /// autovibrato.
+    pub fn for_instrument(instrument: &XMInstrument) -> AutoVibrato {
+        AutoVibrato::new(instrument.vibrato_type().unwrap_or(0), instrument.vibrato_sweep().unwrap_or(0),
+            instrument.vibrato_depth().unwrap_or(0), instrument.vibrato_rate().unwrap_or(0))
+    }
+
+    /// Returns the offset for the current tick, to be added to the period, and advances the autovibrato. The sweep
+    /// is halted while the note is released.
+    pub fn tick(&mut self, key_off: bool) -> i16 {
+        if self.depth == 0 {
+            return 0;
+        }
+
+        let mut amplitude = self.amplitude;
+        if self.sweep > 0 && !key_off {
+            amplitude = amplitude.saturating_add(self.sweep);
+            if amplitude >> 8 >= self.depth as u16 {
+                amplitude = (self.depth as u16) << 8;
+                self.sweep = 0;
+            }
+            self.amplitude = amplitude;
+        }
+
+        self.position = self.position.wrapping_add(self.rate);
+        ((self.waveform.autovibrato_value(self.position) as i32 * amplitude as i32) >> 14) as i16
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_lfo() {
+    // sine, ramp down, and square over one cycle
+    assert_eq!(LfoWaveform::Sine.value(0), 0);
+    assert_eq!(LfoWaveform::Sine.value(64), 255);
+    assert_eq!(LfoWaveform::Sine.value(192), -255);
+    assert_eq!(LfoWaveform::RampDown.value(4), 8);
+    assert_eq!(LfoWaveform::RampDown.value(128), -255);
+    assert_eq!(LfoWaveform::RampDown.value(252), -7);
+    assert_eq!(LfoWaveform::Square.value(127), 255);
+    assert_eq!(LfoWaveform::Square.value(128), -255);
+    assert_eq!(LfoWaveform::from_control(3), LfoWaveform::Square);
+    assert_eq!(LfoWaveform::from_autovibrato_type(2), LfoWaveform::RampUp);
+    let random: Vec<i16> = (0..=255).map(|pos| LfoWaveform::Random.value(pos)).collect();
+    assert!(random.iter().all(|value| (-255..=255).contains(value)));
+    assert_ne!(random[0], random[1]);
+
+    // 48F on sine: a quarter cycle takes 2 ticks at speed 8
+    let mut lfo = Lfo::default();
+    let offsets: Vec<i16> = (0..8).map(|_| lfo.vibrato(8, 0xf)).collect();
+    assert_eq!(offsets, vec![0, 84, 119, 84, 0, -84, -119, -84]);
+    assert_eq!(LfoWaveform::RampUp.value(4), -8);
+    lfo.position = 128;
+    lfo.set_control(4);
+    lfo.retrigger();
+    assert_eq!(lfo.position, 128);
+    lfo.set_control(1);
+    lfo.retrigger();
+    assert_eq!((lfo.position, lfo.waveform), (0, LfoWaveform::RampDown));
+
+    let mut tremolo = Lfo::default();
+    assert_eq!((0..3).map(|_| tremolo.tremolo(8, 8, 0)).collect::<Vec<_>>(), vec![0, 22, 31]);
+
+    // autovibrato sweeping to full depth over 4 ticks
+    let mut auto = AutoVibrato::new(1, 4, 8, 0x40);
+    let offsets: Vec<i16> = (0..6).map(|_| auto.tick(false)).collect();
+    assert_eq!(offsets, vec![-2, 4, 6, -8, -8, 8]);
+    assert_eq!(AutoVibrato::new(0, 0, 0, 0x40).tick(false), 0);
+    let mut released = AutoVibrato::new(1, 4, 8, 0x80);
+    assert_eq!((released.tick(true), released.tick(true)), (0, 0));
+
+    // depths beyond 15 are capped, and a fast sweep doesn't overflow
+    let mut deep = AutoVibrato::new(0, 1, 200, 0x40);
+    assert_eq!(deep.depth, 15);
+    assert!((0..1000).all(|_| deep.tick(false).abs() <= 15));
+    assert_eq!(deep.amplitude, 15 << 8);
+}
@@ -0,0 +1,133 @@
+use super::{ChannelState, Snapshot, XModule, XMParseError, XM_FX_3XX, XM_FX_5XX, XM_FX_FXX, XM_FX_GXX};
+use super::state::ChannelSim;
+
+/// A point in the song to loop back to from the end, along with how well the song continues when jumping there.
+/// See XModule::suggest_loop().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopPoint {
+    /// The sequence position to jump to.
+    pub order: u16,
+    /// The row to jump to.
+    pub row: u16,
+    /// The channels whose state at the end of the song differs from their state before the loop point, and that
+    /// are not reset by a new note at the loop point.
+    pub mismatched_channels: Vec<u8>,
+    /// True if the tempo and BPM at the end of the song are those in effect at the loop point.
+    pub tempo_matches: bool,
+    /// True if the global volume at the end of the song is the one in effect at the loop point.
+    pub global_volume_matches: bool,
+}
+
+impl LoopPoint {
+    /// Returns true if nothing changes audibly when jumping from the end of the song to the loop point.
+    pub fn is_seamless(&self) -> bool {
+        self.mismatched_channels.is_empty() && self.tempo_matches && self.global_volume_matches
+    }
+
+    // Number of problems at the loop point, for ranking.
+    fn discontinuities(&self) -> usize {
+        self.mismatched_channels.len() + !self.tempo_matches as usize + !self.global_volume_matches as usize
+    }
+}
+
+
+impl XModule {
+
+    /// Proposes a loop point for playing the song endlessly, such as for game music. Each row in the first half of
+    /// the song (see play_rows()) is evaluated with loop_point_at(), and the row with the fewest discontinuities is
+    /// returned. Ties are broken in favor of the restart position, then rows at the start of a pattern, then earlier
+    /// rows. Returns None if the song doesn't play any rows.
+    pub fn suggest_loop(&self) -> Option<LoopPoint> {
+        let snapshots = self.snapshots();
+        let end = snapshots.last()?;
+        let restart = self.restart_pos();
+
+        (0..snapshots.len().div_ceil(2))
+            .map(|index| self.evaluate_loop(&snapshots, index, end))
+            .enumerate()
+            .min_by_key(|(index, point)| (point.discontinuities(), !(point.order == restart && point.row == 0),
+                point.row != 0, *index))
+            .map(|(_, point)| point)
+    }
+
+    /// Evaluates how well the song continues when jumping from its end to the given row of the given sequence
+    /// position. If the position is played several times, the first time is used.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given position is never reached during playback.
+    pub fn loop_point_at(&self, order: u16, row: u16) -> Result<LoopPoint, XMParseError> {
+        let snapshots = self.snapshots();
+        match (snapshots.iter().position(|snapshot| snapshot.order == order && snapshot.row == row), snapshots.last()) {
+            (Some(index), Some(end)) => Ok(self.evaluate_loop(&snapshots, index, end)),
+            _ => Err(XMParseError::new(&format!("Position {}:{} is not reached during playback.", order, row))),
+        }
+    }
+
+    // Compares the state at the end of the song with the state before the row at the given index.
+    fn evaluate_loop(&self, snapshots: &[Snapshot], index: usize, end: &Snapshot) -> LoopPoint {
+        let target = &snapshots[index];
+        let initial = ChannelSim::new().state;
+        let (tempo, bpm, global_volume) = match index {
+            0 => (self.tempo(), self.bpm(), 0x40),
+            _ => (snapshots[index - 1].tempo, snapshots[index - 1].bpm, snapshots[index - 1].global_volume),
+        };
+
+        let ptn = self.patterns.get(target.pattern as usize);
+        let row = target.row as usize;
+        let row_sets = |cmd: u8| ptn.is_some_and(|ptn| ptn.tracks.iter()
+            .any(|trk| trk.cells[row].fx_command() == Some(cmd)));
+
+        let mismatched_channels = end.channels.iter().enumerate().filter(|(chan, state)| {
+            let before = if index == 0 { &initial } else { &snapshots[index - 1].channels[*chan] };
+            let triggered = ptn.and_then(|ptn| ptn.tracks.get(*chan)).is_some_and(|trk| {
+                let tone_porta = matches!(trk.cells[row].fx_command(), Some(XM_FX_3XX) | Some(XM_FX_5XX))
+                    || trk.cells[row].volume().is_some_and(|vol| vol >= 0xf0);
+                trk.cells[row].note().is_some_and(|note| (1..97).contains(&note)) && !tone_porta
+            });
+            !triggered && !same_sound(state, before)
+        }).map(|(chan, _)| chan as u8).collect();
+
+        LoopPoint {
+            order: target.order,
+            row: target.row,
+            mismatched_channels,
+            tempo_matches: (end.tempo, end.bpm) == (tempo, bpm) || row_sets(XM_FX_FXX),
+            global_volume_matches: end.global_volume == global_volume || row_sets(XM_FX_GXX),
+        }
+    }
+}
+
+// Returns true if two channel states sound the same. All silent states are considered equal.
+fn same_sound(a: &ChannelState, b: &ChannelState) -> bool {
+    let silent = |state: &ChannelState| state.note == 0 || state.volume == 0;
+    (silent(a) && silent(b)) || (a.note, a.instrument, a.volume, a.panning, a.key_off)
+        == (b.note, b.instrument, b.volume, b.panning, b.key_off)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_suggest_loop() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let rows = xm.play_rows();
+    let point = xm.suggest_loop().unwrap();
+    let index = rows.iter().position(|row| (row.order, row.row) == (point.order, point.row)).unwrap();
+    assert!(index < rows.len().div_ceil(2));
+    assert_eq!(xm.loop_point_at(point.order, point.row).unwrap(), point);
+
+    // no row in the first half of the song is a better loop point
+    for row in &rows[..rows.len().div_ceil(2)] {
+        assert!(xm.loop_point_at(row.order, row.row).unwrap().discontinuities() >= point.discontinuities());
+    }
+    assert!(xm.loop_point_at(0, 0).unwrap().mismatched_channels.iter().all(|chan| *chan < 4));
+    assert!(xm.loop_point_at(9, 0).is_err());
+
+    // pattern 0 played twice ends the way its first pass ended, but the tempo differs from the start of the song
+    let mut xm = xm;
+    xm.header[super::XM_SEQUENCE_BEGIN + 1] = 0;
+    XModule::write_u16(&mut xm.header, super::XM_SEQUENCE_LEN, 2);
+    assert!(xm.loop_point_at(1, 0).unwrap().is_seamless());
+    assert!(!xm.loop_point_at(0, 0).unwrap().tempo_matches);
+}
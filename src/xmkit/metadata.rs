@@ -0,0 +1,97 @@
+use super::{NamePolicy, XModule, XMParseError, XM_TRACKER_NAME, XM_RESTART_POS, XM_FREQ_TABLE_TYPE,
+    XM_DEFAULT_TEMPO, XM_DEFAULT_BPM};
+
+impl XModule {
+
+    /// Sets the module name. Names longer than 20 characters are truncated, shorter names are padded with spaces.
+    /// Characters outside the printable ASCII range are replaced with '?'. Returns the name as it was stored.
+    /// Use set_name_with_policy() to store other characters.
+    pub fn set_name(&mut self, name: &str) -> String {
+        self.set_name_with_policy(name, NamePolicy::default())
+    }
+
+    /// Sets the tracker name, which is encoded the same way as the module name (see set_name()).
+    pub fn set_tracker_name(&mut self, name: &str) {
+        XModule::write_string(&mut self.header, XM_TRACKER_NAME, 20, name);
+    }
+
+    /// Sets the sequence loop point (restart position).
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the position is beyond the end of the sequence.
+    pub fn set_restart_pos(&mut self, pos: u16) -> Result<(), XMParseError> {
+        if pos >= self.len() {
+            return Err(XMParseError::new(&format!("Restart position {} is beyond sequence length {}.", pos,
+                self.len())));
+        }
+
+        XModule::write_u16(&mut self.header, XM_RESTART_POS, pos);
+        Ok(())
+    }
+
+    /// Sets the default tempo (ticks per row).
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the tempo is not between 1 and 31.
+    pub fn set_tempo(&mut self, tempo: u8) -> Result<(), XMParseError> {
+        if tempo == 0 || tempo > 31 {
+            return Err(XMParseError::new(&format!("Invalid tempo {}.", tempo)));
+        }
+
+        XModule::write_u16(&mut self.header, XM_DEFAULT_TEMPO, tempo as u16);
+        Ok(())
+    }
+
+    /// Sets the default BPM value.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the BPM value is below 32.
+    pub fn set_bpm(&mut self, bpm: u8) -> Result<(), XMParseError> {
+        if bpm < 32 {
+            return Err(XMParseError::new(&format!("Invalid BPM value {}.", bpm)));
+        }
+
+        XModule::write_u16(&mut self.header, XM_DEFAULT_BPM, bpm as u16);
+        Ok(())
+    }
+
+    /// Selects the Amiga frequency table if amiga_ft is true, or the linear frequency table otherwise. This only
+    /// changes the flag in the module header, note data is not adjusted.
+    pub fn set_amiga_ft(&mut self, amiga_ft: bool) {
+        XModule::write_u16(&mut self.header, XM_FREQ_TABLE_TYPE, if amiga_ft { 0 } else { 1 });
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_set_metadata() {
+    use std::path::Path;
+    use super::XM_MODULE_NAME;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    xm.set_name("retagged");
+    assert_eq!(xm.name(), "retagged");
+    assert_eq!(&xm.header[XM_MODULE_NAME..XM_MODULE_NAME + 20], b"retagged            ");
+    xm.set_name("a name that is much too long \u{e9}");
+    assert_eq!(xm.name(), "a name that is much");
+    xm.set_name("caf\u{e9}");
+    assert_eq!(xm.name(), "caf?");
+
+    xm.set_tracker_name("xmkit");
+    assert_eq!(xm.tracker_name(), "xmkit");
+    assert_eq!(xm.header[0x25], 0x1a);
+
+    assert!(xm.set_restart_pos(xm.len()).is_err());
+    xm.set_restart_pos(1).unwrap();
+    assert!(xm.set_tempo(0).is_err() && xm.set_tempo(32).is_err());
+    xm.set_tempo(3).unwrap();
+    assert!(xm.set_bpm(31).is_err());
+    xm.set_bpm(150).unwrap();
+    xm.set_amiga_ft(true);
+
+    let xm = XModule::parse(xm.to_bytes()).unwrap();
+    assert_eq!(xm.name(), "caf?");
+    assert_eq!((xm.restart_pos(), xm.tempo(), xm.bpm(), xm.amiga_ft()), (1, 3, 150, true));
+}
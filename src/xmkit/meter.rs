@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use super::XModule;
+
+// The usual length of a beat in ticks: 4 rows at the default tempo of 6, which makes the BPM setting match the
+// actual beats per minute.
+const BEAT_TICKS: f64 = 24.0;
+// The beat lengths considered, in rows.
+const ROWS_PER_BEAT: [u16; 7] = [2, 3, 4, 6, 8, 12, 16];
+// The bar lengths considered, in beats, along with how common they are.
+const BEATS_PER_BAR: [(u16, f64); 6] = [(4, 1.0), (3, 0.9), (2, 0.8), (6, 0.8), (5, 0.6), (7, 0.6)];
+
+/// The meter of a module, as returned by XModule::infer_meter().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Meter {
+    /// The number of rows per beat.
+    pub rows_per_beat: u16,
+    /// The number of beats per bar.
+    pub beats_per_bar: u16,
+    /// True if the meter was taken from the row highlights stored by OpenMPT, false if it was estimated.
+    pub from_highlights: bool,
+}
+
+impl Meter {
+
+    /// Returns the number of rows per bar.
+    pub fn rows_per_bar(&self) -> u16 {
+        self.rows_per_beat * self.beats_per_bar
+    }
+}
+
+
+impl XModule {
+
+    /// Returns the meter of the module, for aligning exports and visualizations to beats and bars. If OpenMPT stored
+    /// its row highlights (the RPB. and RPM. song properties), they are used as is. Otherwise the meter is estimated:
+    /// beats and bars are assumed to start on the first row of each pattern, and the lengths are chosen whose grid
+    /// catches the most note triggers in the played rows, preferring lengths that divide the pattern lengths. Beats
+    /// of about 24 ticks at the prevailing tempo (4 rows at tempo 6) and bars of 4 beats are preferred when the notes
+    /// are ambiguous. Modules without notes get the preferred meter.
+    pub fn infer_meter(&self) -> Meter {
+        if let Some(meter) = self.stored_meter() {
+            return meter;
+        }
+
+        let play_rows = self.play_rows();
+        let played: Vec<Played> = play_rows.iter().filter_map(|row| {
+            let ptn = self.patterns.get(row.pattern as usize)?;
+            let notes = ptn.tracks.iter().filter_map(|trk| trk.cells.get(row.row as usize))
+                .filter(|cell| matches!(cell.note(), Some(1..=96))).count();
+            Some(Played { row: row.row, pattern_len: ptn.len(), notes: notes as f64 })
+        }).collect();
+
+        let mut tempos: HashMap<u8, usize> = HashMap::new();
+        for row in &play_rows {
+            *tempos.entry(row.tempo).or_default() += 1;
+        }
+        let tempo = tempos.into_iter().max_by_key(|&(tempo, count)| (count, tempo)).map(|(tempo, _)| tempo)
+            .unwrap_or_else(|| self.tempo()).max(1);
+
+        let rows_per_beat = best(ROWS_PER_BEAT.iter().map(|&rows| {
+            let preference = 0.25f64.powf((rows as f64 * tempo as f64 / BEAT_TICKS).log2().abs());
+            (rows, grid_score(&played, rows) * preference)
+        }));
+        let beats_per_bar = best(BEATS_PER_BAR.iter().map(|&(beats, preference)| {
+            (beats, grid_score(&played, rows_per_beat * beats) * preference)
+        }));
+        Meter { rows_per_beat, beats_per_bar, from_highlights: false }
+    }
+
+    // Returns the meter set by the OpenMPT row highlights, if they are stored and the bar is a whole number of beats.
+    fn stored_meter(&self) -> Option<Meter> {
+        let extensions = self.openmpt_extensions();
+        let value = |code: &[u8; 4]| extensions.song_property(code)
+            .map(|property| property.data.iter().take(4).rev().fold(0u32, |value, byte| value << 8 | *byte as u32));
+        let (beat, bar) = (value(b"RPB.")?, value(b"RPM.")?);
+        if beat == 0 || bar % beat != 0 || bar > u16::MAX as u32 {
+            return None;
+        }
+        Some(Meter { rows_per_beat: beat as u16, beats_per_bar: (bar / beat) as u16, from_highlights: true })
+    }
+}
+
+// A played row, reduced to what the meter is estimated from.
+struct Played {
+    row: u16,
+    pattern_len: u16,
+    notes: f64,
+}
+
+// Rates a grid with the given spacing in rows: how much more often notes trigger on the grid than on average,
+// scaled down if the grid does not divide the lengths of the patterns played. Returns 1 if no notes are played.
+fn grid_score(played: &[Played], spacing: u16) -> f64 {
+    let total: f64 = played.iter().map(|row| row.notes).sum();
+    let on_grid: Vec<&Played> = played.iter().filter(|row| row.row % spacing == 0).collect();
+    let accent = if total == 0.0 || on_grid.is_empty() { 1.0 } else {
+        on_grid.iter().map(|row| row.notes).sum::<f64>() / on_grid.len() as f64 / (total / played.len() as f64)
+    };
+    let fitting = played.iter().filter(|row| row.pattern_len % spacing == 0).count() as f64
+        / played.len().max(1) as f64;
+    accent * (0.5 + 0.5 * fitting)
+}
+
+// Returns the candidate with the highest score, or the first of the candidates with the highest score.
+fn best<I: Iterator<Item = (u16, f64)>>(candidates: I) -> u16 {
+    candidates.fold((0, f64::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best }).0
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_infer_meter() {
+    use std::path::Path;
+    use super::{ExtensionChunk, XMPattern, XM_FX_FXX, XM_SEQUENCE_BEGIN, XM_SEQUENCE_LEN};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    xm.header[XM_SEQUENCE_BEGIN] = 0;
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, 1);
+    let with_notes = |xm: &XModule, len: u16, notes: &[(usize, u16, u16)]| {
+        let mut xm = xm.clone();
+        let mut ptn = XMPattern::empty(len, 3);
+        for &(chn, spacing, offset) in notes {
+            for row in (offset..len).step_by(spacing as usize) {
+                ptn.tracks[chn].cells[row as usize].set_note(Some(49));
+            }
+        }
+        xm.patterns = vec![ptn];
+        xm
+    };
+    let meter = |rows_per_beat: u16, beats_per_bar: u16| Meter { rows_per_beat, beats_per_bar, from_highlights: false };
+    assert_eq!(xm.tempo(), 6);
+
+    // kick on every beat, snare on every other beat, and hi-hats on every half beat
+    let four_four = with_notes(&xm, 64, &[(0, 4, 0), (1, 8, 4), (2, 2, 0)]);
+    assert_eq!(four_four.infer_meter(), meter(4, 4));
+    assert_eq!(four_four.infer_meter().rows_per_bar(), 16);
+    // a waltz with the bass on the first beat of the bar
+    assert_eq!(with_notes(&xm, 48, &[(0, 12, 0), (1, 4, 0), (2, 2, 0)]).infer_meter(), meter(4, 3));
+    // a shuffle with triplets at tempo 6
+    assert_eq!(with_notes(&xm, 48, &[(0, 12, 0), (1, 3, 0)]).infer_meter(), meter(3, 4));
+
+    // at tempo 3, beats are usually twice as long
+    let mut fast = with_notes(&xm, 64, &[(0, 8, 0), (1, 2, 0)]);
+    fast.patterns[0].tracks[2].cells[0].set_fx_command(Some(XM_FX_FXX));
+    fast.patterns[0].tracks[2].cells[0].set_fx_param(Some(3));
+    assert_eq!(fast.infer_meter(), meter(8, 4));
+    assert_eq!(with_notes(&xm, 64, &[]).infer_meter(), meter(4, 4));
+
+    // stored highlights take precedence
+    let chunk = ExtensionChunk { id: *b"STPM", data: [&b"RPB."[..], &[4, 0], &[6, 0, 0, 0], b"RPM.", &[4, 0],
+        &[18, 0, 0, 0]].concat() };
+    let mut stored = four_four.clone();
+    stored.trailing.extend(chunk.to_bytes());
+    assert_eq!(stored.infer_meter(), Meter { rows_per_beat: 6, beats_per_bar: 3, from_highlights: true });
+}
@@ -0,0 +1,57 @@
+use super::XMInstrument;
+
+// Offset of the MIDI settings in the instrument header, following the volume fadeout.
+const MIDI_SETTINGS: usize = 241;
+
+/// The MIDI output settings of an instrument. FT2 can play instruments on external MIDI devices instead of, or in
+/// addition to, their samples. See XMInstrument::midi_settings().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MidiSettings {
+    /// True if notes of the instrument are sent to MIDI.
+    pub enabled: bool,
+    /// The MIDI channel, counted from 0.
+    pub channel: u8,
+    /// The MIDI program (patch), counted from 0.
+    pub program: u16,
+    /// The pitch bend range in semitones.
+    pub bend_range: u16,
+    /// True if the samples of the instrument are muted while it is played on MIDI.
+    pub mute_computer: bool,
+}
+
+impl XMInstrument {
+
+    /// Returns the MIDI settings of the instrument, or None if the instrument has no samples, since instruments
+    /// without samples are stored without them.
+    pub fn midi_settings(&self) -> Option<MidiSettings> {
+        if self.sample_count() == 0 {
+            return None;
+        }
+
+        let data = self.header.get(MIDI_SETTINGS..MIDI_SETTINGS + 7)?;
+        Some(MidiSettings {
+            enabled: data[0] != 0,
+            channel: data[1],
+            program: u16::from_le_bytes([data[2], data[3]]),
+            bend_range: u16::from_le_bytes([data[4], data[5]]),
+            mute_computer: data[6] != 0,
+        })
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_midi_settings() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut instr = xm.instruments[1].clone();
+    assert!(!instr.midi_settings().unwrap().enabled);
+
+    instr.header[MIDI_SETTINGS..MIDI_SETTINGS + 7].copy_from_slice(&[1, 9, 0x2a, 0, 12, 0, 1]);
+    assert_eq!(instr.midi_settings(), Some(MidiSettings { enabled: true, channel: 9, program: 42, bend_range: 12,
+        mute_computer: true }));
+    assert_eq!(XMInstrument::empty().midi_settings(), None);
+}
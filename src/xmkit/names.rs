@@ -0,0 +1,299 @@
+use super::{XModule, XMInstrument, XMSample, XM_MODULE_NAME};
+
+// Unicode equivalents of the CP437 graphic characters at 0x01..0x1f and 0x7f..0xff.
+const CP437_LOW: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: &str = "⌂ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Selects how the bytes of module, instrument, and sample names are converted to text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NameEncoding {
+    /// Decodes names as UTF-8, replacing invalid sequences with U+FFFD. This is what name() uses.
+    #[default]
+    Utf8Lossy,
+    /// Decodes names as DOS codepage 437, as used by FT2. Control codes are decoded as the graphic characters
+    /// displayed by DOS, eg. 0x03 as '♥'.
+    Cp437,
+    /// Decodes names as printable ASCII, replacing all other bytes with '?'.
+    Ascii,
+}
+
+/// Determines how names that do not fit into their field are shortened, see NamePolicy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NameTruncation {
+    /// Cuts the name after the last character that fits.
+    #[default]
+    Cut,
+    /// Cuts the name so that "..." fits after it.
+    Ellipsis,
+}
+
+/// Determines how text is converted to the bytes of a module, instrument, or sample name when setting names with
+/// set_name_with_policy(). Characters are never split, so multibyte characters that do not fit are left out as a
+/// whole. The default policy stores printable ASCII, replacing everything else with '?', and cuts long names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NamePolicy {
+    /// The encoding of the stored bytes. Characters that the encoding cannot represent are replaced with '?'.
+    pub encoding: NameEncoding,
+    /// If true, characters that the encoding cannot represent are replaced with similar characters where possible,
+    /// eg. 'é' with 'e' or 'ß' with "ss", before falling back to '?'.
+    pub transliterate: bool,
+    /// How names that are too long are shortened.
+    pub truncation: NameTruncation,
+}
+
+impl Default for NamePolicy {
+    fn default() -> NamePolicy {
+        NamePolicy { encoding: NameEncoding::Ascii, transliterate: false, truncation: NameTruncation::Cut }
+    }
+}
+
+impl NameEncoding {
+    // Converts the given name bytes to text. Trailing spaces are removed.
+    fn decode(self, bytes: &[u8]) -> String {
+        let text: String = match self {
+            NameEncoding::Utf8Lossy => String::from_utf8_lossy(bytes).into_owned(),
+            NameEncoding::Cp437 => bytes.iter().map(|byte| match *byte {
+                0x01..=0x1f => CP437_LOW.chars().nth(*byte as usize - 1).unwrap_or('?'),
+                0x7f..=0xff => CP437_HIGH.chars().nth(*byte as usize - 0x7f).unwrap_or('?'),
+                _ => *byte as char,
+            }).collect(),
+            NameEncoding::Ascii => bytes.iter()
+                .map(|byte| if (0x20..0x7f).contains(byte) { *byte as char } else { '?' }).collect(),
+        };
+
+        text.trim_end().to_string()
+    }
+
+    // Converts a character to bytes, or returns None if the encoding cannot represent it.
+    fn encode_char(self, c: char) -> Option<Vec<u8>> {
+        if c.is_control() {
+            return None;
+        }
+        match self {
+            NameEncoding::Utf8Lossy => Some(c.to_string().into_bytes()),
+            NameEncoding::Cp437 if c.is_ascii() => Some(vec![c as u8]),
+            NameEncoding::Cp437 => CP437_LOW.chars().position(|other| other == c).map(|pos| vec![pos as u8 + 0x01])
+                .or_else(|| CP437_HIGH.chars().position(|other| other == c).map(|pos| vec![pos as u8 + 0x7f])),
+            NameEncoding::Ascii => Some(vec![c as u8]).filter(|_| c.is_ascii()),
+        }
+    }
+}
+
+// Returns similar ASCII characters for some common characters outside of ASCII, or None if there are none.
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'À'..='Å' | 'Ą' => "A",
+        'à'..='å' | 'ą' => "a",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ç' | 'ć' | 'č' => "c",
+        'È'..='Ë' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ę' | 'ě' => "e",
+        'Ì'..='Ï' => "I",
+        'ì'..='ï' => "i",
+        'Ł' => "L",
+        'ł' => "l",
+        'Ñ' | 'Ń' => "N",
+        'ñ' | 'ń' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ő' => "o",
+        'Ř' => "R",
+        'ř' => "r",
+        'Ś' | 'Š' => "S",
+        'ś' | 'š' => "s",
+        'Ù'..='Ü' | 'Ů' | 'Ű' => "U",
+        'ù'..='ü' | 'ů' | 'ű' => "u",
+        'Ý' => "Y",
+        'ý' | 'ÿ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        'ß' => "ss",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Œ' => "OE",
+        'œ' => "oe",
+        '‘' | '’' | '‚' => "'",
+        '“' | '”' | '„' => "\"",
+        '–' | '—' => "-",
+        '…' => "...",
+        '\u{a0}' => " ",
+        _ => return None,
+    })
+}
+
+// Encodes text into a name field of the given length following the policy, padded with spaces. Returns the field
+// and the stored name, decoded again.
+fn encode_name(text: &str, len: usize, policy: NamePolicy) -> (Vec<u8>, String) {
+    let encoding = policy.encoding;
+    let encode = |c: char| match encoding.encode_char(c) {
+        Some(bytes) => bytes,
+        None => transliterate(c).filter(|_| policy.transliterate)
+            .and_then(|replacement| replacement.chars().map(|c| encoding.encode_char(c)).collect::<Option<Vec<_>>>())
+            .map_or_else(|| vec![b'?'], |bytes| bytes.concat()),
+    };
+    let pieces: Vec<Vec<u8>> = text.chars().map(encode).collect();
+
+    let too_long = pieces.iter().map(|piece| piece.len()).sum::<usize>() > len;
+    let ellipsis = too_long && policy.truncation == NameTruncation::Ellipsis;
+    let available = if ellipsis { len.saturating_sub(3) } else { len };
+    let mut bytes: Vec<u8> = Vec::with_capacity(len);
+    for piece in &pieces {
+        if bytes.len() + piece.len() > available {
+            break;
+        }
+        bytes.extend(piece);
+    }
+    if ellipsis {
+        bytes.extend(b"...".iter().take(len));
+    }
+    let stored = encoding.decode(&bytes);
+    bytes.resize(len, b' ');
+
+    (bytes, stored)
+}
+
+// Returns the bytes of a zero-terminated name field, up to the first zero byte.
+fn name_field(data: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    data.iter().skip(offset).take(len).take_while(|byte| **byte != 0).cloned().collect()
+}
+
+
+impl XModule {
+
+    /// Returns the raw bytes of the module name, up to the first zero byte. Trailing spaces are included.
+    pub fn name_bytes(&self) -> Vec<u8> {
+        name_field(&self.header, XM_MODULE_NAME, 20)
+    }
+
+    /// Returns the module name, decoded with the given encoding.
+    pub fn name_with_encoding(&self, encoding: NameEncoding) -> String {
+        encoding.decode(&self.name_bytes())
+    }
+
+    /// Sets the module name, converting it to at most 20 bytes as determined by the given policy. Shorter names are
+    /// padded with spaces. Returns the name as it was stored, decoded with the encoding of the policy.
+    pub fn set_name_with_policy(&mut self, name: &str, policy: NamePolicy) -> String {
+        let (bytes, stored) = encode_name(name, 20, policy);
+        self.header[XM_MODULE_NAME..XM_MODULE_NAME + 20].copy_from_slice(&bytes);
+        stored
+    }
+}
+
+impl XMInstrument {
+
+    /// Returns the raw bytes of the instrument name, up to the first zero byte. Trailing spaces are included.
+    pub fn name_bytes(&self) -> Vec<u8> {
+        name_field(&self.header, 4, 22)
+    }
+
+    /// Returns the instrument name, decoded with the given encoding.
+    pub fn name_with_encoding(&self, encoding: NameEncoding) -> String {
+        encoding.decode(&self.name_bytes())
+    }
+
+    /// Sets the instrument name, in the same way as the module name (see set_name()), but with up to 22 characters.
+    /// Returns the name as it was stored.
+    pub fn set_name(&mut self, name: &str) -> String {
+        self.set_name_with_policy(name, NamePolicy::default())
+    }
+
+    /// Sets the instrument name, converting it to at most 22 bytes as determined by the given policy. Shorter names
+    /// are padded with spaces. Returns the name as it was stored, decoded with the encoding of the policy.
+    pub fn set_name_with_policy(&mut self, name: &str, policy: NamePolicy) -> String {
+        let (bytes, stored) = encode_name(name, 22, policy);
+        self.header[4..26].copy_from_slice(&bytes);
+        stored
+    }
+}
+
+impl XMSample {
+
+    /// Returns the raw bytes of the sample name, up to the first zero byte. Trailing spaces are included.
+    pub fn name_bytes(&self) -> Vec<u8> {
+        name_field(&self.header, 18, 22)
+    }
+
+    /// Returns the sample name, decoded with the given encoding.
+    pub fn name_with_encoding(&self, encoding: NameEncoding) -> String {
+        encoding.decode(&self.name_bytes())
+    }
+
+    /// Sets the sample name, in the same way as the module name (see XModule::set_name()), but with up to 22
+    /// characters. Returns the name as it was stored.
+    pub fn set_name(&mut self, name: &str) -> String {
+        self.set_name_with_policy(name, NamePolicy::default())
+    }
+
+    /// Sets the sample name, converting it to at most 22 bytes as determined by the given policy. Shorter names are
+    /// padded with spaces. Returns the name as it was stored, decoded with the encoding of the policy.
+    pub fn set_name_with_policy(&mut self, name: &str, policy: NamePolicy) -> String {
+        let (bytes, stored) = encode_name(name, 22, policy);
+        self.header[18..40].copy_from_slice(&bytes);
+        stored
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_name_encoding() {
+    use std::path::Path;
+
+    assert_eq!(CP437_LOW.chars().count(), 31);
+    assert_eq!(CP437_HIGH.chars().count(), 129);
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert_eq!(xm.name_with_encoding(NameEncoding::Cp437), xm.name());
+    assert_eq!(xm.instruments[1].samples[1].name_with_encoding(NameEncoding::Cp437), "high");
+
+    let name = b"\x8e\x99\x9a \xc9\xcd\xbb \x03  ";
+    xm.header[XM_MODULE_NAME..XM_MODULE_NAME + name.len()].copy_from_slice(name);
+    xm.header[XM_MODULE_NAME + name.len()] = 0;
+    assert_eq!(xm.name_bytes(), name.to_vec());
+    assert_eq!(xm.name_with_encoding(NameEncoding::Cp437), "ÄÖÜ ╔═╗ ♥");
+    assert_eq!(xm.name_with_encoding(NameEncoding::Utf8Lossy), xm.name());
+    assert!(xm.name().contains('\u{fffd}'));
+
+    xm.instruments[0].header[4] = 0xe1;
+    assert_eq!(xm.instruments[0].name_bytes()[0], 0xe1);
+    assert!(xm.instruments[0].name_with_encoding(NameEncoding::Cp437).starts_with('ß'));
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_names() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let utf8 = NamePolicy { encoding: NameEncoding::Utf8Lossy, ..NamePolicy::default() };
+
+    // multibyte characters are never split
+    assert_eq!(xm.set_name_with_policy("Grüße aus Köln", utf8), "Grüße aus Köln");
+    assert_eq!(xm.name(), "Grüße aus Köln");
+    assert_eq!(xm.set_name_with_policy("nineteen characters\u{e9}", utf8), "nineteen characters");
+    assert_eq!(xm.name_bytes(), b"nineteen characters ".to_vec());
+    assert_eq!(xm.set_name_with_policy("♪♪♪♪♪♪♪", utf8), "♪♪♪♪♪♪");
+
+    // transliteration, and the other encodings
+    let ascii = NamePolicy { transliterate: true, ..NamePolicy::default() };
+    assert_eq!(xm.set_name_with_policy("Grüße – ½", ascii), "Grusse - ?");
+    let cp437 = NamePolicy { encoding: NameEncoding::Cp437, transliterate: true, ..NamePolicy::default() };
+    assert_eq!(xm.set_name_with_policy("Grüße – ½ ł", cp437), "Grüße - ½ l");
+    assert_eq!(xm.name_bytes()[2], 0x81);
+    assert_eq!(xm.set_name("caf\u{e9}\ttab"), "caf??tab");
+
+    // truncation
+    let ellipsis = NamePolicy { truncation: NameTruncation::Ellipsis, ..utf8 };
+    let instr = &mut xm.instruments[0];
+    assert_eq!(instr.set_name_with_policy("a rather long instrument name", ellipsis), "a rather long instr...");
+    assert_eq!(instr.name(), "a rather long instr...");
+    assert_eq!(instr.set_name_with_policy("exactly twenty-two chr", ellipsis), "exactly twenty-two chr");
+    assert_eq!(instr.set_name("lead"), "lead");
+    assert_eq!(instr.name_bytes(), b"lead                  ".to_vec());
+    let smp = &mut xm.instruments[1].samples[0];
+    assert_eq!(smp.set_name_with_policy("Überlänge mit Umlauten ä", ellipsis), "Überlänge mit Uml...");
+    assert_eq!(smp.name(), "Überlänge mit Uml...");
+
+    let xm = XModule::parse(xm.to_bytes()).unwrap();
+    assert_eq!((xm.instruments[0].name(), xm.instruments[1].samples[0].name()),
+        ("lead".to_string(), "Überlänge mit Uml...".to_string()));
+}
@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use super::{XModule, XMParseError};
+
+// The result of the parse, and the waker of the task waiting for it.
+type Shared = Arc<Mutex<(Option<Result<XModule, XMParseError>>, Option<Waker>)>>;
+
+/// A module being read and parsed on a separate thread, as returned by XModule::parse_file_async() and
+/// XModule::parse_reader_async(). It does not depend on any particular async runtime. Only available with the
+/// `async` feature.
+///
+/// Every call starts a new OS thread, which is cheap enough for loading a few modules, but not for thousands at
+/// once. Applications that already use a runtime with a blocking thread pool can run the synchronous parsers there
+/// instead, for example with tokio::task::spawn_blocking(move || XModule::parse_file(&path)).
+#[derive(Debug)]
+pub struct ParseFuture {
+    shared: Shared,
+}
+
+impl ParseFuture {
+    // Runs the given parse on a new thread. If the parse panics, the future resolves to an error instead of waiting
+    // forever.
+    fn spawn<F: FnOnce() -> Result<XModule, XMParseError> + Send + 'static>(parse: F) -> ParseFuture {
+        let shared: Shared = Arc::new(Mutex::new((None, None)));
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(parse)).unwrap_or_else(|payload| {
+                let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned()).unwrap_or_default();
+                Err(XMParseError::new(&format!("The parser panicked: {}", message)))
+            });
+            let mut state = thread_shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.0 = Some(result);
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        ParseFuture { shared }
+    }
+}
+
+impl Future for ParseFuture {
+    type Output = Result<XModule, XMParseError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match state.0.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+
+impl XModule {
+
+    /// Reads and parses an XM file like parse_file(), on a separate thread, so that the calling task does not block
+    /// its executor while waiting for disk I/O.
+    pub fn parse_file_async(filepath: &Path) -> ParseFuture {
+        let filepath = filepath.to_path_buf();
+        ParseFuture::spawn(move || XModule::parse_file(&filepath))
+    }
+
+    /// Reads the given reader to its end and parses the data like parse(), on a separate thread, so that the calling
+    /// task does not block its executor while waiting for the reader.
+    pub fn parse_reader_async<R: Read + Send + 'static>(mut reader: R) -> ParseFuture {
+        ParseFuture::spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)
+                .map_err(|e| XMParseError::new(&format!("Couldn't read module data: {}", e)))?;
+            XModule::parse(data)
+        })
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_parse_async() {
+    use std::fs;
+    use std::io::Cursor;
+    use std::task::Wake;
+
+    // a minimal executor that parks the thread until the future is woken
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    let xm = block_on(XModule::parse_file_async(Path::new("test.xm"))).unwrap();
+    assert_eq!(xm.to_bytes(), fs::read("test.xm").unwrap());
+    let xm = block_on(XModule::parse_reader_async(Cursor::new(fs::read("test.xm").unwrap()))).unwrap();
+    assert_eq!(xm.patterns.len(), 3);
+    assert!(block_on(XModule::parse_file_async(Path::new("missing.xm"))).is_err());
+    let panicked = block_on(ParseFuture::spawn(|| panic!("out of bounds"))).unwrap_err();
+    assert!(panicked.to_string().contains("out of bounds"));
+}
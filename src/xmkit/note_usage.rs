@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use super::XModule;
+
+impl XModule {
+
+    /// Returns, for each instrument, how often each note (1..96) is played with it. The result is indexed by
+    /// instrument number minus 1. Patterns are processed in sequence order, so patterns that appear several times
+    /// in the sequence are counted each time, and patterns that are not part of the sequence are ignored. Notes
+    /// without an instrument are attributed to the instrument last used on the same channel. Jumps and loops are
+    /// not followed.
+    pub fn note_usage(&self) -> Vec<BTreeMap<u8, usize>> {
+        let mut usage = vec![BTreeMap::new(); self.instruments.len()];
+        let mut instruments = vec![0u8; self.channel_count() as usize];
+
+        for ptn in self.sequence().iter().filter_map(|nr| self.patterns.get(*nr as usize)) {
+            for (trk, current) in ptn.tracks.iter().zip(instruments.iter_mut()) {
+                for row in 0..trk.cells.len() {
+                    if let Some(instr) = trk.cells[row].instrument().filter(|instr| *instr > 0) {
+                        *current = instr;
+                    }
+                    if let (Some(note @ 1..=96), Some(notes)) = (trk.cells[row].note(),
+                        usage.get_mut((*current as usize).wrapping_sub(1))) {
+                        *notes.entry(note).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        usage
+    }
+
+    /// Returns the lowest and highest note (1..96) played with the given instrument, counted from 1, or None if the
+    /// instrument is not used. See note_usage() for how notes are attributed to instruments.
+    pub fn note_range_for_instrument(&self, instrument: u8) -> Option<(u8, u8)> {
+        let usage = self.note_usage();
+        let notes = usage.get((instrument as usize).wrapping_sub(1))?;
+
+        Some((*notes.keys().next()?, *notes.keys().next_back()?))
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_note_usage() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let usage = xm.note_usage();
+    assert_eq!(usage.len(), 2);
+
+    let total: usize = usage.iter().flat_map(|notes| notes.values()).sum();
+    let played = xm.sequence().iter().map(|nr| xm.patterns[*nr as usize].find(|cell|
+        cell.note.is_some_and(|note| note > 0 && note < 97)).len()).sum::<usize>();
+    assert_eq!(total, played);
+    assert_eq!(usage[0].get(&58), Some(&xm.sequence().iter().filter(|nr| **nr == 0).count()));
+
+    let (low, high) = xm.note_range_for_instrument(2).unwrap();
+    assert!(low <= high && usage[1].contains_key(&low) && usage[1].contains_key(&high));
+    assert_eq!(xm.note_range_for_instrument(0), None);
+    assert_eq!(xm.note_range_for_instrument(3), None);
+}
@@ -0,0 +1,223 @@
+use super::{ExtensionChunk, XModule};
+
+// Size of the plugin information that precedes the plugin data in FXnn chunks.
+const PLUGIN_INFO_SIZE: usize = 128;
+
+/// A property of the extended instrument (XTPM) or song (STPM) settings stored by OpenMPT, identified by a four
+/// character code such as "DT.." (tempo) or "VE.." (volume envelope points).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtensionProperty {
+    /// The four character code of the property.
+    pub code: [u8; 4],
+    /// The size of the property value in bytes.
+    pub size: u16,
+    /// The property value. Instrument properties hold one value of the given size for each instrument.
+    pub data: Vec<u8>,
+}
+
+impl ExtensionProperty {
+
+    /// Returns the property code as text.
+    pub fn code_str(&self) -> String {
+        String::from_utf8_lossy(&self.code).into_owned()
+    }
+
+    /// Returns the value of an instrument property for the given instrument, counted from 0, or None if there is
+    /// no value for the instrument.
+    pub fn instrument_value(&self, instrument: usize) -> Option<&[u8]> {
+        let size = self.size as usize;
+        self.data.get(instrument * size..(instrument + 1) * size)
+    }
+}
+
+
+/// A plugin slot stored by OpenMPT in a FXnn chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginSlot {
+    /// The slot number (0..99).
+    pub slot: u8,
+    /// The identifiers of the plugin, eg. its VST magic and unique ID.
+    pub plugin_id: (u32, u32),
+    /// The input routing flags.
+    pub input_routing: u32,
+    /// The output routing setting.
+    pub output_routing: u32,
+    /// The name of the plugin instance.
+    pub name: String,
+    /// The name of the plugin library.
+    pub library_name: String,
+    /// The opaque plugin state.
+    pub data: Vec<u8>,
+    /// Additional settings stored after the plugin state, such as the dry/wet ratio.
+    pub extra: Vec<u8>,
+}
+
+impl PluginSlot {
+    // Parses a FXnn chunk. Returns None if the chunk is incomplete.
+    fn parse(chunk: &ExtensionChunk) -> Option<PluginSlot> {
+        let data = &chunk.data;
+        if data.len() < PLUGIN_INFO_SIZE + 4 {
+            return None;
+        }
+        let data_end = PLUGIN_INFO_SIZE + 4 + XModule::read_usize(data, PLUGIN_INFO_SIZE);
+        if data_end > data.len() {
+            return None;
+        }
+
+        Some(PluginSlot {
+            slot: (chunk.id[2] - b'0') * 10 + chunk.id[3] - b'0',
+            plugin_id: (XModule::read_usize(data, 0) as u32, XModule::read_usize(data, 4) as u32),
+            input_routing: XModule::read_usize(data, 8) as u32,
+            output_routing: XModule::read_usize(data, 12) as u32,
+            name: XModule::read_string(data, 32, 32),
+            library_name: XModule::read_string(data, 64, 64),
+            data: data[PLUGIN_INFO_SIZE + 4..data_end].to_vec(),
+            extra: data[data_end..].to_vec(),
+        })
+    }
+}
+
+
+/// The OpenMPT specific settings of a module, as returned by XModule::openmpt_extensions().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenMptExtensions {
+    /// The song message, with line breaks converted to '\n'.
+    pub song_message: Option<String>,
+    /// The names of the patterns, by pattern number.
+    pub pattern_names: Vec<String>,
+    /// The names of the channels, by channel number.
+    pub channel_names: Vec<String>,
+    /// The plugin each channel is routed to, by channel number. 0 means no plugin, otherwise the plugin slot
+    /// plus 1.
+    pub channel_plugins: Vec<u32>,
+    /// The plugin slots in use.
+    pub plugins: Vec<PluginSlot>,
+    /// The extended instrument properties (XTPM).
+    pub instrument_properties: Vec<ExtensionProperty>,
+    /// The extended song properties (STPM).
+    pub song_properties: Vec<ExtensionProperty>,
+}
+
+impl OpenMptExtensions {
+
+    /// Returns the song property with the given code, eg. b"RPB." for the rows per beat.
+    pub fn song_property(&self, code: &[u8; 4]) -> Option<&ExtensionProperty> {
+        self.song_properties.iter().find(|property| &property.code == code)
+    }
+
+    /// Returns the instrument property with the given code, eg. b"VE.." for the number of volume envelope points.
+    pub fn instrument_property(&self, code: &[u8; 4]) -> Option<&ExtensionProperty> {
+        self.instrument_properties.iter().find(|property| &property.code == code)
+    }
+}
+
+
+impl XModule {
+
+    /// Parses the OpenMPT extensions stored after the last instrument (see extension_chunks()). Chunks that are
+    /// incomplete are skipped. Modules without extensions return empty settings.
+    pub fn openmpt_extensions(&self) -> OpenMptExtensions {
+        let mut extensions = OpenMptExtensions::default();
+
+        for chunk in self.extension_chunks() {
+            let data = &chunk.data;
+            match &chunk.id {
+                b"text" => extensions.song_message = Some(String::from_utf8_lossy(data).replace("\r\n", "\n")
+                    .replace('\r', "\n").trim_end_matches('\0').to_string()),
+                b"PNAM" => extensions.pattern_names = data.chunks_exact(32)
+                    .map(|name| XModule::read_string(name, 0, 32)).collect(),
+                b"CNAM" => extensions.channel_names = data.chunks_exact(20)
+                    .map(|name| XModule::read_string(name, 0, 20)).collect(),
+                b"CHFX" => extensions.channel_plugins = data.chunks_exact(4)
+                    .map(|plugin| XModule::read_usize(plugin, 0) as u32).collect(),
+                b"XTPM" => extensions.instrument_properties = parse_properties(data, self.instruments.len()),
+                b"STPM" => extensions.song_properties = parse_properties(data, 1),
+                id if &id[..2] == b"FX" => extensions.plugins.extend(PluginSlot::parse(&chunk)),
+                _ => (),
+            }
+        }
+
+        extensions
+    }
+
+    /// Returns the name of each channel, as stored by OpenMPT. Channels without a name get an empty string.
+    pub fn channel_names(&self) -> Vec<String> {
+        let mut names = self.openmpt_extensions().channel_names;
+        names.resize(self.channel_count() as usize, String::new());
+        names
+    }
+}
+
+// Parses a list of properties, each consisting of a code, a 16-bit size, and count values of that size.
+// Parsing stops at the first incomplete property.
+fn parse_properties(data: &[u8], count: usize) -> Vec<ExtensionProperty> {
+    let mut properties = Vec::new();
+    let mut offset = 0;
+
+    while offset + 6 <= data.len() {
+        let mut code = [0u8; 4];
+        code.copy_from_slice(&data[offset..offset + 4]);
+        let size = XModule::read_u16(data, offset + 4);
+        let end = offset + 6 + size as usize * count;
+        if end > data.len() {
+            break;
+        }
+
+        properties.push(ExtensionProperty { code, size, data: data[offset + 6..end].to_vec() });
+        offset = end;
+    }
+
+    properties
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_openmpt_extensions() {
+    use std::fs;
+
+    let mut data = fs::read("test.xm").unwrap();
+    let xm = XModule::parse(data.clone()).unwrap();
+    assert_eq!(xm.openmpt_extensions(), OpenMptExtensions::default());
+    assert_eq!(xm.channel_names(), vec![String::new(); 4]);
+
+    let mut plugin = vec![0u8; PLUGIN_INFO_SIZE];
+    plugin[..8].copy_from_slice(b"PtsVXfoo");
+    plugin[32..37].copy_from_slice(b"Delay");
+    plugin[64..72].copy_from_slice(b"delay.so");
+    plugin.extend(&[3, 0, 0, 0, 7, 8, 9]);
+    plugin.extend(b"DWRT");
+
+    let chunks = vec![
+        ExtensionChunk { id: *b"text", data: b"line 1\rline 2".to_vec() },
+        ExtensionChunk { id: *b"PNAM", data: [&b"intro"[..], &[0; 27]].concat() },
+        ExtensionChunk { id: *b"CNAM", data: [&b"lead"[..], &[0; 16], b"bass", &[0; 16], b"perc", &[0; 16]].concat() },
+        ExtensionChunk { id: *b"CHFX", data: vec![0, 0, 0, 0, 1, 0, 0, 0] },
+        ExtensionChunk { id: *b"FX00", data: plugin },
+        ExtensionChunk { id: *b"XTPM", data: [&b"VE.."[..], &[1, 0], &[6, 12]].concat() },
+        ExtensionChunk { id: *b"STPM", data: [&b"RPB."[..], &[4, 0], &[4, 0, 0, 0], b"RPM.", &[4, 0], &[16]].concat() },
+    ];
+    for chunk in &chunks {
+        data.extend(chunk.to_bytes());
+    }
+
+    let xm = XModule::parse(data).unwrap();
+    let extensions = xm.openmpt_extensions();
+    assert_eq!(extensions.song_message, Some("line 1\nline 2".to_string()));
+    assert_eq!(extensions.pattern_names, vec!["intro"]);
+    assert_eq!(xm.channel_names(), vec!["lead", "bass", "perc", ""]);
+    assert_eq!(extensions.channel_plugins, vec![0, 1]);
+
+    let plugin = &extensions.plugins[0];
+    assert_eq!((plugin.slot, plugin.name.as_str(), plugin.library_name.as_str()), (0, "Delay", "delay.so"));
+    assert_eq!(plugin.plugin_id.0, u32::from_le_bytes(*b"PtsV"));
+    assert_eq!((plugin.data.as_slice(), plugin.extra.as_slice()), (&[7, 8, 9][..], &b"DWRT"[..]));
+
+    let envelope_points = extensions.instrument_property(b"VE..").unwrap();
+    assert_eq!(envelope_points.instrument_value(1), Some(&[12][..]));
+    assert_eq!(envelope_points.instrument_value(2), None);
+    assert_eq!(extensions.song_property(b"RPB.").unwrap().data, vec![4, 0, 0, 0]);
+    // the incomplete RPM. property is skipped
+    assert_eq!(extensions.song_properties.len(), 1);
+    assert_eq!(extensions.song_properties[0].code_str(), "RPB.");
+}
@@ -0,0 +1,158 @@
+use super::{XModule, Effect, QuirksMode, XM_FX_0XX, XM_FX_1XX, XM_FX_2XX, XM_FX_AXX, XM_FX_CXX, XM_FX_E1X, XM_FX_E2X,
+    XM_FX_EAX, XM_FX_EBX, XM_FX_EEX, XM_FX_PXX, XM_FX_X1X, XM_FX_X2X};
+use super::cells::PackedCell;
+
+// Slides that do nothing with a parameter of 0, as long as their memory is 0 as well. Effects that have side effects
+// regardless of their parameter, like tone portamento keeping notes from being triggered, are not included.
+const NO_OP_SLIDES: [u8; 10] = [XM_FX_1XX, XM_FX_2XX, XM_FX_AXX, XM_FX_PXX, XM_FX_X1X, XM_FX_X2X, XM_FX_E1X, XM_FX_E2X,
+    XM_FX_EAX, XM_FX_EBX];
+
+impl XModule {
+
+    /// Moves set volume effects (Cxx) into the volume column of the same cell, where this does not change playback:
+    /// if the volume column is empty, or sets a volume that Cxx overrides anyway. Cells with volume column effects
+    /// are left unchanged. This frees the effect column for other commands, and usually makes patterns pack better.
+    /// Returns the number of cells changed.
+    pub fn move_volume_fx_to_volume_column(&mut self) -> usize {
+        let mut changed = 0;
+        for cell in self.cells_mut() {
+            let free = cell.volume().is_none_or(|vol| (0x10..=0x50).contains(&vol));
+            if cell.fx_command() != Some(XM_FX_CXX) || !free {
+                continue;
+            }
+            cell.set_volume(Some(0x10 + cell.fx_param().unwrap_or(0).min(0x40)));
+            cell.set_fx_command(None);
+            cell.set_fx_param(None);
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Moves volumes set in the volume column into the effect column as Cxx, where the effect column is empty.
+    /// This is the reverse of move_volume_fx_to_volume_column(), for replayers that do not support the volume column.
+    /// Returns the number of cells changed.
+    pub fn move_volume_column_to_volume_fx(&mut self) -> usize {
+        let mut changed = 0;
+        for cell in self.cells_mut() {
+            let vol = match cell.volume() {
+                Some(vol) if (0x10..=0x50).contains(&vol) => vol,
+                _ => continue,
+            };
+            if cell.fx_command().unwrap_or(0) != 0 || cell.fx_param().unwrap_or(0) != 0 {
+                continue;
+            }
+            cell.set_volume(None);
+            cell.set_fx_command(Some(XM_FX_CXX));
+            cell.set_fx_param(Some(vol - 0x10));
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Removes effects that do not change playback: empty arpeggios (000) and pattern delays (EE0), and slides with a
+    /// parameter of 0 (100, 200, A00, P00, E10, E20, EA0, EB0, X10, X20) on channels where the slide, or any effect
+    /// sharing its memory (see Effect::shares_memory_with()), never has a parameter other than 0, so that the memory
+    /// it would continue with is always 0. Effect memory is shared the way FT2 does.
+    /// Returns the number of cells changed.
+    pub fn canonicalize_effects(&mut self) -> usize {
+        // the effects that have a non-zero parameter somewhere, by channel
+        let mut used: Vec<Vec<Effect>> = Vec::new();
+        for ptn in &self.patterns {
+            used.resize(used.len().max(ptn.tracks.len()), Vec::new());
+            for (trk, used) in ptn.tracks.iter().zip(used.iter_mut()) {
+                for cell in &trk.cells {
+                    match raw_effect(cell) {
+                        Some((fx, param)) if param > 0 && !used.contains(&fx) => used.push(fx),
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        let mut changed = 0;
+        for ptn in &mut self.patterns {
+            for (trk, used) in ptn.tracks.iter_mut().zip(&used) {
+                for cell in trk.cells.iter_mut() {
+                    let fx = match raw_effect(cell) {
+                        Some((fx, 0)) => fx,
+                        _ => continue,
+                    };
+                    let no_op = fx.command() == XM_FX_0XX || fx.command() == XM_FX_EEX
+                        || (NO_OP_SLIDES.contains(&fx.command())
+                            && !used.iter().any(|other| fx.shares_memory_with(*other, QuirksMode::FT2)));
+                    if no_op {
+                        cell.set_fx_command(None);
+                        cell.set_fx_param(None);
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    // All cells of all patterns.
+    fn cells_mut(&mut self) -> impl Iterator<Item = &mut PackedCell> {
+        self.patterns.iter_mut().flat_map(|ptn| ptn.tracks.iter_mut()).flat_map(|trk| trk.cells.iter_mut())
+    }
+}
+
+// The effect of the cell and its parameter, or None if the cell has no valid effect.
+fn raw_effect(cell: &PackedCell) -> Option<(Effect, u8)> {
+    Effect::from_raw(cell.fx_command()?, cell.fx_param().unwrap_or(0))
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_move_volume_fx() {
+    use super::{XMPattern, XM_FX_AXX};
+
+    let mut xm = XModule::default();
+    let mut ptn = XMPattern::empty(4, 1);
+    let cells = [(None, Some((XM_FX_CXX, 0x20))), (Some(0x30), Some((XM_FX_CXX, 0x50))),
+        (Some(0x65), Some((XM_FX_CXX, 0x10))), (Some(0x18), Some((XM_FX_AXX, 0x01)))];
+    for (row, (vol, fx)) in cells.iter().enumerate() {
+        let cell = &mut ptn.tracks[0].cells[row];
+        cell.set_note(Some(49));
+        cell.set_volume(*vol);
+        cell.set_fx_command(fx.map(|fx| fx.0));
+        cell.set_fx_param(fx.map(|fx| fx.1));
+    }
+    xm.patterns.push(ptn);
+
+    assert_eq!(xm.move_volume_fx_to_volume_column(), 2);
+    let columns = |xm: &XModule| xm.patterns[0].tracks[0].cells.iter()
+        .map(|cell| (cell.volume(), cell.fx_command(), cell.fx_param())).collect::<Vec<_>>();
+    assert_eq!(columns(&xm), vec![(Some(0x30), None, None), (Some(0x50), None, None),
+        (Some(0x65), Some(XM_FX_CXX), Some(0x10)), (Some(0x18), Some(XM_FX_AXX), Some(0x01))]);
+
+    assert_eq!(xm.move_volume_column_to_volume_fx(), 2);
+    assert_eq!(columns(&xm)[..2], [(None, Some(XM_FX_CXX), Some(0x20)), (None, Some(XM_FX_CXX), Some(0x40))]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_canonicalize_effects() {
+    use super::{XMPattern, XM_FX_3XX, XM_FX_5XX};
+
+    // 000, 100 on a channel without portamento, EE0, 300, and A00 on a channel with 5xx, 5xx, E21, E20 after E21
+    let fx = [[(XM_FX_0XX, 0), (XM_FX_1XX, 0), (0xe, 0xe0), (XM_FX_3XX, 0)],
+        [(XM_FX_AXX, 0), (XM_FX_5XX, 0x10), (0xe, 0x21), (0xe, 0x20)]];
+    let mut xm = XModule::default();
+    let mut ptn = XMPattern::empty(4, 2);
+    for (trk, fx) in ptn.tracks.iter_mut().zip(&fx) {
+        for (cell, (cmd, param)) in trk.cells.iter_mut().zip(fx) {
+            cell.set_fx_command(Some(*cmd));
+            cell.set_fx_param(Some(*param));
+        }
+    }
+    xm.patterns.push(ptn);
+
+    assert_eq!(xm.canonicalize_effects(), 3);
+    let kept = |trk: usize| xm.patterns[0].tracks[trk].cells.iter().map(|cell| cell.fx_command().is_some())
+        .collect::<Vec<bool>>();
+    assert_eq!(kept(0), vec![false, false, false, true]);
+    assert_eq!(kept(1), vec![true, true, true, true]);
+    assert_eq!(xm.canonicalize_effects(), 0);
+}
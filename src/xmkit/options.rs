@@ -0,0 +1,155 @@
+use std::fmt;
+use std::sync::Arc;
+use super::{XModule, XMParseError};
+
+/// The parts of a module, in the order in which they are parsed. See ParseOptions::on_progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseStage {
+    /// The module header, including the pattern sequence.
+    Header,
+    /// The patterns.
+    Patterns,
+    /// The instruments, including the sample headers.
+    Instruments,
+    /// The sample data. Samples are stored with their instruments, so this stage advances along with Instruments.
+    Samples,
+}
+
+/// Options for parsing modules with `XModule::parse_with_options`.
+#[derive(Clone)]
+pub struct ParseOptions {
+    /// The highest number of channels that is accepted. FT2 supports up to 32 channels, while OpenMPT writes XMs
+    /// with up to 127 channels. Defaults to 127.
+    pub max_channels: u8,
+    /// The largest size in bytes that a module may have, both as stored and with all patterns unpacked (see
+    /// PatternPacking::Unpacked). Since patterns are unpacked while they are parsed, this limits the memory that a
+    /// small file with large, empty patterns can claim. Defaults to no limit.
+    pub max_total_size: usize,
+    /// The largest size in bytes of a single sample's data. Defaults to no limit.
+    pub max_sample_bytes: usize,
+    /// Called with the stage and the fraction of it that is done (0.0..1.0) as parsing progresses, so that loaders
+    /// can show a progress bar. Each stage is reported as done (1.0), even if the module has no patterns or
+    /// instruments. Patterns and instruments are counted, sample data is measured in bytes. Defaults to None.
+    pub on_progress: Option<Arc<dyn Fn(ParseStage, f32) + Send + Sync>>,
+}
+
+impl ParseOptions {
+    // Reports progress to the on_progress callback, if there is one.
+    pub(crate) fn report(&self, stage: ParseStage, done: usize, total: usize) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(stage, if total == 0 { 1.0 } else { done as f32 / total as f32 });
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions { max_channels: 127, max_total_size: usize::MAX, max_sample_bytes: usize::MAX, on_progress: None }
+    }
+}
+
+impl fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("max_channels", &self.max_channels)
+            .field("max_total_size", &self.max_total_size)
+            .field("max_sample_bytes", &self.max_sample_bytes)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+
+impl XModule {
+
+    /// Parses eXtended Module data like parse(), applying the given options.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the data is not a valid module, or if the module exceeds the limits set in the
+    /// options.
+    pub fn parse_with_options(data: Vec<u8>, options: ParseOptions) -> Result<XModule, XMParseError> {
+        XModule::parse_with(data, &options, None)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_parse_options() {
+    use std::path::Path;
+    use super::{XMPattern, Severity, XM_CHANNEL_COUNT};
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut wide = xm.clone();
+    wide.header[XM_CHANNEL_COUNT] = 100;
+    for ptn in wide.patterns.iter_mut() {
+        let mut tracks = XMPattern::empty(ptn.len(), 96).tracks;
+        ptn.tracks.append(&mut tracks);
+    }
+    wide.patterns[0].tracks[99].cells[0].set_note(Some(49));
+    wide.patterns[0].tracks[99].cells[0].set_instrument(Some(1));
+    let data = wide.to_bytes();
+
+    let parsed = XModule::parse(data.clone()).unwrap();
+    assert_eq!(parsed.channel_count(), 100);
+    assert!(parsed.patterns.iter().all(|ptn| ptn.channel_count() == 100));
+    assert_eq!(parsed.patterns[0].tracks[99].cell(0).unwrap().note, Some(49));
+    assert_eq!(parsed.to_bytes(), data);
+    assert_eq!(parsed.snapshot(0, 0).unwrap().channels[99].note, 49);
+    assert_eq!(parsed.render_stems(8000).len(), 100);
+    assert!(parsed.validate().issues.iter().any(|issue| issue.severity == Severity::Warning
+        && issue.message.contains("32 channels")));
+
+    assert!(XModule::parse_with_options(data.clone(), ParseOptions::default()).is_ok());
+    let e = XModule::parse_with_options(data, ParseOptions { max_channels: 32, ..Default::default() }).unwrap_err();
+    assert_eq!(e.offset(), Some(XM_CHANNEL_COUNT));
+    assert!(XModule::parse_with_options(xm.to_bytes(), ParseOptions { max_channels: 32, ..Default::default() }).is_ok());
+}
+
+#[cfg(test)]
+#[test]
+fn test_size_limits() {
+    use std::path::Path;
+    use super::{ValidationLocation, PatternPacking};
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let data = xm.to_bytes();
+    let unpacked = xm.to_bytes_with_packing(PatternPacking::Unpacked).len();
+    let largest_sample = xm.instruments.iter().flat_map(|instr| instr.samples.iter()).map(|smp| smp.len()).max()
+        .unwrap();
+
+    let limits = |max_total_size, max_sample_bytes| ParseOptions { max_total_size, max_sample_bytes,
+        ..Default::default() };
+    assert!(XModule::parse_with_options(data.clone(), limits(unpacked, largest_sample)).is_ok());
+
+    let e = XModule::parse_with_options(data.clone(), limits(data.len() - 1, largest_sample)).unwrap_err();
+    assert_eq!(e.location, Some(ValidationLocation::Module));
+    let e = XModule::parse_with_options(data.clone(), limits(unpacked - 1, largest_sample)).unwrap_err();
+    assert!(matches!(e.location, Some(ValidationLocation::Pattern(_))));
+    let e = XModule::parse_with_options(data, limits(unpacked, largest_sample - 1)).unwrap_err();
+    assert!(matches!(e.location, Some(ValidationLocation::Sample(_, _))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_progress() {
+    use std::fs;
+    use std::sync::Mutex;
+
+    let reports: Arc<Mutex<Vec<(ParseStage, f32)>>> = Arc::new(Mutex::new(Vec::new()));
+    let log = reports.clone();
+    let options = ParseOptions { on_progress: Some(Arc::new(move |stage, fraction| {
+        log.lock().unwrap().push((stage, fraction));
+    })), ..Default::default() };
+    XModule::parse_with_options(fs::read("test.xm").unwrap(), options).unwrap();
+
+    let reports = reports.lock().unwrap();
+    let stages: Vec<ParseStage> = reports.iter().map(|(stage, _)| *stage).collect();
+    assert_eq!(stages[..4], [ParseStage::Header, ParseStage::Patterns, ParseStage::Patterns, ParseStage::Patterns]);
+    for stage in [ParseStage::Header, ParseStage::Patterns, ParseStage::Instruments, ParseStage::Samples] {
+        let fractions: Vec<f32> = reports.iter().filter(|(s, _)| *s == stage).map(|(_, fraction)| *fraction)
+            .collect();
+        assert!(fractions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(fractions.last(), Some(&1.0));
+    }
+}
@@ -0,0 +1,54 @@
+use super::{XModule, XMPattern};
+
+impl XModule {
+
+    /// Returns the pattern played at the given sequence position (order), counted from 0. Returns None if the
+    /// position is beyond the end of the sequence, or if it refers to a pattern that does not exist, which FT2
+    /// plays as an empty 64-row pattern.
+    pub fn pattern_at_order(&self, order: u16) -> Option<&XMPattern> {
+        self.pattern_nr_at_order(order).and_then(|ptn_nr| self.patterns.get(ptn_nr as usize))
+    }
+
+    /// Returns the number of the pattern played at the given sequence position, or None if the position is beyond
+    /// the end of the sequence. Unlike pattern_at_order(), this also returns numbers of patterns that do not exist.
+    pub fn pattern_nr_at_order(&self, order: u16) -> Option<u8> {
+        self.sequence().get(order as usize).copied()
+    }
+
+    /// Returns the sequence positions that play the given pattern, in ascending order.
+    pub fn orders_using_pattern(&self, ptn_nr: u8) -> Vec<u16> {
+        self.sequence().iter().enumerate()
+            .filter(|(_, nr)| **nr == ptn_nr)
+            .map(|(order, _)| order as u16)
+            .collect()
+    }
+
+    /// Returns the first sequence position that plays the given pattern, or None if the pattern is not in the
+    /// sequence.
+    pub fn first_order_of_pattern(&self, ptn_nr: u8) -> Option<u16> {
+        self.sequence().iter().position(|nr| *nr == ptn_nr).map(|order| order as u16)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_order_helpers() {
+    use std::path::Path;
+    use super::XM_SEQUENCE_BEGIN;
+
+    // the sequence of the test module is 0, 1, 2, 1
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert!(std::ptr::eq(xm.pattern_at_order(2).unwrap(), &xm.patterns[2]));
+    assert!(xm.pattern_at_order(4).is_none());
+    assert_eq!(xm.orders_using_pattern(1), vec![1, 3]);
+    assert!(xm.orders_using_pattern(5).is_empty());
+    assert_eq!(xm.first_order_of_pattern(1), Some(1));
+    assert_eq!(xm.first_order_of_pattern(5), None);
+
+    // a position that refers to a pattern that does not exist
+    xm.header[XM_SEQUENCE_BEGIN + 3] = 5;
+    assert_eq!(xm.pattern_nr_at_order(3), Some(5));
+    assert!(xm.pattern_at_order(3).is_none());
+    assert_eq!(xm.first_order_of_pattern(5), Some(3));
+}
@@ -0,0 +1,136 @@
+use super::XMSample;
+
+// Amiga periods in 1/8 semitone steps, starting one semitone below C (finetune -128) and ending at the following C.
+const AMIGA_FINE_PERIODS: [u16; 105] = [
+    907, 900, 894, 887, 881, 875, 868, 862, 856, 850, 844, 838, 832, 826, 820, 814,
+    808, 802, 796, 791, 785, 779, 774, 768, 762, 757, 752, 746, 741, 736, 730, 725,
+    720, 715, 709, 704, 699, 694, 689, 684, 678, 675, 670, 665, 660, 655, 651, 646,
+    640, 636, 632, 628, 623, 619, 614, 610, 604, 601, 597, 592, 588, 584, 580, 575,
+    570, 567, 563, 559, 555, 551, 547, 543, 538, 535, 532, 528, 524, 520, 516, 513,
+    508, 505, 502, 498, 494, 491, 487, 484, 480, 477, 474, 470, 467, 463, 460, 457,
+    453, 450, 447, 443, 440, 437, 434, 431, 428];
+
+// The highest note FT2 can play, counted from C-0 = 0.
+const MAX_REAL_NOTE: i16 = 118;
+
+pub(crate) const NOTE_NAMES: [&str; 12] = ["C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-"];
+
+/// Returns the name of the given note as displayed in trackers, eg. "C#4" for note 50.
+/// Key off (note 97) is displayed as "===", empty and invalid notes are displayed as "---".
+pub fn note_name(note: u8) -> String {
+    match note {
+        1..=96 => format!("{}{}", NOTE_NAMES[(note as usize - 1) % 12], (note - 1) / 12),
+        97 => "===".to_string(),
+        _ => "---".to_string(),
+    }
+}
+
+/// Returns the relative note and finetune that make a sample recorded at the given rate play back at its original
+/// pitch on C-4. FT2 only uses the upper 5 bits of the finetune, so the result is accurate to 1/16 semitone.
+pub fn tuning_for_rate(sample_rate_hz: u32) -> (i8, i8) {
+    if sample_rate_hz == 0 {
+        return (0, 0);
+    }
+
+    // distance from the 8363 Hz C-4 base rate, in 1/128 semitones
+    let fine = ((sample_rate_hz as f64 / 8363.0).log2() * 12.0 * 128.0).round() as i32;
+    let relative_note = ((fine + 64) >> 7).clamp(-96, 95);
+    let finetune = (fine - relative_note * 128).clamp(-128, 127);
+
+    (relative_note as i8, finetune as i8)
+}
+
+impl XMSample {
+    /// Sets the relative note and finetune so that the sample plays at its original pitch on C-4,
+    /// given the rate it was recorded at.
+    pub fn set_tuning_from_rate(&mut self, sample_rate_hz: u32) {
+        let (relative_note, finetune) = tuning_for_rate(sample_rate_hz);
+        self.header[13] = finetune as u8;
+        self.header[16] = relative_note as u8;
+    }
+}
+
+/// Returns the period FT2 uses for a note on the linear or Amiga frequency table. The note is counted from C-0 = 0
+/// and includes the sample's relative note, so C-4 played on a sample with relative note 0 is note 48. Notes above
+/// B-9 are clamped. Finetune is the sample finetune value (-128..127), of which FT2 only uses the upper 5 bits.
+/// On the Amiga table, odd finetune steps are interpolated between the entries of FT2's 1/8 semitone period table,
+/// and lower octaves are derived by shifting, exactly as FT2 does.
+pub fn period_for_note(note: i16, finetune: i8, amiga_ft: bool) -> u16 {
+    let note = note.clamp(0, MAX_REAL_NOTE) as usize;
+    let fine = (finetune >> 3) as i32;
+
+    if !amiga_ft {
+        return (7680 - (note as i32) * 64 - fine * 4) as u16;
+    }
+
+    let pos = (note % 12) * 8 + (8 + (fine >> 1)) as usize;
+    let octave = note / 12;
+    let mut period = AMIGA_FINE_PERIODS[pos] as u32 * 32;
+    if fine & 1 != 0 {
+        period = (AMIGA_FINE_PERIODS[pos] as u32 + AMIGA_FINE_PERIODS[pos + 1] as u32) * 16;
+    }
+
+    (period >> octave) as u16
+}
+
+/// Returns the playback frequency in Hz for a period on the linear or Amiga frequency table. On both tables,
+/// C-4 (period 4608 and 1712, respectively) plays at 8363 Hz. A period of 0 returns 0.0.
+pub fn frequency_for_period(period: u16, amiga_ft: bool) -> f64 {
+    if period == 0 {
+        0.0
+    }
+    else if amiga_ft {
+        8363.0 * 1712.0 / period as f64
+    }
+    else {
+        8363.0 * 2f64.powf((4608.0 - period as f64) / 768.0)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_tuning_for_rate() {
+    assert_eq!(tuning_for_rate(8363), (0, 0));
+    assert_eq!(tuning_for_rate(16726), (12, 0));
+    assert_eq!(tuning_for_rate(44100), (29, -28));
+    assert_eq!(tuning_for_rate(22050), (17, -28));
+    assert_eq!(tuning_for_rate(1), (-96, -128));
+
+    // C-4 with the computed tuning should play at roughly the original rate
+    let (relative_note, finetune) = tuning_for_rate(44100);
+    let freq = frequency_for_period(period_for_note(48 + relative_note as i16, finetune, false), false);
+    assert!((freq - 44100.0).abs() < 44100.0 * 0.004);
+}
+
+#[cfg(test)]
+#[test]
+fn test_period_for_note() {
+    // linear table
+    assert_eq!(period_for_note(0, 0, false), 7680);
+    assert_eq!(period_for_note(48, 0, false), 4608);
+    assert_eq!(period_for_note(48, 127, false), 4548);
+    assert_eq!(period_for_note(48, -128, false), 4672);
+    assert_eq!(period_for_note(48, 7, false), 4608);
+    assert_eq!(period_for_note(200, 0, false), 128);
+    assert_eq!(period_for_note(-5, 0, false), 7680);
+
+    // Amiga table
+    assert_eq!(period_for_note(0, -128, true), 29024);
+    assert_eq!(period_for_note(0, 0, true), 27392);
+    assert_eq!(period_for_note(48, 0, true), 1712);
+    assert_eq!(period_for_note(57, 0, true), 1016);
+    assert_eq!(period_for_note(60, 0, true), 856);
+    // odd finetune steps are interpolated
+    assert_eq!(period_for_note(48, 8, true), 1706);
+    assert_eq!(period_for_note(48, -8, true), 1718);
+    assert_eq!(period_for_note(48, 16, true), 1700);
+
+    assert_eq!(frequency_for_period(4608, false), 8363.0);
+    assert_eq!(frequency_for_period(3840, false), 16726.0);
+    assert_eq!(frequency_for_period(1712, true), 8363.0);
+    assert_eq!(frequency_for_period(856, true), 16726.0);
+    assert_eq!(frequency_for_period(0, true), 0.0);
+    let a4 = frequency_for_period(period_for_note(57, 0, false), false);
+    assert!((a4 - 8363.0 * 2f64.powf(0.75)).abs() < 1e-9);
+}
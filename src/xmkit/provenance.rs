@@ -0,0 +1,108 @@
+use super::{XModule, XM_HEADER_SIZE, XM_MODULE_NAME, XM_TRACKER_NAME};
+
+// The sample header reserved byte value that marks ModPlug ADPCM compressed sample data.
+const MODPLUG_ADPCM: u8 = 0xad;
+
+/// The tracker that probably wrote a module, as determined by XModule::probable_tracker().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tracker {
+    /// FastTracker 2.
+    FastTracker2,
+    /// A tracker that identifies itself as FastTracker 2, but does not write modules the way FT2 does.
+    FastTracker2Clone,
+    /// ModPlug Tracker, or an OpenMPT version from before it identified itself.
+    ModPlugTracker,
+    OpenMpt,
+    MilkyTracker,
+    Skale,
+    MadTracker,
+    /// None of the known trackers. tracker_name() may tell more.
+    Unknown,
+}
+
+impl XModule {
+
+    /// Guesses which tracker wrote the module. Many trackers write "FastTracker v2.00" as their tracker name, so
+    /// this looks at fingerprints in the data as well:
+    /// - OpenMPT and ModPlug Tracker store extension chunks after the last instrument (only OpenMPT writes song
+    ///   properties), and mark ADPCM compressed samples with 0xAD in the reserved byte of the sample header. Early
+    ///   ModPlug versions write "FastTracker v 2.00".
+    /// - FT2 pads the module name with spaces rather than zeros, writes a 276 byte module header, and never stores
+    ///   empty fields in packed pattern data. Modules that claim to come from FT2 but differ in any of these ways
+    ///   were written by a clone.
+    /// - MilkyTracker, Skale Tracker, and MadTracker are recognized by their tracker names.
+    ///
+    /// These are heuristics, so modules that have been edited after loading, or that were deliberately disguised,
+    /// may be attributed to the wrong tracker.
+    pub fn probable_tracker(&self) -> Tracker {
+        let tracker_name = &self.header[XM_TRACKER_NAME..XM_TRACKER_NAME + 20];
+        let chunks = self.extension_chunks();
+
+        if tracker_name.starts_with(b"OpenMPT") || chunks.iter().any(|chunk| &chunk.id == b"STPM") {
+            return Tracker::OpenMpt;
+        }
+        if !chunks.is_empty() || tracker_name.starts_with(b"FastTracker v 2.00") || self.instruments.iter()
+            .flat_map(|instr| instr.samples.iter()).any(|smp| smp.header[17] == MODPLUG_ADPCM) {
+            return Tracker::ModPlugTracker;
+        }
+        if tracker_name.starts_with(b"MilkyTracker") {
+            return Tracker::MilkyTracker;
+        }
+        if tracker_name.starts_with(b"Skale Tracker") {
+            return Tracker::Skale;
+        }
+        if tracker_name.starts_with(b"MadTracker") {
+            return Tracker::MadTracker;
+        }
+        if !tracker_name.starts_with(b"FastTracker v2.00") {
+            return Tracker::Unknown;
+        }
+
+        let name_padded_with_zeros = self.header[XM_MODULE_NAME..XM_MODULE_NAME + 20].contains(&0);
+        let stores_empty_fields = self.patterns.iter().flat_map(|ptn| ptn.tracks.iter())
+            .flat_map(|trk| trk.cells.iter()).any(|cell| (0..5).any(|field| cell.get(field) == Some(0)));
+        if name_padded_with_zeros || stores_empty_fields || XModule::read_usize(&self.header, XM_HEADER_SIZE) != 276 {
+            Tracker::FastTracker2Clone
+        }
+        else {
+            Tracker::FastTracker2
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_probable_tracker() {
+    use std::path::Path;
+
+    // the test module has a zero padded name
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert_eq!(xm.probable_tracker(), Tracker::FastTracker2Clone);
+
+    let mut ft2 = xm.clone();
+    ft2.set_name("xmkit test module");
+    assert_eq!(ft2.probable_tracker(), Tracker::FastTracker2);
+
+    let mut packed_zero = ft2.clone();
+    packed_zero.patterns[0].tracks[0].cells[0].set_volume(Some(0));
+    assert_eq!(packed_zero.probable_tracker(), Tracker::FastTracker2Clone);
+
+    let mut adpcm = ft2.clone();
+    adpcm.instruments[0].samples[0].header[17] = MODPLUG_ADPCM;
+    assert_eq!(adpcm.probable_tracker(), Tracker::ModPlugTracker);
+
+    let mut extended = ft2.clone();
+    extended.set_trailing_data(b"text\x02\x00\x00\x00hi".to_vec());
+    assert_eq!(extended.probable_tracker(), Tracker::ModPlugTracker);
+    extended.set_trailing_data(b"STPM".to_vec());
+    assert_eq!(extended.probable_tracker(), Tracker::OpenMpt);
+
+    let mut renamed = ft2.clone();
+    for (name, tracker) in [("MilkyTracker 1.04.00", Tracker::MilkyTracker), ("OpenMPT 1.31", Tracker::OpenMpt),
+        ("FastTracker v 2.00", Tracker::ModPlugTracker), ("MadTracker 2.0", Tracker::MadTracker),
+        ("Skale Tracker", Tracker::Skale), ("Renoise", Tracker::Unknown)] {
+        renamed.set_tracker_name(name);
+        assert_eq!(renamed.probable_tracker(), tracker);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Effect, XModule};
+
+// The note played at 440 Hz, A-4.
+const A4_NOTE: i32 = 58;
+
+/// How a sound chip's volume levels relate to output amplitude, for quantize_volume().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VolumeCurve {
+    /// Each level step adds the same amount of amplitude, as with simple DACs and pulse width based 1-bit engines.
+    Linear,
+    /// Each level step adds 3 dB, as with the AY-3-8910/YM2149 and SN76489.
+    Logarithmic,
+}
+
+/// The XM channels assigned to the tone channels of a sound chip on a played row, as returned by
+/// XModule::map_tone_channels().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToneChannelMap {
+    /// The sequence position of the row.
+    pub order: u16,
+    /// The row number within the pattern.
+    pub row: u16,
+    /// For each tone channel, the XM channel it plays, or None if the tone channel is free.
+    pub channels: Vec<Option<u8>>,
+    /// The XM channels that are audible on the row but did not get a tone channel.
+    pub dropped: Vec<u8>,
+}
+
+/// An effect that a sound engine does not support, as returned by XModule::unsupported_effects().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsupportedEffect {
+    /// The pattern containing the effect.
+    pub pattern: u8,
+    /// The row containing the effect.
+    pub row: u16,
+    /// The channel containing the effect.
+    pub channel: u8,
+    /// The effect.
+    pub effect: Effect,
+}
+
+
+/// Converts an XM volume (0..0x40) to a 4-bit volume level (0..15). Only a volume of 0 results in level 0.
+pub fn quantize_volume(volume: u8, curve: VolumeCurve) -> u8 {
+    let volume = volume.min(0x40);
+    if volume == 0 {
+        return 0;
+    }
+
+    let level = match curve {
+        VolumeCurve::Linear => (volume as f64 * 15.0 / 64.0).round(),
+        // 3 dB per level, so the amplitude halves every 2 levels
+        VolumeCurve::Logarithmic => 15.0 + ((volume as f64 / 64.0).log2() * 2.0).round(),
+    };
+    level.clamp(1.0, 15.0) as u8
+}
+
+/// Calculates the tone periods of notes 1..96 (C-0..B-7) for a sound chip running at the given clock rate.
+/// The divider is the number of clock cycles per period step, eg. 16 for the AY-3-8910, or 32 for the SN76489.
+/// Tuning is equal tempered with A-4 at 440 Hz. Periods are clamped to 1..max_period, eg. 0xfff for the 12-bit
+/// tone registers of the AY-3-8910.
+pub fn tone_periods(clock_hz: u32, divider: u32, max_period: u16) -> Vec<u16> {
+    (1..=96).map(|note: i32| {
+        let frequency = 440.0 * 2f64.powf((note - A4_NOTE) as f64 / 12.0);
+        let period = (clock_hz as f64 / (divider.max(1) as f64 * frequency)).round();
+        period.clamp(1.0, max_period.max(1) as f64) as u16
+    }).collect()
+}
+
+
+impl XModule {
+
+    /// Assigns the XM channels to the given number of tone channels, row by row, for sound chips that can play
+    /// fewer notes at once than the module uses. A channel is audible from a note trigger until the note is
+    /// released, cut, or its volume drops to 0 (see snapshot()). Audible channels keep the tone channel they were
+    /// assigned to for as long as they are audible. New notes take over free tone channels in the order of their
+    /// XM channel. If there are more audible channels than tone channels, the loudest channels are kept.
+    /// Returns one mapping for each row of play_rows().
+    pub fn map_tone_channels(&self, tone_channels: u8) -> Vec<ToneChannelMap> {
+        let mut assigned: Vec<Option<u8>> = vec![None; tone_channels as usize];
+
+        self.snapshots().iter().map(|snapshot| {
+            let audible = |chan: u8| snapshot.channels.get(chan as usize)
+                .is_some_and(|state| state.note > 0 && state.volume > 0 && !state.key_off);
+            let volume = |chan: u8| snapshot.channels[chan as usize].volume;
+
+            for slot in assigned.iter_mut() {
+                *slot = slot.filter(|chan| audible(*chan));
+            }
+
+            // the loudest channels win, ties go to the lower channel number
+            let mut waiting: Vec<u8> = (0..snapshot.channels.len() as u8)
+                .filter(|chan| audible(*chan) && !assigned.contains(&Some(*chan)))
+                .collect();
+            waiting.sort_by_key(|chan| (std::cmp::Reverse(volume(*chan)), *chan));
+            let free = assigned.iter().filter(|slot| slot.is_none()).count().min(waiting.len());
+            let mut admitted = waiting[..free].to_vec();
+            admitted.sort_unstable();
+            for (slot, chan) in assigned.iter_mut().filter(|slot| slot.is_none()).zip(admitted) {
+                *slot = Some(chan);
+            }
+
+            let mut dropped = Vec::new();
+            for &chan in &waiting[free..] {
+                match assigned.iter_mut().min_by_key(|slot| slot.map(volume)) {
+                    Some(slot) if slot.is_some_and(|quietest| volume(quietest) < volume(chan)) => {
+                        dropped.extend(slot.replace(chan));
+                    },
+                    _ => dropped.push(chan),
+                }
+            }
+            dropped.sort_unstable();
+
+            ToneChannelMap { order: snapshot.order, row: snapshot.row, channels: assigned.clone(), dropped }
+        }).collect()
+    }
+
+    /// Lists the effects used in the patterns that are not in the given list of effects supported by a sound
+    /// engine. 000, which is an empty effect column rather than an arpeggio, is ignored. Other effects with a
+    /// parameter of 0, such as A00, are reported, since they recall the parameter last used on the channel. Only the
+    /// effect column is checked; volume column effects are not reported.
+    pub fn unsupported_effects(&self, supported: &[Effect]) -> Vec<UnsupportedEffect> {
+        let mut unsupported = Vec::new();
+
+        for (pattern, ptn) in self.patterns.iter().enumerate() {
+            for (channel, trk) in ptn.tracks.iter().enumerate() {
+                for (row, cell) in trk.cells.iter().enumerate() {
+                    let (cmd, param) = (cell.fx_command().unwrap_or(0), cell.fx_param().unwrap_or(0));
+                    let effect = match Effect::from_raw(cmd, param) {
+                        Some((effect, 0)) if effect.command() == 0 => continue,
+                        Some((effect, _)) => effect,
+                        None => continue,
+                    };
+                    if !supported.contains(&effect) {
+                        unsupported.push(UnsupportedEffect { pattern: pattern as u8, row: row as u16,
+                            channel: channel as u8, effect });
+                    }
+                }
+            }
+        }
+
+        unsupported
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_psg_helpers() {
+    use std::path::Path;
+    use super::{XM_FX_1XX, XM_FX_2XX, XM_FX_3XX, XM_FX_CXX, XM_FX_FXX};
+
+    assert_eq!(quantize_volume(0, VolumeCurve::Linear), 0);
+    assert_eq!(quantize_volume(1, VolumeCurve::Linear), 1);
+    assert_eq!(quantize_volume(0x20, VolumeCurve::Linear), 8);
+    assert_eq!(quantize_volume(0x50, VolumeCurve::Linear), 15);
+    assert_eq!(quantize_volume(0x40, VolumeCurve::Logarithmic), 15);
+    assert_eq!(quantize_volume(0x20, VolumeCurve::Logarithmic), 13);
+    assert_eq!(quantize_volume(0x10, VolumeCurve::Logarithmic), 11);
+
+    // ZX Spectrum 128 AY at 1.7734 MHz
+    let periods = tone_periods(1_773_400, 16, 0xfff);
+    assert_eq!(periods.len(), 96);
+    assert_eq!(periods[A4_NOTE as usize - 1], 252);
+    assert_eq!(periods[A4_NOTE as usize - 13], 504);
+    assert_eq!(periods[0], 0xfff);
+    assert!(periods.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let maps = xm.map_tone_channels(2);
+    assert_eq!(maps.len(), xm.play_rows().len());
+    assert!(maps.iter().all(|map| map.channels.len() == 2));
+    // row 0 triggers 3 notes, channel 2 (volume 0x20) is the quietest
+    assert_eq!((maps[0].channels.clone(), maps[0].dropped.clone()), (vec![Some(0), Some(1)], vec![2]));
+    for (map, snapshot) in maps.iter().zip(xm.snapshots()) {
+        for chan in map.channels.iter().flatten() {
+            assert!(snapshot.channels[*chan as usize].volume > 0);
+        }
+    }
+    assert!(xm.map_tone_channels(4).iter().all(|map| map.dropped.is_empty()));
+
+    let supported: Vec<Effect> = [XM_FX_1XX, XM_FX_2XX, XM_FX_3XX, XM_FX_CXX, XM_FX_FXX].iter()
+        .filter_map(|cmd| Effect::new(*cmd)).collect();
+    let unsupported = xm.unsupported_effects(&supported);
+    assert!(unsupported.iter().all(|fx| !supported.contains(&fx.effect)));
+    // G20 on row 14 of pattern 0, channel 3
+    assert!(unsupported.contains(&UnsupportedEffect { pattern: 0, row: 14, channel: 3,
+        effect: Effect::new(super::XM_FX_GXX).unwrap() }));
+    assert!(!unsupported.iter().any(|fx| (fx.pattern, fx.row) == (0, 8)));
+}
@@ -0,0 +1,125 @@
+use super::{XModule, XMPattern, XMTrack, XMParseError, XM_FX_X1X, XM_FX_X2X};
+
+/// The raw contents of a single pattern cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XMCell {
+    pub note: Option<u8>,
+    pub instrument: Option<u8>,
+    pub volume: Option<u8>,
+    pub fx_command: Option<u8>,
+    pub fx_param: Option<u8>,
+}
+
+impl XMCell {
+    /// Returns true if the cell contains the given effect. Use XM_FX_* constants to pass the fx_command value.
+    /// Extended effects (E1x..EEx, X1, X2) are considered seperate effects. 000 is not considered an arpeggio.
+    pub fn has_effect(&self, fx_command: u8) -> bool {
+        let cmd = self.fx_command.unwrap_or(0);
+        let param = self.fx_param.unwrap_or(0);
+
+        match fx_command {
+            0xe1..=0xef => cmd == 0xe && param >> 4 == fx_command & 0xf,
+            XM_FX_X1X | XM_FX_X2X => cmd == 0x21 && param >> 4 == fx_command - 0x21,
+            0 => cmd == 0 && param != 0,
+            _ => cmd == fx_command,
+        }
+    }
+
+    /// Returns true if the cell uses the given instrument, counted from 1.
+    pub fn uses_instrument(&self, instrument: u8) -> bool {
+        self.instrument == Some(instrument)
+    }
+}
+
+impl XMTrack {
+    /// Returns the raw contents of the given row.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn cell(&self, row: u16) -> Result<XMCell, XMParseError> {
+        self.validate_row(&row)?;
+        Ok(self.cell_unchecked(row as usize))
+    }
+
+    pub(crate) fn cell_unchecked(&self, row: usize) -> XMCell {
+        XMCell {
+            note: self.cells[row].note(),
+            instrument: self.cells[row].instrument(),
+            volume: self.cells[row].volume(),
+            fx_command: self.cells[row].fx_command(),
+            fx_param: self.cells[row].fx_param(),
+        }
+    }
+}
+
+impl XMPattern {
+    /// Returns the (channel, row) positions of all cells for which the given predicate returns true,
+    /// ordered by row and then by channel.
+    pub fn find<F: Fn(&XMCell) -> bool>(&self, predicate: F) -> Vec<(u8, u16)> {
+        let mut result = Vec::new();
+
+        for row in 0..self.len() {
+            for (chan, trk) in self.tracks.iter().enumerate() {
+                if (row as usize) < trk.cells.len() && predicate(&trk.cell_unchecked(row as usize)) {
+                    result.push((chan as u8, row));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Returns the (channel, row) positions of all cells using the given instrument, counted from 1.
+    pub fn rows_using_instrument(&self, instrument: u8) -> Vec<(u8, u16)> {
+        self.find(|cell| cell.uses_instrument(instrument))
+    }
+
+    /// Returns the (channel, row) positions of all cells containing the given effect.
+    /// Use XM_FX_* constants to pass the fx_command value.
+    pub fn rows_with_effect(&self, fx_command: u8) -> Vec<(u8, u16)> {
+        self.find(|cell| cell.has_effect(fx_command))
+    }
+}
+
+impl XModule {
+    /// Returns the (pattern, channel, row) positions of all cells in the module for which the given predicate
+    /// returns true. Patterns are searched in the order they are stored, regardless of the sequence.
+    pub fn find<F: Fn(&XMCell) -> bool>(&self, predicate: F) -> Vec<(usize, u8, u16)> {
+        self.patterns.iter().enumerate()
+            .flat_map(|(nr, ptn)| ptn.find(&predicate).into_iter().map(move |(chan, row)| (nr, chan, row)))
+            .collect()
+    }
+
+    /// Returns the (pattern, channel, row) positions of all cells using the given instrument, counted from 1.
+    pub fn rows_using_instrument(&self, instrument: u8) -> Vec<(usize, u8, u16)> {
+        self.find(|cell| cell.uses_instrument(instrument))
+    }
+
+    /// Returns the (pattern, channel, row) positions of all cells containing the given effect.
+    /// Use XM_FX_* constants to pass the fx_command value.
+    pub fn rows_with_effect(&self, fx_command: u8) -> Vec<(usize, u8, u16)> {
+        self.find(|cell| cell.has_effect(fx_command))
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_pattern_queries() {
+    use std::path::Path;
+    use super::{XM_FX_0XX, XM_FX_EDX, XM_FX_FXX};
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ptn = &xm.patterns[0];
+
+    assert_eq!(ptn.rows_using_instrument(2), vec![(1, 0), (1, 2), (1, 9)]);
+    assert_eq!(ptn.rows_with_effect(XM_FX_0XX), vec![(0, 4), (0, 5)]);
+    assert_eq!(ptn.rows_with_effect(XM_FX_EDX), vec![(1, 2)]);
+    assert_eq!(ptn.find(|cell| cell.note == Some(97)), vec![(0, 12)]);
+    assert_eq!(ptn.tracks[2].cell(0).unwrap(),
+        XMCell { note: Some(58), instrument: Some(1), volume: None, fx_command: Some(0xc), fx_param: Some(0x20) });
+    assert!(ptn.tracks[0].cell(16).is_err());
+
+    assert_eq!(xm.rows_using_instrument(2), vec![(0, 1, 0), (0, 1, 2), (0, 1, 9), (2, 0, 0)]);
+    assert_eq!(xm.rows_with_effect(XM_FX_FXX), vec![(0, 3, 8), (1, 3, 0)]);
+}
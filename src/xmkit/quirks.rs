@@ -0,0 +1,107 @@
+/// Selects the reference behavior for computing effective state from pattern data, where trackers and replayers
+/// disagree on how the XM format should be interpreted.
+///
+/// Arpeggio does not depend on the mode: 000 is always an empty effect column rather than an arpeggio, and
+/// XMTrack::arpeggio() always follows FT2's tick order, including its behavior at tempo 16 and above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    /// Follows FastTracker 2.08/2.09, including its bugs. This is the behavior of the tick-level functions
+    /// (tick_events(), state_at()).
+    FT2,
+    /// Follows OpenMPT, which emulates FT2 closely but resets the pattern loop start row at the beginning of
+    /// each pattern.
+    OpenMPT,
+    /// A simplified interpretation, which is what xmkit's functions without a QuirksMode parameter use:
+    /// note triggers reset effect memory, every effect keeps its own memory, only the volume column sets the
+    /// volume, and F00 sets the tempo to 0.
+    #[default]
+    Generic,
+}
+
+impl QuirksMode {
+    /// Returns true if triggering a note resets the memory of all effects.
+    pub fn note_resets_fx_memory(self) -> bool {
+        self == QuirksMode::Generic
+    }
+
+    /// Returns true if volume slide (Axy), tone portamento + volume slide (5xy), and vibrato + volume slide (6xy)
+    /// share their parameter memory.
+    pub fn shared_volume_slide_memory(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if multi retrig (Rxy) remembers its volume change and interval separately, so that eg. R03
+    /// keeps the volume change of a previous R83.
+    pub fn retrig_nibble_memory(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if Cxx sets the channel volume in addition to the volume column.
+    pub fn set_volume_fx(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if F00 stops playback instead of setting the tempo to 0. The tempo in effect is unchanged.
+    pub fn speed_zero_stops(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if only note triggers switch the instrument that is playing. Instrument numbers without a note,
+    /// or with a key-off or tone portamento, then only reset the volume and envelopes.
+    pub fn instrument_needs_note(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if a note with an instrument number beyond the module's instrument count is cut, rather than
+    /// playing a silent instrument.
+    pub fn invalid_instrument_cuts(self) -> bool {
+        self != QuirksMode::Generic
+    }
+
+    /// Returns true if the pattern loop start row (set by E60) carries over into the following patterns,
+    /// so that a loop without an E60 in the current pattern jumps to the row set in a previous pattern.
+    pub fn loop_start_persists(self) -> bool {
+        self == QuirksMode::FT2
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_quirks_mode() {
+    use super::{XMTrack, XM_FX_AXX, XM_FX_3XX, XM_FX_6XX, XM_FX_RXX};
+
+    let trk = XMTrack::from_columns(
+        &[Some(49), None, Some(50), None],
+        &[None; 4],
+        &[Some(0x30), None, None, None],
+        &[Some(XM_FX_AXX), Some(XM_FX_RXX), Some(XM_FX_RXX), Some(0xc)],
+        &[Some(0x04), Some(0x83), Some(0x05), Some(0x18)],
+    );
+
+    assert_eq!(trk.fx(XM_FX_6XX, 1).unwrap(), 0);
+    assert_eq!(trk.fx_with_quirks(XM_FX_6XX, 1, QuirksMode::FT2).unwrap(), 4);
+
+    assert_eq!(trk.fx(XM_FX_RXX, 2).unwrap(), 0x05);
+    assert_eq!(trk.fx_with_quirks(XM_FX_RXX, 2, QuirksMode::FT2).unwrap(), 0x85);
+
+    assert_eq!(trk.volume(1).unwrap(), 0x20);
+    assert_eq!(trk.volume(3).unwrap(), 0x40);
+    assert_eq!(trk.volume_with_quirks(3, QuirksMode::OpenMPT).unwrap(), 0x18);
+    assert_eq!(trk.volume_with_quirks(0, QuirksMode::FT2).unwrap(), 0x20);
+
+    // instrument without note, key-off, tone portamento, note with the last instrument, invalid instrument
+    let trk = XMTrack::from_columns(
+        &[Some(49), None, Some(97), Some(50), Some(51), Some(52)],
+        &[Some(1), Some(2), Some(3), Some(2), None, Some(9)],
+        &[None; 6],
+        &[None, None, None, Some(XM_FX_3XX), None, None],
+        &[None; 6],
+    );
+    let generic: Vec<u8> = (0..6).map(|row| trk.instrument(row).unwrap()).collect();
+    assert_eq!(generic, vec![1, 2, 3, 2, 2, 9]);
+    let ft2: Vec<u8> = (0..6).map(|row| trk.instrument_with_quirks(row, 4, QuirksMode::FT2).unwrap()).collect();
+    assert_eq!(ft2, vec![1, 1, 1, 1, 2, 0]);
+    assert_eq!(trk.instrument_with_quirks(5, 9, QuirksMode::OpenMPT).unwrap(), 9);
+    assert!(trk.instrument_with_quirks(6, 4, QuirksMode::FT2).is_err());
+}
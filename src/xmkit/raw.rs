@@ -0,0 +1,90 @@
+use super::{XMPattern, XMInstrument, XMSample, PatternPacking};
+
+impl XMPattern {
+
+    /// Returns the packed cell data of the pattern, without the pattern header, the way XModule::to_bytes() writes
+    /// it: as it was stored if the pattern has not been modified, and packed the way FT2 does otherwise. Empty
+    /// patterns may have no packed data at all.
+    pub fn packed_data(&self) -> Vec<u8> {
+        match self.original_bytes() {
+            Some(bytes) => bytes[self.header.len()..].to_vec(),
+            None => self.to_bytes(PatternPacking::Canonical).split_off(self.header.len()),
+        }
+    }
+
+    /// Returns the size of the packed cell data in bytes, which is the length of packed_data().
+    pub fn packed_size(&self) -> usize {
+        match self.original_bytes() {
+            Some(bytes) => bytes.len() - self.header.len(),
+            None => self.to_bytes(PatternPacking::Canonical).len() - self.header.len(),
+        }
+    }
+
+    /// Returns the packed size of the cell data relative to its unpacked size of 5 bytes per cell, so lower is
+    /// better. Empty patterns are stored without packed data, so their ratio is 0.0.
+    pub fn packing_ratio(&self) -> f32 {
+        let unpacked = self.tracks.iter().map(|trk| trk.cells.len() * 5).sum::<usize>();
+        if unpacked == 0 { 0.0 } else { self.packed_size() as f32 / unpacked as f32 }
+    }
+}
+
+
+impl XMInstrument {
+
+    /// Returns the raw instrument header, without the sample headers. It starts with the header size, and is
+    /// usually 263 bytes long for instruments with samples, and 29 bytes or more for instruments without samples.
+    /// Use this to read fields that the typed accessors do not cover, such as the reserved bytes at the end.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header
+    }
+}
+
+
+impl XMSample {
+
+    /// Returns the raw 40 byte sample header, which starts with the length of the sample data in bytes.
+    /// Use this to read fields that the typed accessors do not cover, such as the reserved byte at offset 17.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_raw_accessors() {
+    use std::fs;
+    use super::{XModule, XM_HEADER_SIZE, XM_INSTRUMENT_HEADER_SIZE, XM_SAMPLE_HEADER_SIZE};
+
+    let data = fs::read("test.xm").unwrap();
+    let mut xm = XModule::parse(data.clone()).unwrap();
+
+    // the first pattern follows the module header
+    let offset = XM_HEADER_SIZE + XModule::read_usize(&data, XM_HEADER_SIZE);
+    let ptn_header_size = XModule::read_usize(&data, offset);
+    let packed = xm.patterns[0].packed_data();
+    assert_eq!(packed.len(), XModule::read_u16(&data, offset + 7) as usize);
+    assert_eq!(&data[offset + ptn_header_size..offset + ptn_header_size + packed.len()], &packed[..]);
+
+    // modified patterns are repacked
+    xm.patterns[0].tracks[0].cells[0].set_note(Some(50));
+    let repacked = xm.patterns[0].packed_data();
+    assert_ne!(repacked, packed);
+    assert_eq!(XMPattern::parse([xm.patterns[0].header.clone(), repacked].concat(), 4).unwrap().tracks[0].note(0)
+        .unwrap(), 50);
+
+    assert_eq!(xm.patterns[0].packed_size(), xm.patterns[0].packed_data().len());
+    let ratio = xm.patterns[0].packing_ratio();
+    assert!(ratio > 0.0 && ratio < 1.0);
+    let unpacked = XMPattern::parse(xm.patterns[0].to_bytes(PatternPacking::Unpacked), 4).unwrap();
+    assert_eq!(unpacked.packing_ratio(), 1.0);
+    assert_eq!(XMPattern::empty(64, 4).packing_ratio(), 0.0);
+
+    let instr = &xm.instruments[1];
+    assert_eq!(instr.header_bytes().len(), XM_INSTRUMENT_HEADER_SIZE);
+    assert_eq!(XModule::read_usize(instr.header_bytes(), 0), XM_INSTRUMENT_HEADER_SIZE);
+    for smp in &instr.samples {
+        assert_eq!(smp.header_bytes().len(), XM_SAMPLE_HEADER_SIZE);
+        assert_eq!(XModule::read_usize(smp.header_bytes(), 0), smp.len());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use super::{XModule, XMPattern, XMInstrument, XMParseError, ParseOptions, ValidationLocation};
+
+/// A problem that was recovered from while parsing a module with XModule::parse_lenient().
+/// The affected pattern or instrument has been replaced with an empty one.
+#[derive(Debug)]
+pub struct ParseWarning {
+    /// The pattern or instrument that was replaced.
+    pub location: ValidationLocation,
+    /// The error that occured while parsing it.
+    pub error: XMParseError,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Replaced {} with an empty placeholder.", self.error, self.location)
+    }
+}
+
+
+impl XModule {
+
+    /// Parses eXtended Module data like parse(), but doesn't abort when a pattern or instrument is broken.
+    /// Instead, the broken pattern or instrument is replaced with an empty one, and parsing continues.
+    /// If the size of the broken structure cannot be determined, all following patterns and instruments
+    /// are replaced as well. Returns the module along with a list of the problems encountered.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the data is not an XM file, or if the module header is incomplete.
+    pub fn parse_lenient(data: Vec<u8>) -> Result<(XModule, Vec<ParseWarning>), XMParseError> {
+        let mut warnings = Vec::new();
+        let xm = XModule::parse_with(data, &ParseOptions::default(), Some(&mut warnings))?;
+        Ok((xm, warnings))
+    }
+}
+
+impl XMPattern {
+
+    /// Constructs an empty pattern with the given number of rows (1..256) and channels.
+    pub fn empty(rows: u16, channel_count: u8) -> XMPattern {
+        let rows = rows.clamp(1, 256);
+        let mut header = vec![0u8; 9];
+        XModule::write_usize(&mut header, 0, 9);
+        XModule::write_u16(&mut header, 5, rows);
+
+        XMPattern::parse(header, channel_count).unwrap_or_default()
+    }
+}
+
+impl XMInstrument {
+
+    /// Constructs an unnamed instrument without samples.
+    pub fn empty() -> XMInstrument {
+        let mut header = vec![0u8; 29];
+        XModule::write_usize(&mut header, 0, 29);
+        XMInstrument { header, samples: Vec::new(), original: None }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_parse_lenient() {
+    use std::fs;
+    use super::PatternPacking;
+
+    let data = fs::read("test.xm").unwrap();
+    let (xm, warnings) = XModule::parse_lenient(data.clone()).unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(xm.to_bytes(), data);
+
+    // break the first cell of pattern 1 by making it claim more packed bytes than there are
+    let mut broken = data.clone();
+    let ptn1 = 60 + 276 + xm.patterns[0].to_bytes(PatternPacking::Canonical).len();
+    broken[ptn1 + 9] = 0x9f;
+    assert!(XModule::parse(broken.clone()).is_err());
+    let (recovered, warnings) = XModule::parse_lenient(broken).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].location, ValidationLocation::Pattern(1));
+    assert!(recovered.patterns[1].is_empty());
+    assert_eq!(recovered.patterns[1].len(), 8);
+    assert_eq!(recovered.patterns[2].len(), 4);
+    assert_eq!(recovered.instruments.len(), 2);
+    assert_eq!(recovered.instruments[1].samples.len(), 2);
+    assert!(XModule::parse(recovered.to_bytes()).is_ok());
+
+    // truncated sample data only affects the last instrument
+    let (recovered, warnings) = XModule::parse_lenient(data[..data.len() - 10].to_vec()).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].location, ValidationLocation::Instrument(1));
+    assert_eq!(warnings[0].error.location(), Some(ValidationLocation::Sample(1, 1)));
+    assert_eq!(recovered.instruments[0].samples.len(), 1);
+    assert!(recovered.instruments[1].samples.is_empty());
+    assert!(XModule::parse(recovered.to_bytes()).is_ok());
+
+    // an instrument without samples whose header size points past the end swallows the following instrument
+    let mut xm = XModule::parse(data.clone()).unwrap();
+    xm.instruments.extend([XMInstrument::empty(), XMInstrument::empty()]);
+    let mut broken = xm.to_bytes();
+    let len = broken.len();
+    broken[len - 57] = 0xae;
+    let (recovered, warnings) = XModule::parse_lenient(broken).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].location, ValidationLocation::Instrument(3));
+    assert_eq!(recovered.instruments[2].header.len(), 58);
+    let reparsed = XModule::parse(recovered.to_bytes()).unwrap();
+    assert_eq!(reparsed.instruments.len(), 4);
+    assert!(XModule::parse(recovered.to_bytes_without_samples()).is_ok());
+
+    assert!(XModule::parse_lenient(data[..40].to_vec()).is_err());
+}
@@ -0,0 +1,363 @@
+use super::{frequency_for_period, tick_duration, AutoVibrato, XModule, XMInstrument, XMSample, PlayRow, TickEventKind,
+    XM_ENVELOPE_ON, XM_ENVELOPE_SUSTAIN, XM_ENVELOPE_LOOP, XM_FX_9XX, XM_SAMPLE_LOOP_NONE, XM_SAMPLE_LOOP_PINGPONG};
+use super::state::{sample_for, ChannelSim, GlobalVolumeSim};
+
+// The sample playing on a channel, and the state of the instrument's envelopes. The sample and instrument are
+// clones, which share the sample data with the module.
+#[derive(Clone, Default)]
+struct Voice {
+    sample: Option<XMSample>,
+    instrument: Option<XMInstrument>,
+    // playback position in sample frames
+    position: f64,
+    backwards: bool,
+    volume_tick: u16,
+    panning_tick: u16,
+    fadeout: u32,
+    auto_vibrato: AutoVibrato,
+    // the period offset caused by autovibrato on the current tick
+    auto_vibrato_offset: i16,
+}
+
+impl Voice {
+
+    fn trigger(&mut self, xm: &XModule, instrument: u8, note: u8, offset: usize) {
+        // decode the sample data in the module, so that it is shared instead of being decoded by each clone
+        self.sample = sample_for(xm, instrument, note).map(|smp| {
+            smp.pcm_16bit();
+            smp.clone()
+        });
+        self.instrument = xm.instruments.get((instrument as usize).wrapping_sub(1)).cloned();
+        self.position = offset as f64;
+        self.backwards = false;
+        self.volume_tick = 0;
+        self.panning_tick = 0;
+        self.fadeout = 0x10000;
+        self.auto_vibrato = self.instrument.as_ref().map(AutoVibrato::for_instrument).unwrap_or_default();
+        self.auto_vibrato_offset = 0;
+        if self.sample.as_ref().is_some_and(|smp| offset >= smp.pcm_16bit().len()) {
+            self.sample = None;
+        }
+    }
+
+    // Advances the envelopes and the autovibrato by one tick.
+    fn tick_envelopes(&mut self, key_off: bool) {
+        self.auto_vibrato_offset = self.auto_vibrato.tick(key_off);
+        if let Some(instr) = &self.instrument {
+            let volume_flags = instr.volume_type().unwrap_or(0);
+            self.volume_tick = next_envelope_tick(self.volume_tick, instr.volume_envelope_points(), volume_flags,
+                instr.volume_sustain(), instr.volume_loop_start().zip(instr.volume_loop_end()), key_off);
+            self.panning_tick = next_envelope_tick(self.panning_tick, instr.panning_envelope_points(),
+                instr.panning_type().unwrap_or(0), instr.panning_sustain(),
+                instr.panning_loop_start().zip(instr.panning_loop_end()), key_off);
+
+            // without a volume envelope, the note is cut on key off instead
+            if key_off && volume_flags & XM_ENVELOPE_ON != 0 {
+                self.fadeout = self.fadeout.saturating_sub(instr.volume_fadeout().unwrap_or(0) as u32 * 2);
+            }
+        }
+    }
+
+    // Returns the current volume (0.0..1.0) and panning (0..255) after applying envelopes and fadeout.
+    fn levels(&self, volume: u8, panning: u8) -> (f64, f64) {
+        let instr = match &self.instrument {
+            Some(instr) => instr,
+            None => return (volume as f64 / 64.0, panning as f64),
+        };
+
+        let envelope_volume = envelope_value(instr.volume_envelope_points(), instr.volume_type(), self.volume_tick)
+            .unwrap_or(64.0);
+        let volume = volume as f64 / 64.0 * envelope_volume / 64.0 * self.fadeout as f64 / 65536.0;
+        let panning = match envelope_value(instr.panning_envelope_points(), instr.panning_type(), self.panning_tick) {
+            Some(env) => {
+                let pan = panning as f64;
+                pan + (env - 32.0) * (128.0 - (pan - 128.0).abs()) / 32.0
+            },
+            None => panning as f64,
+        };
+
+        (volume, panning.clamp(0.0, 255.0))
+    }
+
+    // Mixes the sample into a stereo buffer, advancing the position by step frames per output frame.
+    fn mix(&mut self, out: &mut [f32], step: f64, left: f64, right: f64) {
+        let smp = match &self.sample {
+            Some(smp) => smp,
+            None => return,
+        };
+        let pcm = smp.pcm_16bit();
+        let (loop_start, loop_len) = if smp.loop_type() == XM_SAMPLE_LOOP_NONE { (0, 0) } else { smp.loop_frames() };
+        let loop_end = (loop_start + loop_len).min(pcm.len()) as f64;
+        let looped = loop_len > 0;
+        let pingpong = smp.loop_type() == XM_SAMPLE_LOOP_PINGPONG;
+        let mut ended = false;
+
+        for frame in out.chunks_exact_mut(2) {
+            let pos = self.position as usize;
+            if pos >= pcm.len() {
+                ended = true;
+                break;
+            }
+            let next = if self.backwards { pos.saturating_sub(1) } else { (pos + 1).min(pcm.len() - 1) };
+            let fraction = self.position.fract();
+            let value = (pcm[pos] as f64 * (1.0 - fraction) + pcm[next] as f64 * fraction) / 32768.0;
+            frame[0] += (value * left) as f32;
+            frame[1] += (value * right) as f32;
+
+            if self.backwards {
+                self.position -= step;
+                if self.position < loop_start as f64 {
+                    self.position = 2.0 * loop_start as f64 - self.position;
+                    self.backwards = false;
+                }
+            }
+            else {
+                self.position += step;
+                if looped && self.position >= loop_end {
+                    if pingpong {
+                        self.position = (2.0 * loop_end - self.position - 1.0).max(loop_start as f64);
+                        self.backwards = true;
+                    }
+                    else {
+                        self.position = loop_start as f64 + (self.position - loop_end) % loop_len as f64;
+                    }
+                }
+            }
+        }
+        if ended {
+            self.sample = None;
+        }
+    }
+}
+
+// Returns the next tick of an envelope, taking sustain and loop points into account.
+pub(crate) fn next_envelope_tick(tick: u16, points: Option<Vec<(u16, u16)>>, flags: u8, sustain: Option<u8>,
+    loop_points: Option<(u8, u8)>, key_off: bool) -> u16 {
+    let points = match points {
+        Some(points) if flags & XM_ENVELOPE_ON != 0 => points,
+        _ => return tick,
+    };
+    let frame = |index: u8| points.get(index as usize).map(|point| point.0);
+
+    if flags & XM_ENVELOPE_SUSTAIN != 0 && !key_off && sustain.and_then(frame) == Some(tick) {
+        return tick;
+    }
+    if flags & XM_ENVELOPE_LOOP != 0 {
+        if let Some((start, end)) = loop_points.and_then(|(start, end)| frame(start).zip(frame(end))) {
+            if tick >= end {
+                return start;
+            }
+        }
+    }
+
+    tick.saturating_add(1)
+}
+
+// Returns the value of an envelope (0.0..64.0) at the given tick, or None if the envelope is off.
+pub(crate) fn envelope_value(points: Option<Vec<(u16, u16)>>, flags: Option<u8>, tick: u16) -> Option<f64> {
+    let points = points.filter(|_| flags.unwrap_or(0) & XM_ENVELOPE_ON != 0)?;
+    let value = |point: &(u16, u16)| (point.1 as f64).min(64.0);
+
+    match points.iter().position(|point| point.0 > tick) {
+        Some(0) => points.first().map(value),
+        Some(next) => {
+            let (a, b) = (points[next - 1], points[next]);
+            let fraction = (tick - a.0) as f64 / (b.0 - a.0) as f64;
+            Some(value(&a) + (value(&b) - value(&a)) * fraction)
+        },
+        None => points.last().map(value),
+    }
+}
+
+
+// Plays the module's sequence row by row, rendering each channel into its own stereo buffer. The renderer does not
+// hold on to the module, so the module must be passed to render_row() each time.
+pub(crate) struct Renderer {
+    rows: Vec<PlayRow>,
+    position: usize,
+    sample_rate: u32,
+    channels: Vec<ChannelSim>,
+    voices: Vec<Voice>,
+    global: GlobalVolumeSim,
+    // fractional output frames left over from previous ticks
+    frame_remainder: f64,
+}
+
+impl Renderer {
+
+    pub(crate) fn new(xm: &XModule, sample_rate: u32) -> Renderer {
+        let channels = xm.channel_count() as usize;
+        Renderer {
+            rows: xm.play_rows(),
+            position: 0,
+            sample_rate: sample_rate.max(1),
+            channels: vec![ChannelSim::new(); channels],
+            voices: vec![Voice::default(); channels],
+            global: GlobalVolumeSim::new(),
+            frame_remainder: 0.0,
+        }
+    }
+
+    // Renders the next row into one interleaved stereo buffer per channel, with samples in the range -1.0..1.0.
+    // Returns false if the end of the song has been reached.
+    pub(crate) fn render_row(&mut self, xm: &XModule, stems: &mut [Vec<f32>]) -> bool {
+        let play_row = match self.rows.get(self.position) {
+            Some(row) => *row,
+            None => return false,
+        };
+        self.position += 1;
+        let ptn = match xm.patterns.get(play_row.pattern as usize) {
+            Some(ptn) => ptn,
+            None => {
+                self.render_ticks(xm, play_row.bpm, stems, play_row.ticks as usize);
+                return true;
+            },
+        };
+        let row = play_row.row as usize;
+
+        self.global.start_row(&ptn.tracks, row);
+
+        for t in 0..play_row.ticks {
+            let tick = (t % play_row.tempo as u16) as u8;
+            if t > 0 && tick == 0 {
+                self.render_ticks(xm, play_row.bpm, stems, 1);
+                continue;
+            }
+
+            for (chan, trk) in ptn.tracks.iter().enumerate().take(self.channels.len()) {
+                let sim = &mut self.channels[chan];
+                if tick == 0 {
+                    sim.start_row(xm, trk, row);
+                }
+                else {
+                    sim.tick(xm, tick);
+                }
+
+                let events = trk.tick_events(play_row.row, play_row.tempo).unwrap_or_default();
+                for event in events.iter().filter(|event| event.tick == tick) {
+                    match event.kind {
+                        TickEventKind::NoteOn { .. } | TickEventKind::Retrigger { .. } => {
+                            let offset = if trk.cells[row].fx_command() == Some(XM_FX_9XX) {
+                                trk.cells[row].fx_param().unwrap_or(0) as usize * 256
+                            } else { 0 };
+                            self.voices[chan].trigger(xm, sim.state.instrument, sim.state.note, offset);
+                        },
+                        _ => (),
+                    }
+                }
+                self.voices[chan].tick_envelopes(sim.state.key_off);
+            }
+
+            if tick > 0 {
+                self.global.tick();
+            }
+
+            let arpeggio: Vec<u8> = ptn.tracks.iter().map(|trk| trk.arpeggio(play_row.row, play_row.tempo)
+                .ok().flatten().map_or(0, |offsets| offsets[tick as usize])).collect();
+            self.render_voices(xm, play_row.bpm, stems, &arpeggio);
+        }
+
+        true
+    }
+
+    // Renders the given number of ticks without processing any pattern data, for missing patterns and the first
+    // tick of repeated rows.
+    fn render_ticks(&mut self, xm: &XModule, bpm: u8, stems: &mut [Vec<f32>], ticks: usize) {
+        let arpeggio = vec![0; self.channels.len()];
+        for _ in 0..ticks {
+            self.render_voices(xm, bpm, stems, &arpeggio);
+        }
+    }
+
+    // Mixes one tick of all voices into the stems.
+    fn render_voices(&mut self, xm: &XModule, bpm: u8, stems: &mut [Vec<f32>], arpeggio: &[u8]) {
+        let frames = self.sample_rate as f64 * tick_duration(bpm).as_secs_f64() + self.frame_remainder;
+        self.frame_remainder = frames.fract();
+        let frames = frames as usize;
+
+        for (chan, stem) in stems.iter_mut().enumerate().take(self.channels.len()) {
+            let sim = &self.channels[chan];
+            let state = sim.state;
+            let voice = &mut self.voices[chan];
+            let volume = (state.volume as i16 + sim.tremolo_offset).clamp(0, 0x40) as u8;
+            let (volume, panning) = voice.levels(volume, state.panning);
+            let volume = volume * self.global.volume as f64 / 64.0;
+            let left = volume * ((256.0 - panning) / 256.0).sqrt();
+            let right = volume * (panning / 256.0).sqrt();
+            let period = match state.period {
+                0 => 0,
+                period => (period as i32 + sim.vibrato_offset as i32 + voice.auto_vibrato_offset as i32)
+                    .clamp(1, 32000) as u16,
+            };
+            let step = frequency_for_period(period, xm.amiga_ft()) * 2f64.powf(arpeggio.get(chan).copied().unwrap_or(0) as f64 / 12.0)
+                / self.sample_rate as f64;
+
+            let start = stem.len();
+            stem.resize(start + frames * 2, 0.0);
+            voice.mix(&mut stem[start..], step, left, right);
+        }
+    }
+}
+
+
+impl XModule {
+
+    /// Renders the song into one interleaved stereo buffer of signed 16-bit PCM per channel, at the given sample
+    /// rate. The sequence is played once, following the same path as play_rows(). Each channel is rendered at full
+    /// scale with its panning applied, so the stems add up to the complete song. Samples are linearly interpolated,
+    /// and volume and panning envelopes, fadeout, global volume, arpeggio, portamento, vibrato, tremolo, and
+    /// autovibrato are applied.
+    pub fn render_stems(&self, sample_rate: u32) -> Vec<Vec<i16>> {
+        let mut stems = vec![Vec::new(); self.channel_count() as usize];
+        let mut renderer = Renderer::new(self, sample_rate);
+        while renderer.render_row(self, &mut stems) {}
+
+        stems.iter().map(|stem| to_pcm(stem)).collect()
+    }
+
+    /// Renders the song into a single interleaved stereo buffer of signed 16-bit PCM at the given sample rate, by
+    /// adding up the stems returned by render_stems(). The result is clipped to the 16-bit range.
+    pub fn render(&self, sample_rate: u32) -> Vec<i16> {
+        let mut stems = vec![Vec::new(); self.channel_count() as usize];
+        let mut renderer = Renderer::new(self, sample_rate);
+        while renderer.render_row(self, &mut stems) {}
+
+        let len = stems.iter().map(|stem| stem.len()).max().unwrap_or(0);
+        let mix: Vec<f32> = (0..len).map(|i| stems.iter().map(|stem| stem.get(i).copied().unwrap_or(0.0)).sum())
+            .collect();
+        to_pcm(&mix)
+    }
+}
+
+// Converts floating point samples to signed 16-bit PCM, clipping values beyond full scale.
+fn to_pcm(buffer: &[f32]) -> Vec<i16> {
+    buffer.iter().map(|value| (*value * 32767.0).round().clamp(-32768.0, 32767.0) as i16).collect()
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_render_stems() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let rows = xm.play_rows();
+    let duration = rows.last().map(|row| (row.start + row.duration()).as_secs_f64()).unwrap();
+
+    let stems = xm.render_stems(22050);
+    assert_eq!(stems.len(), 4);
+    let frames = stems[0].len() / 2;
+    assert!(stems.iter().all(|stem| stem.len() == frames * 2));
+    assert!((frames as f64 - duration * 22050.0).abs() < 2.0);
+
+    // channel 3 only contains effects
+    assert!(stems[3].iter().all(|smp| *smp == 0));
+    assert!(stems[0].iter().any(|smp| *smp != 0));
+    // C-4 on the first row plays at 8363 Hz
+    assert!(stems[0][..200].iter().any(|smp| *smp != 0));
+
+    let mix = xm.render(22050);
+    assert_eq!(mix.len(), frames * 2);
+    let expected = (stems[0][1000] as i32 + stems[1][1000] as i32 + stems[2][1000] as i32).clamp(-32768, 32767);
+    assert!((mix[1000] as i32 - expected).abs() <= 2);
+}
@@ -0,0 +1,89 @@
+use super::{XModule, XMCell, XM_FX_E1X, XM_FX_EEX, XM_FX_X1X, XM_FX_X2X};
+
+impl XModule {
+
+    /// Replaces the instrument number `from` with `to` (both counted from 1) in all cells of all patterns. A `to` of 0
+    /// removes the instrument number from the cells. The instruments themselves are left unchanged.
+    /// Returns the number of cells changed.
+    pub fn replace_instrument(&mut self, from: u8, to: u8) -> usize {
+        let mut changed = 0;
+        for trk in self.patterns.iter_mut().flat_map(|ptn| ptn.tracks.iter_mut()) {
+            for cell in trk.cells.iter_mut().filter(|cell| cell.instrument() == Some(from)) {
+                cell.set_instrument(if to > 0 { Some(to) } else { None });
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    /// Replaces the effect `from_cmd` with `to_cmd` in all cells of all patterns, and passes the parameter of each
+    /// replaced effect through param_map. Use XM_FX_* constants to pass the effects. For extended effects (E1x..EEx,
+    /// X1x, X2x), param_map receives and returns only the lower nibble of the parameter. Empty effects (000) are not
+    /// considered arpeggios. Returns the number of cells changed.
+    pub fn replace_effect<F: FnMut(u8) -> u8>(&mut self, from_cmd: u8, to_cmd: u8, mut param_map: F) -> usize {
+        let (from_extended, (to_raw, to_sub)) = (extended(from_cmd).1.is_some(), extended(to_cmd));
+        let mut changed = 0;
+        for trk in self.patterns.iter_mut().flat_map(|ptn| ptn.tracks.iter_mut()) {
+            for cell in trk.cells.iter_mut() {
+                if !XMCell::from(*cell).has_effect(from_cmd) {
+                    continue;
+                }
+                let param = cell.fx_param().unwrap_or(0);
+                let param = param_map(if from_extended { param & 0xf } else { param });
+                cell.set_fx_command(Some(to_raw));
+                cell.set_fx_param(Some(match to_sub {
+                    Some(sub) => sub << 4 | (param & 0xf),
+                    None => param,
+                }));
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+// Splits an XM_FX_* constant into the effect command as stored, and the subcommand of extended effects.
+fn extended(cmd: u8) -> (u8, Option<u8>) {
+    match cmd {
+        XM_FX_E1X..=XM_FX_EEX => (0xe, Some(cmd & 0xf)),
+        XM_FX_X1X | XM_FX_X2X => (0x21, Some(cmd - 0x21)),
+        _ => (cmd, None),
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_replace() {
+    use std::path::Path;
+    use super::{XM_FX_AXX, XM_FX_CXX};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let count = |xm: &XModule, instrument: u8| xm.patterns.iter().flat_map(|ptn| ptn.tracks.iter())
+        .map(|trk| trk.cells.iter().filter(|cell| cell.instrument() == Some(instrument)).count()).sum::<usize>();
+
+    let used = count(&xm, 1);
+    assert!(used > 0);
+    assert_eq!(xm.replace_instrument(1, 3), used);
+    assert_eq!((count(&xm, 1), count(&xm, 3)), (0, used));
+    assert_eq!(xm.replace_instrument(3, 0), used);
+    assert_eq!(count(&xm, 3), 0);
+
+    // volume slides become halved volume settings
+    let mut ptn = super::XMPattern::empty(4, 2);
+    ptn.tracks[0].cells[0].set_fx_command(Some(XM_FX_AXX));
+    ptn.tracks[0].cells[0].set_fx_param(Some(0x20));
+    xm.patterns = vec![ptn];
+    assert_eq!(xm.replace_effect(XM_FX_AXX, XM_FX_CXX, |param| param / 2), 1);
+    assert_eq!((xm.patterns[0].tracks[0].cells[0].fx_command(), xm.patterns[0].tracks[0].cells[0].fx_param()),
+        (Some(XM_FX_CXX), Some(0x10)));
+
+    // fine volume slides up become fine slides down
+    xm.patterns[0].tracks[1].cells[1].set_fx_command(Some(0xe));
+    xm.patterns[0].tracks[1].cells[1].set_fx_param(Some(0xa3));
+    assert_eq!(xm.replace_effect(super::XM_FX_EAX, super::XM_FX_EBX, |param| param + 1), 1);
+    assert_eq!(xm.patterns[0].tracks[1].cells[1].fx_param(), Some(0xb4));
+
+    // empty effects are not arpeggios
+    assert_eq!(xm.replace_effect(0, XM_FX_CXX, |param| param), 0);
+}
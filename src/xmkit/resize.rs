@@ -0,0 +1,136 @@
+use std::mem;
+use super::{XModule, XMPattern, XMParseError, XM_FX_DXX, XM_FX_FXX};
+use super::cells::{PackedCell, NOTE, INSTRUMENT, VOLUME};
+
+impl XMPattern {
+
+    /// Spreads the rows of the pattern out by the given factor, inserting empty rows between the original ones.
+    /// Speed settings (Fxx below 0x20) are divided by the factor, and pattern breaks (Dxx) and pattern delays (EEx)
+    /// are multiplied by it, so that a song whose patterns are all expanded by the same factor plays at the same
+    /// speed as before, provided the module's default tempo is adjusted as well.
+    /// Tick-based effects such as slides are not adjusted, and speeds that are not divisible by the factor are
+    /// rounded down.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the factor is 0, or if the expanded pattern would have more than 256 rows.
+    pub fn expand(&mut self, factor: u16) -> Result<(), XMParseError> {
+        let len = self.len() as usize * factor as usize;
+        if factor == 0 || len > 256 {
+            return Err(XMParseError::new(&format!("Cannot expand pattern with {} rows by factor {}.",
+                self.len(), factor)));
+        }
+
+        for trk in &mut self.tracks {
+            let original = mem::take(&mut trk.cells);
+            for cell in original {
+                trk.cells.push(cell);
+                trk.cells.extend((1..factor).map(|_| PackedCell::default()));
+            }
+
+            for cell in trk.cells.iter_mut().step_by(factor as usize) {
+                cell.set_fx_param(scale_timing_fx(cell.fx_command(), cell.fx_param(), factor, 1));
+            }
+        }
+
+        XModule::write_u16(&mut self.header, 5, len as u16);
+        Ok(())
+    }
+
+    /// Condenses the pattern by the given factor, keeping only every n-th row. Data on the removed rows is moved
+    /// to the preceding kept row wherever the corresponding field of that row is empty, and discarded otherwise.
+    /// Speed settings (Fxx below 0x20) are multiplied by the factor, and pattern breaks (Dxx) and pattern delays
+    /// (EEx) are divided by it. This is the reverse of expand().
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the factor is 0.
+    pub fn shrink(&mut self, factor: u16) -> Result<(), XMParseError> {
+        if factor == 0 {
+            return Err(XMParseError::new("Cannot shrink pattern by factor 0."));
+        }
+        let len = (self.len() as usize).div_ceil(factor as usize);
+
+        for trk in &mut self.tracks {
+            for row in 0..len {
+                let first = row * factor as usize;
+                for removed in first + 1..(first + factor as usize).min(trk.cells.len()) {
+                    let source = trk.cells[removed];
+                    let target = &mut trk.cells[first];
+                    for field in [NOTE, INSTRUMENT, VOLUME] {
+                        if target.get(field).is_none() {
+                            target.set(field, source.get(field));
+                        }
+                    }
+                    if target.fx_command().unwrap_or(0) == 0 && target.fx_param().unwrap_or(0) == 0 {
+                        target.set_fx_command(source.fx_command());
+                        target.set_fx_param(source.fx_param());
+                    }
+                }
+                let cell = &mut trk.cells[first];
+                cell.set_fx_param(scale_timing_fx(cell.fx_command(), cell.fx_param(), 1, factor));
+            }
+
+            trk.cells = trk.cells.iter().step_by(factor as usize).copied().collect();
+        }
+
+        XModule::write_u16(&mut self.header, 5, len as u16);
+        Ok(())
+    }
+}
+
+// Scales the parameter of speed, pattern break, and pattern delay effects by multiplying row counts and dividing
+// speeds by mul/div. Returns the new parameter.
+fn scale_timing_fx(cmd: Option<u8>, param: Option<u8>, mul: u16, div: u16) -> Option<u8> {
+    let p = match param {
+        Some(p) => p as u16,
+        None => return None,
+    };
+
+    let scaled = match cmd.unwrap_or(0) {
+        XM_FX_FXX if p > 0 && p < 0x20 => (p * div / mul).clamp(1, 0x1f),
+        XM_FX_DXX => {
+            let row = ((p >> 4) * 10 + (p & 0xf)) * mul / div;
+            let row = row.min(99);
+            ((row / 10) << 4) | (row % 10)
+        },
+        0xe if p >> 4 == 0xe => 0xe0 | ((p & 0xf) * mul / div).min(0xf),
+        _ => p,
+    };
+
+    Some(scaled as u8)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_pattern_resize() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    let mut ptn = xm.patterns[1].clone();
+    ptn.expand(2).unwrap();
+    assert_eq!(ptn.len(), 16);
+    assert_eq!(ptn.tracks[0].note_raw(0).unwrap(), Some(61));
+    assert_eq!(ptn.tracks[0].note_raw(1).unwrap(), None);
+    assert_eq!(ptn.tracks[3].fx_command_raw(8).unwrap(), Some(XM_FX_DXX));
+    assert_eq!(ptn.tracks[3].fx_param_raw(0).unwrap(), Some(0x96));
+
+    ptn.shrink(2).unwrap();
+    assert_eq!(ptn.len(), 8);
+    assert_eq!(ptn.to_bytes(super::PatternPacking::Unpacked), xm.patterns[1].to_bytes(super::PatternPacking::Unpacked));
+
+    let mut ptn = xm.patterns[0].clone();
+    ptn.expand(3).unwrap();
+    assert_eq!(ptn.len(), 48);
+    assert_eq!(ptn.tracks[3].fx_param_raw(24).unwrap(), Some(1));
+    assert!(ptn.expand(6).is_err());
+
+    let mut ptn = xm.patterns[0].clone();
+    ptn.shrink(2).unwrap();
+    assert_eq!(ptn.len(), 8);
+    assert_eq!(ptn.tracks[3].fx_param_raw(4).unwrap(), Some(6));
+    // the note on row 9 and EC2 on row 11 of channel 1 are moved to the preceding empty rows
+    assert_eq!(ptn.tracks[1].fx_param_raw(1).unwrap(), Some(0xd3));
+    assert_eq!(ptn.tracks[1].note_raw(4).unwrap(), Some(40));
+    assert_eq!(ptn.tracks[1].fx_param_raw(5).unwrap(), Some(0xc2));
+}
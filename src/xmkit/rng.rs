@@ -0,0 +1,52 @@
+/// A small pseudo-random number generator (xorshift64*). The same seed always produces the same values.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+
+    /// Creates a generator from the given seed. Any seed is valid, including 0.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    /// Returns the next 32-bit value.
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as u32
+    }
+
+    /// Returns a value in the range min..=max.
+    pub fn range(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        min + ((self.next_u32() as u64 * (max - min + 1) as u64) >> 32) as u32
+    }
+
+    /// Returns true with a probability of 1 in n.
+    #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+    pub fn one_in(&mut self, n: u32) -> bool {
+        self.range(1, n.max(1)) == 1
+    }
+
+    /// Returns a random byte.
+    pub fn byte(&mut self) -> u8 {
+        self.next_u32() as u8
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_rng() {
+    let values: Vec<u32> = (0..4).map(|_| Rng::new(0).next_u32()).collect();
+    assert!(values.iter().all(|value| *value == values[0]));
+    let mut rng = Rng::new(1);
+    assert_ne!(rng.next_u32(), rng.next_u32());
+    assert!((0..1000).map(|_| rng.range(3, 5)).all(|value| (3..=5).contains(&value)));
+    assert_eq!(rng.range(7, 2), 7);
+}
@@ -0,0 +1,288 @@
+use std::f64::consts::PI;
+use super::{XModule, XMSample, SampleDepth, XM_SAMPLE_16BIT, XM_SAMPLE_LOOP_NONE};
+use super::wav::resample;
+
+// Number of filter taps on each side of the center tap of the low-pass filter used for decimation.
+const FILTER_TAPS: isize = 16;
+
+impl XMSample {
+
+    /// Returns the playback rate of the sample on C-4 in Hz, as determined by its relative note and finetune.
+    pub fn c4_rate(&self) -> f64 {
+        8363.0 * 2f64.powf((self.relative_note() as f64 * 128.0 + self.finetune() as f64) / 1536.0)
+    }
+
+    /// Converts the sample to the given resolution and sample rate. When the rate is lowered, the data is low-pass
+    /// filtered before decimating to reduce aliasing. The relative note and finetune are updated so that the sample
+    /// keeps its pitch, and loop points are scaled to match the new length. SampleDepth::Auto keeps the current
+    /// resolution, and a target_rate of None keeps the current rate.
+    pub fn convert(&mut self, depth: SampleDepth, target_rate: Option<u32>) {
+        let is_16bit = match depth {
+            SampleDepth::Auto => self.is_16bit(),
+            SampleDepth::Bits8 => false,
+            SampleDepth::Bits16 => true,
+        };
+        let rate = self.c4_rate().round() as u32;
+
+        let pcm = match target_rate {
+            Some(target) if target > 0 && target != rate => {
+                let filtered = if target < rate { low_pass(self.pcm_16bit(), target as f64 / rate as f64 / 2.0) }
+                    else { self.pcm_16bit().to_vec() };
+                resample(&filtered, rate, target)
+            },
+            _ => self.pcm_16bit().to_vec(),
+        };
+
+        // both ends of the loop are scaled, so that rounding does not accumulate in the loop length
+        let ratio = pcm.len() as f64 / self.pcm_16bit().len().max(1) as f64;
+        let (loop_start, loop_len) = self.loop_frames();
+        let loop_end = (((loop_start + loop_len) as f64 * ratio).round() as usize).min(pcm.len());
+        let loop_start = ((loop_start as f64 * ratio).round() as usize).min(loop_end);
+        let loop_len = loop_end - loop_start;
+
+        self.set_pcm(&pcm, is_16bit, loop_start, loop_len);
+        if let Some(target) = target_rate.filter(|target| *target > 0 && *target != rate) {
+            self.set_tuning_from_rate(target);
+        }
+    }
+
+    /// Returns a copy of the sample resampled so that C-4 plays at the given rate in Hz, such as the fixed mixing
+    /// rate of a target engine (eg. 48000 or 96000), so the engine can play it back without resampling. The
+    /// relative note and finetune are adjusted to keep the pitch, and loop points are scaled to the new length.
+    /// The resolution is kept. A target_rate of 0 returns an unchanged copy.
+    pub fn resampled(&self, target_rate: u32) -> XMSample {
+        let mut smp = self.clone();
+        smp.convert(SampleDepth::Auto, Some(target_rate));
+        smp
+    }
+
+    /// Scales the sample data so that its highest absolute value reaches the given fraction of full scale
+    /// (0.0..1.0). Silent samples are left unchanged.
+    pub fn normalize(&mut self, peak: f64) {
+        let max = self.pcm_16bit().iter().map(|smp| (*smp as i32).abs()).max().unwrap_or(0);
+        if max == 0 {
+            return;
+        }
+
+        let full_scale = if self.is_16bit() { 32767.0 } else { 127.0 * 256.0 };
+        let gain = peak.clamp(0.0, 1.0) * full_scale / max as f64;
+        let pcm: Vec<i16> = self.pcm_16bit().iter()
+            .map(|smp| (*smp as f64 * gain).round().clamp(-32768.0, 32767.0) as i16)
+            .collect();
+        self.replace_pcm(&pcm);
+    }
+
+    /// Removes any DC offset from the sample data, by subtracting the average sample value.
+    pub fn remove_dc_offset(&mut self) {
+        let len = self.pcm_16bit().len();
+        if len == 0 {
+            return;
+        }
+
+        let offset = (self.pcm_16bit().iter().map(|smp| *smp as i64).sum::<i64>() / len as i64) as i32;
+        let pcm: Vec<i16> = self.pcm_16bit().iter()
+            .map(|smp| (*smp as i32 - offset).clamp(-32768, 32767) as i16)
+            .collect();
+        self.replace_pcm(&pcm);
+    }
+
+    /// Removes the sample data after the end of the loop, which is never played. Samples without a loop are left
+    /// unchanged, including samples with a loop of length 0, which FT2 plays to the end like unlooped ones.
+    /// Returns the number of bytes removed.
+    pub fn trim_after_loop(&mut self) -> usize {
+        let (loop_start, loop_len) = self.loop_frames();
+        if self.loop_type() == XM_SAMPLE_LOOP_NONE || loop_len == 0 {
+            return 0;
+        }
+
+        self.truncate_frames(loop_start + loop_len)
+    }
+
+    /// Removes trailing sample data whose absolute value does not exceed the given fraction of full scale
+    /// (0.0..1.0). Data up to the end of the loop is always kept. Returns the number of bytes removed.
+    pub fn trim_silence(&mut self, threshold: f64) -> usize {
+        let threshold = (threshold.clamp(0.0, 1.0) * 32767.0) as i32;
+        let (loop_start, loop_len) = if self.loop_type() == XM_SAMPLE_LOOP_NONE { (0, 0) } else { self.loop_frames() };
+        let end = self.pcm_16bit().iter().rposition(|smp| (*smp as i32).abs() > threshold).map_or(0, |pos| pos + 1);
+
+        self.truncate_frames(end.max(loop_start + loop_len))
+    }
+
+    // Shortens the sample to the given number of frames. Returns the number of bytes removed.
+    fn truncate_frames(&mut self, frames: usize) -> usize {
+        let bytes = if self.is_16bit() { 2 } else { 1 };
+        let length = self.data.len();
+        if frames * bytes >= length {
+            return 0;
+        }
+
+        self.data_mut().truncate(frames * bytes);
+        XModule::write_usize(&mut self.header, 0, frames * bytes);
+        length - frames * bytes
+    }
+
+    // Replaces the sample data with PCM data of the same length, keeping resolution and loop points.
+    fn replace_pcm(&mut self, pcm: &[i16]) {
+        let (loop_start, loop_len) = self.loop_frames();
+        let is_16bit = self.is_16bit();
+        self.set_pcm(pcm, is_16bit, loop_start, loop_len);
+    }
+
+    // Returns the loop start and loop length in sample frames rather than bytes.
+    pub(crate) fn loop_frames(&self) -> (usize, usize) {
+        let bytes = if self.is_16bit() { 2 } else { 1 };
+        (self.loop_start() / bytes, self.loop_len() / bytes)
+    }
+
+    // Replaces the sample data with the given PCM data, delta-encoding it at the given resolution.
+    // Loop points are given in sample frames. The loop type is kept unless the loop becomes empty.
+    pub(crate) fn set_pcm(&mut self, pcm: &[i16], is_16bit: bool, loop_start: usize, loop_len: usize) {
+        let encoded = XMSample::from_pcm(pcm, is_16bit, "");
+        let bytes = if is_16bit { 2 } else { 1 };
+
+        XModule::write_usize(&mut self.header, 0, encoded.data.len());
+        XModule::write_usize(&mut self.header, 4, loop_start * bytes);
+        XModule::write_usize(&mut self.header, 8, loop_len * bytes);
+        self.header[14] = (self.header[14] & !XM_SAMPLE_16BIT) | if is_16bit { XM_SAMPLE_16BIT } else { 0 };
+        if loop_len == 0 {
+            self.header[14] &= !3;
+        }
+        *self.data_mut() = encoded.data.to_vec();
+    }
+}
+
+// Filters the given PCM data with a windowed sinc low-pass filter. The cutoff frequency is given as a fraction of
+// the sample rate.
+fn low_pass(pcm: &[i16], cutoff: f64) -> Vec<i16> {
+    let kernel: Vec<f64> = (-FILTER_TAPS..=FILTER_TAPS).map(|i| {
+        let x = i as f64;
+        let sinc = if i == 0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+        let window = 0.5 + 0.5 * (PI * x / (FILTER_TAPS + 1) as f64).cos();
+        sinc * window
+    }).collect();
+    let gain: f64 = kernel.iter().sum();
+
+    (0..pcm.len() as isize).map(|pos| {
+        let sum: f64 = kernel.iter().enumerate().map(|(i, k)| {
+            let src = (pos + i as isize - FILTER_TAPS).clamp(0, pcm.len() as isize - 1);
+            pcm[src as usize] as f64 * k
+        }).sum();
+        (sum / gain).round().clamp(-32768.0, 32767.0) as i16
+    }).collect()
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_sample_convert() {
+    use std::path::Path;
+    use super::XM_SAMPLE_LOOP_PINGPONG;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    // 16-bit pingpong sample tuned an octave down, with a slight positive finetune
+    let mut smp = xm.instruments[1].samples[1].clone();
+    assert!((smp.c4_rate() - 4242.3).abs() < 0.1);
+    let original = smp.data_8bit_signed();
+    smp.convert(SampleDepth::Bits8, None);
+    assert!(!smp.is_16bit());
+    assert_eq!(smp.len(), 32);
+    assert_eq!((smp.loop_start(), smp.loop_len()), (4, 16));
+    assert_eq!(smp.loop_type(), XM_SAMPLE_LOOP_PINGPONG);
+    assert_eq!(smp.data_8bit_signed(), original);
+
+    let mut smp = xm.instruments[1].samples[0].clone();
+    let rate = smp.c4_rate();
+    smp.convert(SampleDepth::Auto, Some((rate / 2.0).round() as u32));
+    assert_eq!(smp.len(), 32);
+    assert!((smp.c4_rate() * 2.0 - rate).abs() < rate * 0.01);
+    assert_eq!(smp.loop_type(), XM_SAMPLE_LOOP_NONE);
+    assert!(smp.validate().is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sample_resampled() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let smp = &xm.instruments[1].samples[1];
+    let (loop_start, loop_len) = smp.loop_frames();
+    let frames = smp.len() / 2;
+
+    for target in [48000, 96000] {
+        let resampled = smp.resampled(target);
+        let ratio = target as f64 / smp.c4_rate().round();
+        assert!(resampled.is_16bit());
+        assert!((resampled.c4_rate() - target as f64).abs() < target as f64 * 0.005);
+        assert_eq!(resampled.len() / 2, (frames as f64 * ratio).round() as usize);
+        let (start, len) = resampled.loop_frames();
+        assert_eq!(start, (loop_start as f64 * ratio).round() as usize);
+        assert_eq!(start + len, ((loop_start + loop_len) as f64 * ratio).round() as usize);
+        assert_eq!(resampled.loop_type(), smp.loop_type());
+        assert!(resampled.validate().is_empty());
+    }
+
+    assert!(smp.resampled(0).data_native() == smp.data_native());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sample_normalize() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    for smp in xm.instruments.iter().flat_map(|instr| instr.samples.iter()) {
+        let mut smp = smp.clone();
+        smp.remove_dc_offset();
+        let sum: i64 = smp.pcm_16bit().iter().map(|v| *v as i64).sum();
+        assert!((sum / smp.pcm_16bit().len() as i64).abs() < 256);
+
+        smp.normalize(0.5);
+        let peak = smp.pcm_16bit().iter().map(|v| (*v as i32).abs()).max().unwrap();
+        assert!((peak - 0x3f80).abs() <= 256, "peak {}", peak);
+        assert!(smp.validate().is_empty());
+    }
+
+    let mut silent = xm.instruments[0].samples[0].clone();
+    silent.normalize(0.0);
+    assert!(silent.pcm_16bit().iter().all(|v| *v == 0));
+    silent.normalize(1.0);
+    assert!(silent.pcm_16bit().iter().all(|v| *v == 0));
+}
+
+#[cfg(test)]
+#[test]
+fn test_sample_trim() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    // pingpong loop from byte 8 to 40 of 64
+    let mut smp = xm.instruments[1].samples[1].clone();
+    let pcm = smp.data_16bit_signed();
+    assert_eq!(smp.trim_after_loop(), 24);
+    assert_eq!(smp.len(), 40);
+    assert_eq!(smp.data_16bit_signed(), &pcm[..20]);
+    assert_eq!(smp.trim_after_loop(), 0);
+    assert_eq!(smp.trim_silence(1.0), 0);
+    assert!(smp.validate().is_empty());
+
+    // a loop of length 0 is no loop
+    let mut smp = xm.instruments[1].samples[1].clone();
+    XModule::write_usize(&mut smp.header, 8, 0);
+    assert_ne!(smp.loop_type(), XM_SAMPLE_LOOP_NONE);
+    assert_eq!(smp.trim_after_loop(), 0);
+    assert_eq!(smp.len(), 64);
+
+    // unlooped sample with a silent tail
+    let mut smp = xm.instruments[1].samples[0].clone();
+    let mut pcm = smp.data_16bit_signed();
+    pcm.extend(vec![0x80; 16]);
+    let (loop_start, loop_len) = smp.loop_frames();
+    smp.set_pcm(&pcm, false, loop_start, loop_len);
+    assert_eq!(smp.trim_after_loop(), 0);
+    assert_eq!(smp.trim_silence(0.01), 16);
+    assert_eq!(smp.len(), 64);
+}
@@ -0,0 +1,188 @@
+use super::{XMSample, XM_SAMPLE_LOOP_NONE, XM_SAMPLE_LOOP_PINGPONG};
+#[cfg(feature = "spectral")]
+use std::f64::consts::PI;
+
+// Size of the windows analyzed by the FFT, in frames.
+#[cfg(feature = "spectral")]
+const FFT_SIZE: usize = 1024;
+// Maximum number of windows averaged for the spectrum of a sample.
+#[cfg(feature = "spectral")]
+const FFT_WINDOWS: usize = 16;
+
+/// Basic descriptors of the sound of a sample, as returned by XMSample::features(). They are meant to help tools
+/// tell drums, basses, and leads apart: drums tend to be noisy and decay quickly, basses have a low spectral
+/// centroid, and leads loop well.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleFeatures {
+    /// The fraction of consecutive frames whose signs differ (0.0..1.0). Noise has a high rate, low tones have a low
+    /// rate.
+    pub zero_crossing_rate: f64,
+    /// The spectral centroid in Hz, when the sample is played at C-4. This is the "center of mass" of the spectrum,
+    /// which correlates with the perceived brightness of the sound. It is only computed with the `spectral` feature,
+    /// which adds a built-in FFT, and is None otherwise.
+    pub spectral_centroid: Option<f64>,
+    /// How well the sample sustains when looped, from 0.0 to 1.0. For looped samples, this rates how smoothly the
+    /// loop end joins the loop start and how well the level matches at both ends of the loop. For samples without a
+    /// loop, it rates how steady the level is between the first and the second half of the sample, so decaying
+    /// sounds score low.
+    pub loopability: f64,
+}
+
+impl XMSample {
+
+    /// Computes descriptors of the sound of the sample. Empty and silent samples have all descriptors set to 0, and
+    /// no spectral centroid. The spectral centroid requires the spectral feature.
+    pub fn features(&self) -> SampleFeatures {
+        let pcm: Vec<f64> = self.pcm_16bit().iter().map(|smp| *smp as f64 / 32768.0).collect();
+        if pcm.iter().all(|smp| *smp == 0.0) {
+            return SampleFeatures::default();
+        }
+
+        let crossings = pcm.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+        SampleFeatures {
+            zero_crossing_rate: crossings as f64 / (pcm.len() - 1).max(1) as f64,
+            #[cfg(feature = "spectral")]
+            spectral_centroid: Some(spectral_centroid(&pcm, self.c4_rate())),
+            #[cfg(not(feature = "spectral"))]
+            spectral_centroid: None,
+            loopability: self.loopability(&pcm),
+        }
+    }
+
+    // Rates how well the sample sustains when looped, see SampleFeatures::loopability.
+    fn loopability(&self, pcm: &[f64]) -> f64 {
+        let (start, len) = self.loop_frames();
+        let end = (start + len).min(pcm.len());
+        if self.loop_type() == XM_SAMPLE_LOOP_NONE || end < start + 2 {
+            let half = pcm.len() / 2;
+            return level_match(&pcm[..half], &pcm[half..]);
+        }
+
+        let looped = &pcm[start..end];
+        let edge = (looped.len() / 4).max(1);
+        let level = level_match(&looped[..edge], &looped[looped.len() - edge..]);
+        if self.loop_type() == XM_SAMPLE_LOOP_PINGPONG {
+            return level;
+        }
+
+        // the step from the loop end back to the loop start, compared to the steps next to it
+        let last = looped.len() - 1;
+        let step = (looped[1] - looped[0]).abs().max((looped[last] - looped[last - 1]).abs());
+        let seam = (looped[0] - looped[last]).abs();
+        let smoothness = if seam <= step { 1.0 } else { step / seam };
+        level * smoothness
+    }
+}
+
+// Returns the ratio of the lower to the higher RMS level of the two blocks, or 0 if either is empty or both are
+// silent.
+fn level_match(block: &[f64], other: &[f64]) -> f64 {
+    let rms = |pcm: &[f64]| (pcm.iter().map(|smp| smp * smp).sum::<f64>() / pcm.len().max(1) as f64).sqrt();
+    let (rms, other_rms) = (rms(block), rms(other));
+    if rms.max(other_rms) == 0.0 { 0.0 } else { rms.min(other_rms) / rms.max(other_rms) }
+}
+
+// Returns the spectral centroid of the PCM data in Hz, at the given sample rate. The magnitude spectra of up to
+// FFT_WINDOWS Hann windows spread evenly over the data are averaged. Data shorter than a window is zero-padded.
+#[cfg(feature = "spectral")]
+fn spectral_centroid(pcm: &[f64], rate: f64) -> f64 {
+    let size = FFT_SIZE.min(pcm.len().next_power_of_two());
+    let windows = (pcm.len() / size).clamp(1, FFT_WINDOWS);
+    let stride = if windows > 1 { (pcm.len() - size) / (windows - 1) } else { 0 };
+
+    let mut magnitudes = vec![0.0; size / 2 + 1];
+    for window in 0..windows {
+        let offset = window * stride;
+        let mut re: Vec<f64> = (0..size).map(|i| {
+            let hann = 0.5 - 0.5 * (2.0 * PI * i as f64 / size as f64).cos();
+            pcm.get(offset + i).unwrap_or(&0.0) * hann
+        }).collect();
+        let mut im = vec![0.0; size];
+        fft(&mut re, &mut im);
+        for (bin, magnitude) in magnitudes.iter_mut().enumerate() {
+            *magnitude += re[bin].hypot(im[bin]);
+        }
+    }
+
+    let total: f64 = magnitudes.iter().sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    magnitudes.iter().enumerate().map(|(bin, magnitude)| bin as f64 * rate / size as f64 * magnitude).sum::<f64>()
+        / total
+}
+
+// Transforms the complex data given as real and imaginary parts in place, with an iterative radix-2 FFT. The length
+// must be a power of two.
+#[cfg(feature = "spectral")]
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (t_re, t_im) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_sample_features() {
+    // a tone with a period of 32 frames, which plays at about 261 Hz on C-4
+    let sine: Vec<i16> = (0..4096).map(|i| ((i as f64 * std::f64::consts::PI / 16.0).sin() * 20000.0) as i16)
+        .collect();
+    let mut tone = XMSample::from_pcm(&sine, true, "");
+    let features = tone.features();
+    assert!((features.zero_crossing_rate - 1.0 / 16.0).abs() < 0.001);
+    assert!(features.loopability > 0.99);
+    #[cfg(feature = "spectral")]
+    assert!((features.spectral_centroid.unwrap() - tone.c4_rate() / 32.0).abs() < 10.0);
+    #[cfg(not(feature = "spectral"))]
+    assert_eq!(features.spectral_centroid, None);
+
+    // a loop covering whole periods joins smoothly, one that ends a quarter period early does not
+    tone.set_pcm(&sine, true, 1024, 2048);
+    tone.header[14] |= 1;
+    assert!(tone.features().loopability > 0.99);
+    tone.set_pcm(&sine, true, 1024, 2040);
+    assert!(tone.features().loopability < 0.5);
+
+    // a decaying noise burst
+    let mut state: u32 = 1;
+    let noise: Vec<i16> = (0..4096).map(|i| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        ((state >> 16) as i16 as f64 * (1.0 - i as f64 / 4096.0).powi(4)) as i16
+    }).collect();
+    let features = XMSample::from_pcm(&noise, true, "").features();
+    assert!(features.zero_crossing_rate > 0.4);
+    assert!(features.loopability < 0.2);
+    #[cfg(feature = "spectral")]
+    assert!(features.spectral_centroid.unwrap() > 1500.0);
+
+    assert_eq!(XMSample::default().features(), SampleFeatures::default());
+}
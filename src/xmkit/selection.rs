@@ -0,0 +1,125 @@
+use std::ops::RangeInclusive;
+use super::{XModule, XMCell};
+use super::cells::PackedCell;
+
+/// The position of a cell in a module.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellPos {
+    /// The pattern number.
+    pub pattern: usize,
+    /// The channel, counted from 0.
+    pub channel: u8,
+    /// The row within the pattern.
+    pub row: u16,
+}
+
+/// A rectangular block of cells within a pattern, like a selection in a tracker. Both ends of the channel and row
+/// ranges are included. Selections may extend beyond the end of the pattern, in which case they only cover the
+/// cells that exist.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Selection {
+    /// The pattern number.
+    pub pattern: usize,
+    /// The selected channels, counted from 0.
+    pub channels: RangeInclusive<u8>,
+    /// The selected rows.
+    pub rows: RangeInclusive<u16>,
+}
+
+impl Selection {
+
+    /// Creates a selection of the given channels and rows of a pattern.
+    pub fn new(pattern: usize, channels: RangeInclusive<u8>, rows: RangeInclusive<u16>) -> Selection {
+        Selection { pattern, channels, rows }
+    }
+
+    /// Creates a selection covering all cells of a pattern.
+    pub fn whole_pattern(pattern: usize) -> Selection {
+        Selection::new(pattern, 0..=u8::MAX, 0..=u16::MAX)
+    }
+
+    /// Creates the selection spanned by two opposite corners, in any order, or returns None if the corners are in
+    /// different patterns.
+    pub fn between(corner: CellPos, other: CellPos) -> Option<Selection> {
+        if corner.pattern != other.pattern {
+            return None;
+        }
+        Some(Selection::new(corner.pattern, corner.channel.min(other.channel)..=corner.channel.max(other.channel),
+            corner.row.min(other.row)..=corner.row.max(other.row)))
+    }
+
+    /// Returns true if the given position is inside the selection.
+    pub fn contains(&self, pos: &CellPos) -> bool {
+        pos.pattern == self.pattern && self.channels.contains(&pos.channel) && self.rows.contains(&pos.row)
+    }
+}
+
+
+impl XModule {
+
+    /// Returns the positions and contents of all cells in the given selection, ordered by row and then by channel.
+    /// Selections of patterns that do not exist are empty.
+    pub fn cells_in<'a>(&'a self, selection: &Selection) -> impl Iterator<Item = (CellPos, XMCell)> + 'a {
+        let Selection { pattern, channels, rows } = selection.clone();
+        self.patterns.get(pattern).into_iter().flat_map(move |ptn| {
+            let channels = channels.clone();
+            rows.clone().take_while(move |row| *row < ptn.len()).flat_map(move |row| {
+                channels.clone().take_while(move |chn| (*chn as usize) < ptn.tracks.len()).map(move |channel| {
+                    (CellPos { pattern, channel, row }, ptn.tracks[channel as usize].cell_unchecked(row as usize))
+                })
+            })
+        })
+    }
+
+    /// Passes every cell in the given selection through transform, along with its position, and stores the cells it
+    /// returns. Cells are visited in the same order as by cells_in(). Returns the number of cells changed.
+    pub fn transform_cells_in<F: FnMut(CellPos, XMCell) -> XMCell>(&mut self, selection: &Selection, mut transform: F)
+        -> usize {
+        let cells: Vec<(CellPos, XMCell)> = self.cells_in(selection).collect();
+        let mut changed = 0;
+        for (pos, cell) in cells {
+            let transformed = transform(pos, cell);
+            if transformed != cell {
+                self.patterns[pos.pattern].tracks[pos.channel as usize].cells[pos.row as usize] =
+                    PackedCell::from(transformed);
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_selection() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let pos = |pattern: usize, channel: u8, row: u16| CellPos { pattern, channel, row };
+
+    let selection = Selection::between(pos(0, 2, 1), pos(0, 1, 0)).unwrap();
+    assert_eq!(selection, Selection::new(0, 1..=2, 0..=1));
+    assert!(selection.contains(&pos(0, 2, 0)));
+    assert!(!selection.contains(&pos(0, 3, 0)) && !selection.contains(&pos(1, 1, 0)));
+    assert_eq!(Selection::between(pos(0, 0, 0), pos(1, 0, 0)), None);
+    let positions: Vec<CellPos> = xm.cells_in(&selection).map(|(pos, _)| pos).collect();
+    assert_eq!(positions, vec![pos(0, 1, 0), pos(0, 2, 0), pos(0, 1, 1), pos(0, 2, 1)]);
+    assert_eq!(xm.cells_in(&selection).nth(1).unwrap().1, xm.patterns[0].tracks[2].cell(0).unwrap());
+
+    // selections are clipped to the pattern
+    assert_eq!(xm.cells_in(&Selection::whole_pattern(2)).count(), 4 * 4);
+    assert_eq!(xm.cells_in(&Selection::new(1, 3..=9, 6..=20)).count(), 2);
+    assert_eq!(xm.cells_in(&Selection::whole_pattern(3)).count(), 0);
+
+    // transpose all notes in the selection up an octave
+    let notes = xm.cells_in(&selection).filter(|(_, cell)| cell.note.is_some_and(|note| note < 97)).count();
+    assert!(notes > 0);
+    let changed = xm.transform_cells_in(&selection, |_, cell| XMCell {
+        note: cell.note.map(|note| if note < 97 { note + 12 } else { note }),
+        ..cell
+    });
+    assert_eq!(changed, notes);
+    assert_eq!(xm.patterns[0].tracks[2].note_raw(0).unwrap(), Some(70));
+    assert_eq!(xm.transform_cells_in(&selection, |_, cell| cell), 0);
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+use super::{XMCell, XMPattern};
+
+impl XMPattern {
+
+    /// Returns how similar the pattern is to the other pattern, from 0.0 (nothing in common) to 1.0 (identical
+    /// contents). Cells at the same row and channel are compared field by field: each cell scores the fraction of
+    /// its set fields (note, instrument, volume, effect command, effect parameter) that are equal in both patterns.
+    /// The result is the average score of all cells that are not empty in at least one of the patterns. Rows and
+    /// channels that only exist in one of the patterns count as empty in the other. Two empty patterns are
+    /// considered identical.
+    pub fn similarity(&self, other: &XMPattern) -> f32 {
+        self.similarity_at(other, 0)
+    }
+
+    /// Like similarity(), but ignores transposition: notes in the other pattern are shifted by the number of
+    /// semitones that gives the highest similarity. Returns the similarity along with that number of semitones.
+    /// If several transpositions score the same, the one closest to 0 is returned.
+    pub fn similarity_transposed(&self, other: &XMPattern) -> (f32, i8) {
+        // only transpositions that make at least one pair of notes equal can improve the score
+        let mut candidates: BTreeSet<i8> = BTreeSet::new();
+        candidates.insert(0);
+        for (trk, other_trk) in self.tracks.iter().zip(&other.tracks) {
+            for (cell, other_cell) in trk.cells.iter().zip(&other_trk.cells) {
+                if let (Some(note @ 1..=96), Some(other_note @ 1..=96)) = (cell.note(), other_cell.note()) {
+                    candidates.insert(note as i8 - other_note as i8);
+                }
+            }
+        }
+
+        let mut best: (f32, i8) = (self.similarity_at(other, 0), 0);
+        for transpose in candidates {
+            let score = self.similarity_at(other, transpose);
+            if score > best.0 || (score == best.0 && transpose.abs() < best.1.abs()) {
+                best = (score, transpose);
+            }
+        }
+
+        best
+    }
+
+    // Computes the similarity with the notes of the other pattern shifted by the given number of semitones.
+    fn similarity_at(&self, other: &XMPattern, transpose: i8) -> f32 {
+        let rows = self.rows().max(other.rows());
+        let channels = self.tracks.len().max(other.tracks.len());
+        let mut total = 0.0;
+        let mut cells = 0;
+
+        for chan in 0..channels {
+            for row in 0..rows {
+                let cell = self.cell_or_empty(chan, row);
+                let mut other_cell = other.cell_or_empty(chan, row);
+                other_cell.note = other_cell.note.map(|note| match note {
+                    1..=96 => (note as i16 + transpose as i16).clamp(1, 96) as u8,
+                    _ => note,
+                });
+
+                let fields = [(cell.note, other_cell.note), (cell.instrument, other_cell.instrument),
+                    (cell.volume, other_cell.volume), (cell.fx_command, other_cell.fx_command),
+                    (cell.fx_param, other_cell.fx_param)];
+                let set = fields.iter().filter(|(a, b)| a.is_some() || b.is_some()).count();
+                if set == 0 {
+                    continue;
+                }
+                let equal = fields.iter().filter(|(a, b)| a.is_some() && a == b).count();
+                total += equal as f32 / set as f32;
+                cells += 1;
+            }
+        }
+
+        if cells == 0 { 1.0 } else { total / cells as f32 }
+    }
+
+    // Number of rows actually stored in the tracks.
+    fn rows(&self) -> usize {
+        self.tracks.first().map_or(0, |trk| trk.cells.len())
+    }
+
+    fn cell_or_empty(&self, chan: usize, row: usize) -> XMCell {
+        match self.tracks.get(chan) {
+            Some(trk) if row < trk.cells.len() => trk.cell_unchecked(row),
+            _ => XMCell::default(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_pattern_similarity() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ptn = &xm.patterns[0];
+    assert_eq!(ptn.similarity(ptn), 1.0);
+    assert_eq!(ptn.similarity(&XMPattern::empty(64, 4)), 0.0);
+    assert_eq!(XMPattern::empty(64, 4).similarity(&XMPattern::empty(32, 2)), 1.0);
+    assert_eq!(ptn.similarity(&xm.patterns[1]), xm.patterns[1].similarity(ptn));
+    assert!(ptn.similarity(&xm.patterns[1]) < 1.0);
+
+    // an octave higher, with one changed volume
+    let mut transposed = ptn.clone();
+    for cell in transposed.tracks.iter_mut().flat_map(|trk| trk.cells.iter_mut()) {
+        if let Some(value @ 1..=84) = cell.note() {
+            cell.set_note(Some(value + 12));
+        }
+    }
+    let score = ptn.similarity(&transposed);
+    assert!(score > 0.5 && score < 1.0);
+    assert_eq!(ptn.similarity_transposed(&transposed), (1.0, -12));
+
+    transposed.tracks[3].cells[0].set_volume(Some(0x20));
+    let (score, transpose) = ptn.similarity_transposed(&transposed);
+    assert!(score < 1.0 && score > ptn.similarity(&transposed));
+    assert_eq!(transpose, -12);
+}
@@ -0,0 +1,112 @@
+use super::{XModule, XMInstrument, PatternPacking, XM_SAMPLE_LOOP_NONE};
+
+/// The number of bytes that the parts of a module take up when it is written with XModule::to_bytes(), along with
+/// the number of bytes that some optimizations would save. See XModule::size_report().
+///
+/// The savings do not overlap, so they can be added up: repacking only counts the patterns in the sequence, and
+/// trimming only counts the samples of used instruments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// The module header, including the pattern sequence.
+    pub header: usize,
+    /// The size of each pattern, including its header.
+    pub patterns: Vec<usize>,
+    /// The size of each instrument header, including the sample headers.
+    pub instruments: Vec<usize>,
+    /// The size of the data of each sample, by instrument.
+    pub samples: Vec<Vec<usize>>,
+    /// Data following the last instrument, such as OpenMPT extensions.
+    pub trailing: usize,
+    /// Bytes saved by packing the patterns in the sequence the way FT2 does.
+    pub repacking_savings: usize,
+    /// Bytes saved by removing the patterns that are not in the sequence.
+    pub unused_pattern_savings: usize,
+    /// Bytes saved by replacing the instruments that are not used in the sequence with empty ones.
+    pub unused_instrument_savings: usize,
+    /// Bytes saved by removing the sample data after the loop end of the samples of used instruments,
+    /// see XMSample::trim_after_loop().
+    pub trim_savings: usize,
+}
+
+impl SizeReport {
+
+    /// Returns the size of the module.
+    pub fn total(&self) -> usize {
+        self.header + self.patterns.iter().sum::<usize>() + self.instruments.iter().sum::<usize>()
+            + self.samples.iter().flatten().sum::<usize>() + self.trailing
+    }
+
+    /// Returns the size of the module after applying all of the optimizations.
+    pub fn optimized_total(&self) -> usize {
+        self.total() - self.repacking_savings - self.unused_pattern_savings - self.unused_instrument_savings
+            - self.trim_savings
+    }
+}
+
+
+impl XModule {
+
+    /// Itemizes the bytes used by the module header, each pattern, each instrument, and each sample, and estimates
+    /// the savings of removing unused patterns and instruments, repacking patterns, and trimming samples.
+    pub fn size_report(&self) -> SizeReport {
+        let sequence = self.sequence();
+        let used_patterns: Vec<bool> = (0..self.patterns.len()).map(|nr| sequence.contains(&(nr as u8))).collect();
+        let used_instruments: Vec<bool> = (1..=self.instruments.len()).map(|instrument| {
+            sequence.iter().filter_map(|ptn| self.patterns.get(*ptn as usize))
+                .any(|ptn| !ptn.find(|cell| cell.uses_instrument(instrument as u8)).is_empty())
+        }).collect();
+
+        let mut report = SizeReport { header: self.header.len(), trailing: self.trailing.len(), ..Default::default() };
+        for (ptn, used) in self.patterns.iter().zip(&used_patterns) {
+            let canonical_size = || ptn.to_bytes(PatternPacking::Canonical).len();
+            let size = ptn.original_bytes().map_or_else(canonical_size, |bytes| bytes.len());
+            if *used {
+                report.repacking_savings += size.saturating_sub(canonical_size());
+            }
+            else {
+                report.unused_pattern_savings += size;
+            }
+            report.patterns.push(size);
+        }
+
+        let empty_size = XMInstrument::empty().encode_headers().len();
+        for (instr, used) in self.instruments.iter().zip(&used_instruments) {
+            let size = instr.original_bytes().map_or_else(|| instr.encode_headers().len(), |bytes| bytes.len());
+            let samples: Vec<usize> = instr.samples.iter().map(|smp| smp.data.len()).collect();
+            if *used {
+                report.trim_savings += instr.samples.iter().filter(|smp| smp.loop_type() != XM_SAMPLE_LOOP_NONE)
+                    .map(|smp| smp.data.len().saturating_sub(smp.loop_start() + smp.loop_len())).sum::<usize>();
+            }
+            else {
+                report.unused_instrument_savings += size.saturating_sub(empty_size) + samples.iter().sum::<usize>();
+            }
+            report.instruments.push(size);
+            report.samples.push(samples);
+        }
+
+        report
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_size_report() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let report = xm.size_report();
+    assert_eq!(report.total(), xm.to_bytes().len());
+    assert_eq!((report.header, report.patterns.len(), report.instruments.len()), (0x150, 3, xm.instruments.len()));
+    assert_eq!(report.unused_pattern_savings, 0);
+
+    // drop pattern 2 from the sequence, and store all patterns unpacked
+    let mut unpacked = XModule::parse(xm.to_bytes_with_packing(PatternPacking::Unpacked)).unwrap();
+    unpacked.header[super::XM_SEQUENCE_BEGIN + 2] = 0;
+    let report = unpacked.size_report();
+    assert_eq!(report.total(), unpacked.to_bytes().len());
+    assert_eq!(report.unused_pattern_savings, report.patterns[2]);
+    let used: usize = [0, 1].iter().map(|nr| unpacked.patterns[*nr].to_bytes(PatternPacking::Canonical).len()).sum();
+    assert_eq!(report.repacking_savings, report.patterns[0] + report.patterns[1] - used);
+    assert!(report.optimized_total() < report.total());
+}
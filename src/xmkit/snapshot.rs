@@ -0,0 +1,141 @@
+use std::fmt;
+use super::{note_name, ChannelState, XModule, XMParseError};
+use super::state::{ChannelSim, GlobalVolumeSim};
+
+/// The state of all channels at the end of a row during playback of the song, as returned by XModule::snapshot().
+/// Its Display implementation produces a canonical text dump that only changes if the state changes, for comparing
+/// replayers against golden files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// The sequence position of the row.
+    pub order: u16,
+    /// The row number within the pattern.
+    pub row: u16,
+    /// The pattern number at the sequence position.
+    pub pattern: u8,
+    /// The tempo (ticks per row) in effect on the row.
+    pub tempo: u8,
+    /// The BPM setting in effect on the row.
+    pub bpm: u8,
+    /// The global volume (0..0x40) at the end of the row.
+    pub global_volume: u8,
+    /// The state of each channel at the end of the row.
+    pub channels: Vec<ChannelState>,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "order {:02X} row {:02X} pattern {:02X} tempo {:02X} bpm {:02X} global {:02X}", self.order, self.row,
+            self.pattern, self.tempo, self.bpm, self.global_volume)?;
+
+        for (chan, state) in self.channels.iter().enumerate() {
+            let lfo = |params: Option<(u8, u8)>| params.map_or("--".to_string(), |(x, y)| format!("{:X}{:X}", x, y));
+            writeln!(f, "{:02} {} {:02X} {:04X} {:.3} {:02X} {:02X} {} {} {}", chan, note_name(state.note),
+                state.instrument, state.period, state.frequency, state.volume, state.panning, lfo(state.vibrato),
+                lfo(state.tremolo), if state.key_off { "off" } else { "on" })?;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl XModule {
+
+    /// Returns the state of all channels at the end of the given row of the given sequence position, when playing
+    /// the song from the beginning (see play_rows()). Unlike XMTrack::state_at(), the state carries over from the
+    /// previous patterns. If the position is played several times, the first time is used.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given position is never reached during playback.
+    pub fn snapshot(&self, order: u16, row: u16) -> Result<Snapshot, XMParseError> {
+        let mut found = None;
+        self.simulate(|snapshot| {
+            if snapshot.order == order && snapshot.row == row {
+                found = Some(snapshot);
+            }
+            found.is_none()
+        });
+
+        found.ok_or_else(|| XMParseError::new(&format!("Position {}:{} is not reached during playback.", order, row)))
+    }
+
+    // Returns the snapshots of all rows played, in playback order.
+    pub(crate) fn snapshots(&self) -> Vec<Snapshot> {
+        let mut snapshots = Vec::new();
+        self.simulate(|snapshot| {
+            snapshots.push(snapshot);
+            true
+        });
+        snapshots
+    }
+
+    // Plays the song, passing the snapshot of each row played to the given function until it returns false.
+    fn simulate<F: FnMut(Snapshot) -> bool>(&self, mut visit: F) {
+        let mut sims = vec![ChannelSim::new(); self.channel_count() as usize];
+        let mut global = GlobalVolumeSim::new();
+
+        for play_row in self.play_rows() {
+            let r = play_row.row as usize;
+            if let Some(ptn) = self.patterns.get(play_row.pattern as usize) {
+                global.start_row(&ptn.tracks, r);
+                for (sim, trk) in sims.iter_mut().zip(&ptn.tracks) {
+                    sim.start_row(self, trk, r);
+                }
+                for t in 1..play_row.ticks {
+                    let tick = (t % play_row.tempo as u16) as u8;
+                    if tick == 0 {
+                        continue;
+                    }
+                    for sim in sims.iter_mut() {
+                        sim.tick(self, tick);
+                    }
+                    global.tick();
+                }
+            }
+
+            let snapshot = Snapshot {
+                order: play_row.order,
+                row: play_row.row,
+                pattern: play_row.pattern,
+                tempo: play_row.tempo,
+                bpm: play_row.bpm,
+                global_volume: global.volume,
+                channels: sims.iter().map(|sim| ChannelState { global_volume: global.volume, ..sim.state }).collect(),
+            };
+            if !visit(snapshot) {
+                return;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_snapshot() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let snapshot = xm.snapshot(0, 13).unwrap();
+    assert_eq!(snapshot.channels.len(), 4);
+    for (chan, state) in snapshot.channels.iter().enumerate() {
+        assert_eq!(*state, xm.patterns[0].tracks[chan].state_at(13, &xm).unwrap());
+    }
+
+    let dump = snapshot.to_string();
+    assert_eq!(dump, xm.clone().snapshot(0, 13).unwrap().to_string());
+    assert_eq!(dump.lines().count(), 5);
+    assert!(dump.starts_with("order 00 row 0D pattern 00 tempo "));
+    assert!(dump.lines().nth(3).unwrap().starts_with("02 A-4 01 0F40 "));
+    assert!(dump.lines().nth(4).unwrap().starts_with("03 --- 00 0000 0.000 00 80 -- -- on"));
+
+    // the state carries over into the next sequence position
+    if let Some(next) = xm.play_rows().iter().find(|play_row| play_row.order == 1 && play_row.row == 0) {
+        let snapshot = xm.snapshot(next.order, 0).unwrap();
+        assert_eq!(snapshot.pattern, xm.sequence()[1]);
+        assert!(snapshot.channels.iter().any(|state| state.note > 0));
+    }
+    assert!(xm.snapshot(0, 256).is_err());
+    assert!(xm.snapshot(xm.sequence().len() as u16, 0).is_err());
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+use super::XModule;
+use super::render::Renderer;
+
+// The sample rate used by XmSource::new().
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// A streaming audio source that renders a module row by row while it is played, for audio playback libraries.
+/// It yields interleaved stereo frames as floating point samples from -1.0 to 1.0, with the same output as
+/// XModule::render() before it is converted to 16 bits, and ends when the sequence has been played once.
+///
+/// The source owns the module, so it can be handed to a playback thread. With the `rodio` feature, it implements
+/// `rodio::Source`, so a module plays with `sink.append(XmSource::new(xm))`.
+pub struct XmSource {
+    xm: XModule,
+    renderer: Renderer,
+    sample_rate: u32,
+    total_duration: Duration,
+    stems: Vec<Vec<f32>>,
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl XmSource {
+
+    /// Creates a source that plays the module at 44.1 kHz.
+    pub fn new(xm: XModule) -> XmSource {
+        XmSource::with_sample_rate(xm, DEFAULT_SAMPLE_RATE)
+    }
+
+    /// Creates a source that plays the module at the given sample rate.
+    pub fn with_sample_rate(xm: XModule, sample_rate: u32) -> XmSource {
+        let sample_rate = sample_rate.max(1);
+        let renderer = Renderer::new(&xm, sample_rate);
+        let total_duration = xm.play_rows().last().map_or(Duration::ZERO, |row| row.start + row.duration());
+        let stems = vec![Vec::new(); xm.channel_count() as usize];
+        XmSource { xm, renderer, sample_rate, total_duration, stems, buffer: Vec::new(), position: 0 }
+    }
+
+    /// Returns the number of audio channels, which is always 2.
+    pub fn channels(&self) -> u16 {
+        2
+    }
+
+    /// Returns the sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the duration of one pass through the sequence.
+    pub fn total_duration(&self) -> Option<Duration> {
+        Some(self.total_duration)
+    }
+
+    /// Returns the module being played.
+    pub fn module(&self) -> &XModule {
+        &self.xm
+    }
+
+    // Renders the next row into the buffer. Returns false if the end of the song has been reached.
+    fn render_row(&mut self) -> bool {
+        for stem in self.stems.iter_mut() {
+            stem.clear();
+        }
+        if !self.renderer.render_row(&self.xm, &mut self.stems) {
+            return false;
+        }
+
+        let len = self.stems.iter().map(|stem| stem.len()).max().unwrap_or(0);
+        self.buffer = (0..len)
+            .map(|i| self.stems.iter().map(|stem| stem.get(i).copied().unwrap_or(0.0)).sum::<f32>().clamp(-1.0, 1.0))
+            .collect();
+        self.position = 0;
+        true
+    }
+}
+
+impl Iterator for XmSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.position >= self.buffer.len() {
+            if !self.render_row() {
+                return None;
+            }
+        }
+        self.position += 1;
+        Some(self.buffer[self.position - 1])
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl ::rodio::Source for XmSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        XmSource::channels(self)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        XmSource::sample_rate(self)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        XmSource::total_duration(self)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_xm_source() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let rendered = xm.render(22050);
+    let source = XmSource::with_sample_rate(xm.clone(), 22050);
+    assert_eq!((source.channels(), source.sample_rate()), (2, 22050));
+    let duration = source.total_duration().unwrap().as_secs_f64();
+    assert!((rendered.len() as f64 / 2.0 - duration * 22050.0).abs() < 2.0);
+    // the 16-bit output can clip one step lower
+    let samples: Vec<i16> = source.map(|smp| (smp * 32767.0).round() as i16).collect();
+    assert_eq!(samples.len(), rendered.len());
+    assert!(samples.iter().zip(&rendered).all(|(smp, expected)| smp == expected || *expected == -32768));
+
+    // playback libraries move sources to their audio thread
+    fn playable<S: Iterator<Item = f32> + Send + 'static>(source: S) -> S { source }
+    assert_eq!(playable(XmSource::new(xm.clone())).sample_rate(), 44100);
+
+    #[cfg(feature = "rodio")]
+    {
+        use rodio::Source;
+        let source = XmSource::with_sample_rate(xm, 22050);
+        assert_eq!((Source::channels(&source), Source::sample_rate(&source)), (2, 22050));
+        assert_eq!(Source::total_duration(&source), source.total_duration());
+        assert_eq!(source.current_span_len(), None);
+    }
+}
@@ -0,0 +1,477 @@
+use std::ptr;
+use super::{XModule, XMSample, XMTrack, XMParseError, XM_ENVELOPE_ON};
+use super::{XM_FX_1XX, XM_FX_2XX, XM_FX_3XX, XM_FX_4XX, XM_FX_5XX, XM_FX_6XX, XM_FX_7XX, XM_FX_8XX, XM_FX_AXX,
+    XM_FX_CXX, XM_FX_FXX, XM_FX_GXX, XM_FX_HXX, XM_FX_KXX, XM_FX_PXX, XM_FX_RXX};
+use super::{frequency_for_period, period_for_note, Lfo};
+
+/// A snapshot of what a channel is doing at a given point during playback.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelState {
+    /// The note that is playing (1..96), or 0 if no note has been triggered.
+    pub note: u8,
+    /// The active instrument, or 0 if no instrument has been set.
+    pub instrument: u8,
+    /// The period after portamento effects have been applied, or 0 if no note has been triggered.
+    /// Vibrato is not included.
+    pub period: u16,
+    /// The playback frequency in Hz corresponding to the period.
+    pub frequency: f64,
+    /// The channel volume (0..0x40), after volume slides have been applied. Tremolo is not included.
+    pub volume: u8,
+    /// The channel panning (0..0xff).
+    pub panning: u8,
+    /// The vibrato speed and depth, if vibrato (4xy, 6xy, volume column Vx) is active.
+    pub vibrato: Option<(u8, u8)>,
+    /// The tremolo speed and depth, if tremolo (7xy) is active.
+    pub tremolo: Option<(u8, u8)>,
+    /// The global volume (0..0x40).
+    pub global_volume: u8,
+    /// True if the note has been released by a key off.
+    pub key_off: bool,
+}
+
+
+// Tick-based simulation of a single channel. Effect memory is tracked per effect, as in FT2.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ChannelSim {
+    pub(crate) state: ChannelState,
+    real_note: i16,
+    finetune: i8,
+    target_period: u16,
+    cell: [u8; 5],
+    porta_up: u8,
+    porta_down: u8,
+    tone_porta: u8,
+    vol_slide: u8,
+    fine_porta_up: u8,
+    fine_porta_down: u8,
+    fine_vol_up: u8,
+    fine_vol_down: u8,
+    extra_fine_up: u8,
+    extra_fine_down: u8,
+    pan_slide: u8,
+    retrig: u8,
+    vibrato: (u8, u8),
+    tremolo: (u8, u8),
+    vibrato_lfo: Lfo,
+    tremolo_lfo: Lfo,
+    // the offsets to the period and volume caused by vibrato and tremolo on the current tick
+    pub(crate) vibrato_offset: i16,
+    pub(crate) tremolo_offset: i16,
+}
+
+impl ChannelSim {
+
+    pub(crate) fn new() -> ChannelSim {
+        ChannelSim {
+            state: ChannelState { panning: 0x80, global_volume: 0x40, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    // Processes tick 0 of the given row.
+    pub(crate) fn start_row(&mut self, xm: &XModule, trk: &XMTrack, row: usize) {
+        self.cell = [
+            trk.cells[row].note().unwrap_or(0),
+            trk.cells[row].instrument().unwrap_or(0),
+            trk.cells[row].volume().unwrap_or(0),
+            trk.cells[row].fx_command().unwrap_or(0),
+            trk.cells[row].fx_param().unwrap_or(0),
+        ];
+        self.state.vibrato = None;
+        self.state.tremolo = None;
+
+        let (cmd, param) = (self.cell[3], self.cell[4]);
+        if !(cmd == 0xe && param >> 4 == 0xd && param & 0xf > 0) {
+            self.trigger(xm);
+        }
+
+        let x = param >> 4;
+        let y = param & 0xf;
+        match cmd {
+            XM_FX_1XX if param > 0 => self.porta_up = param,
+            XM_FX_2XX if param > 0 => self.porta_down = param,
+            XM_FX_3XX if param > 0 => self.tone_porta = param,
+            XM_FX_4XX => self.set_vibrato(x, y),
+            XM_FX_5XX | XM_FX_AXX if param > 0 => self.vol_slide = param,
+            XM_FX_6XX => {
+                self.set_vibrato(0, 0);
+                if param > 0 { self.vol_slide = param; }
+            },
+            XM_FX_7XX => {
+                if x > 0 { self.tremolo.0 = x; }
+                if y > 0 { self.tremolo.1 = y; }
+                self.state.tremolo = Some(self.tremolo);
+            },
+            XM_FX_8XX => self.state.panning = param,
+            XM_FX_CXX => self.state.volume = param.min(0x40),
+            XM_FX_KXX if param == 0 => self.key_off(xm),
+            XM_FX_PXX if param > 0 => self.pan_slide = param,
+            XM_FX_RXX => {
+                if x > 0 { self.retrig = (self.retrig & 0xf) | (x << 4); }
+                if y > 0 { self.retrig = (self.retrig & 0xf0) | y; }
+            },
+            0x21 => match x {
+                1 => {
+                    if y > 0 { self.extra_fine_up = y; }
+                    let slide = self.extra_fine_up as u16;
+                    self.slide_period(-(slide as i32));
+                },
+                2 => {
+                    if y > 0 { self.extra_fine_down = y; }
+                    let slide = self.extra_fine_down as u16;
+                    self.slide_period(slide as i32);
+                },
+                _ => (),
+            },
+            0xe => match x {
+                1 => {
+                    if y > 0 { self.fine_porta_up = y; }
+                    let slide = self.fine_porta_up as i32 * 4;
+                    self.slide_period(-slide);
+                },
+                2 => {
+                    if y > 0 { self.fine_porta_down = y; }
+                    let slide = self.fine_porta_down as i32 * 4;
+                    self.slide_period(slide);
+                },
+                0xa => {
+                    if y > 0 { self.fine_vol_up = y; }
+                    self.state.volume = (self.state.volume + self.fine_vol_up).min(0x40);
+                },
+                0xb => {
+                    if y > 0 { self.fine_vol_down = y; }
+                    self.state.volume = self.state.volume.saturating_sub(self.fine_vol_down);
+                },
+                0xc if y == 0 => self.state.volume = 0,
+                0x4 => self.vibrato_lfo.set_control(y),
+                0x7 => self.tremolo_lfo.set_control(y),
+                _ => (),
+            },
+            _ => (),
+        }
+
+        // vibrato and tremolo are not applied on tick 0, but keep their offset if they continue on the row
+        if self.state.vibrato.is_none() {
+            self.vibrato_offset = 0;
+        }
+        if self.state.tremolo.is_none() {
+            self.tremolo_offset = 0;
+        }
+        self.update_frequency(xm);
+    }
+
+    // Processes a tick other than tick 0 of the current row.
+    pub(crate) fn tick(&mut self, xm: &XModule, tick: u8) {
+        let [_, _, vol, cmd, param] = self.cell;
+        let x = param >> 4;
+        let y = param & 0xf;
+
+        if cmd == 0xe && x == 0xd && y == tick {
+            self.trigger(xm);
+        }
+
+        match vol >> 4 {
+            0x6 => self.state.volume = self.state.volume.saturating_sub(vol & 0xf),
+            0x7 => self.state.volume = (self.state.volume + (vol & 0xf)).min(0x40),
+            0xd => self.state.panning = self.state.panning.saturating_sub(vol & 0xf),
+            0xe => self.state.panning = self.state.panning.saturating_add(vol & 0xf),
+            0xf => self.do_tone_porta(),
+            _ => (),
+        }
+
+        match cmd {
+            XM_FX_1XX => {
+                let slide = self.porta_up as i32 * 4;
+                self.slide_period(-slide);
+            },
+            XM_FX_2XX => {
+                let slide = self.porta_down as i32 * 4;
+                self.slide_period(slide);
+            },
+            XM_FX_3XX => self.do_tone_porta(),
+            XM_FX_5XX => {
+                self.do_tone_porta();
+                self.do_vol_slide();
+            },
+            XM_FX_6XX | XM_FX_AXX => self.do_vol_slide(),
+            XM_FX_KXX if param == tick => self.key_off(xm),
+            XM_FX_PXX => {
+                let (left, right) = (self.pan_slide & 0xf, self.pan_slide >> 4);
+                if right > 0 { self.state.panning = self.state.panning.saturating_add(right); }
+                else { self.state.panning = self.state.panning.saturating_sub(left); }
+            },
+            XM_FX_RXX => {
+                let interval = self.retrig & 0xf;
+                if interval > 0 && tick.is_multiple_of(interval) {
+                    self.state.volume = retrig_volume(self.state.volume, self.retrig >> 4);
+                }
+            },
+            0xe if x == 0xc && y == tick => self.state.volume = 0,
+            _ => (),
+        }
+
+        if let Some((speed, depth)) = self.state.vibrato {
+            self.vibrato_offset = self.vibrato_lfo.vibrato(speed, depth);
+        }
+        if let Some((speed, depth)) = self.state.tremolo {
+            self.tremolo_offset = self.tremolo_lfo.tremolo(speed, depth, self.vibrato_lfo.position);
+        }
+        self.update_frequency(xm);
+    }
+
+    // Handles the note, instrument, and volume column of the current row.
+    fn trigger(&mut self, xm: &XModule) {
+        let [note, instr, vol, cmd, param] = self.cell;
+        let tone_porta = cmd == XM_FX_3XX || cmd == XM_FX_5XX || vol >= 0xf0;
+
+        if instr > 0 {
+            self.state.instrument = instr;
+        }
+
+        if note == 97 {
+            self.key_off(xm);
+        }
+        else if note > 0 && note < 97 {
+            let (relative_note, mut finetune) = match sample_for(xm, self.state.instrument, note) {
+                Some(smp) => (smp.relative_note() as i16, smp.finetune()),
+                None => (0, 0),
+            };
+            if cmd == 0xe && param >> 4 == 5 {
+                finetune = ((param & 0xf) as i16 * 16 - 128) as i8;
+            }
+            let real_note = note as i16 - 1 + relative_note;
+
+            if tone_porta && self.state.period > 0 {
+                self.target_period = period_for_note(real_note, finetune, xm.amiga_ft());
+            }
+            else {
+                self.state.note = note;
+                self.state.key_off = false;
+                self.real_note = real_note;
+                self.finetune = finetune;
+                self.state.period = period_for_note(real_note, finetune, xm.amiga_ft());
+                self.target_period = self.state.period;
+                self.vibrato_lfo.retrigger();
+                self.tremolo_lfo.retrigger();
+            }
+        }
+
+        if instr > 0 && note != 97 {
+            if let Some(smp) = sample_for(xm, instr, self.state.note) {
+                self.state.volume = smp.volume().min(0x40);
+                self.state.panning = smp.panning();
+            }
+        }
+
+        let x = vol & 0xf;
+        match vol >> 4 {
+            0x1..=0x4 => self.state.volume = vol - 0x10,
+            0x5 => self.state.volume = 0x40,
+            0x8 => self.state.volume = self.state.volume.saturating_sub(x),
+            0x9 => self.state.volume = (self.state.volume + x).min(0x40),
+            0xa if x > 0 => self.vibrato.0 = x,
+            0xb => self.set_vibrato(0, x),
+            0xc => self.state.panning = x << 4,
+            0xf if x > 0 => self.tone_porta = x << 4,
+            _ => (),
+        }
+    }
+
+    fn key_off(&mut self, xm: &XModule) {
+        self.state.key_off = true;
+        // without a volume envelope, FT2 cuts the note on key off
+        let has_envelope = match xm.instruments.get((self.state.instrument as usize).wrapping_sub(1)) {
+            Some(instr) => instr.volume_type().unwrap_or(0) & XM_ENVELOPE_ON != 0,
+            None => false,
+        };
+        if !has_envelope {
+            self.state.volume = 0;
+        }
+    }
+
+    fn set_vibrato(&mut self, speed: u8, depth: u8) {
+        if speed > 0 { self.vibrato.0 = speed; }
+        if depth > 0 { self.vibrato.1 = depth; }
+        self.state.vibrato = Some(self.vibrato);
+    }
+
+    fn slide_period(&mut self, delta: i32) {
+        if self.state.period > 0 {
+            self.state.period = (self.state.period as i32 + delta).clamp(1, 32000) as u16;
+        }
+    }
+
+    fn do_tone_porta(&mut self) {
+        let speed = self.tone_porta as i32 * 4;
+        let period = self.state.period as i32;
+        let target = self.target_period as i32;
+        if period == 0 || target == 0 {
+            return;
+        }
+        self.state.period = if period < target { (period + speed).min(target) } else { (period - speed).max(target) } as u16;
+    }
+
+    fn do_vol_slide(&mut self) {
+        let (up, down) = (self.vol_slide >> 4, self.vol_slide & 0xf);
+        if up > 0 { self.state.volume = (self.state.volume + up).min(0x40); }
+        else { self.state.volume = self.state.volume.saturating_sub(down); }
+    }
+
+    fn update_frequency(&mut self, xm: &XModule) {
+        self.state.frequency = frequency_for_period(self.state.period, xm.amiga_ft());
+    }
+}
+
+
+// Tick-based simulation of the global volume, which is set by Gxx and slid by Hxx on any channel of a row.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GlobalVolumeSim {
+    pub(crate) volume: u8,
+    // the last non-zero Hxx parameter, and whether the current row slides
+    slide: u8,
+    sliding: bool,
+}
+
+impl GlobalVolumeSim {
+
+    pub(crate) fn new() -> GlobalVolumeSim {
+        GlobalVolumeSim { volume: 0x40, slide: 0, sliding: false }
+    }
+
+    // Processes tick 0 of the given row, with the tracks of all channels of the pattern.
+    pub(crate) fn start_row<'a, I: IntoIterator<Item = &'a XMTrack>>(&mut self, tracks: I, row: usize) {
+        self.sliding = false;
+        for trk in tracks {
+            let param = trk.cells[row].fx_param().unwrap_or(0);
+            match trk.cells[row].fx_command() {
+                Some(XM_FX_GXX) => self.volume = param.min(0x40),
+                Some(XM_FX_HXX) => {
+                    if param > 0 { self.slide = param; }
+                    self.sliding = true;
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Processes any tick of the current row except tick 0.
+    pub(crate) fn tick(&mut self) {
+        if self.sliding {
+            let (up, down) = (self.slide >> 4, self.slide & 0xf);
+            if up > 0 { self.volume = (self.volume + up).min(0x40); }
+            else { self.volume = self.volume.saturating_sub(down); }
+        }
+    }
+}
+
+
+// Returns the sample that the given instrument plays for the given note.
+pub(crate) fn sample_for(xm: &XModule, instrument: u8, note: u8) -> Option<&XMSample> {
+    let instr = xm.instruments.get((instrument as usize).checked_sub(1)?)?;
+    let keymap = instr.sample_numbers()?;
+    let sample_nr = *keymap.get((note as usize).checked_sub(1)?)?;
+    instr.samples.get(sample_nr as usize)
+}
+
+// Applies the Rxy volume change.
+fn retrig_volume(volume: u8, change: u8) -> u8 {
+    let vol = volume as i16;
+    let vol = match change {
+        0x1..=0x5 => vol - (1 << (change - 1)),
+        0x6 => vol * 2 / 3,
+        0x7 => vol / 2,
+        0x9..=0xd => vol + (1 << (change - 9)),
+        0xe => vol * 3 / 2,
+        0xf => vol * 2,
+        _ => vol,
+    };
+    vol.clamp(0, 0x40) as u8
+}
+
+
+impl XMTrack {
+
+    /// Returns the state of the channel at the end of the given row: the effective note, period and frequency,
+    /// instrument, volume and panning with all slides applied, the active vibrato and tremolo parameters,
+    /// and the global volume.
+    /// Like all track functions, the result is computed from the beginning of the pattern, starting with
+    /// the module's default settings. This function requires a reference to the XModule the track belongs to,
+    /// since instrument, sample, and tempo information is needed to compute the state. Tempo and global volume
+    /// effects on other channels of the pattern are taken into account.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn state_at(&self, row: u16, xm: &XModule) -> Result<ChannelState, XMParseError> {
+        self.validate_row(&row)?;
+
+        let tracks: Vec<&XMTrack> = match xm.patterns.iter().find(|p| p.tracks.iter().any(|t| ptr::eq(t, self))) {
+            Some(ptn) => ptn.tracks.iter().collect(),
+            None => vec![self],
+        };
+
+        let mut sim = ChannelSim::new();
+        let mut speed = xm.tempo().max(1);
+        let mut global = GlobalVolumeSim::new();
+
+        for r in 0..row as usize + 1 {
+            let mut delay: u16 = 0;
+            for trk in &tracks {
+                let param = trk.cells[r].fx_param().unwrap_or(0);
+                match trk.cells[r].fx_command() {
+                    Some(XM_FX_FXX) if param > 0 && param < 0x20 => speed = param,
+                    Some(0xe) if param >> 4 == 0xe => delay = (param & 0xf) as u16,
+                    _ => (),
+                }
+            }
+
+            sim.start_row(xm, self, r);
+            global.start_row(tracks.iter().copied(), r);
+
+            for t in 1..speed as u16 * (delay + 1) {
+                let tick = (t % speed as u16) as u8;
+                if tick == 0 {
+                    continue;
+                }
+                sim.tick(xm, tick);
+                global.tick();
+            }
+        }
+
+        let mut state = sim.state;
+        state.global_volume = global.volume;
+        Ok(state)
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_state_at() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ptn = &xm.patterns[0];
+
+    // C-4 on "lead", sample volume 0x30
+    let state = ptn.tracks[0].state_at(0, &xm).unwrap();
+    assert_eq!((state.note, state.instrument, state.volume, state.panning), (49, 1, 0x30, 0x80));
+    assert_eq!(state.period, 4608);
+    assert!((state.frequency - 8363.0).abs() < 0.01);
+
+    // A0x on row 10 at tempo 3 slides the volume down twice
+    assert_eq!(ptn.tracks[0].state_at(10, &xm).unwrap().volume, 0x20 - 8);
+    // key off with volume envelope keeps the volume
+    let state = ptn.tracks[0].state_at(12, &xm).unwrap();
+    assert!(state.key_off);
+    assert_eq!(state.volume, 0x18);
+
+    // Cxx, then 1xx slides the period up by 5 ticks * 8 * 4
+    let state = ptn.tracks[2].state_at(1, &xm).unwrap();
+    assert_eq!(state.volume, 0x20);
+    assert_eq!(state.period, 7680 - 57 * 64 - 5 * 32);
+    assert_eq!(ptn.tracks[2].state_at(7, &xm).unwrap().vibrato, Some((4, 6)));
+    assert_eq!(ptn.tracks[2].state_at(13, &xm).unwrap().panning, 0xc0);
+
+    assert_eq!(ptn.tracks[0].state_at(14, &xm).unwrap().global_volume, 0x20);
+}
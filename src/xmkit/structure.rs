@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use super::XModule;
+
+// Patterns that are at least this similar (see XMPattern::similarity_transposed()) count as the same material.
+const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.9;
+
+/// A span of the sequence that forms a section of the song, as returned by XModule::structure().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SongSection {
+    /// The label of the section. Sections that repeat the same material share a label. Labels are assigned in
+    /// order of first appearance: "A", "B", ..., "Z", "AA", "AB", ...
+    pub label: String,
+    /// The sequence position at which the section starts.
+    pub start: u16,
+    /// The number of sequence positions the section spans.
+    pub len: u16,
+}
+
+
+impl XModule {
+
+    /// Divides the sequence into sections of repeated material, like verse and chorus, and labels them.
+    /// Sequence positions are considered equal if they play the same pattern, or patterns that are at least 90%
+    /// similar regardless of transposition (see XMPattern::similarity_transposed()). The sequence is then split
+    /// greedily from the start, taking the longest run of positions that repeats elsewhere in the sequence as the
+    /// next section. Consecutive positions that do not repeat anywhere are joined into a single section.
+    pub fn structure(&self) -> Vec<SongSection> {
+        self.structure_with_threshold(DEFAULT_SIMILARITY_THRESHOLD)
+    }
+
+    /// Like structure(), but with the given similarity threshold (0.0..1.0) for considering two patterns equal.
+    /// A threshold above 1.0 only considers identical pattern numbers equal.
+    pub fn structure_with_threshold(&self, threshold: f32) -> Vec<SongSection> {
+        let classes = self.pattern_classes(threshold);
+        let len = classes.len();
+        let mut spans: Vec<(usize, usize, bool)> = Vec::new();
+
+        let mut pos = 0;
+        while pos < len {
+            let run = (2..=len - pos).rev().find(|run| repeats(&classes, pos, *run)).unwrap_or(1);
+            let repeated = run > 1 || repeats(&classes, pos, 1);
+
+            match spans.last_mut() {
+                Some(last) if !repeated && !last.2 => last.1 += 1,
+                _ => spans.push((pos, run, repeated)),
+            }
+            pos += run;
+        }
+
+        let mut labels: HashMap<&[usize], String> = HashMap::new();
+        spans.iter().map(|&(start, run, _)| {
+            let count = labels.len();
+            let label = labels.entry(&classes[start..start + run]).or_insert_with(|| section_label(count));
+            SongSection { label: label.clone(), start: start as u16, len: run as u16 }
+        }).collect()
+    }
+
+    // Assigns each sequence position a class, so that positions playing similar patterns share a class. A pattern
+    // always gets the same class, so each pattern is only compared once with the representatives of the classes.
+    fn pattern_classes(&self, threshold: f32) -> Vec<usize> {
+        let mut representatives: Vec<u8> = Vec::new();
+        let mut classes: HashMap<u8, usize> = HashMap::new();
+
+        self.sequence().iter().map(|nr| {
+            if let Some(class) = classes.get(nr) {
+                return *class;
+            }
+            let class = representatives.iter().position(|rep| threshold <= 1.0
+                && match (self.patterns.get(*rep as usize), self.patterns.get(*nr as usize)) {
+                    (Some(a), Some(b)) => a.similarity_transposed(b).0 >= threshold,
+                    _ => false,
+                }).unwrap_or_else(|| {
+                    representatives.push(*nr);
+                    representatives.len() - 1
+                });
+            classes.insert(*nr, class);
+            class
+        }).collect()
+    }
+}
+
+// Returns true if the run of the given length at the given position occurs elsewhere, without overlapping it.
+fn repeats(classes: &[usize], pos: usize, run: usize) -> bool {
+    let needle = &classes[pos..pos + run];
+    (0..=classes.len() - run).any(|other| (other + run <= pos || other >= pos + run)
+        && &classes[other..other + run] == needle)
+}
+
+// Returns the label of the section with the given index: A..Z, AA..AZ, BA, ...
+fn section_label(index: usize) -> String {
+    let letter = |n: usize| (b'A' + (n % 26) as u8) as char;
+    if index < 26 { letter(index).to_string() } else { format!("{}{}", section_label(index / 26 - 1), letter(index)) }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_song_structure() {
+    use std::path::Path;
+    use super::{XM_SEQUENCE_BEGIN, XM_SEQUENCE_LEN};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let section = |label: &str, start: u16, len: u16| SongSection { label: label.to_string(), start, len };
+
+    // sequence 0, 1, 2, 1
+    assert_eq!(xm.structure(), vec![section("A", 0, 1), section("B", 1, 1), section("C", 2, 1), section("B", 3, 1)]);
+
+    let sequence = [0u8, 1, 0, 1, 2, 2, 0, 1, 2];
+    xm.header[XM_SEQUENCE_BEGIN..XM_SEQUENCE_BEGIN + sequence.len()].copy_from_slice(&sequence);
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, sequence.len() as u16);
+    assert_eq!(xm.structure_with_threshold(2.0), vec![section("A", 0, 2), section("B", 2, 3), section("C", 5, 1),
+        section("B", 6, 3)]);
+
+    // a copy of pattern 0, transposed by an octave
+    let mut transposed = xm.patterns[0].clone();
+    for cell in transposed.tracks.iter_mut().flat_map(|trk| trk.cells.iter_mut()) {
+        if let Some(value @ 1..=84) = cell.note() {
+            cell.set_note(Some(value + 12));
+        }
+    }
+    xm.patterns.push(transposed);
+    let sequence = [0u8, 1, 3, 1];
+    xm.header[XM_SEQUENCE_BEGIN..XM_SEQUENCE_BEGIN + sequence.len()].copy_from_slice(&sequence);
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, sequence.len() as u16);
+    assert_eq!(xm.structure(), vec![section("A", 0, 2), section("A", 2, 2)]);
+    assert_eq!(xm.structure_with_threshold(2.0), vec![section("A", 0, 1), section("B", 1, 1), section("C", 2, 1),
+        section("B", 3, 1)]);
+
+    assert_eq!(section_label(25), "Z");
+    assert_eq!(section_label(26), "AA");
+    assert_eq!(section_label(53), "BB");
+}
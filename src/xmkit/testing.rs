@@ -0,0 +1,337 @@
+//! Generators for random, valid modules, patterns, instruments, and samples, for property tests of code that reads
+//! or writes XM data. Only available with the `testing` feature.
+//!
+//! All generators draw from a seedable Rng, so a failing case can be reproduced from its seed. Generated modules
+//! pass XModule::validate() without warnings, and use both packed and unpacked pattern cells.
+//!
+//! ```
+//! use xmkit::testing::{check_modules, check_round_trip, GeneratorOptions};
+//!
+//! check_modules(20, 1, &GeneratorOptions::default(), |_, data| check_round_trip(data));
+//! ```
+
+use super::{XModule, XMPattern, XMInstrument, XMSample, PatternPacking, XM_SAMPLE_16BIT, XM_ENVELOPE_ON,
+    XM_ENVELOPE_SUSTAIN, XM_ENVELOPE_LOOP, XM_HEADER_SIZE, XM_SEQUENCE_LEN, XM_RESTART_POS, XM_CHANNEL_COUNT,
+    XM_PATTERN_COUNT, XM_INSTRUMENT_COUNT, XM_FREQ_TABLE_TYPE, XM_DEFAULT_TEMPO, XM_DEFAULT_BPM, XM_SEQUENCE_BEGIN,
+    XM_SEQUENCE_MAX_LEN, XM_INSTRUMENT_HEADER_SIZE, XM_SAMPLE_HEADER_SIZE};
+pub use super::rng::Rng;
+
+// The raw effect commands as stored in pattern data.
+const RAW_EFFECTS: [u8; 23] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0x10, 0x11, 0x14, 0x15,
+    0x19, 0x1b, 0x1d];
+
+/// Limits for the generated data. Lower limits make for faster tests, higher limits for more thorough ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GeneratorOptions {
+    /// The maximum number of channels, 2..32. Generated modules have an even number of channels.
+    pub max_channels: u8,
+    /// The maximum number of patterns, 1..256.
+    pub max_patterns: u16,
+    /// The maximum number of rows per pattern, 1..256.
+    pub max_rows: u16,
+    /// The maximum number of instruments, 0..128.
+    pub max_instruments: u8,
+    /// The maximum number of samples per instrument, 0..16.
+    pub max_samples: u8,
+    /// The maximum length of sample data in bytes.
+    pub max_sample_len: usize,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        GeneratorOptions { max_channels: 8, max_patterns: 4, max_rows: 64, max_instruments: 4, max_samples: 3,
+            max_sample_len: 256 }
+    }
+}
+
+
+/// Generates the data of a random, valid module.
+pub fn module_bytes(rng: &mut Rng, options: &GeneratorOptions) -> Vec<u8> {
+    let channels = (rng.range(1, options.max_channels.clamp(2, 32) as u32 / 2) * 2) as u8;
+    let pattern_count = rng.range(1, options.max_patterns.clamp(1, 256) as u32) as usize;
+    let instrument_count = rng.range(0, options.max_instruments.min(128) as u32) as u8;
+
+    let mut data = b"Extended Module: ".to_vec();
+    data.extend(name(rng, 20));
+    data.push(0x1a);
+    data.extend(name(rng, 20));
+    data.extend(&[4, 1]);
+    data.resize(XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN, 0);
+    XModule::write_usize(&mut data, XM_HEADER_SIZE, XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN - XM_HEADER_SIZE);
+
+    // every pattern is used at least once
+    let mut sequence: Vec<u8> = (0..pattern_count).map(|ptn| ptn as u8).collect();
+    for _ in 0..rng.range(0, (XM_SEQUENCE_MAX_LEN - pattern_count).min(16) as u32) {
+        let pos = rng.range(0, sequence.len() as u32) as usize;
+        sequence.insert(pos, rng.range(0, pattern_count as u32 - 1) as u8);
+    }
+    XModule::write_u16(&mut data, XM_SEQUENCE_LEN, sequence.len() as u16);
+    XModule::write_u16(&mut data, XM_RESTART_POS, rng.range(0, sequence.len() as u32 - 1) as u16);
+    XModule::write_u16(&mut data, XM_CHANNEL_COUNT, channels as u16);
+    XModule::write_u16(&mut data, XM_PATTERN_COUNT, pattern_count as u16);
+    XModule::write_u16(&mut data, XM_INSTRUMENT_COUNT, instrument_count as u16);
+    XModule::write_u16(&mut data, XM_FREQ_TABLE_TYPE, rng.range(0, 1) as u16);
+    XModule::write_u16(&mut data, XM_DEFAULT_TEMPO, rng.range(1, 31) as u16);
+    XModule::write_u16(&mut data, XM_DEFAULT_BPM, rng.range(32, 255) as u16);
+    data[XM_SEQUENCE_BEGIN..XM_SEQUENCE_BEGIN + sequence.len()].copy_from_slice(&sequence);
+
+    for _ in 0..pattern_count {
+        data.extend(pattern_bytes(rng, channels, instrument_count, options.max_rows));
+    }
+    for _ in 0..instrument_count {
+        data.extend(instrument_bytes(rng, options.max_samples, options.max_sample_len));
+    }
+
+    data
+}
+
+/// Generates a random, valid module.
+pub fn module(rng: &mut Rng, options: &GeneratorOptions) -> XModule {
+    XModule::parse(module_bytes(rng, options)).expect("generated module is valid")
+}
+
+/// Generates the data of a random pattern with the given number of channels, and up to max_rows rows.
+/// Notes are in the range 0..=97, and instrument numbers do not exceed instrument_count. Cells are packed
+/// or unpacked at random, so the data is not necessarily packed the way FT2 does.
+pub fn pattern_bytes(rng: &mut Rng, channels: u8, instrument_count: u8, max_rows: u16) -> Vec<u8> {
+    let rows = rng.range(1, max_rows.clamp(1, 256) as u32) as u16;
+    let mut data = vec![9, 0, 0, 0, 0, 0, 0, 0, 0];
+    XModule::write_u16(&mut data, 5, rows);
+
+    // some patterns are stored without packed data
+    if rng.one_in(8) {
+        return data;
+    }
+
+    let density = rng.range(1, 4);
+    for _ in 0..rows as usize * channels as usize {
+        let mut fields = [0u8; 5];
+        if rng.range(1, 4) <= density {
+            fields = [rng.range(0, 97) as u8, rng.range(0, instrument_count as u32) as u8, volume(rng),
+                RAW_EFFECTS[rng.range(0, RAW_EFFECTS.len() as u32 - 1) as usize], rng.byte()];
+            for field in fields.iter_mut() {
+                if rng.one_in(2) {
+                    *field = 0;
+                }
+            }
+        }
+
+        if rng.one_in(6) {
+            data.extend(&fields);
+        }
+        else {
+            // packed cells may store fields that are 0
+            let ctrl = fields.iter().enumerate()
+                .filter(|(_, field)| **field != 0 || rng.one_in(8))
+                .fold(0x80u8, |ctrl, (bit, _)| ctrl | 1 << bit);
+            data.push(ctrl);
+            data.extend((0..5).filter(|bit| ctrl & (1 << bit) != 0).map(|bit| fields[bit]));
+        }
+    }
+
+    let packed_size = (data.len() - 9) as u16;
+    XModule::write_u16(&mut data, 7, packed_size);
+    data
+}
+
+/// Generates a random pattern, see pattern_bytes().
+pub fn pattern(rng: &mut Rng, channels: u8, instrument_count: u8, max_rows: u16) -> XMPattern {
+    XMPattern::parse(pattern_bytes(rng, channels, instrument_count, max_rows), channels)
+        .expect("generated pattern is valid")
+}
+
+/// Generates the data of a random instrument with up to max_samples samples, including sample data of up to
+/// max_sample_len bytes per sample. Envelopes, sample maps, and sample loops are consistent.
+pub fn instrument_bytes(rng: &mut Rng, max_samples: u8, max_sample_len: usize) -> Vec<u8> {
+    let sample_count = rng.range(0, max_samples.min(16) as u32) as u8;
+    let header_size = if sample_count == 0 { 29 } else { XM_INSTRUMENT_HEADER_SIZE };
+    let mut header = vec![0u8; header_size];
+    XModule::write_usize(&mut header, 0, header_size);
+    header[4..26].copy_from_slice(&name(rng, 22));
+    header[27] = sample_count;
+
+    let mut samples = Vec::new();
+    if sample_count > 0 {
+        XModule::write_usize(&mut header, 29, XM_SAMPLE_HEADER_SIZE);
+        for key in header[33..129].iter_mut() {
+            *key = rng.range(0, sample_count as u32 - 1) as u8;
+        }
+        for envelope in 0..2 {
+            envelope_bytes(rng, &mut header, envelope);
+        }
+        header[235] = rng.range(0, 3) as u8;
+        for setting in header[236..239].iter_mut() {
+            *setting = rng.range(0, 0x40) as u8;
+        }
+        XModule::write_u16(&mut header, 239, rng.range(0, 0xfff) as u16);
+
+        for _ in 0..sample_count {
+            samples.push(sample_bytes(rng, max_sample_len));
+        }
+    }
+
+    let mut data = header;
+    for (smp_header, _) in &samples {
+        data.extend(smp_header);
+    }
+    for (_, smp_data) in samples {
+        data.extend(smp_data);
+    }
+    data
+}
+
+/// Generates a random instrument, see instrument_bytes().
+pub fn instrument(rng: &mut Rng, max_samples: u8, max_sample_len: usize) -> XMInstrument {
+    XMInstrument::parse(instrument_bytes(rng, max_samples, max_sample_len)).expect("generated instrument is valid")
+}
+
+/// Generates the header and data of a random sample of up to max_len bytes. 16-bit samples and their loops have
+/// even lengths, and loops fit the sample.
+pub fn sample_bytes(rng: &mut Rng, max_len: usize) -> (Vec<u8>, Vec<u8>) {
+    let is_16bit = rng.one_in(3);
+    let align = if is_16bit { !1 } else { !0 };
+    let len = rng.range(0, max_len.min(u32::MAX as usize) as u32) as usize & align;
+
+    let mut header = vec![0u8; XM_SAMPLE_HEADER_SIZE];
+    XModule::write_usize(&mut header, 0, len);
+    let mut flags = if is_16bit { XM_SAMPLE_16BIT } else { 0 };
+    if len > 2 && !rng.one_in(3) {
+        let loop_start = rng.range(0, len as u32 - 2) as usize & align;
+        let loop_len = (rng.range(1, (len - loop_start) as u32) as usize & align).max(if is_16bit { 2 } else { 1 });
+        XModule::write_usize(&mut header, 4, loop_start);
+        XModule::write_usize(&mut header, 8, loop_len);
+        // loop type bits: 1 is a forward loop, 2 a ping-pong loop
+        flags |= rng.range(1, 2) as u8;
+    }
+    header[12] = rng.range(0, 0x40) as u8;
+    header[13] = rng.byte();
+    header[14] = flags;
+    header[15] = rng.byte();
+    header[16] = rng.byte();
+    header[18..40].copy_from_slice(&name(rng, 22));
+
+    let data = (0..len).map(|_| rng.byte()).collect();
+    (header, data)
+}
+
+/// Generates a random sample, see sample_bytes().
+pub fn sample(rng: &mut Rng, max_len: usize) -> XMSample {
+    let (mut data, smp_data) = sample_bytes(rng, max_len);
+    let mut instr = vec![0u8; XM_INSTRUMENT_HEADER_SIZE];
+    XModule::write_usize(&mut instr, 0, XM_INSTRUMENT_HEADER_SIZE);
+    instr[27] = 1;
+    instr.append(&mut data);
+    instr.extend(smp_data);
+    XMInstrument::parse(instr).expect("generated sample is valid").samples.remove(0)
+}
+
+
+/// Runs a property check on the given number of generated modules. The property receives the parsed module and the
+/// data it was parsed from, and returns an error message if the property does not hold. Case n uses the seed
+/// seed + n, so failing cases can be reproduced with module_bytes(&mut Rng::new(seed + n), options).
+///
+/// # Panics
+/// Panics with the seed and the error message of the first case for which the property does not hold.
+pub fn check_modules<F>(cases: u32, seed: u64, options: &GeneratorOptions, mut property: F)
+    where F: FnMut(&XModule, &[u8]) -> Result<(), String> {
+
+    for case in 0..cases as u64 {
+        let case_seed = seed.wrapping_add(case);
+        let data = module_bytes(&mut Rng::new(case_seed), options);
+        let xm = XModule::parse(data.clone()).expect("generated module is valid");
+        if let Err(e) = property(&xm, &data) {
+            panic!("property does not hold for seed {}: {}", case_seed, e);
+        }
+    }
+}
+
+/// Checks that the given module data survives a round trip through the parser and the writer: writing the parsed
+/// module reproduces the data, and re-packing the patterns does not change their contents.
+pub fn check_round_trip(data: &[u8]) -> Result<(), String> {
+    let xm = XModule::parse(data.to_vec()).map_err(|e| format!("parsing failed: {}", e))?;
+    if xm.to_bytes() != data {
+        return Err("writing the parsed module does not reproduce the data".to_string());
+    }
+
+    let canonical = xm.to_bytes_with_packing(PatternPacking::Canonical);
+    let repacked = XModule::parse(canonical.clone()).map_err(|e| format!("parsing the re-packed module failed: {}", e))?;
+    if repacked.to_bytes() != canonical {
+        return Err("writing the re-packed module does not reproduce it".to_string());
+    }
+    if repacked.to_bytes_with_packing(PatternPacking::Unpacked) != xm.to_bytes_with_packing(PatternPacking::Unpacked) {
+        return Err("re-packing changed the pattern contents".to_string());
+    }
+    Ok(())
+}
+
+
+// Generates a name of printable ASCII characters, padded with zeros.
+fn name(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let name_len = rng.range(0, len as u32) as usize;
+    (0..len).map(|pos| if pos < name_len { rng.range(0x20, 0x7e) as u8 } else { 0 }).collect()
+}
+
+// Generates a volume column value: empty, a volume, or a volume column effect.
+fn volume(rng: &mut Rng) -> u8 {
+    match rng.range(0, 2) {
+        0 => 0,
+        1 => rng.range(0x10, 0x50) as u8,
+        _ => rng.range(0x60, 0xff) as u8,
+    }
+}
+
+// Generates a valid envelope (0 = volume, 1 = panning) in the given instrument header.
+fn envelope_bytes(rng: &mut Rng, header: &mut [u8], envelope: usize) {
+    let points = rng.range(0, 12) as u8;
+    if points == 0 {
+        return;
+    }
+
+    let mut frame = 0;
+    for point in 0..points as usize {
+        let offset = 129 + envelope * 48 + point * 4;
+        XModule::write_u16(header, offset, frame);
+        XModule::write_u16(header, offset + 2, rng.range(0, 64) as u16);
+        frame += rng.range(1, 32) as u16;
+    }
+
+    let loop_start = rng.range(0, points as u32 - 1) as u8;
+    header[225 + envelope] = points;
+    header[227 + envelope * 3] = rng.range(0, points as u32 - 1) as u8;
+    header[228 + envelope * 3] = loop_start;
+    header[229 + envelope * 3] = rng.range(loop_start as u32, points as u32 - 1) as u8;
+    let flags = [XM_ENVELOPE_ON, XM_ENVELOPE_SUSTAIN, XM_ENVELOPE_LOOP].iter()
+        .filter(|_| rng.one_in(2))
+        .fold(0, |flags, flag| flags | flag);
+    header[233 + envelope] = flags;
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_generators() {
+    let options = GeneratorOptions::default();
+    check_modules(64, 0, &options, |xm, data| {
+        let report = xm.validate();
+        if !report.is_ok() {
+            return Err(format!("generated module is invalid:\n{}", report));
+        }
+        check_round_trip(data)
+    });
+
+    // the same seed produces the same module
+    assert_eq!(module_bytes(&mut Rng::new(7), &options), module_bytes(&mut Rng::new(7), &options));
+    assert_ne!(module_bytes(&mut Rng::new(7), &options), module_bytes(&mut Rng::new(8), &options));
+
+    let mut rng = Rng::new(1);
+    let ptn = pattern(&mut rng, 6, 2, 16);
+    assert_eq!(ptn.tracks.len(), 6);
+    assert!(ptn.len() <= 16);
+    for _ in 0..16 {
+        let smp = sample(&mut rng, 100);
+        assert!(smp.validate().is_empty());
+        assert!(smp.data.len() <= 100);
+        let instr = instrument(&mut rng, 3, 100);
+        assert!(instr.validate().is_empty() && instr.samples.len() <= 3);
+    }
+}
@@ -0,0 +1,163 @@
+use super::{XMTrack, XMParseError, QuirksMode, XM_FX_3XX, XM_FX_5XX, XM_FX_KXX, XM_FX_RXX};
+
+/// The kind of event happening on a given tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickEventKind {
+    /// A note is triggered, optionally together with an instrument change.
+    NoteOn { note: u8, instrument: Option<u8> },
+    /// The current note is retriggered (E9x, Rxy). `volume_change` holds the Rxy volume change nibble,
+    /// or 0 if the retrigger does not affect the volume.
+    Retrigger { volume_change: u8 },
+    /// The current note is cut, ie. its volume is set to 0 (ECx).
+    NoteCut,
+    /// The current note is released (key off note, or Kxx).
+    KeyOff,
+}
+
+/// An event happening on a given tick within a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TickEvent {
+    /// The tick on which the event happens, counted from the beginning of the row.
+    pub tick: u8,
+    /// What happens on the tick.
+    pub kind: TickEventKind,
+}
+
+
+impl XMTrack {
+
+    /// Expands the given row into a list of events, ordered by the tick on which they happen.
+    /// The tempo parameter is the number of ticks per row, as returned by XMPattern::tempo() or PlayRow.
+    /// Note delays (EDx), retriggers (E9x), multi retriggers (Rxy), note cuts (ECx), and key offs (Kxx) are
+    /// taken into account. Events that would happen on a tick beyond the end of the row are discarded.
+    /// Notes accompanied by a tone portamento (3xx, 5xy, or volume column Mx) do not generate a NoteOn event,
+    /// since they do not retrigger the sample.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn tick_events(&self, row: u16, tempo: u8) -> Result<Vec<TickEvent>, XMParseError> {
+        self.validate_row(&row)?;
+        let r = row as usize;
+
+        let note = self.cells[r].note().unwrap_or(0);
+        let instrument = match self.cells[r].instrument() {
+            Some(0) | None => None,
+            instr => instr,
+        };
+        let cmd = self.cells[r].fx_command().unwrap_or(0);
+        let param = self.cells[r].fx_param().unwrap_or(0);
+        let tone_porta = cmd == XM_FX_3XX || cmd == XM_FX_5XX || self.cells[r].volume().unwrap_or(0) >= 0xf0;
+
+        let mut events: Vec<TickEvent> = Vec::new();
+        let mut push = |tick: u8, kind: TickEventKind| if tick < tempo { events.push(TickEvent{tick, kind}); };
+
+        let trigger_tick = if cmd == 0xe && param & 0xf0 == 0xd0 { param & 0xf } else { 0 };
+        if note == 97 {
+            push(trigger_tick, TickEventKind::KeyOff);
+        }
+        else if note > 0 && note < 97 && !tone_porta {
+            push(trigger_tick, TickEventKind::NoteOn{note, instrument});
+        }
+
+        let mut retrig_interval = 0;
+        let mut volume_change = 0;
+
+        match cmd {
+            0xe => match param >> 4 {
+                0x9 => retrig_interval = param & 0xf,
+                0xc => push(param & 0xf, TickEventKind::NoteCut),
+                _ => (),
+            },
+            XM_FX_KXX => push(param, TickEventKind::KeyOff),
+            XM_FX_RXX => {
+                let p = self.fx_with_quirks(XM_FX_RXX, row, QuirksMode::FT2)?;
+                retrig_interval = p & 0xf;
+                volume_change = p >> 4;
+            },
+            _ => (),
+        }
+
+        if retrig_interval > 0 {
+            for tick in (retrig_interval..tempo).step_by(retrig_interval as usize) {
+                push(tick, TickEventKind::Retrigger{volume_change});
+            }
+        }
+
+        events.sort_by_key(|ev| ev.tick);
+        Ok(events)
+    }
+
+    /// Expands an arpeggio (0xy) on the given row into the semitone offsets that apply on each tick of the row,
+    /// or returns None if there is no arpeggio on the row. The tempo parameter is the number of ticks per row.
+    /// Offsets are to be added to the note returned by note().
+    /// The offsets follow FT2's arpeggio order, which depends on the tempo: for example, at tempo 6, 037 results
+    /// in the offsets 0, 7, 3, 0, 7, 3. This includes FT2's behaviour at tempo 16 and above.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given row is greater than the length of the track.
+    pub fn arpeggio(&self, row: u16, tempo: u8) -> Result<Option<Vec<u8>>, XMParseError> {
+        self.validate_row(&row)?;
+        let r = row as usize;
+
+        let param = match (self.cells[r].fx_command(), self.cells[r].fx_param()) {
+            (Some(0), Some(p)) | (None, Some(p)) if p > 0 => p,
+            _ => return Ok(None),
+        };
+
+        let mut offsets: Vec<u8> = Vec::with_capacity(tempo as usize);
+        for tick in 0..tempo {
+            // FT2 reads the arpeggio offsets from a 16-entry table indexed by the remaining ticks of the row,
+            // and overflows into the next table at higher tempo values.
+            let arp_pos = if tick == 0 { 0 } else {
+                match tempo - tick {
+                    pos if pos > 16 => 2,
+                    16 => 0,
+                    pos => pos % 3,
+                }
+            };
+            offsets.push(match arp_pos {
+                1 => param >> 4,
+                2 => param & 0xf,
+                _ => 0,
+            });
+        }
+
+        Ok(Some(offsets))
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_tick_events() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let ptn = &xm.patterns[0];
+
+    assert_eq!(ptn.tracks[0].tick_events(12, 3).unwrap(), vec![TickEvent{tick: 0, kind: TickEventKind::KeyOff}]);
+    assert_eq!(ptn.tracks[1].tick_events(2, 6).unwrap(),
+        vec![TickEvent{tick: 3, kind: TickEventKind::NoteOn{note: 39, instrument: Some(2)}}]);
+    assert!(ptn.tracks[1].tick_events(2, 3).unwrap().is_empty());
+    assert_eq!(ptn.tracks[1].tick_events(6, 6).unwrap(), vec![TickEvent{tick: 3, kind: TickEventKind::Retrigger{volume_change: 0}}]);
+    assert_eq!(ptn.tracks[1].tick_events(9, 3).unwrap(), vec![
+        TickEvent{tick: 0, kind: TickEventKind::NoteOn{note: 40, instrument: Some(2)}},
+        TickEvent{tick: 2, kind: TickEventKind::Retrigger{volume_change: 8}}]);
+    assert_eq!(ptn.tracks[1].tick_events(11, 3).unwrap(), vec![TickEvent{tick: 2, kind: TickEventKind::NoteCut}]);
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_arpeggio() {
+    use std::path::Path;
+    use super::XModule;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let trk = &xm.patterns[0].tracks[0];
+
+    assert_eq!(trk.arpeggio(4, 6).unwrap(), Some(vec![0, 7, 3, 0, 7, 3]));
+    assert_eq!(trk.arpeggio(5, 3).unwrap(), Some(vec![0, 7, 3]));
+    assert_eq!(trk.arpeggio(0, 6).unwrap(), None);
+}
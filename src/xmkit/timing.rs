@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use super::{QuirksMode, XModule, XMParseError, XM_FX_BXX, XM_FX_DXX, XM_FX_FXX};
+
+// The number of times a row of a sequence position may be played, which allows two nested pattern loops of 16
+// repetitions each. Loops on several channels multiply, so without a limit they expand exponentially.
+const MAX_ROW_PLAYS: u16 = 256;
+// The number of rows that are played at most: four times the longest sequence of the longest patterns.
+const MAX_PLAY_ROWS: usize = 4 * 256 * 256;
+
+/// A row as it is encountered during playback of the module's sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlayRow {
+    /// The sequence (order list) position the row is played from.
+    pub order: u16,
+    /// The pattern number at the sequence position.
+    pub pattern: u8,
+    /// The row number within the pattern.
+    pub row: u16,
+    /// The tempo (ticks per row) in effect on the row.
+    pub tempo: u8,
+    /// The BPM setting in effect on the row.
+    pub bpm: u8,
+    /// The number of ticks the row lasts, including pattern delays (EEx).
+    pub ticks: u16,
+    /// The time at which the row starts playing, relative to the beginning of the song.
+    pub start: Duration,
+}
+
+impl PlayRow {
+    /// Returns the time it takes to play the row.
+    pub fn duration(&self) -> Duration {
+        ticks_duration(self.ticks as u64, self.bpm)
+    }
+}
+
+
+impl XModule {
+
+    /// Returns the rows of the module in the order in which they are played, starting from the first
+    /// sequence position. Tempo and BPM changes (Fxx), position jumps (Bxx), pattern breaks (Dxx),
+    /// pattern loops (E6x), and pattern delays (EEx) are taken into account, so rows inside a pattern loop are
+    /// returned once for every time they are played.
+    /// Playback ends when the end of the sequence is reached, when the tempo is set to 0, or when a
+    /// jump leads to a row that has already been played in the same state. Since pattern loops on several channels
+    /// multiply, playback also ends when a row of a sequence position is about to be played for the 257th time, or
+    /// after 262144 rows.
+    /// Pattern loops follow QuirksMode::Generic, see play_rows_with_quirks().
+    pub fn play_rows(&self) -> Vec<PlayRow> {
+        self.play_rows_with_quirks(QuirksMode::Generic)
+    }
+
+    /// Returns the rows of the module in the order in which they are played, like play_rows(), with pattern loops
+    /// (E6x) following the given QuirksMode. Each channel keeps its own loop start row and loop counter. If a loop
+    /// and a pattern break (Dxx) or position jump (Bxx) are on the same row, playback continues on the next position,
+    /// on the row set by the rightmost loop or pattern break.
+    /// With QuirksMode::FT2, the loop start row carries over into the following patterns (see
+    /// QuirksMode::loop_start_persists()), Bxx resets the row set by channels to its left to 0, and the row a loop
+    /// jumps to is remembered like a pattern break row, so the next pattern starts on it unless another break sets a
+    /// different row. The other modes reset the loop start row at the beginning of each pattern.
+    pub fn play_rows_with_quirks(&self, quirks: QuirksMode) -> Vec<PlayRow> {
+        let sequence = self.sequence();
+        let channels = self.patterns.iter().map(|ptn| ptn.tracks.len()).max().unwrap_or(0);
+        let ft2 = quirks == QuirksMode::FT2;
+        let mut rows: Vec<PlayRow> = Vec::new();
+        // positions played, along with the loop state, which decides where playback continues
+        let mut visited = HashSet::new();
+        let mut row_plays: HashMap<(usize, usize), u16> = HashMap::new();
+        let mut tempo = self.tempo();
+        let mut bpm = self.bpm();
+        let mut time = Duration::new(0, 0);
+        let mut order: usize = 0;
+        let mut row: usize = 0;
+        // loop start row and remaining repetitions by channel
+        let mut loop_start = vec![0usize; channels];
+        let mut loop_count = vec![0u8; channels];
+        // the row to continue on after a break or loop, which FT2 does not reset after loops
+        let mut break_row: usize = 0;
+
+        while order < sequence.len() && tempo > 0 && bpm > 0 {
+            let ptn_nr = sequence[order];
+            if row >= self.pattern_rows(ptn_nr) {
+                order += 1;
+                row = if ft2 { break_row } else { 0 };
+                break_row = 0;
+                if !quirks.loop_start_persists() {
+                    loop_start.iter_mut().for_each(|start| *start = 0);
+                }
+                continue;
+            }
+            if !visited.insert((order, row, loop_start.clone(), loop_count.clone(), break_row)) {
+                break;
+            }
+            let plays = row_plays.entry((order, row)).or_insert(0);
+            *plays += 1;
+            if *plays > MAX_ROW_PLAYS || rows.len() >= MAX_PLAY_ROWS {
+                break;
+            }
+
+            let mut jump: Option<usize> = None;
+            let mut position_jump = false;
+            let mut loop_jump = false;
+            let mut delay: u16 = 0;
+
+            if let Some(ptn) = self.patterns.get(ptn_nr as usize) {
+                for (chn, trk) in ptn.tracks.iter().enumerate() {
+                    let param = trk.cells[row].fx_param().unwrap_or(0);
+                    match trk.cells[row].fx_command() {
+                        Some(XM_FX_FXX) => if param < 0x20 { tempo = param; } else { bpm = param; },
+                        Some(XM_FX_BXX) => {
+                            jump = Some(param as usize);
+                            position_jump = true;
+                            if ft2 {
+                                break_row = 0;
+                            }
+                        },
+                        Some(XM_FX_DXX) => {
+                            break_row = ((param >> 4) * 10 + (param & 0xf)) as usize;
+                            position_jump = true;
+                        },
+                        Some(0xe) if param & 0xf0 == 0x60 => {
+                            let count = param & 0xf;
+                            if count == 0 {
+                                loop_start[chn] = row;
+                                continue;
+                            }
+                            loop_count[chn] = if loop_count[chn] == 0 { count } else { loop_count[chn] - 1 };
+                            if loop_count[chn] > 0 {
+                                break_row = loop_start[chn];
+                                loop_jump = true;
+                            }
+                        },
+                        Some(0xe) if param & 0xf0 == 0xe0 => delay = (param & 0xf) as u16,
+                        _ => (),
+                    }
+                }
+            }
+
+            // F00 stops the song
+            if tempo == 0 {
+                break;
+            }
+
+            let ticks = tempo as u16 * (delay + 1);
+            rows.push(PlayRow {
+                order: order as u16,
+                pattern: ptn_nr,
+                row: row as u16,
+                tempo,
+                bpm,
+                ticks,
+                start: time,
+            });
+            time += ticks_duration(ticks as u64, bpm);
+
+            if position_jump {
+                order = jump.unwrap_or(order + 1);
+                row = break_row;
+                break_row = 0;
+                if order < sequence.len() && row >= self.pattern_rows(sequence[order]) {
+                    row = 0;
+                }
+                if !quirks.loop_start_persists() {
+                    loop_start.iter_mut().for_each(|start| *start = 0);
+                }
+            }
+            else if loop_jump {
+                row = break_row;
+                if !ft2 {
+                    break_row = 0;
+                }
+            }
+            else {
+                row += 1;
+            }
+        }
+
+        rows
+    }
+
+    /// Returns the time at which the given row of the given sequence position starts playing.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given position is never reached during playback.
+    pub fn time_at(&self, order: u16, row: u16) -> Result<Duration, XMParseError> {
+        match self.play_rows().iter().find(|r| r.order == order && r.row == row) {
+            Some(r) => Ok(r.start),
+            None => Err(XMParseError::new(&format!("Position {}:{} is not reached during playback.", order, row))),
+        }
+    }
+
+    /// Returns the sequence position and row that is playing at the given time.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the given time is beyond the end of the song.
+    pub fn position_at(&self, time: Duration) -> Result<(u16, u16), XMParseError> {
+        match self.play_rows().iter().find(|r| r.start <= time && time < r.start + r.duration()) {
+            Some(r) => Ok((r.order, r.row)),
+            None => Err(XMParseError::new(&format!("Time {:?} is beyond the end of the song.", time))),
+        }
+    }
+
+    // Number of rows that can actually be addressed in the given pattern. Patterns that are referenced in the
+    // sequence but do not exist are played as empty 64-row patterns by FT2.
+    pub(crate) fn pattern_rows(&self, ptn_nr: u8) -> usize {
+        match self.patterns.get(ptn_nr as usize) {
+            Some(ptn) => match ptn.tracks.first() {
+                Some(trk) => trk.cells.len(),
+                None => ptn.len() as usize,
+            },
+            None => 64,
+        }
+    }
+}
+
+
+/// Returns the duration of a tick at the given BPM setting, which is 2.5/BPM seconds as in FT2. A BPM of 0 is
+/// treated as 1.
+pub fn tick_duration(bpm: u8) -> Duration {
+    ticks_duration(1, bpm)
+}
+
+/// Returns the duration of the given number of ticks at the given BPM setting.
+pub fn ticks_duration(ticks: u64, bpm: u8) -> Duration {
+    Duration::from_nanos(ticks * 2_500_000_000 / bpm.max(1) as u64)
+}
+
+/// Returns the duration of a row at the given tempo (ticks per row) and BPM setting, without pattern delays.
+pub fn row_duration(tempo: u8, bpm: u8) -> Duration {
+    ticks_duration(tempo as u64, bpm)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_row_duration() {
+    assert_eq!(tick_duration(125), Duration::from_millis(20));
+    assert_eq!(row_duration(6, 125), Duration::from_millis(120));
+    assert_eq!(row_duration(3, 150), Duration::from_millis(50));
+    assert_eq!(ticks_duration(0, 125), Duration::ZERO);
+    assert_eq!(tick_duration(0), Duration::from_millis(2500));
+}
+
+#[cfg(test)]
+#[test]
+fn test_time_mapping() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    assert_eq!(xm.time_at(0, 0).unwrap(), Duration::from_millis(0));
+    assert_eq!(xm.time_at(0, 8).unwrap(), Duration::from_millis(960));
+    assert_eq!(xm.time_at(1, 0).unwrap(), Duration::from_millis(1500));
+    assert_eq!(xm.time_at(2, 0).unwrap(), Duration::from_millis(1750));
+    assert!(xm.time_at(1, 5).is_err());
+
+    assert_eq!(xm.position_at(Duration::from_millis(1000)).unwrap(), (0, 8));
+    assert_eq!(xm.position_at(Duration::from_millis(1600)).unwrap(), (1, 2));
+    // the pattern loop in pattern 2 plays rows 0..2 three times
+    assert_eq!(xm.position_at(Duration::from_millis(2200)).unwrap(), (2, 3));
+    assert_eq!(xm.time_at(3, 0).unwrap(), Duration::from_millis(2250));
+    assert!(xm.position_at(Duration::from_millis(2500)).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_pattern_loops() {
+    use std::path::Path;
+    use super::{XMPattern, XM_SEQUENCE_BEGIN, XM_SEQUENCE_LEN};
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    xm.patterns.clear();
+    xm.header[XM_SEQUENCE_BEGIN..XM_SEQUENCE_BEGIN + 2].copy_from_slice(&[0, 1]);
+    XModule::write_u16(&mut xm.header, XM_SEQUENCE_LEN, 2);
+    let positions = |xm: &XModule, quirks: QuirksMode| xm.play_rows_with_quirks(quirks).iter()
+        .map(|play_row| (play_row.order, play_row.row)).collect::<Vec<_>>();
+    let set_fx = |ptn: &mut XMPattern, chn: usize, row: usize, cmd: u8, param: u8| {
+        ptn.tracks[chn].cells[row].set_fx_command(Some(cmd));
+        ptn.tracks[chn].cells[row].set_fx_param(Some(param));
+    };
+
+    // loops on two channels: channel 0 repeats rows 1..2 once, channel 1 repeats rows 0..3 once
+    let mut ptn = XMPattern::empty(4, 2);
+    set_fx(&mut ptn, 0, 1, 0xe, 0x60);
+    set_fx(&mut ptn, 0, 2, 0xe, 0x61);
+    set_fx(&mut ptn, 1, 3, 0xe, 0x61);
+    xm.patterns.push(ptn);
+    xm.patterns.push(XMPattern::empty(4, 2));
+    let generic = positions(&xm, QuirksMode::Generic);
+    assert_eq!(generic[..12], [(0, 0), (0, 1), (0, 2), (0, 1), (0, 2), (0, 3), (0, 0), (0, 1), (0, 2), (0, 1),
+        (0, 2), (0, 3)]);
+    assert_eq!(generic[12..], [(1, 0), (1, 1), (1, 2), (1, 3)]);
+    assert_eq!(xm.play_rows().len(), generic.len());
+
+    // FT2 continues the next pattern on the row the last loop jumped to
+    let ft2 = positions(&xm, QuirksMode::FT2);
+    assert_eq!(ft2[..12], generic[..12]);
+    assert_eq!(ft2[12..], [(1, 1), (1, 2), (1, 3)]);
+
+    // FT2 keeps the loop start row of the previous pattern
+    set_fx(&mut xm.patterns[1], 0, 3, 0xe, 0x61);
+    let ft2 = positions(&xm, QuirksMode::FT2);
+    assert_eq!(ft2[12..], [(1, 1), (1, 2), (1, 3), (1, 1), (1, 2), (1, 3)]);
+    let openmpt = positions(&xm, QuirksMode::OpenMPT);
+    assert_eq!(openmpt[12..], [(1, 0), (1, 1), (1, 2), (1, 3), (1, 0), (1, 1), (1, 2), (1, 3)]);
+
+    // a loop and a pattern break on the same row continue on the next position, on the rightmost break row
+    let mut ptn = XMPattern::empty(4, 2);
+    set_fx(&mut ptn, 0, 1, 0xe, 0x62);
+    set_fx(&mut ptn, 1, 1, XM_FX_DXX, 0x02);
+    xm.patterns[0] = ptn;
+    assert_eq!(positions(&xm, QuirksMode::FT2)[..4], [(0, 0), (0, 1), (1, 2), (1, 3)]);
+
+    // loops on many channels are cut off, rather than multiplying the rows played
+    let mut ptn = XMPattern::empty(16, 32);
+    for chn in 0..32 {
+        set_fx(&mut ptn, chn, chn % 16, 0xe, 0x6f);
+    }
+    xm.patterns = vec![ptn, XMPattern::empty(4, 32)];
+    for quirks in [QuirksMode::Generic, QuirksMode::FT2, QuirksMode::OpenMPT] {
+        let rows = positions(&xm, quirks);
+        assert!(rows.len() > MAX_ROW_PLAYS as usize && rows.len() <= MAX_PLAY_ROWS);
+        assert!(rows.iter().filter(|pos| **pos == (0, 0)).count() <= MAX_ROW_PLAYS as usize);
+    }
+}
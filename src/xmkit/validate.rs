@@ -0,0 +1,561 @@
+use std::fmt;
+use super::{XModule, XMInstrument, XMSample, XM_SAMPLE_LOOP_NONE, XM_PATTERN_COUNT, XM_INSTRUMENT_COUNT};
+
+/// Identifies one of the two envelopes of an instrument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Envelope {
+    Volume,
+    Panning,
+}
+
+impl Envelope {
+    // Offsets of the point data, point count, sustain point, loop start, and loop end in the instrument header.
+    pub(crate) fn offsets(self) -> (usize, usize, usize, usize, usize) {
+        match self {
+            Envelope::Volume => (129, 225, 227, 228, 229),
+            Envelope::Panning => (177, 226, 230, 231, 232),
+        }
+    }
+
+    // Offset of the envelope type flags in the instrument header.
+    pub(crate) fn type_offset(self) -> usize {
+        match self {
+            Envelope::Volume => 233,
+            Envelope::Panning => 234,
+        }
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Envelope::Volume => write!(f, "volume envelope"),
+            Envelope::Panning => write!(f, "panning envelope"),
+        }
+    }
+}
+
+
+/// An inconsistency in an instrument envelope, as reported by XMInstrument::validate().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeWarning {
+    /// The envelope has more than the 12 points that fit into the instrument header.
+    TooManyPoints { envelope: Envelope, count: u8 },
+    /// The frame number of the point is not greater than the one of the preceding point.
+    PointOutOfOrder { envelope: Envelope, point: u8 },
+    /// The value of the point is greater than 64.
+    ValueOutOfRange { envelope: Envelope, point: u8, value: u16 },
+    /// The sustain point index refers to a point that does not exist.
+    SustainOutOfRange { envelope: Envelope, index: u8 },
+    /// The loop start index refers to a point that does not exist.
+    LoopStartOutOfRange { envelope: Envelope, index: u8 },
+    /// The loop end index refers to a point that does not exist.
+    LoopEndOutOfRange { envelope: Envelope, index: u8 },
+    /// The loop start point comes after the loop end point.
+    LoopReversed { envelope: Envelope, start: u8, end: u8 },
+}
+
+impl fmt::Display for EnvelopeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EnvelopeWarning::TooManyPoints{envelope, count} =>
+                write!(f, "{} has {} points, at most 12 are allowed", envelope, count),
+            EnvelopeWarning::PointOutOfOrder{envelope, point} =>
+                write!(f, "{} point {} is not positioned after the preceding point", envelope, point),
+            EnvelopeWarning::ValueOutOfRange{envelope, point, value} =>
+                write!(f, "{} point {} has value {}, at most 64 is allowed", envelope, point, value),
+            EnvelopeWarning::SustainOutOfRange{envelope, index} =>
+                write!(f, "{} sustain point {} does not exist", envelope, index),
+            EnvelopeWarning::LoopStartOutOfRange{envelope, index} =>
+                write!(f, "{} loop start point {} does not exist", envelope, index),
+            EnvelopeWarning::LoopEndOutOfRange{envelope, index} =>
+                write!(f, "{} loop end point {} does not exist", envelope, index),
+            EnvelopeWarning::LoopReversed{envelope, start, end} =>
+                write!(f, "{} loop start point {} comes after loop end point {}", envelope, start, end),
+        }
+    }
+}
+
+
+impl XMInstrument {
+
+    /// Checks the instrument's envelopes for inconsistencies, such as envelopes with more than 12 points,
+    /// points that are out of order, or sustain and loop points that refer to non-existing points.
+    /// Returns an empty list if no problems were found, or if the instrument has no samples.
+    /// To fix the reported problems, call normalize_envelopes().
+    pub fn validate(&self) -> Vec<EnvelopeWarning> {
+        let mut warnings: Vec<EnvelopeWarning> = Vec::new();
+        if self.sample_count() == 0 {
+            return warnings;
+        }
+
+        for envelope in &[Envelope::Volume, Envelope::Panning] {
+            let envelope = *envelope;
+            let (points_offset, count_offset, sustain_offset, loop_start_offset, loop_end_offset) = envelope.offsets();
+            let count = self.header[count_offset];
+            if count == 0 {
+                continue;
+            }
+            if count > 12 {
+                warnings.push(EnvelopeWarning::TooManyPoints{envelope, count});
+            }
+
+            let count = count.min(12);
+            let points = XMInstrument::envelope_points(&self.header[points_offset..points_offset + count as usize * 4]);
+            for (point, &(frame, value)) in points.iter().enumerate() {
+                if point > 0 && frame <= points[point - 1].0 {
+                    warnings.push(EnvelopeWarning::PointOutOfOrder{envelope, point: point as u8});
+                }
+                if value > 64 {
+                    warnings.push(EnvelopeWarning::ValueOutOfRange{envelope, point: point as u8, value});
+                }
+            }
+
+            let (sustain, loop_start, loop_end) = (self.header[sustain_offset], self.header[loop_start_offset],
+                self.header[loop_end_offset]);
+            if sustain >= count {
+                warnings.push(EnvelopeWarning::SustainOutOfRange{envelope, index: sustain});
+            }
+            if loop_start >= count {
+                warnings.push(EnvelopeWarning::LoopStartOutOfRange{envelope, index: loop_start});
+            }
+            if loop_end >= count {
+                warnings.push(EnvelopeWarning::LoopEndOutOfRange{envelope, index: loop_end});
+            }
+            if loop_start > loop_end {
+                warnings.push(EnvelopeWarning::LoopReversed{envelope, start: loop_start, end: loop_end});
+            }
+        }
+
+        warnings
+    }
+
+    /// Fixes the problems reported by validate(): surplus points are dropped, points are moved so that each point
+    /// comes after the preceding one, values are clamped to 64, and sustain and loop points are clamped to the
+    /// last existing point. Returns the list of problems that were fixed.
+    pub fn normalize_envelopes(&mut self) -> Vec<EnvelopeWarning> {
+        let warnings = self.validate();
+        if warnings.is_empty() {
+            return warnings;
+        }
+
+        for envelope in &[Envelope::Volume, Envelope::Panning] {
+            let (points_offset, count_offset, sustain_offset, loop_start_offset, loop_end_offset) = envelope.offsets();
+            let count = self.header[count_offset].min(12);
+            if count == 0 {
+                continue;
+            }
+            self.header[count_offset] = count;
+
+            let mut previous_frame: Option<u16> = None;
+            for point in 0..count as usize {
+                let offset = points_offset + point * 4;
+                let mut frame = XModule::read_u16(&self.header, offset);
+                if let Some(previous) = previous_frame {
+                    if frame <= previous {
+                        frame = previous.saturating_add(1);
+                        XModule::write_u16(&mut self.header, offset, frame);
+                    }
+                }
+                previous_frame = Some(frame);
+
+                let value = XModule::read_u16(&self.header, offset + 2).min(64);
+                XModule::write_u16(&mut self.header, offset + 2, value);
+            }
+
+            for offset in &[sustain_offset, loop_start_offset, loop_end_offset] {
+                self.header[*offset] = self.header[*offset].min(count - 1);
+            }
+            if self.header[loop_start_offset] > self.header[loop_end_offset] {
+                self.header[loop_end_offset] = self.header[loop_start_offset];
+            }
+        }
+
+        warnings
+    }
+}
+
+
+/// An inconsistency in a sample, as reported by XMSample::validate().
+/// All lengths and loop points are given in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleWarning {
+    /// The sample length stored in the sample header differs from the length of the sample data.
+    LengthMismatch { header: usize, data: usize },
+    /// The sample has 16-bit resolution, but the data has an odd number of bytes.
+    OddLength16Bit { length: usize },
+    /// The sample has 16-bit resolution, but the loop start or loop length is odd.
+    OddLoop16Bit { loop_start: usize, loop_len: usize },
+    /// The loop extends beyond the end of the sample data.
+    LoopOutOfRange { loop_start: usize, loop_len: usize, length: usize },
+    /// Both the forward and the ping-pong loop flags are set.
+    InvalidLoopType { flags: u8 },
+}
+
+impl fmt::Display for SampleWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SampleWarning::LengthMismatch{header, data} =>
+                write!(f, "sample header specifies {} bytes, but sample has {} bytes of data", header, data),
+            SampleWarning::OddLength16Bit{length} =>
+                write!(f, "16-bit sample has an odd length of {} bytes", length),
+            SampleWarning::OddLoop16Bit{loop_start, loop_len} =>
+                write!(f, "16-bit sample has an odd loop start ({}) or loop length ({})", loop_start, loop_len),
+            SampleWarning::LoopOutOfRange{loop_start, loop_len, length} =>
+                write!(f, "loop ({} + {} bytes) extends beyond the sample length of {} bytes", loop_start, loop_len, length),
+            SampleWarning::InvalidLoopType{flags} =>
+                write!(f, "invalid loop type flags 0x{:02x}", flags),
+        }
+    }
+}
+
+
+impl XMSample {
+
+    /// Checks the sample for inconsistencies between the sample header and the sample data, such as loops extending
+    /// beyond the end of the sample, or odd lengths in 16-bit samples.
+    /// Returns an empty list if no problems were found. To fix the reported problems, call repair().
+    pub fn validate(&self) -> Vec<SampleWarning> {
+        let mut warnings: Vec<SampleWarning> = Vec::new();
+        let length = self.data.len();
+
+        if self.len() != length {
+            warnings.push(SampleWarning::LengthMismatch{header: self.len(), data: length});
+        }
+        if self.is_16bit() && length & 1 != 0 {
+            warnings.push(SampleWarning::OddLength16Bit{length});
+        }
+        if self.header[14] & 3 == 3 {
+            warnings.push(SampleWarning::InvalidLoopType{flags: self.header[14]});
+        }
+
+        if self.loop_type() != XM_SAMPLE_LOOP_NONE {
+            let (loop_start, loop_len) = (self.loop_start(), self.loop_len());
+            if self.is_16bit() && (loop_start | loop_len) & 1 != 0 {
+                warnings.push(SampleWarning::OddLoop16Bit{loop_start, loop_len});
+            }
+            if loop_start.saturating_add(loop_len) > length {
+                warnings.push(SampleWarning::LoopOutOfRange{loop_start, loop_len, length});
+            }
+        }
+
+        warnings
+    }
+
+    /// Fixes the problems reported by validate(): the header length is set to the actual length of the sample data,
+    /// 16-bit samples are truncated to an even number of bytes, loops are aligned and shortened to fit the sample,
+    /// and invalid loop types are reduced to ping-pong loops. Loops that start beyond the end of the sample are
+    /// disabled. Returns the list of problems that were fixed.
+    pub fn repair(&mut self) -> Vec<SampleWarning> {
+        let warnings = self.validate();
+        if warnings.is_empty() {
+            return warnings;
+        }
+
+        if self.is_16bit() && self.data.len() & 1 != 0 {
+            let length = self.data.len() - 1;
+            self.data_mut().truncate(length);
+        }
+        let length = self.data.len();
+        XModule::write_usize(&mut self.header, 0, length);
+
+        if self.header[14] & 3 == 3 {
+            self.header[14] &= !1;
+        }
+
+        if self.loop_type() != XM_SAMPLE_LOOP_NONE {
+            let align = if self.is_16bit() { !1 } else { !0 };
+            let loop_start = self.loop_start() & align;
+            let loop_len = (self.loop_len() & align).min(length.saturating_sub(loop_start));
+
+            if loop_len == 0 {
+                self.header[14] &= !3;
+                XModule::write_usize(&mut self.header, 4, 0);
+                XModule::write_usize(&mut self.header, 8, 0);
+            }
+            else {
+                XModule::write_usize(&mut self.header, 4, loop_start);
+                XModule::write_usize(&mut self.header, 8, loop_len);
+            }
+        }
+
+        warnings
+    }
+}
+
+
+/// The severity of a problem found by XModule::validate().
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Not a problem as such, but something that might be worth knowing, such as unused patterns.
+    Info,
+    /// Something that trackers and players handle in different ways, or not at all.
+    Warning,
+    /// Something that will likely prevent the module from being loaded or played correctly.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+
+/// The part of a module a validation issue or parse error refers to. Pattern and instrument numbers are indices into
+/// XModule::patterns and XModule::instruments, ie. instrument 0 is the first instrument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValidationLocation {
+    Module,
+    Order(u16),
+    Pattern(usize),
+    Instrument(usize),
+    Sample(usize, usize),
+}
+
+impl fmt::Display for ValidationLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationLocation::Module => write!(f, "module"),
+            ValidationLocation::Order(pos) => write!(f, "sequence position {}", pos),
+            ValidationLocation::Pattern(ptn) => write!(f, "pattern {}", ptn),
+            ValidationLocation::Instrument(instr) => write!(f, "instrument {}", instr),
+            ValidationLocation::Sample(instr, smp) => write!(f, "instrument {} sample {}", instr, smp),
+        }
+    }
+}
+
+
+/// A single problem found by XModule::validate().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub location: ValidationLocation,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.location, self.message)
+    }
+}
+
+
+/// The result of validating a module with XModule::validate().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+
+    /// Returns true if no issues with a severity of Warning or Error were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.iter().all(|issue| issue.severity < Severity::Warning)
+    }
+
+    /// Returns the highest severity of all issues, or None if no issues were found.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.issues.iter().map(|issue| issue.severity).max()
+    }
+
+    /// Returns the issues with the given severity.
+    pub fn with_severity(&self, severity: Severity) -> Vec<&ValidationIssue> {
+        self.issues.iter().filter(|issue| issue.severity == severity).collect()
+    }
+
+    fn add(&mut self, severity: Severity, location: ValidationLocation, message: String) {
+        self.issues.push(ValidationIssue{severity, location, message});
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl XModule {
+
+    /// Checks the module for problems, such as header values that are out of bounds or do not match the module data,
+    /// sequence entries referring to missing patterns, or invalid pattern data, and collects them into a report.
+    /// The instrument and sample checks of XMInstrument::validate() and XMSample::validate() are included.
+    pub fn validate(&self) -> ValidationReport {
+        use self::ValidationLocation::*;
+        use self::Severity::*;
+
+        let mut report: ValidationReport = Default::default();
+        let channel_count = self.channel_count() as usize;
+        let pattern_count = XModule::read_u16(&self.header, XM_PATTERN_COUNT) as usize;
+        let instrument_count = XModule::read_u16(&self.header, XM_INSTRUMENT_COUNT) as usize;
+
+        if channel_count == 0 {
+            report.add(Error, Module, "module has no channels".to_string());
+        }
+        else if channel_count > 32 {
+            report.add(Warning, Module, format!("{} channels exceed FT2's limit of 32 channels", channel_count));
+        }
+        else if channel_count & 1 != 0 {
+            report.add(Warning, Module, format!("odd number of channels ({}) is not supported by FT2", channel_count));
+        }
+        if pattern_count != self.patterns.len() {
+            report.add(Error, Module, format!("header specifies {} patterns, but module has {}", pattern_count,
+                self.patterns.len()));
+        }
+        if pattern_count > 256 {
+            report.add(Error, Module, format!("{} patterns exceed the limit of 256 patterns", pattern_count));
+        }
+        if instrument_count != self.instruments.len() {
+            report.add(Error, Module, format!("header specifies {} instruments, but module has {}", instrument_count,
+                self.instruments.len()));
+        }
+        if instrument_count > 128 {
+            report.add(Error, Module, format!("{} instruments exceed the limit of 128 instruments", instrument_count));
+        }
+        if self.is_empty() {
+            report.add(Warning, Module, "sequence is empty".to_string());
+        }
+        if self.len() > 256 {
+            report.add(Error, Module, format!("sequence length {} exceeds the limit of 256", self.len()));
+        }
+        if self.restart_pos() >= self.len() && !self.is_empty() {
+            report.add(Warning, Module, format!("restart position {} is beyond the end of the sequence", self.restart_pos()));
+        }
+        if self.tempo() == 0 || self.tempo() > 31 {
+            report.add(Warning, Module, format!("default tempo {} is out of range", self.tempo()));
+        }
+        if self.bpm() < 32 {
+            report.add(Warning, Module, format!("default BPM {} is out of range", self.bpm()));
+        }
+
+        if self.len() <= 256 {
+            for (pos, ptn) in self.sequence().iter().enumerate() {
+                if *ptn as usize >= self.patterns.len() {
+                    report.add(Warning, Order(pos as u16), format!("pattern {} does not exist", ptn));
+                }
+            }
+        }
+
+        for (nr, ptn) in self.patterns.iter().enumerate() {
+            if !self.pattern_used(nr as u8) || nr > 255 {
+                report.add(Info, Pattern(nr), "pattern is not used in the sequence".to_string());
+            }
+            if ptn.tracks.len() != channel_count {
+                report.add(Error, Pattern(nr), format!("pattern has {} channels, but module has {}", ptn.tracks.len(),
+                    channel_count));
+            }
+
+            let mut invalid_notes = 0;
+            let mut invalid_instruments = 0;
+            for trk in &ptn.tracks {
+                if trk.cells.len() != ptn.len() as usize {
+                    report.add(Error, Pattern(nr), format!("track has {} rows, but pattern has {}", trk.cells.len(),
+                        ptn.len()));
+                }
+                invalid_notes += trk.cells.iter().filter(|cell| cell.note().unwrap_or(0) > 97).count();
+                invalid_instruments += trk.cells.iter()
+                    .filter(|cell| cell.instrument().unwrap_or(0) as usize > instrument_count).count();
+            }
+            if invalid_notes > 0 {
+                report.add(Warning, Pattern(nr), format!("{} cells contain invalid notes", invalid_notes));
+            }
+            if invalid_instruments > 0 {
+                report.add(Warning, Pattern(nr), format!("{} cells refer to non-existing instruments", invalid_instruments));
+            }
+        }
+
+        for (nr, instr) in self.instruments.iter().enumerate() {
+            for warning in instr.validate() {
+                report.add(Warning, Instrument(nr), warning.to_string());
+            }
+            if let Some(keymap) = instr.sample_numbers() {
+                if keymap.iter().any(|smp| *smp as usize >= instr.samples.len()) {
+                    report.add(Warning, Instrument(nr), "sample map refers to non-existing samples".to_string());
+                }
+            }
+            for (smp_nr, smp) in instr.samples.iter().enumerate() {
+                for warning in smp.validate() {
+                    report.add(Warning, Sample(nr, smp_nr), warning.to_string());
+                }
+            }
+        }
+
+        report
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_envelope_validation() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let instr = &mut xm.instruments[0];
+    assert_eq!(instr.volume_envelope_points(), Some(vec![(0, 64), (4, 48), (16, 32), (32, 0)]));
+    assert!(instr.validate().is_empty());
+
+    // point 2 before point 1, value of point 3 too large, loop end beyond the last point
+    instr.header[129 + 8] = 2;
+    instr.header[129 + 14] = 65;
+    instr.header[229] = 4;
+    assert_eq!(instr.validate(), vec![
+        EnvelopeWarning::PointOutOfOrder{envelope: Envelope::Volume, point: 2},
+        EnvelopeWarning::ValueOutOfRange{envelope: Envelope::Volume, point: 3, value: 65},
+        EnvelopeWarning::LoopEndOutOfRange{envelope: Envelope::Volume, index: 4}]);
+
+    assert_eq!(instr.normalize_envelopes().len(), 3);
+    assert!(instr.validate().is_empty());
+    assert_eq!(instr.volume_envelope_points(), Some(vec![(0, 64), (4, 48), (5, 32), (32, 64)]));
+    assert_eq!(instr.header[229], 3);
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_sample_validation() {
+    use std::path::Path;
+    use super::XM_SAMPLE_LOOP_PINGPONG;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let smp = &mut xm.instruments[1].samples[1];
+    assert_eq!(smp.loop_type(), XM_SAMPLE_LOOP_PINGPONG);
+    assert!(smp.validate().is_empty());
+
+    smp.data_mut().truncate(39);
+    assert_eq!(smp.validate(), vec![
+        SampleWarning::LengthMismatch{header: 64, data: 39},
+        SampleWarning::OddLength16Bit{length: 39},
+        SampleWarning::LoopOutOfRange{loop_start: 8, loop_len: 32, length: 39}]);
+
+    assert_eq!(smp.repair().len(), 3);
+    assert!(smp.validate().is_empty());
+    assert_eq!((smp.len(), smp.loop_start(), smp.loop_len()), (38, 8, 30));
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_module_validation() {
+    use std::path::Path;
+
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    assert!(xm.validate().issues.is_empty());
+
+    xm.header[super::XM_SEQUENCE_BEGIN + 1] = 7;
+    xm.instruments[0].header[229] = 12;
+    xm.patterns.pop();
+    let report = xm.validate();
+    assert!(!report.is_ok());
+    assert_eq!(report.max_severity(), Some(Severity::Error));
+    assert_eq!(report.with_severity(Severity::Warning).iter().map(|issue| issue.location).collect::<Vec<_>>(),
+        vec![ValidationLocation::Order(1), ValidationLocation::Order(2), ValidationLocation::Instrument(0)]);
+}
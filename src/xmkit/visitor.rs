@@ -0,0 +1,93 @@
+use super::{XModule, XMPattern, XMInstrument, XMSample, XMCell};
+
+/// Callbacks for XModule::walk(). All methods do nothing by default, so implementors only need to provide the ones
+/// they are interested in.
+pub trait ModuleVisitor {
+    /// Called once, before anything else, with the module whose header data is visited.
+    fn header(&mut self, _xm: &XModule) {}
+
+    /// Called for each pattern, counted from 0, before its cells.
+    fn pattern(&mut self, _ptn_nr: usize, _ptn: &XMPattern) {}
+
+    /// Called for each cell of a pattern, row by row, and in channel order within a row. Empty cells are included.
+    fn cell(&mut self, _ptn_nr: usize, _row: usize, _channel: usize, _cell: &XMCell) {}
+
+    /// Called for each instrument, counted from 1 the way cells refer to it, before its samples.
+    fn instrument(&mut self, _instr_nr: usize, _instr: &XMInstrument) {}
+
+    /// Called for each sample of an instrument, with the instrument counted from 1 and the sample counted from 0.
+    fn sample(&mut self, _instr_nr: usize, _smp_nr: usize, _smp: &XMSample) {}
+}
+
+
+impl XModule {
+
+    /// Visits the contents of the module in file order: the header, then each pattern followed by its cells, then
+    /// each instrument followed by its samples.
+    pub fn walk<V: ModuleVisitor>(&self, visitor: &mut V) {
+        visitor.header(self);
+
+        for (ptn_nr, ptn) in self.patterns.iter().enumerate() {
+            visitor.pattern(ptn_nr, ptn);
+            let rows = ptn.tracks.iter().map(|trk| trk.cells.len()).max().unwrap_or(0);
+            for row in 0..rows {
+                for (channel, trk) in ptn.tracks.iter().enumerate().filter(|(_, trk)| row < trk.cells.len()) {
+                    visitor.cell(ptn_nr, row, channel, &trk.cell_unchecked(row));
+                }
+            }
+        }
+
+        for (instr_nr, instr) in self.instruments.iter().enumerate() {
+            visitor.instrument(instr_nr + 1, instr);
+            for (smp_nr, smp) in instr.samples.iter().enumerate() {
+                visitor.sample(instr_nr + 1, smp_nr, smp);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_walk() {
+    use std::path::Path;
+
+    #[derive(Default)]
+    struct Counter {
+        headers: usize,
+        patterns: Vec<usize>,
+        cells: usize,
+        notes: usize,
+        samples: Vec<(usize, usize)>,
+    }
+
+    impl ModuleVisitor for Counter {
+        fn header(&mut self, _xm: &XModule) {
+            self.headers += 1;
+        }
+
+        fn pattern(&mut self, ptn_nr: usize, _ptn: &XMPattern) {
+            self.patterns.push(ptn_nr);
+        }
+
+        fn cell(&mut self, _ptn_nr: usize, _row: usize, _channel: usize, cell: &XMCell) {
+            self.cells += 1;
+            self.notes += cell.note.is_some() as usize;
+        }
+
+        fn sample(&mut self, instr_nr: usize, smp_nr: usize, _smp: &XMSample) {
+            self.samples.push((instr_nr, smp_nr));
+        }
+    }
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let mut counter = Counter::default();
+    xm.walk(&mut counter);
+    assert_eq!(counter.headers, 1);
+    assert_eq!(counter.patterns, vec![0, 1, 2]);
+    assert_eq!(counter.cells, xm.patterns.iter().map(|ptn| ptn.len() as usize * ptn.tracks.len()).sum::<usize>());
+    let notes: usize = xm.patterns.iter().flat_map(|ptn| ptn.tracks.iter())
+        .map(|trk| trk.cells.iter().filter(|cell| cell.note().is_some()).count()).sum();
+    assert_eq!(counter.notes, notes);
+    assert_eq!(&counter.samples[..3], &[(1, 0), (2, 0), (2, 1)]);
+}
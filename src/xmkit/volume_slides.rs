@@ -0,0 +1,189 @@
+use super::{Effect, QuirksMode, XModule, XMPattern, XM_FX_AXX, XM_FX_EAX, XM_FX_EBX};
+use super::cells::PackedCell;
+
+/// How a module slides volumes, as returned by XModule::volume_slide_usage(). FT2 offers two paths for the same
+/// slides: the volume column (-x, +x, Dx, Ux), which has no effect memory, and the effect column (Axy, EAx, EBx),
+/// whose parameters are remembered. Replayers often implement only one of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VolumeSlideUsage {
+    /// Volume column slides (-x, +x) with a non-zero speed.
+    pub column_slides: usize,
+    /// Volume column fine slides (Dx, Ux) with a non-zero amount.
+    pub column_fine_slides: usize,
+    /// Volume slide effects with a parameter (Axy).
+    pub effect_slides: usize,
+    /// Fine volume slide effects with a parameter (EAx, EBx).
+    pub effect_fine_slides: usize,
+    /// Volume slide effects that continue with the parameter from effect memory (A00, EA0, EB0).
+    pub memory_slides: usize,
+}
+
+impl VolumeSlideUsage {
+
+    /// Returns true if the module slides volumes in the volume column.
+    pub fn uses_volume_column(&self) -> bool {
+        self.column_slides + self.column_fine_slides > 0
+    }
+
+    /// Returns true if the module slides volumes in the effect column.
+    pub fn uses_effect_column(&self) -> bool {
+        self.effect_slides + self.effect_fine_slides + self.memory_slides > 0
+    }
+}
+
+
+impl XModule {
+
+    /// Counts the volume slides in all patterns, by the path they take.
+    pub fn volume_slide_usage(&self) -> VolumeSlideUsage {
+        let mut usage = VolumeSlideUsage::default();
+        for cell in self.patterns.iter().flat_map(|ptn| ptn.tracks.iter()).flat_map(|trk| trk.cells.iter()) {
+            match cell.volume().unwrap_or(0) {
+                vol @ 0x60..=0x7f if vol & 0xf > 0 => usage.column_slides += 1,
+                vol @ 0x80..=0x9f if vol & 0xf > 0 => usage.column_fine_slides += 1,
+                _ => (),
+            }
+            match slide_effect(cell) {
+                Some((_, 0)) => usage.memory_slides += 1,
+                Some((fx, _)) if fx.command() == XM_FX_AXX => usage.effect_slides += 1,
+                Some(_) => usage.effect_fine_slides += 1,
+                None => (),
+            }
+        }
+        usage
+    }
+
+    /// Moves volume slides from the effect column to the volume column, for replayers that only implement the volume
+    /// column path: Axy becomes +x or -y (FT2 ignores y if x is set), EAx becomes Ux, and EBx becomes Dx. Slides are
+    /// only moved if the volume column is empty, and on channels that never continue a slide from effect memory
+    /// (A00, EA0, EB0, or 500 and 600 for Axy), since the memory would no longer be set.
+    /// Returns the number of cells changed.
+    pub fn move_volume_slides_to_volume_column(&mut self) -> usize {
+        let recalled = self.recalled_effects();
+        let mut changed = 0;
+        for (cell, recalled) in cells_with_recalled(&mut self.patterns, &recalled) {
+            let (fx, param) = match slide_effect(cell) {
+                Some((fx, param)) if param > 0 && cell.volume().unwrap_or(0) == 0 => (fx, param),
+                _ => continue,
+            };
+            if recalled.iter().any(|other| fx.shares_memory_with(*other, QuirksMode::FT2)) {
+                continue;
+            }
+            cell.set_volume(Some(match fx.command() {
+                XM_FX_AXX if param >> 4 > 0 => 0x70 | param >> 4,
+                XM_FX_AXX => 0x60 | param,
+                XM_FX_EAX => 0x90 | param,
+                _ => 0x80 | param,
+            }));
+            cell.set_fx_command(None);
+            cell.set_fx_param(None);
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Moves volume slides from the volume column to the effect column, for replayers that do not implement volume
+    /// column slides: +x becomes Ax0, -x becomes A0x, Ux becomes EAx, and Dx becomes EBx. This is the reverse of
+    /// move_volume_slides_to_volume_column(), with the same restrictions: slides are only moved if the effect column
+    /// is empty, and on channels that never continue a slide from effect memory. Returns the number of cells changed.
+    pub fn move_volume_column_slides_to_effects(&mut self) -> usize {
+        let recalled = self.recalled_effects();
+        let mut changed = 0;
+        for (cell, recalled) in cells_with_recalled(&mut self.patterns, &recalled) {
+            let vol = cell.volume().unwrap_or(0);
+            let (cmd, param) = match (vol >> 4, vol & 0xf) {
+                (_, 0) => continue,
+                (6, x) => (XM_FX_AXX, x),
+                (7, x) => (XM_FX_AXX, x << 4),
+                (8, x) => (0xe, 0xb0 | x),
+                (9, x) => (0xe, 0xa0 | x),
+                _ => continue,
+            };
+            let fx = match Effect::from_raw(cmd, param) {
+                Some((fx, _)) => fx,
+                None => continue,
+            };
+            let free = cell.fx_command().unwrap_or(0) == 0 && cell.fx_param().unwrap_or(0) == 0;
+            if !free || recalled.iter().any(|other| fx.shares_memory_with(*other, QuirksMode::FT2)) {
+                continue;
+            }
+            cell.set_volume(None);
+            cell.set_fx_command(Some(cmd));
+            cell.set_fx_param(Some(param));
+            changed += 1;
+        }
+        changed
+    }
+
+    // The effects that continue from effect memory anywhere on each channel.
+    fn recalled_effects(&self) -> Vec<Vec<Effect>> {
+        let mut recalled: Vec<Vec<Effect>> = Vec::new();
+        for ptn in &self.patterns {
+            recalled.resize(recalled.len().max(ptn.tracks.len()), Vec::new());
+            for (trk, recalled) in ptn.tracks.iter().zip(recalled.iter_mut()) {
+                for cell in &trk.cells {
+                    match Effect::from_raw(cell.fx_command().unwrap_or(0), cell.fx_param().unwrap_or(0)) {
+                        Some((fx, 0)) if fx.has_memory() && !recalled.contains(&fx) => recalled.push(fx),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        recalled
+    }
+}
+
+// All cells of the patterns, along with the effects recalled on their channel.
+fn cells_with_recalled<'a>(patterns: &'a mut [XMPattern], recalled: &'a [Vec<Effect>])
+    -> impl Iterator<Item = (&'a mut PackedCell, &'a [Effect])> {
+    patterns.iter_mut().flat_map(move |ptn| ptn.tracks.iter_mut().zip(recalled))
+        .flat_map(|(trk, recalled)| trk.cells.iter_mut().map(move |cell| (cell, &recalled[..])))
+}
+
+// The volume slide effect (Axy, EAx, EBx) of the cell and its parameter, or None if the cell has none.
+fn slide_effect(cell: &PackedCell) -> Option<(Effect, u8)> {
+    Effect::from_raw(cell.fx_command()?, cell.fx_param().unwrap_or(0))
+        .filter(|(fx, _)| [XM_FX_AXX, XM_FX_EAX, XM_FX_EBX].contains(&fx.command()))
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_volume_slides() {
+    use super::XM_FX_5XX;
+
+    let mut xm = XModule::default();
+    let mut ptn = XMPattern::empty(4, 2);
+    // channel 0: A20, A03, EA2, and a volume column -4; channel 1: A10, 500 continuing its memory, and -4
+    let cells = [[(None, Some((XM_FX_AXX, 0x20))), (None, Some((XM_FX_AXX, 0x03))), (None, Some((0xe, 0xa2))),
+        (Some(0x64), None)], [(None, Some((XM_FX_AXX, 0x10))), (None, Some((XM_FX_5XX, 0))), (None, None),
+        (Some(0x64), None)]];
+    for (trk, cells) in ptn.tracks.iter_mut().zip(&cells) {
+        for (cell, (vol, fx)) in trk.cells.iter_mut().zip(cells) {
+            cell.set_volume(*vol);
+            cell.set_fx_command(fx.map(|fx| fx.0));
+            cell.set_fx_param(fx.map(|fx| fx.1));
+        }
+    }
+    xm.patterns.push(ptn);
+
+    assert_eq!(xm.volume_slide_usage(), VolumeSlideUsage { column_slides: 2, column_fine_slides: 0, effect_slides: 3,
+        effect_fine_slides: 1, memory_slides: 0 });
+    let columns = |xm: &XModule, chn: usize| xm.patterns[0].tracks[chn].cells.iter()
+        .map(|cell| (cell.volume(), cell.fx_command(), cell.fx_param())).collect::<Vec<_>>();
+
+    // the A10 on channel 1 sets the memory used by 500, so it stays
+    assert_eq!(xm.move_volume_slides_to_volume_column(), 3);
+    assert_eq!(columns(&xm, 0), vec![(Some(0x72), None, None), (Some(0x63), None, None), (Some(0x92), None, None),
+        (Some(0x64), None, None)]);
+    assert_eq!(columns(&xm, 1)[0], (None, Some(XM_FX_AXX), Some(0x10)));
+    assert_eq!(xm.volume_slide_usage().effect_slides, 1);
+
+    assert_eq!(xm.move_volume_column_slides_to_effects(), 4);
+    assert_eq!(columns(&xm, 0), vec![(None, Some(XM_FX_AXX), Some(0x20)), (None, Some(XM_FX_AXX), Some(0x03)),
+        (None, Some(0xe), Some(0xa2)), (None, Some(XM_FX_AXX), Some(0x04))]);
+    assert_eq!(columns(&xm, 1)[3], (Some(0x64), None, None));
+    let usage = xm.volume_slide_usage();
+    assert!(usage.uses_volume_column() && usage.uses_effect_column());
+    assert_eq!((usage.column_slides, usage.column_fine_slides), (1, 0));
+}
@@ -0,0 +1,284 @@
+use std::io::Read;
+use std::sync::{Arc, OnceLock};
+use super::{codec, XModule, XMSample, XMParseError, XM_SAMPLE_16BIT, XM_SAMPLE_LOOP_NONE, XM_SAMPLE_LOOP_PINGPONG};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Determines the resolution of imported sample data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SampleDepth {
+    /// Imports 8-bit WAVs as 8-bit samples, and everything else as 16-bit samples.
+    #[default]
+    Auto,
+    /// Converts the sample data to 8-bit.
+    Bits8,
+    /// Converts the sample data to 16-bit.
+    Bits16,
+}
+
+/// Options for importing sample data with `XMSample::from_wav`.
+#[derive(Clone, Debug, Default)]
+pub struct SampleImportOptions {
+    /// The name of the sample. Names longer than 22 bytes are truncated.
+    pub name: String,
+    /// The resolution of the imported sample data.
+    pub depth: SampleDepth,
+    /// If set, the sample data is resampled to the given rate (in Hz), using linear interpolation.
+    pub sample_rate: Option<u32>,
+}
+
+impl XMSample {
+
+    /// Constructs a sample from a RIFF WAVE file. Supports integer PCM with 8, 16, 24, or 32 bits and 32-bit float data.
+    /// Multi-channel data is mixed down to mono. The sample is created without a loop, at full volume, centered,
+    /// and tuned to play at its original pitch on C-4.
+    pub fn from_wav<R: Read>(mut reader: R, options: SampleImportOptions) -> Result<XMSample, XMParseError> {
+        let mut wav: Vec<u8> = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut wav) {
+            return Err(XMParseError::new(&format!("Couldn't read WAV data: {}", e)));
+        }
+
+        if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+            return Err(XMParseError::new("Not a RIFF WAVE file."));
+        }
+
+        let mut format: Option<(u16, usize, u32, usize)> = None;
+        let mut pcm: Option<&[u8]> = None;
+        let mut pos = 12;
+
+        while pos + 8 <= wav.len() {
+            let chunk_len = XModule::read_usize(&wav, pos + 4);
+            let chunk = &wav[pos + 8..(pos + 8).saturating_add(chunk_len).min(wav.len())];
+
+            match &wav[pos..pos + 4] {
+                b"fmt " if chunk.len() >= 16 => {
+                    let mut tag = XModule::read_u16(chunk, 0);
+                    if tag == WAVE_FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+                        tag = XModule::read_u16(chunk, 24);
+                    }
+                    format = Some((tag, XModule::read_u16(chunk, 2) as usize, XModule::read_usize(chunk, 4) as u32,
+                        XModule::read_u16(chunk, 14) as usize));
+                },
+                b"data" => pcm = Some(chunk),
+                _ => (),
+            }
+
+            // chunks are padded to an even length
+            pos = (pos + 8).saturating_add(chunk_len).saturating_add(chunk_len & 1);
+        }
+
+        let (tag, channels, rate, bits) = match format {
+            Some(f) => f,
+            None => return Err(XMParseError::new("WAV format chunk missing or corrupt.")),
+        };
+        let pcm = match pcm {
+            Some(p) => p,
+            None => return Err(XMParseError::new("WAV data chunk missing.")),
+        };
+
+        let frames = match (tag, bits) {
+            (WAVE_FORMAT_PCM, 8) | (WAVE_FORMAT_PCM, 16) | (WAVE_FORMAT_PCM, 24) | (WAVE_FORMAT_PCM, 32)
+                | (WAVE_FORMAT_IEEE_FLOAT, 32) if channels > 0 && rate > 0 => decode_frames(pcm, tag, channels, bits),
+            _ => return Err(XMParseError::new(&format!("Unsupported WAV format {} with {} bits, {} channels.",
+                tag, bits, channels))),
+        };
+
+        let frames = match options.sample_rate {
+            Some(target) if target > 0 && target != rate => resample(&frames, rate, target),
+            _ => frames,
+        };
+
+        let is_16bit = match options.depth {
+            SampleDepth::Auto => bits != 8,
+            SampleDepth::Bits8 => false,
+            SampleDepth::Bits16 => true,
+        };
+
+        let mut smp = XMSample::from_pcm(&frames, is_16bit, &options.name);
+        smp.set_tuning_from_rate(options.sample_rate.filter(|r| *r > 0).unwrap_or(rate));
+        Ok(smp)
+    }
+
+    /// Encodes the sample as a mono RIFF WAVE file, with 8-bit unsigned or 16-bit signed PCM data depending on the
+    /// sample's resolution. The sample rate is the sample's C-4 rate. Loops are stored in a sampler ("smpl") chunk.
+    pub fn to_wav(&self) -> Vec<u8> {
+        let bytes = if self.is_16bit() { 2 } else { 1 };
+        let pcm: Vec<u8> = if self.is_16bit() {
+            self.pcm_16bit().iter().flat_map(|smp| vec![*smp as u8, (*smp >> 8) as u8]).collect()
+        }
+        else {
+            self.data_8bit_unsigned()
+        };
+        let rate = self.c4_rate().round() as u32;
+
+        let mut wav: Vec<u8> = Vec::with_capacity(pcm.len() + 112);
+        wav.extend(b"RIFF\0\0\0\0WAVEfmt ");
+        wav.extend(&16u32.to_le_bytes());
+        wav.extend(&WAVE_FORMAT_PCM.to_le_bytes());
+        wav.extend(&1u16.to_le_bytes());
+        wav.extend(&rate.to_le_bytes());
+        wav.extend(&(rate * bytes).to_le_bytes());
+        wav.extend(&(bytes as u16).to_le_bytes());
+        wav.extend(&(bytes as u16 * 8).to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(&(pcm.len() as u32).to_le_bytes());
+        wav.extend(&pcm);
+        if pcm.len() & 1 != 0 {
+            wav.push(0);
+        }
+
+        let (loop_start, loop_len) = self.loop_frames();
+        if self.loop_type() != XM_SAMPLE_LOOP_NONE && loop_len > 0 {
+            let loop_type: u32 = if self.loop_type() == XM_SAMPLE_LOOP_PINGPONG { 1 } else { 0 };
+            wav.extend(b"smpl");
+            wav.extend(&60u32.to_le_bytes());
+            // manufacturer, product, sample period, MIDI unity note (C-4 = 60), pitch fraction, SMPTE format/offset
+            for value in &[0u32, 0, 1_000_000_000 / rate.max(1), 60, 0, 0, 0] {
+                wav.extend(&value.to_le_bytes());
+            }
+            // one loop, no sampler data, then the loop: id, type, start, end (inclusive), fraction, play count
+            for value in &[1u32, 0, 0, loop_type, loop_start as u32, (loop_start + loop_len) as u32 - 1, 0, 0] {
+                wav.extend(&value.to_le_bytes());
+            }
+        }
+
+        let riff_len = wav.len() as u32 - 8;
+        wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        wav
+    }
+
+    // Constructs a sample from signed 16-bit PCM, delta-encoding the data as 8-bit or 16-bit values.
+    pub(crate) fn from_pcm(pcm: &[i16], is_16bit: bool, name: &str) -> XMSample {
+        let data: Vec<u8> = if is_16bit {
+            codec::delta_encode_16(pcm).iter().flat_map(|delta| vec![*delta as u8, (*delta >> 8) as u8]).collect()
+        }
+        else {
+            let pcm8: Vec<i8> = pcm.iter().map(|smp| (*smp >> 8) as i8).collect();
+            codec::delta_encode_8(&pcm8).iter().map(|delta| *delta as u8).collect()
+        };
+
+        let mut header = vec![0u8; 40];
+        XModule::write_usize(&mut header, 0, data.len());
+        header[12] = 0x40;
+        header[14] = if is_16bit { XM_SAMPLE_16BIT } else { 0 };
+        header[15] = 0x80;
+        for (i, c) in name.bytes().take(22).enumerate() {
+            header[18 + i] = c;
+        }
+
+        XMSample { header, data: Arc::new(data), pcm: OnceLock::new() }
+    }
+}
+
+// Decodes interleaved WAV data to mono signed 16-bit PCM.
+fn decode_frames(pcm: &[u8], tag: u16, channels: usize, bits: usize) -> Vec<i16> {
+    let bytes = bits / 8;
+    let frame_len = bytes * channels;
+    let mut frames: Vec<i16> = Vec::with_capacity(pcm.len() / frame_len);
+
+    for frame in pcm.chunks(frame_len).filter(|f| f.len() == frame_len) {
+        let mut sum: i64 = 0;
+
+        for smp in frame.chunks(bytes) {
+            sum += match bytes {
+                1 => ((smp[0] as i64) - 0x80) << 8,
+                2 => i16::from_le_bytes([smp[0], smp[1]]) as i64,
+                3 => (i32::from_le_bytes([0, smp[0], smp[1], smp[2]]) >> 16) as i64,
+                _ if tag == WAVE_FORMAT_IEEE_FLOAT => {
+                    let value = f32::from_le_bytes([smp[0], smp[1], smp[2], smp[3]]);
+                    (value.clamp(-1.0, 1.0) * 32767.0) as i64
+                },
+                _ => (i32::from_le_bytes([smp[0], smp[1], smp[2], smp[3]]) >> 16) as i64,
+            };
+        }
+
+        frames.push((sum / channels as i64) as i16);
+    }
+
+    frames
+}
+
+// Resamples mono signed 16-bit PCM from one sample rate to another, using linear interpolation.
+pub(crate) fn resample(pcm: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if pcm.is_empty() {
+        return Vec::new();
+    }
+
+    let len = (pcm.len() as u64 * to as u64 / from as u64).max(1) as usize;
+    let step = from as f64 / to as f64;
+
+    (0..len).map(|i| {
+        let pos = i as f64 * step;
+        let index = (pos as usize).min(pcm.len() - 1);
+        let next = pcm[(index + 1).min(pcm.len() - 1)] as f64;
+        let frac = pos - index as f64;
+        (pcm[index] as f64 * (1.0 - frac) + next * frac).round() as i16
+    }).collect()
+}
+
+
+#[cfg(test)]
+fn wav_16bit_stereo(frames: &[(i16, i16)], rate: u32) -> Vec<u8> {
+    let mut wav: Vec<u8> = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend(&(36 + frames.len() as u32 * 4).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(&16u32.to_le_bytes());
+    wav.extend(&[1, 0, 2, 0]);
+    wav.extend(&rate.to_le_bytes());
+    wav.extend(&(rate * 4).to_le_bytes());
+    wav.extend(&[4, 0, 16, 0]);
+    wav.extend(b"data");
+    wav.extend(&(frames.len() as u32 * 4).to_le_bytes());
+    for &(l, r) in frames {
+        wav.extend(&l.to_le_bytes());
+        wav.extend(&r.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+#[test]
+fn test_wav_import() {
+    let wav = wav_16bit_stereo(&[(0, 0), (1000, 3000), (-4000, -4000), (0x7f00, 0x7f00)], 22050);
+
+    let smp = XMSample::from_wav(&wav[..], SampleImportOptions { name: "kick".to_string(), ..Default::default() })
+        .unwrap();
+    assert!(smp.is_16bit());
+    assert_eq!(smp.name(), "kick");
+    assert_eq!(smp.len(), 8);
+    assert_eq!(smp.data_16bit_signed(), vec![0, 2000, -4000, 0x7f00]);
+    assert_eq!((smp.relative_note(), smp.finetune()), (17, -28));
+
+    let smp = XMSample::from_wav(&wav[..], SampleImportOptions { depth: SampleDepth::Bits8, ..Default::default() })
+        .unwrap();
+    assert!(!smp.is_16bit());
+    assert_eq!(smp.data_8bit_signed(), vec![0, 7, -16, 127]);
+
+    let smp = XMSample::from_wav(&wav[..], SampleImportOptions { sample_rate: Some(44100), ..Default::default() })
+        .unwrap();
+    assert_eq!(smp.data_16bit_signed(), vec![0, 1000, 2000, -1000, -4000, 14256, 0x7f00, 0x7f00]);
+
+    assert!(XMSample::from_wav(&b"RIFF\0\0\0\0WAVE"[..], SampleImportOptions::default()).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_wav_export() {
+    let mut smp = XMSample::from_pcm(&[0, 1000, -1000, 2000, -2000, 0], true, "");
+    smp.header[14] |= 1;
+    XModule::write_usize(&mut smp.header, 4, 2);
+    XModule::write_usize(&mut smp.header, 8, 6);
+    let wav = smp.to_wav();
+    assert_eq!(&wav[44..56], &[0, 0, 0xe8, 0x03, 0x18, 0xfc, 0xd0, 0x07, 0x30, 0xf8, 0, 0]);
+    assert_eq!(&wav[56..60], b"smpl");
+    // the loop covers frames 1..=3
+    assert_eq!(&wav[64 + 44..64 + 52], &[1, 0, 0, 0, 3, 0, 0, 0]);
+    assert_eq!(wav.len(), 56 + 68);
+
+    // a loop shorter than one frame is no loop
+    XModule::write_usize(&mut smp.header, 8, 1);
+    assert_eq!(smp.to_wav().len(), 56);
+}
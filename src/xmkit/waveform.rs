@@ -0,0 +1,41 @@
+use super::XMSample;
+
+impl XMSample {
+
+    /// Returns the lowest and highest value of the sample data as signed 16-bit PCM for each of width columns, for
+    /// drawing an overview of the sample. The frames are split evenly between the columns. If there are more columns
+    /// than frames, columns share frames, so that the result always has width entries. Empty samples return an empty
+    /// list.
+    pub fn waveform_preview(&self, width: usize) -> Vec<(i16, i16)> {
+        let pcm = self.pcm_16bit();
+        if pcm.is_empty() {
+            return Vec::new();
+        }
+
+        (0..width).map(|column| {
+            let start = column * pcm.len() / width;
+            let end = ((column + 1) * pcm.len() / width).max(start + 1);
+            pcm[start..end].iter().fold((i16::MAX, i16::MIN), |(min, max), smp| (min.min(*smp), max.max(*smp)))
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_waveform_preview() {
+    let pcm: Vec<i16> = vec![0, 100, -200, 300, -400, 500, 0, 0, 1000, -1000];
+    let smp = XMSample::from_pcm(&pcm, true, "");
+
+    assert_eq!(smp.waveform_preview(2), vec![(-400, 300), (-1000, 1000)]);
+    assert_eq!(smp.waveform_preview(3), vec![(-200, 100), (-400, 500), (-1000, 1000)]);
+    assert_eq!(smp.waveform_preview(1), vec![(-1000, 1000)]);
+    assert_eq!(smp.waveform_preview(20)[..4], [(0, 0), (0, 0), (100, 100), (100, 100)]);
+    assert_eq!(smp.waveform_preview(20).len(), 20);
+    assert!(smp.waveform_preview(0).is_empty());
+    assert!(XMSample::default().waveform_preview(16).is_empty());
+
+    // 8-bit samples are scaled to 16 bits
+    let smp = XMSample::from_pcm(&pcm, false, "");
+    assert_eq!(smp.waveform_preview(1), vec![(-1024, 768)]);
+}
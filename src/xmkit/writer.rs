@@ -0,0 +1,255 @@
+use std::array;
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::{XModule, XMPattern, XMInstrument, XM_PATTERN_COUNT, XM_INSTRUMENT_COUNT};
+
+/// Determines how pattern cells are encoded when writing patterns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatternPacking {
+    /// Packs cells the way FT2 does: cells with all five fields set are written unpacked, all other cells
+    /// are packed, omitting fields that are empty or zero. Empty patterns are written without any packed data.
+    Canonical,
+    /// Packs all cells, including those with all five fields set.
+    Packed,
+    /// Writes all cells unpacked, ie. as 5 bytes.
+    Unpacked,
+}
+
+
+impl XModule {
+
+    /// Encodes the module in XM format. Patterns and instruments that have not been modified since the module was
+    /// parsed (see is_modified()) are written exactly as they were stored, so unmodified modules are reproduced
+    /// byte by byte. Modified patterns are packed the way FT2 does. Header fields that xmkit does not interpret,
+    /// such as reserved bytes, are written back unchanged even for modified parts, and so is the data following the
+    /// last instrument, such as OpenMPT extensions.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(None, true)
+    }
+
+    /// Encodes the module in XM format, packing all patterns with the given packing method.
+    pub fn to_bytes_with_packing(&self, packing: PatternPacking) -> Vec<u8> {
+        self.encode(Some(packing), true)
+    }
+
+    /// Encodes the module in XM format like to_bytes(), but without any sample data. All instrument and sample
+    /// headers are kept, with the sample lengths set to 0, so the result is a "stripped" module that contains the
+    /// arrangement and instrument settings only. Loop points are left unchanged.
+    pub fn to_bytes_without_samples(&self) -> Vec<u8> {
+        self.encode(None, false)
+    }
+
+    // Encodes the module, reusing the stored bytes of unmodified patterns unless a packing method is given.
+    // Unmodified instruments are reused as well, unless sample data is left out.
+    fn encode(&self, packing: Option<PatternPacking>, sample_data: bool) -> Vec<u8> {
+        let mut data = self.header.clone();
+        XModule::write_u16(&mut data, XM_PATTERN_COUNT, self.patterns.len() as u16);
+        XModule::write_u16(&mut data, XM_INSTRUMENT_COUNT, self.instruments.len() as u16);
+
+        for ptn in &self.patterns {
+            match (packing, ptn.original_bytes()) {
+                (None, Some(bytes)) => data.extend(bytes),
+                (packing, _) => data.extend(ptn.to_bytes(packing.unwrap_or(PatternPacking::Canonical))),
+            }
+        }
+        for instr in &self.instruments {
+            if !sample_data {
+                data.extend(instr.encode_headers_with_lengths(false));
+                continue;
+            }
+            match instr.original_bytes() {
+                Some(bytes) => data.extend(bytes),
+                None => data.extend(instr.encode_headers()),
+            }
+            for smp in &instr.samples {
+                data.extend(smp.data.iter());
+            }
+        }
+        data.extend(&self.trailing);
+
+        data
+    }
+
+    /// Writes the module to an XM file, packing patterns the way FT2 does.
+    ///
+    /// # Errors
+    /// Returns an io::Error if the file cannot be written.
+    pub fn write_file(&self, filepath: &Path) -> io::Result<()> {
+        fs::write(filepath, self.to_bytes())
+    }
+}
+
+
+impl XMPattern {
+
+    /// Encodes the pattern in XM format, using the given packing method for the pattern cells.
+    pub fn to_bytes(&self, packing: PatternPacking) -> Vec<u8> {
+        let mut rows = match self.tracks.first() {
+            Some(trk) => trk.cells.len(),
+            None => self.len() as usize,
+        };
+        let mut cells: Vec<u8> = Vec::with_capacity(rows * self.tracks.len() * 5);
+
+        if packing == PatternPacking::Canonical && self.is_empty() {
+            rows = 0;
+        }
+
+        for row in 0..rows {
+            for trk in &self.tracks {
+                let fields: [Option<u8>; 5] = array::from_fn(|field| trk.cells[row].get(field));
+                let mut ctrl: u8 = 0x80;
+                for (bit, field) in fields.iter().enumerate() {
+                    if field.unwrap_or(0) != 0 {
+                        ctrl |= 1 << bit;
+                    }
+                }
+
+                if packing == PatternPacking::Unpacked || (packing == PatternPacking::Canonical && ctrl == 0x9f) {
+                    cells.extend(fields.iter().map(|field| field.unwrap_or(0)));
+                }
+                else {
+                    cells.push(ctrl);
+                    cells.extend(fields.iter().filter_map(|field| *field).filter(|value| *value != 0));
+                }
+            }
+        }
+
+        let mut data = self.header.clone();
+        XModule::write_u16(&mut data, 7, cells.len() as u16);
+        data.extend(cells);
+        data
+    }
+}
+
+
+impl XMInstrument {
+
+    /// Encodes the instrument in XM format, including sample headers and sample data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.encode_headers();
+        for smp in &self.samples {
+            data.extend(smp.data.iter());
+        }
+
+        data
+    }
+
+    // Encodes the instrument header and the sample headers.
+    pub(crate) fn encode_headers(&self) -> Vec<u8> {
+        self.encode_headers_with_lengths(true)
+    }
+
+    // Encodes the instrument header and the sample headers. Sample lengths are written as 0 if `lengths` is false.
+    fn encode_headers_with_lengths(&self, lengths: bool) -> Vec<u8> {
+        let mut data = self.header.clone();
+        XModule::write_u16(&mut data, 27, self.samples.len() as u16);
+
+        for smp in &self.samples {
+            let mut header = smp.header.clone();
+            XModule::write_usize(&mut header, 0, if lengths { smp.data.len() } else { 0 });
+            data.extend(header);
+        }
+
+        data
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_round_trip() {
+    use std::path::Path;
+
+    let original = fs::read(Path::new("test.xm")).unwrap();
+    let xm = XModule::parse(original.clone()).unwrap();
+    assert!(xm.to_bytes() == original);
+
+    let unpacked = xm.to_bytes_with_packing(PatternPacking::Unpacked);
+    assert!(unpacked.len() > original.len());
+    let xm_unpacked = XModule::parse(unpacked.clone()).unwrap();
+    assert_eq!(xm_unpacked.patterns[0].tracks[1].fx_command_raw(2).unwrap(), Some(0xe));
+    assert!(xm_unpacked.to_bytes() == unpacked);
+    assert!(xm_unpacked.to_bytes_with_packing(PatternPacking::Canonical) == original);
+
+    let packed = XModule::parse(xm.to_bytes_with_packing(PatternPacking::Packed)).unwrap();
+    assert!(packed.to_bytes_with_packing(PatternPacking::Canonical) == original);
+
+    // empty patterns are written without packed data
+    let empty = XMPattern::parse(vec![9, 0, 0, 0, 0, 64, 0, 0, 0], 4).unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(empty.len(), 64);
+    assert_eq!(empty.tracks[3].note_raw(63).unwrap(), None);
+    assert_eq!(empty.to_bytes(PatternPacking::Canonical).len(), 9);
+    assert_eq!(empty.to_bytes(PatternPacking::Packed).len(), 9 + 64 * 4);
+    assert!(!xm.patterns[0].is_empty());
+
+    assert_eq!(XMPattern::parse(vec![9, 0, 0, 0, 0, 0, 0, 0, 0], 4).unwrap().len(), 64);
+    assert!(XMPattern::parse(vec![9, 0, 0, 0, 0, 1, 1, 0, 0], 4).is_err());
+    assert!(XMPattern::parse(vec![9, 0, 0, 0, 0, 1, 0, 2, 0, 0x80, 0x80], 4).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_to_bytes_without_samples() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let sample_bytes: usize = xm.instruments.iter().flat_map(|instr| instr.samples.iter()).map(|smp| smp.len()).sum();
+    assert!(sample_bytes > 0);
+    let stripped = xm.to_bytes_without_samples();
+    assert_eq!(stripped.len(), xm.to_bytes().len() - sample_bytes);
+
+    let reparsed = XModule::parse(stripped).unwrap();
+    let canonical = |xm: &XModule| xm.patterns[0].to_bytes(PatternPacking::Canonical);
+    assert!(canonical(&reparsed) == canonical(&xm));
+    for (instr, original) in reparsed.instruments.iter().zip(&xm.instruments) {
+        assert_eq!((instr.name(), instr.sample_count()), (original.name(), original.sample_count()));
+        for (smp, original) in instr.samples.iter().zip(&original.samples) {
+            assert!(smp.is_empty());
+            assert_eq!((smp.name(), smp.volume(), smp.is_16bit()), (original.name(), original.volume(),
+                original.is_16bit()));
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_unknown_data_preserved() {
+    use std::path::Path;
+    use super::{XM_HEADER_SIZE, XM_SEQUENCE_BEGIN, XM_SEQUENCE_MAX_LEN};
+
+    // store unknown data in every structure: after the sequence, in an extended pattern header, in the reserved
+    // bytes of the instrument and sample headers, and after the last instrument
+    let mut xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+    let module_header_end = XM_SEQUENCE_BEGIN + XM_SEQUENCE_MAX_LEN;
+    xm.header.extend(b"XTRA");
+    XModule::write_usize(&mut xm.header, XM_HEADER_SIZE, module_header_end + 4 - XM_HEADER_SIZE);
+    xm.patterns[0].header[4] = 0x33;
+    xm.patterns[0].header.extend(&[0xab, 0xcd]);
+    XModule::write_usize(&mut xm.patterns[0].header, 0, 11);
+    xm.instruments[1].header[26] = 0x7f;
+    for byte in xm.instruments[1].header[248..263].iter_mut() {
+        *byte = 0x5a;
+    }
+    xm.instruments[1].samples[0].header[17] = 0x42;
+    xm.set_trailing_data(b"junk".to_vec());
+    let data = xm.to_bytes();
+
+    // untouched structures are written back byte by byte
+    assert!(XModule::parse(data.clone()).unwrap().to_bytes() == data);
+
+    // edited structures keep their unknown data
+    let mut edited = XModule::parse(data.clone()).unwrap();
+    edited.set_name("edited");
+    edited.patterns[0].tracks[0].cells[0].set_note(Some(50));
+    edited.instruments[1].samples[0].remove_dc_offset();
+    assert!(edited.is_modified() && edited.patterns[0].is_modified() && edited.instruments[1].is_modified());
+    let reparsed = XModule::parse(edited.to_bytes()).unwrap();
+    assert_eq!(&reparsed.header[module_header_end..], b"XTRA");
+    assert_eq!(&reparsed.patterns[0].header[..], &xm.patterns[0].header[..]);
+    assert_eq!(reparsed.patterns[0].tracks[0].note(0).unwrap(), 50);
+    assert_eq!(&reparsed.instruments[1].header[..], &xm.instruments[1].header[..]);
+    assert_eq!(reparsed.instruments[1].samples[0].header[17], 0x42);
+    assert_eq!(reparsed.trailing_data(), b"junk");
+}
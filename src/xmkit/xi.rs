@@ -0,0 +1,107 @@
+use super::{XModule, XMInstrument, XMParseError};
+
+const XI_SIGNATURE: &[u8] = b"Extended Instrument: ";
+const XI_TRACKER_NAME: &[u8] = b"FastTracker v2.00   ";
+const XI_VERSION: u16 = 0x0102;
+// Size of the XI header, up to and including the sample count.
+const XI_HEADER_SIZE: usize = 298;
+// Size of a complete XM instrument header, as written by FastTracker II.
+const XM_INSTRUMENT_HEADER_SIZE: usize = 263;
+// Offsets of the sample map, envelopes, vibrato settings, fadeout, and reserved bytes, which are identical in both
+// formats.
+const XI_INSTRUMENT_SETTINGS: usize = 66;
+const XM_INSTRUMENT_SETTINGS: usize = 33;
+const INSTRUMENT_SETTINGS_LEN: usize = 230;
+
+impl XMInstrument {
+
+    /// Parses a FastTracker II standalone instrument (.xi file), and constructs an XMInstrument instance from it if
+    /// the data is valid. Only version 1.02 of the format, which is used by FastTracker II and all modern trackers,
+    /// is supported.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the data is not an XI file, if the file version is not supported, or if the sample
+    /// headers or sample data are incomplete.
+    pub fn from_xi(data: Vec<u8>) -> Result<XMInstrument, XMParseError> {
+        if data.len() < XI_HEADER_SIZE {
+            return Err(XMParseError::new("XI header incomplete.").with_sizes(XI_HEADER_SIZE, data.len()).at(0));
+        }
+        if !data.starts_with(XI_SIGNATURE) {
+            return Err(XMParseError::new("Not an XI file.").at(0));
+        }
+        let version = XModule::read_u16(&data, 64);
+        if version != XI_VERSION {
+            return Err(XMParseError::new(&format!("Unsupported XI version {:x}.{:02x}.", version >> 8,
+                version & 0xff)).at(64));
+        }
+
+        let mut instr_data = vec![0u8; XM_INSTRUMENT_HEADER_SIZE];
+        XModule::write_usize(&mut instr_data, 0, XM_INSTRUMENT_HEADER_SIZE);
+        instr_data[4..26].copy_from_slice(&data[21..43]);
+        instr_data[27..29].copy_from_slice(&data[296..298]);
+        XModule::write_usize(&mut instr_data, 29, 40);
+        instr_data[XM_INSTRUMENT_SETTINGS..XM_INSTRUMENT_SETTINGS + INSTRUMENT_SETTINGS_LEN]
+            .copy_from_slice(&data[XI_INSTRUMENT_SETTINGS..XI_INSTRUMENT_SETTINGS + INSTRUMENT_SETTINGS_LEN]);
+        instr_data.extend(&data[XI_HEADER_SIZE..]);
+
+        // offsets in the instrument data are shifted relative to the XI file by the difference in header sizes
+        XMInstrument::parse(instr_data).map_err(|e| {
+            let offset = e.offset().map(|offset| offset + XI_HEADER_SIZE - XM_INSTRUMENT_HEADER_SIZE);
+            XMParseError { offset, ..e }
+        })
+    }
+
+    /// Encodes the instrument as a FastTracker II standalone instrument (.xi file, version 1.02), including sample
+    /// headers and sample data.
+    pub fn to_xi(&self) -> Vec<u8> {
+        let mut header = self.header.clone();
+        header.resize(XM_INSTRUMENT_HEADER_SIZE.max(header.len()), 0);
+
+        let mut data = Vec::with_capacity(XI_HEADER_SIZE);
+        data.extend(XI_SIGNATURE);
+        data.extend(&header[4..26]);
+        data.push(0x1a);
+        data.extend(XI_TRACKER_NAME);
+        data.extend(&XI_VERSION.to_le_bytes());
+        data.extend(&header[XM_INSTRUMENT_SETTINGS..XM_INSTRUMENT_SETTINGS + INSTRUMENT_SETTINGS_LEN]);
+        data.extend(&(self.samples.len() as u16).to_le_bytes());
+
+        // sample headers and data are stored exactly as in XM instruments
+        let instr = self.to_bytes();
+        data.extend(&instr[self.header.len()..]);
+        data
+    }
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_xi_round_trip() {
+    use std::path::Path;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    for instr in &xm.instruments {
+        let xi = instr.to_xi();
+        assert!(xi.starts_with(b"Extended Instrument: "));
+        assert_eq!(xi.len(), XI_HEADER_SIZE + instr.to_bytes().len() - instr.header.len());
+
+        let imported = XMInstrument::from_xi(xi).unwrap();
+        assert_eq!(imported.name(), instr.name());
+        assert_eq!(imported.sample_numbers(), instr.sample_numbers());
+        assert_eq!(imported.volume_envelope_points(), instr.volume_envelope_points());
+        assert_eq!(imported.volume_fadeout(), instr.volume_fadeout());
+        assert_eq!(imported.samples.len(), instr.samples.len());
+        for (a, b) in imported.samples.iter().zip(&instr.samples) {
+            assert_eq!(a.header, b.header);
+            assert_eq!(a.data, b.data);
+        }
+        assert_eq!(imported.to_xi(), instr.to_xi());
+    }
+
+    let mut xi = xm.instruments[1].to_xi();
+    xi.truncate(xi.len() - 10);
+    let e = XMInstrument::from_xi(xi).unwrap_err();
+    assert!(e.offset().unwrap() > XI_HEADER_SIZE);
+    assert!(XMInstrument::from_xi(xm.instruments[0].to_bytes()).is_err());
+}
@@ -0,0 +1,239 @@
+use super::{XModule, XMPattern, XMParseError};
+use super::pitch::NOTE_NAMES;
+
+const XP_VERSION: u16 = 1;
+// XP files always contain data for the maximum number of channels.
+const XP_CHANNELS: usize = 32;
+const OPENMPT_CLIPBOARD_HEADER: &str = "ModPlug Tracker  XM";
+// The most channels OpenMPT supports in XMs, as accepted by XModule::parse().
+const OPENMPT_CHANNELS: usize = 127;
+// Volume column commands as displayed by OpenMPT, starting at 0x60. Volume (v) and panning (p) are handled separately.
+const OPENMPT_VOLUME_COMMANDS: [char; 10] = ['d', 'c', 'b', 'a', 'u', 'h', 'p', 'l', 'r', 'g'];
+
+impl XMPattern {
+
+    /// Parses a FastTracker II pattern file (.xp), and constructs an XMPattern instance with the given number of
+    /// channels from it. XP files always hold 32 channels, so data in channels beyond channel_count is dropped.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the data is not a version 1 XP file, if the number of rows is out of bounds, or if
+    /// the pattern data is incomplete.
+    pub fn from_xp(data: Vec<u8>, channel_count: u8) -> Result<XMPattern, XMParseError> {
+        if data.len() < 4 {
+            return Err(XMParseError::new("XP header incomplete.").with_sizes(4, data.len()).at(0));
+        }
+        let version = XModule::read_u16(&data, 0);
+        if version != XP_VERSION {
+            return Err(XMParseError::new(&format!("Unsupported XP version {}.", version)).at(0));
+        }
+        let rows = XModule::read_u16(&data, 2);
+        if rows == 0 || rows > 256 {
+            return Err(XMParseError::new(&format!("Invalid XP pattern length of {} rows.", rows)).at(2));
+        }
+        let size = 4 + rows as usize * XP_CHANNELS * 5;
+        if data.len() < size {
+            return Err(XMParseError::new("XP pattern data incomplete.").with_sizes(size, data.len()).at(4));
+        }
+
+        let mut ptn = XMPattern::empty(rows, channel_count.min(XP_CHANNELS as u8));
+        for (chan, trk) in ptn.tracks.iter_mut().enumerate() {
+            for row in 0..rows as usize {
+                let cell = 4 + (row * XP_CHANNELS + chan) * 5;
+                let field = |index: usize| Some(data[cell + index]).filter(|value| *value != 0);
+                trk.cells[row].set_note(field(0));
+                trk.cells[row].set_instrument(field(1));
+                trk.cells[row].set_volume(field(2));
+                trk.cells[row].set_fx_command(field(3));
+                trk.cells[row].set_fx_param(field(4));
+            }
+        }
+
+        Ok(ptn)
+    }
+
+    /// Encodes the pattern as a FastTracker II pattern file (.xp). The pattern data is stored unpacked, and padded
+    /// to 32 channels.
+    pub fn to_xp(&self) -> Vec<u8> {
+        let rows = self.tracks.first().map_or(self.len() as usize, |trk| trk.cells.len());
+        let mut data = Vec::with_capacity(4 + rows * XP_CHANNELS * 5);
+        data.extend(&XP_VERSION.to_le_bytes());
+        data.extend(&(rows as u16).to_le_bytes());
+
+        for row in 0..rows {
+            for chan in 0..XP_CHANNELS {
+                match self.tracks.get(chan) {
+                    Some(trk) => data.extend((0..5).map(|field| trk.cells[row].get(field).unwrap_or(0))),
+                    None => data.extend(&[0; 5]),
+                }
+            }
+        }
+
+        data
+    }
+
+    /// Parses pattern data copied to the clipboard by OpenMPT, and constructs an XMPattern instance from it. The
+    /// number of rows and channels is taken from the copied data. Note cuts (^^^) and note fades (~~~), which XM
+    /// does not support, are converted to key off.
+    ///
+    /// # Errors
+    /// Returns an XMParseError if the text does not start with OpenMPT's clipboard header, if it holds no rows or
+    /// more than 256 rows or 127 channels, or if a cell cannot be parsed.
+    pub fn from_openmpt_clipboard(text: &str) -> Result<XMPattern, XMParseError> {
+        let mut lines = text.lines();
+        if !lines.next().is_some_and(|header| header.starts_with("ModPlug Tracker")) {
+            return Err(XMParseError::new("Not an OpenMPT pattern clipboard."));
+        }
+
+        let rows: Vec<Vec<&str>> = lines.filter(|line| line.starts_with('|'))
+            .map(|line| line.split('|').skip(1).collect())
+            .collect();
+        let channel_count = rows.iter().map(|cells| cells.len()).max().unwrap_or(0);
+        if rows.is_empty() || rows.len() > 256 || channel_count > OPENMPT_CHANNELS {
+            return Err(XMParseError::new(&format!("Cannot paste {} rows and {} channels.", rows.len(),
+                channel_count)));
+        }
+
+        let mut ptn = XMPattern::empty(rows.len() as u16, channel_count as u8);
+        for (row, cells) in rows.iter().enumerate() {
+            for (chan, text) in cells.iter().enumerate() {
+                let fields = parse_openmpt_cell(text).ok_or_else(|| XMParseError::new(&format!(
+                    "Invalid cell \"{}\" in row {}, channel {}.", text, row, chan)))?;
+                let trk = &mut ptn.tracks[chan];
+                trk.cells[row].set_note(fields[0]);
+                trk.cells[row].set_instrument(fields[1]);
+                trk.cells[row].set_volume(fields[2]);
+                trk.cells[row].set_fx_command(fields[3]);
+                trk.cells[row].set_fx_param(fields[4]);
+            }
+        }
+
+        Ok(ptn)
+    }
+
+    /// Encodes the pattern in the text format OpenMPT uses for copying pattern data to the clipboard, so it can be
+    /// pasted into OpenMPT. As in OpenMPT, instrument numbers are written as two decimal digits, so instruments
+    /// above 99, which cannot be pasted back, are dropped along with invalid volume column values.
+    pub fn to_openmpt_clipboard(&self) -> String {
+        let rows = self.tracks.first().map_or(self.len() as usize, |trk| trk.cells.len());
+        let mut text = format!("{}\r\n", OPENMPT_CLIPBOARD_HEADER);
+
+        for row in 0..rows {
+            for trk in &self.tracks {
+                let cell = trk.cell_unchecked(row);
+                let note = match cell.note.unwrap_or(0) {
+                    // OpenMPT displays notes one octave higher than FastTracker II
+                    note @ 1..=96 => format!("{}{}", NOTE_NAMES[(note as usize - 1) % 12], (note - 1) / 12 + 1),
+                    97 => "===".to_string(),
+                    _ => "...".to_string(),
+                };
+                let instr = match cell.instrument.unwrap_or(0) {
+                    instr @ 1..=99 => format!("{:02}", instr),
+                    _ => "..".to_string(),
+                };
+                let volume = match cell.volume.unwrap_or(0) {
+                    vol @ 0x10..=0x50 => format!("v{:02}", vol - 0x10),
+                    vol @ 0xc0..=0xcf => format!("p{:02}", (vol & 0xf) * 4),
+                    vol @ 0x60..=0xff => format!("{}{:02}", OPENMPT_VOLUME_COMMANDS[(vol as usize >> 4) - 6], vol & 0xf),
+                    _ => "...".to_string(),
+                };
+                let fx = match (cell.fx_command.unwrap_or(0), cell.fx_param.unwrap_or(0)) {
+                    (0, 0) => "...".to_string(),
+                    (cmd, param) => format!("{}{:02X}",
+                        std::char::from_digit(cmd as u32 % 36, 36).unwrap_or('?').to_ascii_uppercase(), param),
+                };
+                text.push('|');
+                text.push_str(&format!("{}{}{}{}", note, instr, volume, fx));
+            }
+            text.push_str("\r\n");
+        }
+
+        text
+    }
+}
+
+// Parses a single cell of OpenMPT's clipboard format into note, instrument, volume, effect command, and effect
+// parameter. Missing fields, which OpenMPT writes as dots or spaces, are None.
+fn parse_openmpt_cell(text: &str) -> Option<[Option<u8>; 5]> {
+    let chars: Vec<char> = text.chars().collect();
+    let field = |start: usize, len: usize| -> Option<String> {
+        let field: String = chars.iter().skip(start).take(len).collect();
+        if field.chars().all(|c| c == '.' || c == ' ') { None } else { Some(field) }
+    };
+    let mut fields = [None; 5];
+
+    if let Some(note) = field(0, 3) {
+        fields[0] = match note.as_str() {
+            "===" | "^^^" | "~~~" => Some(97),
+            _ => {
+                let name = NOTE_NAMES.iter().position(|name| note.starts_with(name))?;
+                let octave = note.get(2..)?.parse::<usize>().ok().filter(|octave| (1..=8).contains(octave))?;
+                Some((octave - 1) * 12 + name + 1).filter(|note| *note <= 96).map(|note| note as u8)
+            },
+        };
+        fields[0]?;
+    }
+    if let Some(instr) = field(3, 2) {
+        fields[1] = Some(instr.parse::<u8>().ok().filter(|instr| *instr > 0)?);
+    }
+    if let Some(volume) = field(5, 3) {
+        let cmd = volume.chars().next()?;
+        let value = volume.get(1..)?.parse::<u8>().ok()?;
+        fields[2] = Some(match cmd {
+            'v' if value <= 64 => 0x10 + value,
+            'p' if value <= 64 => 0xc0 + (value / 4).min(15),
+            _ if value <= 15 => 0x60 + 0x10 * OPENMPT_VOLUME_COMMANDS.iter().position(|c| *c == cmd)? as u8 + value,
+            _ => return None,
+        });
+    }
+    if let Some(fx) = field(8, 3) {
+        let cmd = fx.chars().next()?.to_digit(36)?;
+        fields[3] = Some(cmd as u8).filter(|cmd| *cmd != 0);
+        fields[4] = Some(u8::from_str_radix(fx.get(1..)?, 16).ok()?).filter(|param| *param != 0);
+    }
+
+    Some(fields)
+}
+
+
+#[cfg(test)]
+#[test]
+fn test_pattern_interchange() {
+    use std::path::Path;
+    use super::PatternPacking;
+
+    let xm = XModule::parse_file(Path::new("test.xm")).unwrap();
+
+    for ptn in &xm.patterns {
+        let xp = ptn.to_xp();
+        assert_eq!(xp.len(), 4 + ptn.len() as usize * 32 * 5);
+        let imported = XMPattern::from_xp(xp, xm.channel_count()).unwrap();
+        assert_eq!(imported.to_bytes(PatternPacking::Unpacked), ptn.to_bytes(PatternPacking::Unpacked));
+
+        let text = ptn.to_openmpt_clipboard();
+        let imported = XMPattern::from_openmpt_clipboard(&text).unwrap();
+        assert_eq!(imported.to_bytes(PatternPacking::Unpacked), ptn.to_bytes(PatternPacking::Unpacked));
+    }
+
+    let text = xm.patterns[0].to_openmpt_clipboard();
+    assert!(text.starts_with("ModPlug Tracker  XM\r\n|"));
+    assert_eq!(text.lines().nth(1).unwrap().split('|').nth(3), Some("A-501...C20"));
+    assert_eq!(text.lines().nth(13).unwrap().split('|').nth(1), Some("===........"));
+
+    // partial selection with a volume column command and an extended effect
+    let ptn = XMPattern::from_openmpt_clipboard("ModPlug Tracker  XM\r\n|C#5  d04E12\r\n|...........").unwrap();
+    assert_eq!((ptn.len(), ptn.channel_count()), (2, 1));
+    assert_eq!(ptn.tracks[0].cell(0).unwrap().to_string(), "C#4 .. 64 E12");
+    assert!(XMPattern::from_openmpt_clipboard("ModPlug Tracker  XM\r\n|X-501...C20").is_err());
+    assert!(XMPattern::from_xp(vec![1, 0, 0, 0], 4).is_err());
+
+    // more than 32 channels, and an instrument that doesn't fit into 2 digits
+    let mut wide = XMPattern::empty(2, 40);
+    wide.tracks[39].cells[0].set_note(Some(49));
+    wide.tracks[39].cells[0].set_instrument(Some(99));
+    wide.tracks[39].cells[1].set_instrument(Some(100));
+    let imported = XMPattern::from_openmpt_clipboard(&wide.to_openmpt_clipboard()).unwrap();
+    assert_eq!(imported.channel_count(), 40);
+    assert_eq!(imported.tracks[39].cell(0).unwrap().to_string(), "C-4 63 .. ...");
+    assert_eq!(imported.tracks[39].cell(1).unwrap().instrument, None);
+    assert!(XMPattern::from_openmpt_clipboard(&XMPattern::empty(1, 128).to_openmpt_clipboard()).is_err());
+}